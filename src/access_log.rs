@@ -0,0 +1,656 @@
+//! Per-request access log
+//!
+//! Writes one entry per S3 request, either as a line in the format of S3
+//! server access logs, so existing log pipelines can parse it unchanged, or
+//! as a JSON object with a configurable set of fields. Entries go to their
+//! own stream or file, or through the application logger; in the latter
+//! case the request trace events are not logged, so each request is logged
+//! once.
+//!
+//! Fields the proxy cannot know, such as the bucket owner, are `-` as in
+//! S3's own logs. The entry is written once the response body has been sent
+//! (or abandoned), so the byte count and total time cover the whole
+//! transfer. Successful responses can be sampled; others are always
+//! logged. Health, readiness and metrics requests are not logged.
+
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use bytes::Bytes;
+use chrono::{DateTime, SecondsFormat, Utc};
+use http_body::{Body as _, Frame, SizeHint};
+use serde_json::{json, Map, Value};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::auth::{Principal, ANONYMOUS};
+use crate::config::{AccessLogConfig, AccessLogField, AccessLogFormat, Config};
+use crate::errors::ErrorCode;
+use crate::request_id::RequestId;
+use crate::routes::{self, ip_filter};
+
+tokio::task_local! {
+    static BACKEND: Arc<BackendUsage>;
+}
+
+/// Storage backend use by one request
+#[derive(Default)]
+struct BackendUsage {
+    /// Time spent in backend calls, in nanoseconds
+    nanos: AtomicU64,
+    /// Failover member (`primary` or `secondary`) that served the request
+    served_by: Mutex<Option<&'static str>>,
+}
+
+/// Add a storage backend call to the current request's backend duration
+pub fn record_backend_time(elapsed: Duration) {
+    let _ = BACKEND.try_with(|usage| usage.nanos.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed));
+}
+
+/// Record which failover member served the current request
+pub fn record_served_by(member: &'static str) {
+    let _ = BACKEND.try_with(|usage| *usage.served_by.lock().unwrap_or_else(|e| e.into_inner()) = Some(member));
+}
+
+/// Where access log entries are written
+enum Sink {
+    Writer(Mutex<Box<dyn Write + Send>>),
+    Logger,
+}
+
+/// Formats and writes access log entries
+pub struct AccessLog {
+    sink: Sink,
+    format: AccessLogFormat,
+    fields: Vec<AccessLogField>,
+    sample_ratio: f64,
+    trust_forwarded_for: bool,
+}
+
+impl AccessLog {
+    /// Open the configured output, or `None` when the access log is disabled
+    pub fn from_config(config: &Config) -> Result<Option<Self>, String> {
+        let access_log = &config.access_log;
+        if !access_log.enabled {
+            return Ok(None);
+        }
+        if !(0.0..=1.0).contains(&access_log.sample_ratio) {
+            return Err(format!(
+                "Access log sample ratio must be between 0 and 1, got {}",
+                access_log.sample_ratio
+            ));
+        }
+        let trust_forwarded_for = config.ip_filter.trust_forwarded_for;
+        let writer: Box<dyn Write + Send> = match access_log.output.as_str() {
+            "log" => return Ok(Some(Self::new(access_log, Sink::Logger, trust_forwarded_for))),
+            "stdout" => Box::new(std::io::stdout()),
+            "stderr" => Box::new(std::io::stderr()),
+            path => Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| format!("Failed to open access log {}: {}", path, e))?,
+            ),
+        };
+        Ok(Some(Self::to_writer(access_log, writer, trust_forwarded_for)))
+    }
+
+    /// Write entries formatted as configured to `writer`, taking client
+    /// addresses from `X-Forwarded-For` when `trust_forwarded_for` is set
+    pub fn to_writer(config: &AccessLogConfig, writer: Box<dyn Write + Send>, trust_forwarded_for: bool) -> Self {
+        Self::new(config, Sink::Writer(Mutex::new(writer)), trust_forwarded_for)
+    }
+
+    fn new(config: &AccessLogConfig, sink: Sink, trust_forwarded_for: bool) -> Self {
+        Self {
+            sink,
+            format: config.format,
+            fields: config.fields.clone(),
+            sample_ratio: config.sample_ratio,
+            trust_forwarded_for,
+        }
+    }
+
+    fn write(&self, entry: &Entry) {
+        if entry.status.is_success() && !sampled(self.sample_ratio) {
+            return;
+        }
+        match (&self.sink, self.format) {
+            (Sink::Writer(writer), format) => {
+                let line = match format {
+                    AccessLogFormat::S3 => format!("{}\n", entry),
+                    AccessLogFormat::Json => format!("{}\n", entry.to_json(&self.fields)),
+                };
+                let mut writer = writer.lock().unwrap_or_else(|e| e.into_inner());
+                if let Err(e) = writer.write_all(line.as_bytes()).and_then(|()| writer.flush()) {
+                    warn!(error = %e, "Failed to write access log");
+                }
+            }
+            (Sink::Logger, AccessLogFormat::S3) => info!("{}", entry),
+            (Sink::Logger, AccessLogFormat::Json) => self.log_event(entry),
+        }
+    }
+
+    /// Emit the selected fields as an application log event
+    fn log_event(&self, entry: &Entry) {
+        let has = |field| self.fields.contains(&field);
+        info!(
+            bucket = has(AccessLogField::Bucket).then_some(entry.bucket.as_deref()).flatten(),
+            key = has(AccessLogField::Key).then_some(entry.key.as_deref()).flatten(),
+            operation = has(AccessLogField::Operation).then_some(entry.s3_operation),
+            status = has(AccessLogField::Status).then_some(entry.status.as_u16()),
+            bytes_received = has(AccessLogField::BytesReceived).then(|| entry.bytes_received()),
+            bytes_sent = has(AccessLogField::BytesSent).then_some(entry.bytes_sent),
+            duration_ms = has(AccessLogField::DurationMs).then(|| millis(entry.total_time)),
+            backend_duration_ms = has(AccessLogField::BackendDurationMs).then(|| millis(entry.backend_time())),
+            client_ip = has(AccessLogField::ClientIp).then_some(entry.remote_ip.as_deref()).flatten(),
+            user_agent = has(AccessLogField::UserAgent).then_some(entry.user_agent.as_deref()).flatten(),
+            request_id = has(AccessLogField::RequestId).then_some(entry.request_id.as_deref()).flatten(),
+            served_by = has(AccessLogField::ServedBy).then(|| entry.served_by()).flatten(),
+            "Access"
+        );
+    }
+}
+
+/// Whether to log a successful response at `ratio`
+fn sampled(ratio: f64) -> bool {
+    if ratio >= 1.0 {
+        return true;
+    }
+    // Top 53 random bits as a fraction in [0, 1)
+    let random = (Uuid::new_v4().as_u64_pair().0 >> 11) as f64 / (1u64 << 53) as f64;
+    random < ratio
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Middleware writing an access log entry for every S3 request
+///
+/// Must run inside the request ID middleware. Does nothing without an
+/// access log.
+pub async fn record(State(log): State<Option<Arc<AccessLog>>>, request: Request, next: Next) -> Response {
+    let Some(log) = log else {
+        return next.run(request).await;
+    };
+    if routes::is_system_path(request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    let start = Instant::now();
+    let mut entry = Entry::from_request(&request, log.trust_forwarded_for);
+    let received = entry.bytes_received.clone();
+    let request = request.map(|body| Body::new(CountingBody { inner: body, count: received }));
+    let response = BACKEND.scope(entry.backend.clone(), next.run(request)).await;
+    entry.complete(&response, start.elapsed());
+
+    response.map(|body| {
+        Body::new(LoggedBody {
+            inner: body,
+            log,
+            entry,
+            start,
+        })
+    })
+}
+
+/// Fields of one access log line
+struct Entry {
+    time: DateTime<Utc>,
+    bucket: Option<String>,
+    key: Option<String>,
+    remote_ip: Option<String>,
+    requester: Option<String>,
+    request_id: Option<String>,
+    host_id: Option<String>,
+    /// Operation name as in metrics, e.g. `GetObject`
+    s3_operation: &'static str,
+    /// Operation in the access log form, e.g. `REST.GET.OBJECT`
+    operation: String,
+    request_uri: String,
+    status: StatusCode,
+    error_code: Option<&'static str>,
+    bytes_sent: u64,
+    bytes_received: Arc<AtomicU64>,
+    backend: Arc<BackendUsage>,
+    object_size: Option<u64>,
+    total_time: Duration,
+    turn_around_time: Duration,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    signature_version: Option<&'static str>,
+    authentication_type: Option<&'static str>,
+    host: Option<String>,
+}
+
+impl Entry {
+    fn from_request(request: &Request, trust_forwarded_for: bool) -> Self {
+        let uri = request.uri();
+        let headers = request.headers();
+        let method = request.method().clone();
+        let operation = routes::operation_name(&method, uri.path(), uri.query());
+        let mut segments = uri.path().trim_start_matches('/').splitn(2, '/');
+        let bucket = segments.next().filter(|s| !s.is_empty()).map(str::to_string);
+        let key = segments.next().filter(|s| !s.is_empty()).map(str::to_string);
+        let (signature_version, authentication_type) = signature(headers, uri.query());
+        let request_id = request.extensions().get::<RequestId>();
+        let s3_operation = operation;
+        let operation = routes::access_log_operation(&method, operation);
+        // Uploaded object size; downloads take it from the response
+        let object_size = (operation == "REST.PUT.OBJECT").then(|| content_length(headers)).flatten();
+
+        Self {
+            time: Utc::now(),
+            bucket,
+            key,
+            remote_ip: ip_filter::client_ip(request, trust_forwarded_for).map(|ip| ip.to_canonical().to_string()),
+            requester: None,
+            request_id: request_id.map(|id| id.to_string()),
+            host_id: request_id.map(RequestId::host_id),
+            s3_operation,
+            operation,
+            request_uri: format!(
+                "{} {} {:?}",
+                method,
+                uri.path_and_query().map_or(uri.path(), |p| p.as_str()),
+                request.version()
+            ),
+            status: StatusCode::OK,
+            error_code: None,
+            bytes_sent: 0,
+            bytes_received: Arc::default(),
+            backend: Arc::default(),
+            object_size,
+            total_time: Duration::ZERO,
+            turn_around_time: Duration::ZERO,
+            referer: header_string(headers, header::REFERER),
+            user_agent: header_string(headers, header::USER_AGENT),
+            signature_version,
+            authentication_type,
+            host: header_string(headers, header::HOST),
+        }
+    }
+
+    /// Record what the response headers tell
+    fn complete(&mut self, response: &Response, turn_around_time: Duration) {
+        self.status = response.status();
+        self.error_code = response.extensions().get::<ErrorCode>().map(|code| code.0);
+        self.requester = response
+            .extensions()
+            .get::<Principal>()
+            .map(|principal| principal.name.clone())
+            .filter(|name| name != ANONYMOUS);
+        self.turn_around_time = turn_around_time;
+        if self.status.is_success() && matches!(self.operation.as_str(), "REST.GET.OBJECT" | "REST.HEAD.OBJECT") {
+            self.object_size = content_length(response.headers()).or_else(|| response.body().size_hint().exact());
+        }
+    }
+
+    fn bytes_received(&self) -> u64 {
+        self.bytes_received.load(Ordering::Relaxed)
+    }
+
+    fn backend_time(&self) -> Duration {
+        Duration::from_nanos(self.backend.nanos.load(Ordering::Relaxed))
+    }
+
+    fn served_by(&self) -> Option<&'static str> {
+        *self.backend.served_by.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Structured form with the selected fields
+    fn to_json(&self, fields: &[AccessLogField]) -> Value {
+        let mut object = Map::new();
+        object.insert(
+            "time".to_string(),
+            json!(self.time.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        );
+        for field in fields {
+            let value = match field {
+                AccessLogField::Bucket => json!(self.bucket),
+                AccessLogField::Key => json!(self.key),
+                AccessLogField::Operation => json!(self.s3_operation),
+                AccessLogField::Status => json!(self.status.as_u16()),
+                AccessLogField::BytesReceived => json!(self.bytes_received()),
+                AccessLogField::BytesSent => json!(self.bytes_sent),
+                AccessLogField::DurationMs => json!(millis(self.total_time)),
+                AccessLogField::BackendDurationMs => json!(millis(self.backend_time())),
+                AccessLogField::ClientIp => json!(self.remote_ip),
+                AccessLogField::UserAgent => json!(self.user_agent),
+                AccessLogField::RequestId => json!(self.request_id),
+                AccessLogField::ServedBy => json!(self.served_by()),
+            };
+            object.insert(field.as_str().to_string(), value);
+        }
+        Value::Object(object)
+    }
+}
+
+impl std::fmt::Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn field(value: Option<&str>) -> &str {
+            value.filter(|v| !v.is_empty()).unwrap_or("-")
+        }
+        fn count(value: Option<u64>) -> String {
+            value.filter(|&v| v > 0).map_or_else(|| "-".to_string(), |v| v.to_string())
+        }
+
+        write!(
+            f,
+            "- {} [{}] {} {} {} {} {} \"{}\" {} {} {} {} {} {} \"{}\" \"{}\" - {} {} - {} {} - - -",
+            field(self.bucket.as_deref()),
+            self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+            field(self.remote_ip.as_deref()),
+            field(self.requester.as_deref()),
+            field(self.request_id.as_deref()),
+            self.operation,
+            field(self.key.as_deref()),
+            self.request_uri,
+            self.status.as_u16(),
+            field(self.error_code),
+            count(Some(self.bytes_sent)),
+            count(self.object_size),
+            self.total_time.as_millis(),
+            self.turn_around_time.as_millis(),
+            field(self.referer.as_deref()),
+            field(self.user_agent.as_deref()),
+            field(self.host_id.as_deref()),
+            field(self.signature_version),
+            field(self.authentication_type),
+            field(self.host.as_deref()),
+        )
+    }
+}
+
+/// Signature version and authentication type as S3 logs them
+fn signature(headers: &HeaderMap, query: Option<&str>) -> (Option<&'static str>, Option<&'static str>) {
+    let authorization = headers.get(header::AUTHORIZATION).and_then(|v| v.to_str().ok());
+    let has_param = |name: &str| {
+        query.is_some_and(|q| q.split('&').any(|pair| pair.split('=').next() == Some(name)))
+    };
+    match authorization {
+        Some(auth) if auth.starts_with("AWS4-HMAC-SHA256") => (Some("SigV4"), Some("AuthHeader")),
+        Some(auth) if auth.starts_with("AWS ") => (Some("SigV2"), Some("AuthHeader")),
+        _ if has_param("X-Amz-Algorithm") => (Some("SigV4"), Some("QueryString")),
+        _ if has_param("Signature") => (Some("SigV2"), Some("QueryString")),
+        _ => (None, None),
+    }
+}
+
+fn header_string(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string)
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers.get(header::CONTENT_LENGTH)?.to_str().ok()?.parse().ok()
+}
+
+/// Request body that counts the bytes received
+struct CountingBody {
+    inner: Body,
+    count: Arc<AtomicU64>,
+}
+
+impl http_body::Body for CountingBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let frame = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &frame {
+            if let Some(data) = frame.data_ref() {
+                self.count.fetch_add(data.len() as u64, Ordering::Relaxed);
+            }
+        }
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Response body that counts bytes sent and writes the log line when
+/// dropped, after the last frame or when the client goes away
+struct LoggedBody {
+    inner: Body,
+    log: Arc<AccessLog>,
+    entry: Entry,
+    start: Instant,
+}
+
+impl http_body::Body for LoggedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let frame = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &frame {
+            if let Some(data) = frame.data_ref() {
+                self.entry.bytes_sent += data.len() as u64;
+            }
+        }
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        self.entry.total_time = self.start.elapsed();
+        self.log.write(&self.entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::middleware::from_fn_with_state;
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    use crate::config::{AccessLogConfig, AccessLogField, AccessLogFormat};
+    use crate::errors::S3ProxyError;
+
+    /// Writer collecting lines for inspection
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Capture {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    fn router(capture: &Capture) -> Router {
+        router_with(capture, &AccessLogConfig::default())
+    }
+
+    fn router_with(capture: &Capture, config: &AccessLogConfig) -> Router {
+        let log = Arc::new(AccessLog::to_writer(config, Box::new(capture.clone()), true));
+        Router::new()
+            .route("/healthz", get(|| async { "OK" }))
+            .route(
+                "/:bucket/*key",
+                get(|| async { "hello" }).put(|body: Bytes| async move {
+                    record_backend_time(Duration::from_millis(3));
+                    format!("{}", body.len())
+                }),
+            )
+            .route(
+                "/:bucket",
+                get(|| async { S3ProxyError::NoSuchBucket { bucket: "missing".to_string() } }),
+            )
+            .layer(from_fn_with_state(Some(log), record))
+    }
+
+    async fn send(router: &Router, request: Request) {
+        let response = router.clone().oneshot(request).await.unwrap();
+        axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_s3_log_line() {
+        let capture = Capture::default();
+        let router = router(&capture);
+
+        let request = Request::builder()
+            .uri("/photos/2024/cat.jpg?versionId=null")
+            .header("x-forwarded-for", "203.0.113.7")
+            .header("user-agent", "aws-cli/2.15")
+            .header("host", "s3.example.com")
+            .header("authorization", "AWS4-HMAC-SHA256 Credential=AKID/20240101/us-east-1/s3/aws4_request")
+            .body(Body::empty())
+            .unwrap();
+        send(&router, request).await;
+
+        let lines = capture.lines();
+        assert_eq!(lines.len(), 1);
+        let line = &lines[0];
+        assert!(line.starts_with("- photos ["), "{}", line);
+        assert!(
+            line.contains(
+                "] 203.0.113.7 - - REST.GET.OBJECT 2024/cat.jpg \"GET /photos/2024/cat.jpg?versionId=null HTTP/1.1\" 200 - 5 5 "
+            ),
+            "{}",
+            line
+        );
+        assert!(line.contains(" \"-\" \"aws-cli/2.15\" - - SigV4 - AuthHeader s3.example.com - - -"), "{}", line);
+        // 26 fields; the time and request URI contain spaces
+        assert_eq!(line.split(' ').count(), 29, "{}", line);
+    }
+
+    #[tokio::test]
+    async fn test_error_code_logged_and_system_paths_skipped() {
+        let capture = Capture::default();
+        let router = router(&capture);
+
+        send(&router, Request::builder().uri("/missing").body(Body::empty()).unwrap()).await;
+        send(&router, Request::builder().uri("/healthz").body(Body::empty()).unwrap()).await;
+
+        let lines = capture.lines();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains(" REST.GET.BUCKET - \"GET /missing HTTP/1.1\" 404 NoSuchBucket "), "{}", lines[0]);
+    }
+
+    #[tokio::test]
+    async fn test_json_fields() {
+        let capture = Capture::default();
+        let config = AccessLogConfig {
+            format: AccessLogFormat::Json,
+            fields: vec![
+                AccessLogField::Bucket,
+                AccessLogField::Key,
+                AccessLogField::Operation,
+                AccessLogField::Status,
+                AccessLogField::BytesReceived,
+                AccessLogField::BackendDurationMs,
+                AccessLogField::ClientIp,
+            ],
+            ..Default::default()
+        };
+        let router = router_with(&capture, &config);
+
+        let request = Request::builder()
+            .method("PUT")
+            .uri("/photos/cat.jpg")
+            .header("x-forwarded-for", "203.0.113.7")
+            .header("user-agent", "aws-cli/2.15")
+            .body(Body::from("meow"))
+            .unwrap();
+        send(&router, request).await;
+
+        let lines = capture.lines();
+        assert_eq!(lines.len(), 1);
+        let entry: Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(entry["bucket"], "photos");
+        assert_eq!(entry["key"], "cat.jpg");
+        assert_eq!(entry["operation"], "PutObject");
+        assert_eq!(entry["status"], 200);
+        assert_eq!(entry["bytes_received"], 4);
+        assert!(entry["backend_duration_ms"].as_f64().unwrap() >= 3.0);
+        assert_eq!(entry["client_ip"], "203.0.113.7");
+        assert!(entry["time"].is_string());
+        // Fields not selected are left out
+        assert!(entry.get("user_agent").is_none());
+        assert!(entry.get("bytes_sent").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sampling_keeps_errors() {
+        let capture = Capture::default();
+        let config = AccessLogConfig {
+            sample_ratio: 0.0,
+            ..Default::default()
+        };
+        let router = router_with(&capture, &config);
+
+        for _ in 0..5 {
+            send(&router, Request::builder().uri("/photos/cat.jpg").body(Body::empty()).unwrap()).await;
+        }
+        send(&router, Request::builder().uri("/missing").body(Body::empty()).unwrap()).await;
+
+        let lines = capture.lines();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains(" 404 NoSuchBucket "), "{}", lines[0]);
+    }
+
+    #[test]
+    fn test_sampled() {
+        assert!(sampled(1.0));
+        assert!(!sampled(0.0));
+        let hits = (0..1000).filter(|_| sampled(0.5)).count();
+        assert!((300..700).contains(&hits), "{}", hits);
+    }
+
+    #[tokio::test]
+    async fn test_disabled() {
+        let router = Router::new()
+            .route("/:bucket", get(|| async { "" }))
+            .layer(from_fn_with_state(None, record));
+        let request = Request::builder().uri("/bucket").body(Body::empty()).unwrap();
+        assert_eq!(router.oneshot(request).await.unwrap().status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,529 @@
+//! Audit log of modifying requests
+//!
+//! Records who wrote or deleted what through the proxy: one JSON record per
+//! PUT, POST and DELETE request on buckets and objects, copies included,
+//! whether it succeeded or was rejected, and optionally per read. Records
+//! carry a schema `version`, bumped whenever a field changes meaning or is
+//! removed, so consumers can tell record layouts apart.
+//!
+//! Records are appended to a dedicated file, rotated by size, or collected
+//! into batches stored as NDJSON objects under a reserved prefix of the
+//! proxied backend. A record that cannot be written is logged, or, when the
+//! policy says so, fails its request with 500 `InternalError`; requests
+//! then wait until their batch is stored, so nothing is acknowledged
+//! without its record.
+
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, Method};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use chrono::{DateTime, SecondsFormat, Utc};
+use http_body::Body as _;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::auth::{Principal, ANONYMOUS};
+use crate::config::{AuditFailurePolicy, AuditLogConfig, AuditSink, Config};
+use crate::errors::{ErrorCode, S3ProxyError};
+use crate::metrics::AUDIT_RECORDS;
+use crate::request_id::RequestId;
+use crate::routes::{self, ip_filter};
+use crate::s3;
+use crate::storage::{BucketRegistry, StorageBackend};
+
+/// Version of the record schema
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Payload size of `aws-chunked` uploads, whose content length includes
+/// the chunk signatures
+const DECODED_CONTENT_LENGTH: &str = "x-amz-decoded-content-length";
+
+/// One audited request
+#[derive(Debug, Serialize)]
+struct Record {
+    version: u32,
+    time: String,
+    /// Authenticated principal, or `anonymous`
+    principal: String,
+    client_ip: Option<String>,
+    bucket: Option<String>,
+    key: Option<String>,
+    operation: &'static str,
+    /// `x-amz-copy-source` of a CopyObject, as the client sent it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    copy_source: Option<String>,
+    /// Size of the object written or read, when known
+    size: Option<u64>,
+    status: u16,
+    /// `success` or `failure`
+    result: &'static str,
+    error_code: Option<&'static str>,
+    request_id: Option<String>,
+}
+
+impl Record {
+    fn from_request(request: &Request, trust_forwarded_for: bool) -> Self {
+        let uri = request.uri();
+        let headers = request.headers();
+        let mut operation = routes::operation_name(request.method(), uri.path(), uri.query());
+        let copy_source = (operation == "PutObject")
+            .then(|| headers.get(s3::COPY_SOURCE)?.to_str().ok())
+            .flatten()
+            .map(str::to_string);
+        if copy_source.is_some() {
+            operation = "CopyObject";
+        }
+        let mut segments = uri.path().trim_start_matches('/').splitn(2, '/');
+        let bucket = segments.next().filter(|s| !s.is_empty()).map(str::to_string);
+        let key = segments.next().filter(|s| !s.is_empty()).map(str::to_string);
+        let size = matches!(operation, "PutObject" | "UploadPart")
+            .then(|| {
+                content_length(headers, DECODED_CONTENT_LENGTH)
+                    .or_else(|| content_length(headers, header::CONTENT_LENGTH.as_str()))
+                    .or_else(|| request.body().size_hint().exact())
+            })
+            .flatten();
+
+        Self {
+            version: SCHEMA_VERSION,
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            principal: ANONYMOUS.to_string(),
+            client_ip: ip_filter::client_ip(request, trust_forwarded_for).map(|ip| ip.to_canonical().to_string()),
+            bucket,
+            key,
+            operation,
+            copy_source,
+            size,
+            status: 0,
+            result: "failure",
+            error_code: None,
+            request_id: request.extensions().get::<RequestId>().map(|id| id.to_string()),
+        }
+    }
+
+    /// Record the outcome the response tells
+    fn complete(&mut self, response: &Response) {
+        let status = response.status();
+        self.status = status.as_u16();
+        self.result = if status.is_success() { "success" } else { "failure" };
+        self.error_code = response.extensions().get::<ErrorCode>().map(|code| code.0);
+        if let Some(principal) = response.extensions().get::<Principal>() {
+            self.principal = principal.name.clone();
+        }
+        if status.is_success() && matches!(self.operation, "GetObject" | "HeadObject") {
+            self.size = content_length(response.headers(), header::CONTENT_LENGTH.as_str())
+                .or_else(|| response.body().size_hint().exact());
+        }
+    }
+
+    fn to_line(&self) -> String {
+        let mut line = serde_json::to_string(self).unwrap_or_default();
+        line.push('\n');
+        line
+    }
+}
+
+fn content_length(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+/// Append-only file rotated once it would grow past `max_bytes`
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn open(config: &AuditLogConfig) -> io::Result<Self> {
+        let path = PathBuf::from(&config.path);
+        let file = Self::append(&path)?;
+        Ok(Self {
+            size: file.metadata()?.len(),
+            path,
+            file,
+            max_bytes: config.max_bytes,
+            max_files: config.max_files,
+        })
+    }
+
+    fn append(path: &PathBuf) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.max_bytes > 0 && self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shift `<path>.<n>` to `<path>.<n + 1>`, dropping the oldest, and
+    /// start a new file
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        for n in (1..self.max_files).rev() {
+            match std::fs::rename(rotated(n), rotated(n + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        std::fs::rename(&self.path, rotated(1))?;
+        self.file = Self::append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Record waiting to be stored by the backend sink
+struct Pending {
+    line: String,
+    /// Told the outcome of the batch, when the request waits for it
+    stored: Option<oneshot::Sender<Result<(), String>>>,
+}
+
+/// Where audit records are written
+enum Sink {
+    File(Mutex<RotatingFile>),
+    Backend(mpsc::UnboundedSender<Pending>),
+}
+
+/// Writes audit records to the configured sink
+pub struct AuditLog {
+    sink: Sink,
+    on_failure: AuditFailurePolicy,
+    include_reads: bool,
+    trust_forwarded_for: bool,
+}
+
+impl AuditLog {
+    /// Open the configured sink, or `None` when the audit log is disabled
+    ///
+    /// The backend sink writes through the backend of the configured
+    /// bucket in `registry`, from a background task.
+    pub fn from_config(config: &Config, registry: &BucketRegistry) -> Result<Option<Self>, String> {
+        let audit_log = &config.audit_log;
+        if !audit_log.enabled {
+            return Ok(None);
+        }
+        let sink = match audit_log.sink {
+            AuditSink::File => {
+                let file = RotatingFile::open(audit_log)
+                    .map_err(|e| format!("Failed to open audit log {}: {}", audit_log.path, e))?;
+                Sink::File(Mutex::new(file))
+            }
+            AuditSink::Backend => {
+                let bucket = audit_log.bucket.as_deref().unwrap_or_default();
+                let backend = registry
+                    .resolve(bucket)
+                    .map_err(|e| format!("No backend for the audit log: {}", e))?;
+                let (records, pending) = mpsc::unbounded_channel();
+                tokio::spawn(store_batches(backend, audit_log.clone(), pending));
+                Sink::Backend(records)
+            }
+        };
+        Ok(Some(Self {
+            sink,
+            on_failure: audit_log.on_failure,
+            include_reads: audit_log.include_reads,
+            trust_forwarded_for: config.ip_filter.trust_forwarded_for,
+        }))
+    }
+
+    /// Whether requests with `method` are recorded
+    fn audits(&self, method: &Method) -> bool {
+        match *method {
+            Method::PUT | Method::POST | Method::DELETE => true,
+            Method::GET | Method::HEAD => self.include_reads,
+            _ => false,
+        }
+    }
+
+    /// Write `record`, returning once it is stored unless the backend sink
+    /// need not wait for it
+    async fn write(&self, record: &Record) -> Result<(), String> {
+        const STOPPED: &str = "the audit log writer has stopped";
+        let line = record.to_line();
+        match &self.sink {
+            Sink::File(file) => {
+                let result = file.lock().unwrap_or_else(|e| e.into_inner()).write(line.as_bytes());
+                let outcome = if result.is_ok() { "written" } else { "failed" };
+                AUDIT_RECORDS.with_label_values(&[outcome]).inc();
+                result.map_err(|e| e.to_string())
+            }
+            // The batch writer counts records once their batch is stored
+            Sink::Backend(records) if self.on_failure == AuditFailurePolicy::Continue => {
+                records.send(Pending { line, stored: None }).map_err(|_| STOPPED.to_string())
+            }
+            Sink::Backend(records) => {
+                let (stored, outcome) = oneshot::channel();
+                records.send(Pending { line, stored: Some(stored) }).map_err(|_| STOPPED.to_string())?;
+                outcome.await.unwrap_or_else(|_| Err(STOPPED.to_string()))
+            }
+        }
+    }
+}
+
+/// Key of a batch of records stored at `time`, sorting in time order
+fn batch_key(prefix: &str, time: DateTime<Utc>) -> String {
+    format!(
+        "{}/{}/{}-{}.ndjson",
+        prefix.trim_matches('/'),
+        time.format("%Y/%m/%d"),
+        time.format("%Y%m%dT%H%M%S%.3fZ"),
+        Uuid::new_v4().simple()
+    )
+}
+
+/// Store records in batches of up to `batch_size`, each written no later
+/// than `flush_interval_ms` after its first record arrived, until every
+/// sender is gone
+async fn store_batches(
+    backend: Arc<dyn StorageBackend>,
+    config: AuditLogConfig,
+    mut records: mpsc::UnboundedReceiver<Pending>,
+) {
+    let interval = Duration::from_millis(config.flush_interval_ms);
+    while let Some(first) = records.recv().await {
+        let mut batch = vec![first];
+        let deadline = tokio::time::sleep(interval);
+        tokio::pin!(deadline);
+        while batch.len() < config.batch_size {
+            tokio::select! {
+                record = records.recv() => match record {
+                    Some(record) => batch.push(record),
+                    None => break,
+                },
+                _ = &mut deadline => break,
+            }
+        }
+
+        let key = batch_key(&config.prefix, Utc::now());
+        let body: String = batch.iter().map(|pending| pending.line.as_str()).collect();
+        let result = backend.put(&key, Bytes::from(body)).await.map_err(|e| e.to_string());
+        let outcome = match &result {
+            Ok(()) => "written",
+            Err(e) => {
+                warn!(key, records = batch.len(), error = %e, "Failed to store audit records");
+                "failed"
+            }
+        };
+        AUDIT_RECORDS.with_label_values(&[outcome]).inc_by(batch.len() as u64);
+        for pending in batch {
+            if let Some(stored) = pending.stored {
+                let _ = stored.send(result.clone());
+            }
+        }
+    }
+}
+
+/// Middleware writing an audit record for every modifying S3 request, and
+/// reads when configured
+///
+/// Must run inside the request ID middleware and outside authorization, so
+/// rejected attempts are recorded with their principal. Does nothing
+/// without an audit log.
+pub async fn record(State(log): State<Option<Arc<AuditLog>>>, request: Request, next: Next) -> Response {
+    let Some(log) = log else {
+        return next.run(request).await;
+    };
+    if routes::is_system_path(request.uri().path()) || !log.audits(request.method()) {
+        return next.run(request).await;
+    }
+
+    let mut record = Record::from_request(&request, log.trust_forwarded_for);
+    let response = next.run(request).await;
+    record.complete(&response);
+    let Err(e) = log.write(&record).await else {
+        return response;
+    };
+    warn!(operation = record.operation, request_id = record.request_id, error = %e, "Failed to write audit record");
+    match log.on_failure {
+        AuditFailurePolicy::Continue => response,
+        AuditFailurePolicy::Fail => {
+            S3ProxyError::Internal("The request could not be audited".to_string()).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::middleware::from_fn_with_state;
+    use axum::Router;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use crate::storage::{MemoryBackend, MockBackend, MockOperation, MockResponse};
+
+    fn router(log: AuditLog, backend: Arc<dyn StorageBackend>) -> Router {
+        routes::create_router(Arc::new(BucketRegistry::single(backend)))
+            .layer(from_fn_with_state(Some(Arc::new(log)), record))
+            .layer(axum::middleware::from_fn(crate::request_id::assign))
+    }
+
+    async fn send(router: &Router, method: &str, uri: &str, body: &'static str) -> StatusCode {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("x-forwarded-for", "203.0.113.7")
+            .body(Body::from(body))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        response.status()
+    }
+
+    fn records(lines: &str) -> Vec<Value> {
+        lines.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    fn config(configure: impl FnOnce(&mut AuditLogConfig)) -> Config {
+        let mut config = Config::default();
+        config.ip_filter.trust_forwarded_for = true;
+        config.audit_log.enabled = true;
+        configure(&mut config.audit_log);
+        config
+    }
+
+    #[tokio::test]
+    async fn test_file_records_writes_and_deletes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let config = config(|audit| audit.path = path.display().to_string());
+        let backend: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new(&Default::default()));
+        let registry = BucketRegistry::single(backend.clone());
+        let router = router(AuditLog::from_config(&config, &registry).unwrap().unwrap(), backend);
+
+        assert_eq!(send(&router, "PUT", "/bucket/a.txt", "hello").await, StatusCode::OK);
+        assert_eq!(send(&router, "GET", "/bucket/a.txt", "").await, StatusCode::OK);
+        let copy = Request::builder()
+            .method("PUT")
+            .uri("/bucket/b.txt")
+            .header(s3::COPY_SOURCE, "/bucket/a.txt")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(router.clone().oneshot(copy).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(send(&router, "DELETE", "/bucket/a.txt", "").await, StatusCode::NO_CONTENT);
+
+        let records = records(&std::fs::read_to_string(&path).unwrap());
+        // Reads are left out by default
+        assert_eq!(records.len(), 3, "{records:?}");
+        let put = &records[0];
+        assert_eq!(put["version"], SCHEMA_VERSION);
+        assert_eq!(put["principal"], ANONYMOUS);
+        assert_eq!(put["client_ip"], "203.0.113.7");
+        assert_eq!(put["bucket"], "bucket");
+        assert_eq!(put["key"], "a.txt");
+        assert_eq!(put["operation"], "PutObject");
+        assert_eq!(put["size"], 5);
+        assert_eq!(put["status"], 200);
+        assert_eq!(put["result"], "success");
+        assert_eq!(put["request_id"].as_str().unwrap().len(), 16);
+        assert!(put["time"].is_string());
+        assert!(put.get("copy_source").is_none());
+        let copy = &records[1];
+        assert_eq!(copy["operation"], "CopyObject");
+        assert_eq!(copy["key"], "b.txt");
+        assert_eq!(copy["copy_source"], "/bucket/a.txt");
+        assert!(copy["size"].is_null());
+        assert_eq!(copy["result"], "success");
+        assert_eq!(records[2]["operation"], "DeleteObject");
+        assert!(records[2]["size"].is_null());
+        assert!(records[2].get("copy_source").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_file_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let config = AuditLogConfig {
+            path: path.display().to_string(),
+            max_bytes: 10,
+            max_files: 2,
+            ..Default::default()
+        };
+        let mut file = RotatingFile::open(&config).unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write(line.as_bytes()).unwrap();
+        }
+
+        let read = |suffix: &str| std::fs::read_to_string(format!("{}{}", path.display(), suffix)).unwrap();
+        assert_eq!(read(""), "fourth\n");
+        assert_eq!(read(".1"), "third\n");
+        assert_eq!(read(".2"), "second\n");
+        assert!(!dir.path().join("audit.log.3").exists());
+    }
+
+    #[tokio::test]
+    async fn test_backend_batches_include_reads() {
+        let backend: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new(&Default::default()));
+        let registry = BucketRegistry::single(backend.clone());
+        let config = config(|audit| {
+            audit.sink = AuditSink::Backend;
+            audit.batch_size = 2;
+            audit.flush_interval_ms = 50;
+            audit.include_reads = true;
+            audit.on_failure = AuditFailurePolicy::Fail;
+        });
+        let router = router(AuditLog::from_config(&config, &registry).unwrap().unwrap(), backend.clone());
+
+        // Each request waits for its batch, so objects exist once answered
+        assert_eq!(send(&router, "PUT", "/bucket/a.txt", "hello").await, StatusCode::OK);
+        assert_eq!(send(&router, "GET", "/bucket/a.txt", "").await, StatusCode::OK);
+        assert_eq!(send(&router, "GET", "/bucket/missing", "").await, StatusCode::NOT_FOUND);
+
+        let batches = backend.list(".audit/", None, None).await.unwrap();
+        let mut lines = String::new();
+        for batch in &batches {
+            let key = batch.location.to_string();
+            assert!(key.starts_with(".audit/") && key.ends_with(".ndjson"), "{key}");
+            lines.push_str(std::str::from_utf8(&backend.get(&key).await.unwrap()).unwrap());
+        }
+        let mut records = records(&lines);
+        records.sort_by_key(|record| record["time"].as_str().unwrap().to_string());
+        let operations: Vec<_> = records.iter().map(|record| record["operation"].as_str().unwrap()).collect();
+        assert_eq!(operations, ["PutObject", "GetObject", "GetObject"]);
+        assert_eq!(records[1]["size"], 5);
+        assert_eq!(records[2]["result"], "failure");
+        assert_eq!(records[2]["error_code"], "NoSuchKey");
+    }
+
+    #[tokio::test]
+    async fn test_failure_policy() {
+        let mock = Arc::new(MockBackend::new());
+        for _ in 0..2 {
+            let source = io::Error::other("backend unavailable");
+            let error = object_store::Error::Generic { store: "Mock", source: Box::new(source) };
+            mock.push(MockOperation::Put, MockResponse::Error(error));
+        }
+        let registry = BucketRegistry::single(mock);
+        let app_backend: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new(&Default::default()));
+
+        for (policy, expected) in [
+            (AuditFailurePolicy::Continue, StatusCode::OK),
+            (AuditFailurePolicy::Fail, StatusCode::INTERNAL_SERVER_ERROR),
+        ] {
+            let config = config(|audit| {
+                audit.sink = AuditSink::Backend;
+                audit.flush_interval_ms = 1;
+                audit.on_failure = policy;
+            });
+            let router = router(AuditLog::from_config(&config, &registry).unwrap().unwrap(), app_backend.clone());
+            assert_eq!(send(&router, "PUT", "/bucket/a.txt", "hello").await, expected, "{policy:?}");
+        }
+    }
+}
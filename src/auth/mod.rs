@@ -0,0 +1,977 @@
+//! Authentication of incoming S3 requests
+//!
+//! Disabled by default. Two modes are supported:
+//! - `sigv4`: every S3 API request must carry a valid AWS Signature
+//!   Version 4 made with one of the configured credentials, either in the
+//!   Authorization header or in the query string of a presigned URL. Legacy
+//!   Signature Version 2 requests are accepted only when `allow_sigv2` is set.
+//! - `bearer`: every S3 API request must carry one of the configured static
+//!   tokens.
+//!
+//! Health, readiness and metrics endpoints stay open unless a separate
+//! `system_token` is configured for them. Admin endpoints are disabled
+//! unless an `admin_token` is configured, and then require it. With `anonymous_read`, requests
+//! carrying no credentials at all act as a read-only anonymous principal,
+//! optionally confined to `anonymous_prefixes`; they never see bucket
+//! notification configuration.
+//!
+//! The authenticated caller is attached to the request as a [`Principal`]
+//! extension, and the [`authorize`] layer then checks the requested
+//! operation against the principal's [`Policy`].
+
+pub mod policy;
+pub mod sigv2;
+pub mod sigv4;
+pub mod token;
+
+use axum::{
+    body::Body,
+    extract::{OriginalUri, Request, State},
+    http::{header, HeaderName},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+use crate::auth::policy::{Action, Policy};
+use crate::auth::token::TokenSet;
+use crate::config::{AuthConfig, AuthMode, Permission, Secret};
+use crate::errors::S3ProxyError;
+use crate::{metrics, routes};
+
+/// Principal name given to requests without credentials
+pub const ANONYMOUS: &str = "anonymous";
+
+/// Identity of an authenticated client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    /// Access key ID for signed requests, `bearer` for token requests or
+    /// [`ANONYMOUS`]
+    pub name: String,
+    pub policy: Arc<Policy>,
+}
+
+/// Secret and policy of a configured credential
+struct Credential {
+    secret: String,
+    policy: Arc<Policy>,
+}
+
+/// Verifies request signatures against the configured credentials
+pub struct Authenticator {
+    mode: AuthMode,
+    /// Whether deprecated Signature V2 requests are accepted
+    allow_sigv2: bool,
+    /// Configured credentials by access key ID
+    credentials: HashMap<String, Credential>,
+    /// Tokens accepted in bearer mode
+    tokens: TokenSet,
+    /// Header carrying the raw token, if not `Authorization: Bearer`
+    token_header: Option<HeaderName>,
+    /// Tokens accepted for system endpoints; empty when they are open
+    system_tokens: TokenSet,
+    /// Tokens accepted for admin endpoints; empty when they are disabled
+    admin_tokens: TokenSet,
+    /// Policy for requests without credentials, when anonymous reads are on
+    anonymous: Option<Arc<Policy>>,
+    /// Upper bound on bodies buffered to check a signed payload hash
+    max_body_size: usize,
+}
+
+impl Authenticator {
+    /// Create an authenticator from configuration
+    pub fn new(config: &AuthConfig, max_body_size: usize) -> Result<Self, String> {
+        let mut credentials = HashMap::new();
+        for credential in &config.credentials {
+            if credential.access_key_id.is_empty() || credential.secret_access_key.expose().is_empty() {
+                return Err("Auth credentials need both an access key ID and a secret".to_string());
+            }
+            let entry = Credential {
+                secret: credential.secret_access_key.expose().to_string(),
+                policy: Arc::new(
+                    Policy::new(credential.permissions.iter().copied())
+                        .with_allowed_prefixes(&credential.allowed_prefixes),
+                ),
+            };
+            if credentials.insert(credential.access_key_id.clone(), entry).is_some() {
+                return Err(format!(
+                    "Access key ID '{}' is configured more than once",
+                    credential.access_key_id
+                ));
+            }
+        }
+
+        let mut tokens = config.tokens.clone();
+        if let Some(path) = &config.token_file {
+            tokens.extend(TokenSet::read_file(std::path::Path::new(path))?.into_iter().map(Secret::from));
+        }
+        let tokens = TokenSet::new(tokens);
+
+        match config.mode {
+            AuthMode::Sigv4 if credentials.is_empty() => {
+                return Err("SigV4 authentication is enabled but no credentials are configured".to_string())
+            }
+            AuthMode::Bearer if tokens.is_empty() => {
+                return Err("Bearer authentication is enabled but no tokens are configured".to_string())
+            }
+            _ => {}
+        }
+
+        let token_header = config
+            .token_header
+            .as_deref()
+            .map(|name| {
+                HeaderName::try_from(name).map_err(|e| format!("Invalid token header '{}': {}", name, e))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            mode: config.mode,
+            allow_sigv2: config.allow_sigv2,
+            credentials,
+            tokens,
+            token_header,
+            system_tokens: TokenSet::new(config.system_token.as_ref()),
+            admin_tokens: TokenSet::new(config.admin_token.as_ref()),
+            anonymous: config.anonymous_read.then(|| {
+                Arc::new(
+                    Policy::new([Permission::Read])
+                        .with_allowed_prefixes(&config.anonymous_prefixes)
+                        .without_bucket_configuration(),
+                )
+            }),
+            max_body_size,
+        })
+    }
+
+    /// Verify a request according to the configured mode
+    pub async fn verify(&self, mut request: Request) -> Result<Request, S3ProxyError> {
+        if let Some(policy) = &self.anonymous {
+            if self.mode != AuthMode::None && !self.has_credentials(&request) {
+                request.extensions_mut().insert(Principal {
+                    name: ANONYMOUS.to_string(),
+                    policy: policy.clone(),
+                });
+                return Ok(request);
+            }
+        }
+
+        match self.mode {
+            AuthMode::None => Ok(request),
+            AuthMode::Sigv4 => self.verify_signed(request).await,
+            AuthMode::Bearer => {
+                self.check_token(&request, &self.tokens)?;
+                request.extensions_mut().insert(Principal {
+                    name: "bearer".to_string(),
+                    policy: Arc::new(Policy::full_access()),
+                });
+                Ok(request)
+            }
+        }
+    }
+
+    /// Whether the request attempts to authenticate at all
+    ///
+    /// Requests that send invalid credentials are rejected rather than
+    /// being downgraded to anonymous access.
+    fn has_credentials(&self, request: &Request) -> bool {
+        let headers = request.headers();
+        if headers.contains_key(header::AUTHORIZATION)
+            || self.token_header.as_ref().is_some_and(|name| headers.contains_key(name))
+        {
+            return true;
+        }
+        url::form_urlencoded::parse(request.uri().query().unwrap_or("").as_bytes())
+            .any(|(name, _)| matches!(name.as_ref(), "X-Amz-Algorithm" | "X-Amz-Signature" | "AWSAccessKeyId" | "Signature"))
+    }
+
+    /// Verify access to a health, readiness or metrics endpoint
+    pub fn verify_system(&self, request: &Request) -> Result<(), S3ProxyError> {
+        if self.system_tokens.is_empty() {
+            return Ok(());
+        }
+        self.check_token(request, &self.system_tokens)
+    }
+
+    /// Verify access to an admin endpoint
+    ///
+    /// Admin endpoints are disabled unless an admin token is configured.
+    pub fn verify_admin(&self, request: &Request) -> Result<(), S3ProxyError> {
+        if self.admin_tokens.is_empty() {
+            return Err(S3ProxyError::AccessDenied("Admin endpoints are disabled".to_string()));
+        }
+        self.check_token(request, &self.admin_tokens)
+    }
+
+    /// Check the request carries one of `tokens`
+    fn check_token(&self, request: &Request, tokens: &TokenSet) -> Result<(), S3ProxyError> {
+        match token::presented(request.headers(), self.token_header.as_ref()) {
+            Some(presented) if tokens.contains(presented) => Ok(()),
+            _ => Err(S3ProxyError::AccessDenied("Access Denied".to_string())),
+        }
+    }
+
+    /// Verify a signed request, returning it with a [`Principal`] attached
+    ///
+    /// The body is buffered only when its hash was signed, so it can be
+    /// checked against `x-amz-content-sha256`.
+    async fn verify_signed(&self, request: Request) -> Result<Request, S3ProxyError> {
+        let access_key_id = self.check_signature(&request)?;
+
+        let payload_hash = request
+            .headers()
+            .get("x-amz-content-sha256")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or(sigv4::UNSIGNED_PAYLOAD)
+            .to_ascii_lowercase();
+        let mut request = if payload_hash.starts_with("streaming-") {
+            return Err(S3ProxyError::NotImplemented(
+                "Chunked payload signing is not supported; sign with UNSIGNED-PAYLOAD instead".to_string(),
+            ));
+        } else if payload_hash.len() == 64 && payload_hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            self.check_payload(request, &payload_hash).await?
+        } else if payload_hash == sigv4::UNSIGNED_PAYLOAD.to_ascii_lowercase() {
+            request
+        } else {
+            return Err(S3ProxyError::InvalidRequest(
+                "x-amz-content-sha256 must be UNSIGNED-PAYLOAD or a SHA-256 hex digest".to_string(),
+            ));
+        };
+
+        debug!(access_key_id = %access_key_id, "Request authenticated");
+        let policy = self.credential(&access_key_id)?.policy.clone();
+        request.extensions_mut().insert(Principal {
+            name: access_key_id,
+            policy,
+        });
+        Ok(request)
+    }
+
+    /// Verify whichever signature the request carries, returning its access key ID
+    fn check_signature(&self, request: &Request) -> Result<String, S3ProxyError> {
+        // V4 clients sign the URI they sent, before any virtual-host
+        // rewriting; V2 signs the path-style resource the rewrite produces
+        let uri = request
+            .extensions()
+            .get::<OriginalUri>()
+            .map(|original| original.0.clone())
+            .unwrap_or_else(|| request.uri().clone());
+        let (method, headers, now) = (request.method(), request.headers(), Utc::now());
+
+        if let Some(authorization) = headers.get(header::AUTHORIZATION) {
+            let authorization = authorization.to_str().map_err(|_| {
+                S3ProxyError::AuthorizationHeaderMalformed("Authorization header is not valid ASCII".to_string())
+            })?;
+            if sigv2::Authorization::is_v2(authorization) {
+                self.require_sigv2()?;
+                let auth = sigv2::Authorization::parse(authorization)?;
+                let secret = &self.credential(&auth.access_key_id)?.secret;
+                sigv2::verify(&auth, secret, method, request.uri(), headers, now)?;
+                return Ok(auth.access_key_id);
+            }
+            let auth = sigv4::Authorization::parse(authorization)?;
+            let secret = &self.credential(&auth.access_key_id)?.secret;
+            sigv4::verify(&auth, secret, method, &uri, headers, now)?;
+            return Ok(auth.access_key_id);
+        }
+
+        let query = uri.query().unwrap_or("");
+        if let Some(presigned) = sigv4::Presigned::from_query(query)? {
+            let secret = &self.credential(&presigned.auth.access_key_id)?.secret;
+            sigv4::verify_presigned(&presigned, secret, method, &uri, headers, now)?;
+            return Ok(presigned.auth.access_key_id);
+        }
+        if let Some(presigned) = sigv2::Presigned::from_query(query)? {
+            self.require_sigv2()?;
+            let secret = &self.credential(&presigned.auth.access_key_id)?.secret;
+            sigv2::verify_presigned(&presigned, secret, method, request.uri(), headers, now)?;
+            return Ok(presigned.auth.access_key_id);
+        }
+
+        Err(S3ProxyError::AccessDenied("Access Denied".to_string()))
+    }
+
+    /// Reject V2 signatures unless they were explicitly enabled
+    fn require_sigv2(&self) -> Result<(), S3ProxyError> {
+        if self.allow_sigv2 {
+            Ok(())
+        } else {
+            Err(S3ProxyError::InvalidRequest(
+                "The authorization mechanism you have provided is not supported. Please use AWS4-HMAC-SHA256."
+                    .to_string(),
+            ))
+        }
+    }
+
+    /// Configured credential for an access key ID
+    fn credential(&self, access_key_id: &str) -> Result<&Credential, S3ProxyError> {
+        self.credentials
+            .get(access_key_id)
+            .ok_or_else(|| S3ProxyError::InvalidAccessKeyId(access_key_id.to_string()))
+    }
+
+    /// Buffer the body and compare its hash with the signed one
+    async fn check_payload(&self, request: Request, expected: &str) -> Result<Request, S3ProxyError> {
+        let (parts, body) = request.into_parts();
+        let bytes = axum::body::to_bytes(body, self.max_body_size)
+            .await
+            .map_err(|e| S3ProxyError::InvalidRequest(format!("Failed to read body: {}", e)))?;
+        if hex::encode(Sha256::digest(&bytes)) != expected {
+            return Err(S3ProxyError::ContentSha256Mismatch);
+        }
+        Ok(Request::from_parts(parts, Body::from(bytes)))
+    }
+}
+
+/// Middleware rejecting S3 requests that fail authentication
+pub async fn authenticate(
+    State(authenticator): State<Arc<Authenticator>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let result = if routes::is_admin_path(request.uri().path()) {
+        authenticator.verify_admin(&request).map(|()| request)
+    } else if routes::is_system_path(request.uri().path()) {
+        authenticator.verify_system(&request).map(|()| request)
+    } else {
+        authenticator.verify(request).await
+    };
+
+    match result {
+        Ok(request) => next.run(request).await,
+        Err(e) => {
+            warn!(error = %e, "Request authentication failed");
+            metrics::AUTH_FAILURES
+                .with_label_values(&[authenticator.mode.as_str()])
+                .inc();
+            e.into_response()
+        }
+    }
+}
+
+/// Middleware rejecting requests the authenticated principal may not make
+///
+/// Requests without a [`Principal`] (authentication disabled) are allowed.
+pub async fn authorize(request: Request, next: Next) -> Response {
+    let path = request.uri().path();
+    if routes::is_system_path(path) {
+        return next.run(request).await;
+    }
+
+    let Some(principal) = request.extensions().get::<Principal>().cloned() else {
+        return next.run(request).await;
+    };
+    let action = Action::from_request(request.method(), path, request.uri().query());
+    let source = Action::copy_source(request.method(), request.headers());
+    let decision = principal
+        .policy
+        .authorize(&action)
+        .and_then(|()| source.map_or(Ok(()), |source| principal.policy.authorize(&source)));
+    let mut response = match decision {
+        Ok(()) => next.run(request).await,
+        Err(e) => {
+            warn!(principal = %principal.name, operation = action.operation, "Request not authorized");
+            e.into_response()
+        }
+    };
+    // Identifies the requester in the access log
+    response.extensions_mut().insert(principal);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CredentialConfig;
+    use crate::storage::{BucketRegistry, LocalBackend};
+    use axum::http::{HeaderMap, Method, StatusCode, Uri};
+    use axum::middleware::from_fn_with_state;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    const ACCESS_KEY_ID: &str = "AKIDEXAMPLE";
+    const SECRET: &str = "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY";
+    const READER_KEY_ID: &str = "AKIDREADER";
+    const TENANT_KEY_ID: &str = "AKIDTENANTA";
+
+    fn router(root: &std::path::Path) -> Router {
+        let config = AuthConfig {
+            mode: AuthMode::Sigv4,
+            credentials: vec![CredentialConfig {
+                access_key_id: ACCESS_KEY_ID.to_string(),
+                secret_access_key: SECRET.into(),
+                permissions: vec![Permission::Read, Permission::Write, Permission::Delete],
+                allowed_prefixes: vec![],
+            }, CredentialConfig {
+                access_key_id: READER_KEY_ID.to_string(),
+                secret_access_key: SECRET.into(),
+                permissions: vec![Permission::Read],
+                allowed_prefixes: vec![],
+            }, CredentialConfig {
+                access_key_id: TENANT_KEY_ID.to_string(),
+                secret_access_key: SECRET.into(),
+                permissions: vec![Permission::Read, Permission::Write, Permission::Delete],
+                allowed_prefixes: vec!["team-a/".to_string()],
+            }],
+            allow_sigv2: true,
+            ..Default::default()
+        };
+        let authenticator = Arc::new(Authenticator::new(&config, 1024 * 1024).unwrap());
+        let backend = Arc::new(LocalBackend::new(root).unwrap());
+        routes::create_router(Arc::new(BucketRegistry::single(backend)))
+            .layer(axum::middleware::from_fn(authorize))
+            .layer(from_fn_with_state(authenticator, authenticate))
+    }
+
+    /// Build a request signed with `secret`, optionally sending a different body
+    fn signed(
+        method: Method,
+        uri: &str,
+        access_key_id: &str,
+        secret: &str,
+        body: &'static str,
+        sent_body: Option<&'static str>,
+    ) -> Request {
+        let uri: Uri = uri.parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "localhost:8080".parse().unwrap());
+        headers.insert("x-amz-date", Utc::now().format("%Y%m%dT%H%M%SZ").to_string().parse().unwrap());
+        headers.insert("x-amz-content-sha256", hex::encode(Sha256::digest(body)).parse().unwrap());
+        let authorization = sigv4::sign(
+            access_key_id,
+            secret,
+            "us-east-1",
+            &method,
+            &uri,
+            &headers,
+            "host;x-amz-content-sha256;x-amz-date",
+        );
+
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::AUTHORIZATION, authorization)
+            .body(Body::from(sent_body.unwrap_or(body)))
+            .unwrap();
+        request.headers_mut().extend(headers);
+        request
+    }
+
+    async fn error_code(router: &Router, request: Request) -> (StatusCode, String) {
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let code = body
+            .split_once("<Code>")
+            .and_then(|(_, rest)| rest.split_once("</Code>"))
+            .map(|(code, _)| code.to_string())
+            .unwrap_or_default();
+        (status, code)
+    }
+
+    #[tokio::test]
+    async fn test_signed_requests_are_accepted() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = router(dir.path());
+
+        let put = signed(Method::PUT, "/bucket/a.txt", ACCESS_KEY_ID, SECRET, "hello", None);
+        assert_eq!(router.clone().oneshot(put).await.unwrap().status(), StatusCode::OK);
+
+        let get = signed(Method::GET, "/bucket/a.txt", ACCESS_KEY_ID, SECRET, "", None);
+        assert_eq!(router.clone().oneshot(get).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_credential_permissions() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = router(dir.path());
+
+        let put = signed(Method::PUT, "/bucket/a.txt", ACCESS_KEY_ID, SECRET, "hello", None);
+        assert_eq!(router.clone().oneshot(put).await.unwrap().status(), StatusCode::OK);
+
+        let get = signed(Method::GET, "/bucket/a.txt", READER_KEY_ID, SECRET, "", None);
+        assert_eq!(router.clone().oneshot(get).await.unwrap().status(), StatusCode::OK);
+        let list = signed(Method::GET, "/bucket?prefix=a", READER_KEY_ID, SECRET, "", None);
+        assert_eq!(router.clone().oneshot(list).await.unwrap().status(), StatusCode::OK);
+
+        let put = signed(Method::PUT, "/bucket/a.txt", READER_KEY_ID, SECRET, "overwrite", None);
+        let response = router.clone().oneshot(put).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("PutObject requires write permission"));
+
+        let delete = signed(Method::DELETE, "/bucket/a.txt", READER_KEY_ID, SECRET, "", None);
+        assert_eq!(
+            error_code(&router, delete).await,
+            (StatusCode::FORBIDDEN, "AccessDenied".to_string())
+        );
+        assert_eq!(std::fs::read_to_string(dir.path().join("a.txt")).unwrap(), "hello");
+    }
+
+    async fn body(router: &Router, request: Request) -> (StatusCode, String) {
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_tenant_prefix_isolation() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = router(dir.path());
+        for key in ["team-a/one.txt", "team-a/sub/two.txt", "team-b/secret.txt", "team-ab/x.txt"] {
+            let put = signed(Method::PUT, &format!("/bucket/{}", key), ACCESS_KEY_ID, SECRET, "data", None);
+            assert_eq!(router.clone().oneshot(put).await.unwrap().status(), StatusCode::OK);
+        }
+
+        let own = signed(Method::GET, "/bucket/team-a/one.txt", TENANT_KEY_ID, SECRET, "", None);
+        assert_eq!(body(&router, own).await.0, StatusCode::OK);
+
+        for (method, path) in [
+            (Method::GET, "/bucket/team-b/secret.txt"),
+            (Method::HEAD, "/bucket/team-b/secret.txt"),
+            (Method::PUT, "/bucket/team-b/secret.txt"),
+            (Method::DELETE, "/bucket/team-b/secret.txt"),
+            (Method::GET, "/bucket/team-ab/x.txt"),
+            (Method::GET, "/bucket/team-a%2F..%2Fteam-b/secret.txt"),
+        ] {
+            let request = signed(method.clone(), path, TENANT_KEY_ID, SECRET, "", None);
+            let (status, _) = body(&router, request).await;
+            assert!(status == StatusCode::FORBIDDEN || status == StatusCode::BAD_REQUEST, "{method} {path}: {status}");
+        }
+        assert_eq!(std::fs::read_to_string(dir.path().join("team-b/secret.txt")).unwrap(), "data");
+
+        for query in ["", "?prefix=team", "?prefix=team-b", "?prefix=team-a", "?delimiter=%2F", "?prefix=team-b%2F"] {
+            let list = signed(Method::GET, &format!("/bucket{}", query), TENANT_KEY_ID, SECRET, "", None);
+            let (status, xml) = body(&router, list).await;
+            assert_eq!(status, StatusCode::OK, "{query}");
+            assert!(!xml.contains("team-b") || xml.contains("<Prefix>team-b"), "{query}: {xml}");
+            assert!(!xml.contains("secret.txt") && !xml.contains("team-ab"), "{query}: {xml}");
+        }
+
+        let list = signed(Method::GET, "/bucket?prefix=team", TENANT_KEY_ID, SECRET, "", None);
+        let (_, xml) = body(&router, list).await;
+        assert!(xml.contains("<Key>team-a/one.txt</Key>"));
+        assert!(xml.contains("<Key>team-a/sub/two.txt</Key>"));
+        let list = signed(Method::GET, "/bucket?delimiter=%2F", TENANT_KEY_ID, SECRET, "", None);
+        let (_, xml) = body(&router, list).await;
+        assert!(xml.contains("<Prefix>team-a/</Prefix>"), "{xml}");
+    }
+
+    #[tokio::test]
+    async fn test_copy_source_authorized() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = router(dir.path());
+        for key in ["team-a/one.txt", "team-b/secret.txt"] {
+            let put = signed(Method::PUT, &format!("/bucket/{}", key), ACCESS_KEY_ID, SECRET, "data", None);
+            assert_eq!(router.clone().oneshot(put).await.unwrap().status(), StatusCode::OK);
+        }
+        let copy = |source: &str, key: &str| {
+            let mut request = signed(Method::PUT, &format!("/bucket/{}", key), TENANT_KEY_ID, SECRET, "", None);
+            request.headers_mut().insert(crate::s3::COPY_SOURCE, source.parse().unwrap());
+            request
+        };
+
+        assert_eq!(body(&router, copy("/bucket/team-a/one.txt", "team-a/two.txt")).await.0, StatusCode::OK);
+        // A destination within the tenant's prefix does not open up the source
+        for source in ["/bucket/team-b/secret.txt", "bucket/team-a%2F..%2Fteam-b/secret.txt"] {
+            let (status, _) = body(&router, copy(source, "team-a/stolen.txt")).await;
+            assert!(status == StatusCode::FORBIDDEN || status == StatusCode::BAD_REQUEST, "{source}: {status}");
+        }
+        assert!(!dir.path().join("team-a/stolen.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_bucket_notification_authorized() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = router(dir.path());
+        let request = |method: Method, key_id: &str| {
+            let payload = if method == Method::PUT { "<NotificationConfiguration/>" } else { "" };
+            signed(method, "/bucket?notification", key_id, SECRET, payload, None)
+        };
+
+        for method in [Method::GET, Method::PUT] {
+            let (status, _) = body(&router, request(method.clone(), ACCESS_KEY_ID)).await;
+            assert_eq!(status, StatusCode::OK, "{method}");
+            // A key confined to a prefix could reroute every key's events
+            assert_eq!(
+                error_code(&router, request(method.clone(), TENANT_KEY_ID)).await,
+                (StatusCode::FORBIDDEN, "AccessDenied".to_string()),
+                "{method}"
+            );
+        }
+
+        // Anonymous readers covering every key still cannot read webhook URLs
+        let config = AuthConfig {
+            mode: AuthMode::Sigv4,
+            credentials: vec![CredentialConfig {
+                access_key_id: ACCESS_KEY_ID.to_string(),
+                secret_access_key: SECRET.into(),
+                permissions: vec![Permission::Read],
+                allowed_prefixes: vec![],
+            }],
+            anonymous_read: true,
+            ..Default::default()
+        };
+        let authenticator = Arc::new(Authenticator::new(&config, 1024 * 1024).unwrap());
+        let backend = Arc::new(LocalBackend::new(dir.path()).unwrap());
+        let router = routes::create_router(Arc::new(BucketRegistry::single(backend)))
+            .layer(axum::middleware::from_fn(authorize))
+            .layer(from_fn_with_state(authenticator, authenticate));
+        for method in [Method::GET, Method::PUT] {
+            let anonymous = Request::builder()
+                .method(method.clone())
+                .uri("/bucket?notification")
+                .body(Body::from("<NotificationConfiguration/>"))
+                .unwrap();
+            assert_eq!(
+                error_code(&router, anonymous).await,
+                (StatusCode::FORBIDDEN, "AccessDenied".to_string()),
+                "{method}"
+            );
+        }
+        let list = Request::builder().uri("/bucket").body(Body::empty()).unwrap();
+        assert_eq!(body(&router, list).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_anonymous_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = AuthConfig {
+            mode: AuthMode::Sigv4,
+            credentials: vec![CredentialConfig {
+                access_key_id: ACCESS_KEY_ID.to_string(),
+                secret_access_key: SECRET.into(),
+                permissions: vec![Permission::Read, Permission::Write, Permission::Delete],
+                allowed_prefixes: vec![],
+            }],
+            anonymous_read: true,
+            anonymous_prefixes: vec!["public/".to_string()],
+            ..Default::default()
+        };
+        let authenticator = Arc::new(Authenticator::new(&config, 1024 * 1024).unwrap());
+        let backend = Arc::new(LocalBackend::new(dir.path()).unwrap());
+        let router = routes::create_router(Arc::new(BucketRegistry::single(backend)))
+            .layer(axum::middleware::from_fn(authorize))
+            .layer(from_fn_with_state(authenticator, authenticate));
+
+        for key in ["public/data.csv", "private/plan.txt"] {
+            let put = signed(Method::PUT, &format!("/bucket/{}", key), ACCESS_KEY_ID, SECRET, "data", None);
+            assert_eq!(router.clone().oneshot(put).await.unwrap().status(), StatusCode::OK);
+        }
+        let anonymous = |method: Method, uri: &str| {
+            Request::builder().method(method).uri(uri).body(Body::from("x")).unwrap()
+        };
+
+        let (status, _) = body(&router, anonymous(Method::GET, "/bucket/public/data.csv")).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, xml) = body(&router, anonymous(Method::GET, "/bucket?delimiter=%2F")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(xml.contains("<Prefix>public/</Prefix>") && !xml.contains("private"), "{xml}");
+
+        // Missing and write-protected keys are indistinguishable
+        let (status, private) = body(&router, anonymous(Method::GET, "/bucket/private/plan.txt")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (_, missing) = body(&router, anonymous(Method::GET, "/bucket/private/nothing.txt")).await;
+        assert_eq!(private, missing);
+
+        for method in [Method::PUT, Method::DELETE, Method::POST] {
+            let (status, _) = body(&router, anonymous(method.clone(), "/bucket/public/data.csv")).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{method}");
+        }
+        assert_eq!(std::fs::read_to_string(dir.path().join("public/data.csv")).unwrap(), "data");
+
+        // Bad credentials are rejected, not treated as anonymous
+        let bad = signed(Method::GET, "/bucket/public/data.csv", ACCESS_KEY_ID, "wrong", "", None);
+        assert_eq!(
+            error_code(&router, bad).await,
+            (StatusCode::FORBIDDEN, "SignatureDoesNotMatch".to_string())
+        );
+        // Signed requests keep their own, wider access
+        let signed_get = signed(Method::GET, "/bucket/private/plan.txt", ACCESS_KEY_ID, SECRET, "", None);
+        assert_eq!(body(&router, signed_get).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = router(dir.path());
+
+        let unsigned = Request::builder().uri("/bucket/a.txt").body(Body::empty()).unwrap();
+        assert_eq!(
+            error_code(&router, unsigned).await,
+            (StatusCode::FORBIDDEN, "AccessDenied".to_string())
+        );
+
+        let wrong_secret = signed(Method::GET, "/bucket/a.txt", ACCESS_KEY_ID, "other", "", None);
+        assert_eq!(
+            error_code(&router, wrong_secret).await,
+            (StatusCode::FORBIDDEN, "SignatureDoesNotMatch".to_string())
+        );
+
+        let unknown_key = signed(Method::GET, "/bucket/a.txt", "AKIDUNKNOWN", SECRET, "", None);
+        assert_eq!(
+            error_code(&router, unknown_key).await,
+            (StatusCode::FORBIDDEN, "InvalidAccessKeyId".to_string())
+        );
+
+        let tampered = signed(Method::PUT, "/bucket/a.txt", ACCESS_KEY_ID, SECRET, "hello", Some("evil"));
+        assert_eq!(
+            error_code(&router, tampered).await,
+            (StatusCode::BAD_REQUEST, "XAmzContentSHA256Mismatch".to_string())
+        );
+        assert!(!dir.path().join("a.txt").exists());
+    }
+
+    /// Presign a URL with the AWS SDK signer, as S3 clients do
+    fn presigned(method: &str, path: &str, expires_in: std::time::Duration, signed_at: std::time::SystemTime) -> Request {
+        use aws_sigv4::http_request::{
+            sign, PercentEncodingMode, SignableBody, SignableRequest, SignatureLocation, SigningSettings,
+            UriPathNormalizationMode,
+        };
+        use aws_sigv4::sign::v4;
+
+        let identity = aws_credential_types::Credentials::new(ACCESS_KEY_ID, SECRET, None, None, "test").into();
+        let mut settings = SigningSettings::default();
+        settings.signature_location = SignatureLocation::QueryParams;
+        settings.expires_in = Some(expires_in);
+        settings.percent_encoding_mode = PercentEncodingMode::Single;
+        settings.uri_path_normalization_mode = UriPathNormalizationMode::Disabled;
+        let params = v4::SigningParams::builder()
+            .identity(&identity)
+            .region("us-east-1")
+            .name("s3")
+            .time(signed_at)
+            .settings(settings)
+            .build()
+            .unwrap()
+            .into();
+
+        let url = format!("http://localhost:8080{}", path);
+        let signable = SignableRequest::new(
+            method,
+            &url,
+            std::iter::once(("host", "localhost:8080")),
+            SignableBody::UnsignedPayload,
+        )
+        .unwrap();
+        let (instructions, _) = sign(signable, &params).unwrap().into_parts();
+
+        let mut request = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(header::HOST, "localhost:8080")
+            .body(Body::from("presigned"))
+            .unwrap();
+        instructions.apply_to_request_http1x(&mut request);
+        request
+    }
+
+    #[tokio::test]
+    async fn test_presigned_urls_from_aws_sdk() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = router(dir.path());
+        let now = std::time::SystemTime::now();
+        let minute = std::time::Duration::from_secs(60);
+
+        let put = presigned("PUT", "/bucket/dir/a%20b.txt", 5 * minute, now);
+        assert_eq!(router.clone().oneshot(put).await.unwrap().status(), StatusCode::OK);
+        let get = presigned("GET", "/bucket/dir/a%20b.txt", 5 * minute, now);
+        assert_eq!(router.clone().oneshot(get).await.unwrap().status(), StatusCode::OK);
+
+        // A URL signed for one method cannot be replayed with another
+        let mut delete = presigned("GET", "/bucket/dir/a%20b.txt", 5 * minute, now);
+        *delete.method_mut() = Method::DELETE;
+        assert_eq!(
+            error_code(&router, delete).await,
+            (StatusCode::FORBIDDEN, "SignatureDoesNotMatch".to_string())
+        );
+
+        let expired = presigned("GET", "/bucket/dir/a%20b.txt", minute, now - 2 * minute);
+        let response = router.clone().oneshot(expired).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("Request has expired"));
+    }
+
+    #[tokio::test]
+    async fn test_sigv2_requests() {
+        use base64::Engine as _;
+        use hmac::Mac;
+
+        let dir = tempfile::tempdir().unwrap();
+        let router = router(dir.path());
+        let date = Utc::now().to_rfc2822();
+        let sign = |method: Method, path: &str| {
+            let headers = [(header::DATE, date.parse().unwrap())].into_iter().collect();
+            let string_to_sign = sigv2::string_to_sign(&method, &path.parse().unwrap(), &headers, &date);
+            let mut mac = hmac::Hmac::<sha1::Sha1>::new_from_slice(SECRET.as_bytes()).unwrap();
+            mac.update(string_to_sign.as_bytes());
+            let signature = base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes());
+            Request::builder()
+                .method(method)
+                .uri(path)
+                .header(header::DATE, &date)
+                .header(header::AUTHORIZATION, format!("AWS {}:{}", ACCESS_KEY_ID, signature))
+                .body(Body::from("v2"))
+                .unwrap()
+        };
+
+        let put = sign(Method::PUT, "/bucket/legacy.txt");
+        assert_eq!(router.clone().oneshot(put).await.unwrap().status(), StatusCode::OK);
+
+        let mut tampered = sign(Method::GET, "/bucket/legacy.txt");
+        *tampered.uri_mut() = "/bucket/other.txt".parse().unwrap();
+        assert_eq!(
+            error_code(&router, tampered).await,
+            (StatusCode::FORBIDDEN, "SignatureDoesNotMatch".to_string())
+        );
+
+        // Rejected unless explicitly enabled
+        let config = AuthConfig {
+            mode: AuthMode::Sigv4,
+            credentials: vec![CredentialConfig {
+                access_key_id: ACCESS_KEY_ID.to_string(),
+                secret_access_key: SECRET.into(),
+                permissions: vec![Permission::Read, Permission::Write, Permission::Delete],
+                allowed_prefixes: vec![],
+            }],
+            ..Default::default()
+        };
+        let authenticator = Arc::new(Authenticator::new(&config, 1024).unwrap());
+        let strict = Router::new()
+            .route("/bucket/*key", axum::routing::get(|| async { "ok" }))
+            .layer(from_fn_with_state(authenticator, authenticate));
+        assert_eq!(
+            error_code(&strict, sign(Method::GET, "/bucket/legacy.txt")).await,
+            (StatusCode::BAD_REQUEST, "InvalidRequest".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_system_paths_skip_authentication() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = router(dir.path());
+
+        let request = Request::builder().uri("/healthz").body(Body::empty()).unwrap();
+        assert_eq!(router.oneshot(request).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_enabled_without_credentials_is_rejected() {
+        let config = AuthConfig {
+            mode: AuthMode::Sigv4,
+            ..Default::default()
+        };
+        assert!(Authenticator::new(&config, 1024).is_err());
+        let config = AuthConfig {
+            mode: AuthMode::Bearer,
+            tokens: vec![Secret::from("")],
+            ..Default::default()
+        };
+        assert!(Authenticator::new(&config, 1024).is_err());
+        assert!(Authenticator::new(&AuthConfig::default(), 1024).is_ok());
+    }
+
+    fn bearer_router(config: AuthConfig) -> Router {
+        let authenticator = Arc::new(Authenticator::new(&config, 1024).unwrap());
+        Router::new()
+            .route("/healthz", axum::routing::get(|| async { "ok" }))
+            .route("/bucket/*key", axum::routing::get(|| async { "ok" }))
+            .layer(from_fn_with_state(authenticator, authenticate))
+    }
+
+    fn with_header(uri: &str, name: &str, value: &str) -> Request {
+        Request::builder().uri(uri).header(name, value).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_bearer_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let token_file = dir.path().join("tokens");
+        std::fs::write(&token_file, "from-file\n").unwrap();
+        let router = bearer_router(AuthConfig {
+            mode: AuthMode::Bearer,
+            tokens: vec!["inline".into()],
+            token_file: Some(token_file.to_string_lossy().into_owned()),
+            ..Default::default()
+        });
+
+        for token in ["inline", "from-file"] {
+            let request = with_header("/bucket/a", "authorization", &format!("Bearer {}", token));
+            assert_eq!(router.clone().oneshot(request).await.unwrap().status(), StatusCode::OK);
+        }
+
+        let failures = metrics::AUTH_FAILURES.with_label_values(&["bearer"]).get();
+        let wrong = with_header("/bucket/a", "authorization", "Bearer other");
+        assert_eq!(
+            error_code(&router, wrong).await,
+            (StatusCode::FORBIDDEN, "AccessDenied".to_string())
+        );
+        let missing = Request::builder().uri("/bucket/a").body(Body::empty()).unwrap();
+        assert_eq!(router.clone().oneshot(missing).await.unwrap().status(), StatusCode::FORBIDDEN);
+        assert!(metrics::AUTH_FAILURES.with_label_values(&["bearer"]).get() >= failures + 2);
+
+        let health = Request::builder().uri("/healthz").body(Body::empty()).unwrap();
+        assert_eq!(router.oneshot(health).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_custom_token_header_and_system_token() {
+        let router = bearer_router(AuthConfig {
+            mode: AuthMode::Bearer,
+            tokens: vec!["api".into()],
+            token_header: Some("X-Api-Key".to_string()),
+            system_token: Some("ops".into()),
+            ..Default::default()
+        });
+
+        let request = with_header("/bucket/a", "x-api-key", "api");
+        assert_eq!(router.clone().oneshot(request).await.unwrap().status(), StatusCode::OK);
+        let request = with_header("/bucket/a", "authorization", "Bearer api");
+        assert_eq!(router.clone().oneshot(request).await.unwrap().status(), StatusCode::FORBIDDEN);
+
+        let health = Request::builder().uri("/healthz").body(Body::empty()).unwrap();
+        assert_eq!(router.clone().oneshot(health).await.unwrap().status(), StatusCode::FORBIDDEN);
+        let health = with_header("/healthz", "x-api-key", "ops");
+        assert_eq!(router.clone().oneshot(health).await.unwrap().status(), StatusCode::OK);
+        // Data-plane tokens do not open system endpoints
+        let health = with_header("/healthz", "x-api-key", "api");
+        assert_eq!(router.oneshot(health).await.unwrap().status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_admin_token() {
+        let config = AuthConfig {
+            mode: AuthMode::Bearer,
+            tokens: vec!["api".into()],
+            system_token: Some("ops".into()),
+            ..Default::default()
+        };
+        let admin = |path: &str, token: &str| with_header(path, "authorization", &format!("Bearer {}", token));
+        let disabled = bearer_router(config.clone());
+        let router = bearer_router(AuthConfig {
+            admin_token: Some("root".into()),
+            ..config
+        });
+
+        for path in ["/admin/loglevel", "/admin/config"] {
+            // Disabled without an admin token, whatever the caller presents
+            assert_eq!(disabled.clone().oneshot(admin(path, "ops")).await.unwrap().status(), StatusCode::FORBIDDEN);
+
+            for token in ["api", "ops"] {
+                assert_eq!(router.clone().oneshot(admin(path, token)).await.unwrap().status(), StatusCode::FORBIDDEN);
+            }
+            // Past authentication; the test router has no admin routes
+            assert_eq!(router.clone().oneshot(admin(path, "root")).await.unwrap().status(), StatusCode::NOT_FOUND);
+        }
+        // Other keys in a bucket named admin are ordinary S3 requests
+        let object = with_header("/admin/other", "authorization", "Bearer root");
+        assert_eq!(router.oneshot(object).await.unwrap().status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,303 @@
+//! Authorization of authenticated requests
+//!
+//! Every S3 request is classified into an [`Action`] naming the operation,
+//! the permission it needs and the object key it touches, and checked
+//! against the [`Policy`] of the calling [`Principal`](super::Principal).
+//! A CopyObject request is checked twice: as a write of its destination
+//! and as a read of its source. Bucket notification configuration names
+//! webhook URLs and tokens for the whole bucket, so only policies covering
+//! every key may read or change it, and never anonymous ones.
+//! [`Policy::authorize`] is the single decision point for access checks;
+//! listings are additionally narrowed with [`Policy::list_prefixes`].
+
+use http::{HeaderMap, Method};
+use percent_encoding::percent_decode_str;
+use std::collections::BTreeSet;
+
+use crate::config::Permission;
+use crate::errors::S3ProxyError;
+use crate::routes;
+use crate::s3;
+
+/// Operations on the notification configuration of a bucket
+const BUCKET_CONFIGURATION: [&str; 2] = [
+    "GetBucketNotificationConfiguration",
+    "PutBucketNotificationConfiguration",
+];
+
+/// An S3 operation and the permission it requires
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Action {
+    /// S3 operation name, e.g. `GetObject`
+    pub operation: &'static str,
+    pub permission: Permission,
+    /// Decoded object key, for object operations
+    pub key: Option<String>,
+}
+
+impl Action {
+    /// Classify a path-style S3 request
+    ///
+    /// Reads need `read`, deletions (including `DeleteObjects`) need
+    /// `delete` and everything else needs `write`.
+    pub fn from_request(method: &Method, path: &str, query: Option<&str>) -> Self {
+        let operation = routes::operation_name(method, path, query);
+        let key = path
+            .trim_start_matches('/')
+            .split_once('/')
+            .map(|(_, key)| key)
+            .filter(|key| !key.is_empty());
+
+        let permission = match (operation, method.as_str()) {
+            ("DeleteObjects", _) | (_, "DELETE") => Permission::Delete,
+            (_, "GET" | "HEAD") => Permission::Read,
+            _ => Permission::Write,
+        };
+
+        Self {
+            operation,
+            permission,
+            key: key.map(|k| percent_decode_str(k).decode_utf8_lossy().into_owned()),
+        }
+    }
+
+    /// The read of its source a CopyObject request makes besides writing
+    /// its destination, if the request is one
+    ///
+    /// A source that does not parse names no key; the request is rejected
+    /// before anything is read.
+    pub fn copy_source(method: &Method, headers: &HeaderMap) -> Option<Self> {
+        if method != Method::PUT {
+            return None;
+        }
+        let source = headers.get(s3::COPY_SOURCE)?;
+        let key = source
+            .to_str()
+            .ok()
+            .and_then(|source| s3::parse_copy_source(source).ok())
+            .map(|(_, key)| key);
+        Some(Self {
+            operation: "CopyObject",
+            permission: Permission::Read,
+            key,
+        })
+    }
+}
+
+/// What a principal is allowed to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Policy {
+    permissions: BTreeSet<Permission>,
+    /// Key prefixes object operations are confined to; `None` for all keys
+    allowed_prefixes: Option<Vec<String>>,
+    /// Whether bucket configuration may be read or changed, given access
+    /// to every key
+    bucket_configuration: bool,
+}
+
+impl Policy {
+    /// Policy granting the given permissions on every key
+    pub fn new(permissions: impl IntoIterator<Item = Permission>) -> Self {
+        Self {
+            permissions: permissions.into_iter().collect(),
+            allowed_prefixes: None,
+            bucket_configuration: true,
+        }
+    }
+
+    /// Policy granting every permission
+    pub fn full_access() -> Self {
+        Self::new([Permission::Read, Permission::Write, Permission::Delete])
+    }
+
+    /// Confine object operations to keys under `prefixes`
+    ///
+    /// Each prefix names a directory: `team-a` and `team-a/` both allow
+    /// `team-a/...` but not `team-ab/...`. An empty list leaves the policy
+    /// unrestricted.
+    pub fn with_allowed_prefixes(mut self, prefixes: &[String]) -> Self {
+        if prefixes.is_empty() {
+            return self;
+        }
+        let prefixes = prefixes
+            .iter()
+            .map(|p| match p.trim_matches('/') {
+                "" => String::new(),
+                p => format!("{}/", p),
+            })
+            .collect();
+        self.allowed_prefixes = Some(prefixes);
+        self
+    }
+
+    /// Deny bucket configuration operations whatever the allowed prefixes
+    pub fn without_bucket_configuration(mut self) -> Self {
+        self.bucket_configuration = false;
+        self
+    }
+
+    /// Whether object operations on `key` are within the allowed prefixes
+    pub fn allows_key(&self, key: &str) -> bool {
+        match &self.allowed_prefixes {
+            None => true,
+            Some(prefixes) => prefixes.iter().any(|p| key.starts_with(p.as_str())),
+        }
+    }
+
+    /// Prefixes to list for a requested list prefix
+    ///
+    /// Each result is the intersection of `prefix` with one allowed prefix:
+    /// keys matching any of them are exactly the visible keys matching
+    /// `prefix`. Empty when nothing visible can match.
+    pub fn list_prefixes(&self, prefix: &str) -> Vec<String> {
+        let Some(allowed) = &self.allowed_prefixes else {
+            return vec![prefix.to_string()];
+        };
+        let mut prefixes: Vec<String> = allowed
+            .iter()
+            .filter_map(|a| {
+                if prefix.starts_with(a.as_str()) {
+                    Some(prefix.to_string())
+                } else if a.starts_with(prefix) {
+                    Some(a.clone())
+                } else {
+                    None
+                }
+            })
+            .collect();
+        prefixes.sort();
+        prefixes.dedup();
+        prefixes
+    }
+
+    /// Decide whether `action` is allowed
+    pub fn authorize(&self, action: &Action) -> Result<(), S3ProxyError> {
+        if !self.permissions.contains(&action.permission) {
+            return Err(S3ProxyError::AccessDenied(format!(
+                "Access Denied: {} requires {} permission",
+                action.operation,
+                action.permission.as_str()
+            )));
+        }
+
+        // Confining keys would mean nothing if the caller could redirect
+        // every key's events to itself
+        if BUCKET_CONFIGURATION.contains(&action.operation)
+            && (!self.bucket_configuration || self.allowed_prefixes.is_some())
+        {
+            return Err(S3ProxyError::AccessDenied(format!(
+                "Access Denied: {} requires access to every key",
+                action.operation
+            )));
+        }
+
+        if let Some(key) = &action.key {
+            // Check the key the handler will actually use, so traversal or
+            // encoding tricks cannot step outside an allowed prefix
+            s3::validate_key(key)?;
+            if !self.allows_key(key) {
+                return Err(S3ProxyError::AccessDenied(format!(
+                    "Access Denied: {} is not permitted on this key",
+                    action.operation
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_action_classification() {
+        let cases = [
+            (Method::GET, "/", None, "ListBuckets", Permission::Read),
+            (Method::GET, "/bucket", Some("list-type=2"), "ListObjectsV2", Permission::Read),
+            (Method::PUT, "/bucket", None, "CreateBucket", Permission::Write),
+            (Method::DELETE, "/bucket", None, "DeleteBucket", Permission::Delete),
+            (Method::POST, "/bucket", Some("delete"), "DeleteObjects", Permission::Delete),
+            (Method::GET, "/bucket/a/b", None, "GetObject", Permission::Read),
+            (Method::HEAD, "/bucket/a", None, "HeadObject", Permission::Read),
+            (Method::PUT, "/bucket/a", None, "PutObject", Permission::Write),
+            (Method::POST, "/bucket/a", Some("uploads"), "CreateMultipartUpload", Permission::Write),
+            (Method::POST, "/bucket/a", None, "Unknown", Permission::Write),
+            (Method::DELETE, "/bucket/a", None, "DeleteObject", Permission::Delete),
+        ];
+        for (method, path, query, operation, permission) in cases {
+            let action = Action::from_request(&method, path, query);
+            assert_eq!(action.operation, operation, "{method} {path}");
+            assert_eq!(action.permission, permission, "{method} {path}");
+        }
+    }
+
+    #[test]
+    fn test_policy_decision() {
+        let read_only = Policy::new([Permission::Read]);
+        read_only
+            .authorize(&Action::from_request(&Method::GET, "/bucket/a", None))
+            .unwrap();
+
+        let denied = read_only
+            .authorize(&Action::from_request(&Method::PUT, "/bucket/a", None))
+            .unwrap_err();
+        assert!(denied.to_string().contains("PutObject requires write permission"));
+
+        let full = Policy::full_access();
+        full.authorize(&Action::from_request(&Method::DELETE, "/bucket/a", None))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_allowed_prefixes() {
+        let policy = Policy::full_access().with_allowed_prefixes(&["team-a".to_string()]);
+        assert!(policy.allows_key("team-a/x"));
+        assert!(!policy.allows_key("team-ab/x"));
+        assert!(!policy.allows_key("team-a"));
+
+        policy
+            .authorize(&Action::from_request(&Method::PUT, "/bucket/team-a/x", None))
+            .unwrap();
+        for path in ["/bucket/team-b/x", "/bucket/team-ab", "/bucket/team-a%2F..%2Fteam-b/x"] {
+            assert!(policy.authorize(&Action::from_request(&Method::GET, path, None)).is_err(), "{path}");
+        }
+        // Percent-encoded separators are checked after decoding
+        policy
+            .authorize(&Action::from_request(&Method::GET, "/bucket/team-a%2Fx", None))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_bucket_configuration() {
+        let tenant = Policy::full_access().with_allowed_prefixes(&["team-a".to_string()]);
+        let anonymous = Policy::new([Permission::Read]).without_bucket_configuration();
+        for (method, permission) in [(Method::GET, Permission::Read), (Method::PUT, Permission::Write)] {
+            let action = Action::from_request(&method, "/bucket", Some("notification"));
+            assert_eq!(action.permission, permission);
+            Policy::full_access().authorize(&action).unwrap();
+            let denied = tenant.authorize(&action).unwrap_err();
+            assert!(denied.to_string().contains("requires access to every key"), "{denied}");
+        }
+        let get = Action::from_request(&Method::GET, "/bucket", Some("notification"));
+        assert!(anonymous.authorize(&get).is_err());
+        // Listing stays open to both
+        let list = Action::from_request(&Method::GET, "/bucket", None);
+        tenant.authorize(&list).unwrap();
+        anonymous.authorize(&list).unwrap();
+    }
+
+    #[test]
+    fn test_list_prefix_intersection() {
+        let policy = Policy::full_access()
+            .with_allowed_prefixes(&["team-a/".to_string(), "shared/docs/".to_string()]);
+        assert_eq!(policy.list_prefixes(""), vec!["shared/docs/", "team-a/"]);
+        assert_eq!(policy.list_prefixes("team"), vec!["team-a/"]);
+        assert_eq!(policy.list_prefixes("team-a/x"), vec!["team-a/x"]);
+        assert_eq!(policy.list_prefixes("shared/"), vec!["shared/docs/"]);
+        assert!(policy.list_prefixes("team-b").is_empty());
+        assert!(policy.list_prefixes("team-a-evil").is_empty());
+
+        assert_eq!(Policy::full_access().list_prefixes("x"), vec!["x"]);
+    }
+}
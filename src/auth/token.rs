@@ -0,0 +1,116 @@
+//! Static bearer token authentication
+//!
+//! Tokens are kept only as SHA-256 digests and compared in constant time,
+//! so neither the token contents nor their lengths leak through timing.
+
+use http::{header, HeaderMap, HeaderName};
+use sha2::{Digest, Sha256};
+use subtle::{Choice, ConstantTimeEq};
+
+/// Set of accepted tokens
+#[derive(Default)]
+pub struct TokenSet {
+    digests: Vec<[u8; 32]>,
+}
+
+impl TokenSet {
+    /// Build a set from plain tokens, ignoring empty ones
+    pub fn new<I, S>(tokens: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self {
+            digests: tokens
+                .into_iter()
+                .filter(|token| !token.as_ref().is_empty())
+                .map(|token| digest(token.as_ref()))
+                .collect(),
+        }
+    }
+
+    /// Read tokens from a file, one per line
+    ///
+    /// Blank lines and lines starting with `#` are ignored.
+    pub fn read_file(path: &std::path::Path) -> Result<Vec<String>, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read token file {}: {}", path.display(), e))?;
+        Ok(contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.digests.is_empty()
+    }
+
+    /// Whether `token` is in the set, checking every entry without early exit
+    pub fn contains(&self, token: &str) -> bool {
+        let presented = digest(token);
+        self.digests
+            .iter()
+            .fold(Choice::from(0), |found, accepted| found | accepted.ct_eq(&presented))
+            .into()
+    }
+}
+
+fn digest(token: &str) -> [u8; 32] {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+/// Token sent with a request
+///
+/// Read from `custom_header` as-is when configured, otherwise from an
+/// `Authorization: Bearer <token>` header.
+pub fn presented<'a>(headers: &'a HeaderMap, custom_header: Option<&HeaderName>) -> Option<&'a str> {
+    let token = match custom_header {
+        Some(name) => headers.get(name)?.to_str().ok()?,
+        None => {
+            let (scheme, token) = headers.get(header::AUTHORIZATION)?.to_str().ok()?.split_once(' ')?;
+            if !scheme.eq_ignore_ascii_case("bearer") {
+                return None;
+            }
+            token
+        }
+    };
+    Some(token.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_set() {
+        let tokens = TokenSet::new(["alpha", "", "beta"]);
+        assert!(tokens.contains("alpha"));
+        assert!(tokens.contains("beta"));
+        assert!(!tokens.contains("alph"));
+        assert!(!tokens.contains(""));
+        assert!(TokenSet::new([""]).is_empty());
+    }
+
+    #[test]
+    fn test_presented_token() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "bearer  abc ".parse().unwrap());
+        headers.insert("x-api-key", "xyz".parse().unwrap());
+        assert_eq!(presented(&headers, None), Some("abc"));
+        assert_eq!(presented(&headers, Some(&HeaderName::from_static("x-api-key"))), Some("xyz"));
+
+        headers.insert(header::AUTHORIZATION, "Basic abc".parse().unwrap());
+        assert_eq!(presented(&headers, None), None);
+    }
+
+    #[test]
+    fn test_read_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tokens");
+        std::fs::write(&path, "# deploy tokens\none\n\n  two  \n").unwrap();
+        assert_eq!(TokenSet::read_file(&path).unwrap(), vec!["one", "two"]);
+        assert!(TokenSet::read_file(&dir.path().join("missing")).is_err());
+    }
+}
@@ -6,14 +6,197 @@
 
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
+use std::error::Error as _;
 use thiserror::Error;
 
+use crate::s3::S3Error;
+use crate::storage::{
+    CircuitOpen, QUOTA_STORE, REQUESTER_PAYS_STORE, SOFT_DELETE_STORE, TOO_LARGE_STORE, WRITE_ONCE_STORE,
+};
+
+/// Normalized class of a storage backend error
+///
+/// Shared by the `s3proxy_storage_errors_total` metric and the mapping to
+/// S3 error codes, so both always agree on what kind of failure occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageErrorClass {
+    /// Object or container does not exist
+    NotFound,
+    /// Backend rejected the proxy's credentials or permissions
+    Permission,
+    /// Conditional request failed or the object already exists
+    Precondition,
+    /// Backend is rate limiting requests
+    Throttled,
+    /// Backend did not respond in time
+    Timeout,
+    /// Outbound proxy could not be reached or would not tunnel to the
+    /// backend
+    Proxy,
+    /// Anything else
+    Other,
+}
+
+impl StorageErrorClass {
+    /// Classify an `object_store` error
+    ///
+    /// Most backend failures surface as `Generic` errors wrapping the HTTP
+    /// client error, so those are classified from the status code and
+    /// messages found along their source chain.
+    pub fn of(error: &object_store::Error) -> Self {
+        match error {
+            object_store::Error::NotFound { .. } => Self::NotFound,
+            object_store::Error::Precondition { .. }
+            | object_store::Error::NotModified { .. }
+            | object_store::Error::AlreadyExists { .. } => Self::Precondition,
+            object_store::Error::PermissionDenied { .. } | object_store::Error::Unauthenticated { .. } => {
+                Self::Permission
+            }
+            object_store::Error::Generic { store: REQUESTER_PAYS_STORE, .. } => Self::Permission,
+            object_store::Error::Generic { .. } => Self::from_messages(error),
+            _ => Self::Other,
+        }
+    }
+
+    fn from_messages(error: &object_store::Error) -> Self {
+        let mut source: Option<&(dyn std::error::Error + 'static)> = error.source();
+        while let Some(e) = source {
+            if e.is::<CircuitOpen>() {
+                return Self::Throttled;
+            }
+            if let Some(io) = e.downcast_ref::<std::io::Error>() {
+                match io.kind() {
+                    std::io::ErrorKind::TimedOut => return Self::Timeout,
+                    std::io::ErrorKind::PermissionDenied => return Self::Permission,
+                    _ => {}
+                }
+            }
+            let message = e.to_string().to_lowercase();
+            let mentions = |needles: &[&str]| needles.iter().any(|n| message.contains(n));
+            // Failed CONNECT to the proxy, for HTTPS backends
+            if mentions(&["tunnel error"]) {
+                return Self::Proxy;
+            }
+            if mentions(&["429", "too many requests", "slowdown", "throttl"]) {
+                return Self::Throttled;
+            }
+            if mentions(&["timed out", "timeout"]) {
+                return Self::Timeout;
+            }
+            if mentions(&["401 unauthorized", "403 forbidden", "accessdenied", "access denied"]) {
+                return Self::Permission;
+            }
+            source = e.source();
+        }
+        Self::Other
+    }
+
+    /// Label value for metrics
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NotFound => "not_found",
+            Self::Permission => "permission",
+            Self::Precondition => "precondition",
+            Self::Throttled => "throttled",
+            Self::Timeout => "timeout",
+            Self::Proxy => "proxy",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// Failed storage backend operation
+///
+/// Classified once, when converted from the `object_store` error, so retries,
+/// metrics and the S3 error response all agree. Base backends attach the
+/// backend, operation and path the error happened on, for logging;
+/// decorators pass errors through with that context intact.
+#[derive(Debug)]
+pub struct StorageError {
+    class: StorageErrorClass,
+    context: Option<Box<StorageErrorContext>>,
+    source: object_store::Error,
+}
+
+#[derive(Debug)]
+struct StorageErrorContext {
+    backend: &'static str,
+    operation: &'static str,
+    path: String,
+}
+
+impl StorageError {
+    /// Attach the backend, operation and path the error happened on
+    pub fn with_context(mut self, backend: &'static str, operation: &'static str, path: &str) -> Self {
+        self.context = Some(Box::new(StorageErrorContext {
+            backend,
+            operation,
+            path: path.to_string(),
+        }));
+        self
+    }
+
+    pub fn class(&self) -> StorageErrorClass {
+        self.class
+    }
+
+    /// Backend type the error happened on, when known
+    pub fn backend(&self) -> Option<&'static str> {
+        self.context.as_ref().map(|context| context.backend)
+    }
+
+    /// Storage operation that failed, when known
+    pub fn operation(&self) -> Option<&'static str> {
+        self.context.as_ref().map(|context| context.operation)
+    }
+
+    /// Path the operation failed on, relative to the backend prefix, when
+    /// known
+    pub fn path(&self) -> Option<&str> {
+        self.context.as_ref().map(|context| context.path.as_str())
+    }
+
+    /// The `object_store` error
+    pub fn object_store_error(&self) -> &object_store::Error {
+        &self.source
+    }
+}
+
+impl From<object_store::Error> for StorageError {
+    fn from(source: object_store::Error) -> Self {
+        Self {
+            class: StorageErrorClass::of(&source),
+            context: None,
+            source,
+        }
+    }
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.context {
+            Some(context) => write!(
+                f,
+                "{} {} of {:?} failed: {}",
+                context.backend, context.operation, context.path, self.source
+            ),
+            None => write!(f, "{}", self.source),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
 /// Main error type for S3Proxy operations
 #[derive(Error, Debug)]
 pub enum S3ProxyError {
     /// Storage backend operation failed
     #[error("Storage error: {0}")]
-    Storage(#[from] object_store::Error),
+    Storage(#[from] StorageError),
 
     /// Configuration error
     #[error("Configuration error: {0}")]
@@ -22,14 +205,80 @@ pub enum S3ProxyError {
 
     /// Invalid request
     #[error("Invalid request: {0}")]
-    #[allow(dead_code)] // Part of public API for request validation
     InvalidRequest(String),
 
+    /// A request parameter has an invalid value
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+
+    /// A query parameter has an invalid value, reported with its name
+    #[error("Invalid {name}: {message}")]
+    InvalidParameter {
+        name: &'static str,
+        value: String,
+        message: String,
+    },
+
+    /// Request body is not well-formed XML or not of the expected schema
+    #[error("Malformed XML: {0}")]
+    MalformedXml(String),
+
+    /// Object key exceeds the maximum allowed length
+    #[error("Object key is too long: {size} bytes")]
+    KeyTooLong { size: usize },
+
     /// Object not found
     #[error("Object not found: {path}")]
-    #[allow(dead_code)] // Part of public API, used in error response mapping
     NotFound { path: String },
 
+    /// Bucket is not served by this proxy
+    #[error("Bucket not found: {bucket}")]
+    NoSuchBucket { bucket: String },
+
+    /// The resource does not support the request method
+    #[error("Method {method} not allowed on {resource_type}")]
+    MethodNotAllowed { method: String, resource_type: &'static str },
+
+    /// Request is not permitted
+    #[error("Access denied: {0}")]
+    AccessDenied(String),
+
+    /// Access key in the request signature is not configured
+    #[error("Invalid access key id: {0}")]
+    InvalidAccessKeyId(String),
+
+    /// Request signature does not match the computed signature
+    #[error("Signature does not match")]
+    SignatureDoesNotMatch,
+
+    /// Request time differs too much from the server time
+    #[error("Request time too skewed")]
+    RequestTimeTooSkewed,
+
+    /// Authorization header or signing parameters are malformed
+    #[error("Authorization header malformed: {0}")]
+    AuthorizationHeaderMalformed(String),
+
+    /// Presigned URL query parameters are missing or malformed
+    #[error("Authorization query parameters error: {0}")]
+    AuthorizationQueryParametersError(String),
+
+    /// Request body does not match the signed x-amz-content-sha256
+    #[error("Content SHA256 mismatch")]
+    ContentSha256Mismatch,
+
+    /// The proxy is at capacity and shed the request
+    #[error("Slow down: {0}")]
+    SlowDown(String),
+
+    /// The request was not answered within `server.timeout_secs`
+    #[error("Request timed out")]
+    RequestTimeout,
+
+    /// Recognized but unsupported functionality
+    #[error("Not implemented: {0}")]
+    NotImplemented(String),
+
     /// Internal server error
     #[error("Internal error: {0}")]
     Internal(String),
@@ -52,34 +301,208 @@ pub enum S3ProxyError {
     Xml(String),
 }
 
+impl From<object_store::Error> for S3ProxyError {
+    fn from(error: object_store::Error) -> Self {
+        Self::Storage(error.into())
+    }
+}
+
+/// Query strings that do not deserialize are rejected as S3 errors rather
+/// than with axum's plain text response
+impl From<axum::extract::rejection::QueryRejection> for S3ProxyError {
+    fn from(rejection: axum::extract::rejection::QueryRejection) -> Self {
+        Self::InvalidArgument(rejection.body_text())
+    }
+}
+
+/// S3 error code of an error response, attached as a response extension
+/// for the access log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorCode(pub &'static str);
+
 impl IntoResponse for S3ProxyError {
     fn into_response(self) -> Response {
+        // Clients should wait for an open circuit breaker before retrying
+        let retry_after = match &self {
+            S3ProxyError::Storage(e) => CircuitOpen::find(e).map(CircuitOpen::retry_after),
+            _ => None,
+        };
+        // Elements S3 adds for some errors, after the message
+        let details = match &self {
+            S3ProxyError::MethodNotAllowed { method, resource_type } => S3Error {
+                method: Some(method.clone()),
+                resource_type: Some(resource_type.to_string()),
+                ..S3Error::default()
+            },
+            S3ProxyError::InvalidParameter { name, value, .. } => S3Error {
+                argument_name: Some(name.to_string()),
+                argument_value: Some(value.clone()),
+                ..S3Error::default()
+            },
+            _ => S3Error::default(),
+        };
         let (status, error_code, message) = match self {
             S3ProxyError::NotFound { path } => (
                 StatusCode::NOT_FOUND,
                 "NoSuchKey",
                 format!("The specified key does not exist: {}", path),
             ),
+            S3ProxyError::NoSuchBucket { bucket } => (
+                StatusCode::NOT_FOUND,
+                "NoSuchBucket",
+                format!("The specified bucket does not exist: {}", bucket),
+            ),
+            S3ProxyError::MethodNotAllowed { .. } => (
+                StatusCode::METHOD_NOT_ALLOWED,
+                "MethodNotAllowed",
+                "The specified method is not allowed against this resource.".to_string(),
+            ),
             S3ProxyError::InvalidRequest(msg) => (
                 StatusCode::BAD_REQUEST,
                 "InvalidRequest",
                 msg,
             ),
-            S3ProxyError::Storage(e) => {
-                // Map object_store errors to S3-compatible errors
-                match e {
-                    object_store::Error::NotFound { .. } => (
-                        StatusCode::NOT_FOUND,
-                        "NoSuchKey",
-                        "The specified key does not exist".to_string(),
-                    ),
-                    _ => (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "InternalError",
-                        format!("Storage operation failed: {}", e),
-                    ),
+            S3ProxyError::InvalidParameter { message, .. } => (
+                StatusCode::BAD_REQUEST,
+                "InvalidArgument",
+                message,
+            ),
+            S3ProxyError::InvalidArgument(msg) => (
+                StatusCode::BAD_REQUEST,
+                "InvalidArgument",
+                msg,
+            ),
+            S3ProxyError::MalformedXml(_) => (
+                StatusCode::BAD_REQUEST,
+                "MalformedXML",
+                "The XML you provided was not well-formed or did not validate against our published schema".to_string(),
+            ),
+            S3ProxyError::KeyTooLong { size } => (
+                StatusCode::BAD_REQUEST,
+                "KeyTooLongError",
+                format!(
+                    "Your key is too long: {} bytes exceeds the maximum of {}",
+                    size,
+                    crate::s3::MAX_KEY_LENGTH
+                ),
+            ),
+            S3ProxyError::AccessDenied(msg) => (StatusCode::FORBIDDEN, "AccessDenied", msg),
+            S3ProxyError::InvalidAccessKeyId(_) => (
+                StatusCode::FORBIDDEN,
+                "InvalidAccessKeyId",
+                "The AWS access key Id you provided does not exist in our records.".to_string(),
+            ),
+            S3ProxyError::SignatureDoesNotMatch => (
+                StatusCode::FORBIDDEN,
+                "SignatureDoesNotMatch",
+                "The request signature we calculated does not match the signature you provided. Check your key and signing method.".to_string(),
+            ),
+            S3ProxyError::RequestTimeTooSkewed => (
+                StatusCode::FORBIDDEN,
+                "RequestTimeTooSkewed",
+                "The difference between the request time and the current time is too large.".to_string(),
+            ),
+            S3ProxyError::AuthorizationHeaderMalformed(msg) => (
+                StatusCode::BAD_REQUEST,
+                "AuthorizationHeaderMalformed",
+                msg,
+            ),
+            S3ProxyError::AuthorizationQueryParametersError(msg) => (
+                StatusCode::BAD_REQUEST,
+                "AuthorizationQueryParametersError",
+                msg,
+            ),
+            S3ProxyError::ContentSha256Mismatch => (
+                StatusCode::BAD_REQUEST,
+                "XAmzContentSHA256Mismatch",
+                "The provided 'x-amz-content-sha256' header does not match what was computed.".to_string(),
+            ),
+            S3ProxyError::SlowDown(msg) => (StatusCode::SERVICE_UNAVAILABLE, "SlowDown", msg),
+            // 503 rather than S3's 400, so clients retry it whether they go
+            // by the status or the code
+            S3ProxyError::RequestTimeout => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "RequestTimeout",
+                "The request was not completed within the timeout period".to_string(),
+            ),
+            S3ProxyError::NotImplemented(msg) => (StatusCode::NOT_IMPLEMENTED, "NotImplemented", msg),
+            S3ProxyError::Storage(e) => match (e.class(), e.object_store_error()) {
+                (_, object_store::Error::NotModified { .. }) => (
+                    StatusCode::NOT_MODIFIED,
+                    "NotModified",
+                    "The object was not modified since the given time or ETag".to_string(),
+                ),
+                (_, object_store::Error::AlreadyExists { .. }) => (
+                    StatusCode::PRECONDITION_FAILED,
+                    "PreconditionFailed",
+                    "The object already exists".to_string(),
+                ),
+                (_, object_store::Error::NotImplemented | object_store::Error::NotSupported { .. }) => (
+                    StatusCode::NOT_IMPLEMENTED,
+                    "NotImplemented",
+                    "The storage backend does not support this operation".to_string(),
+                ),
+                (_, object_store::Error::InvalidPath { .. }) => (
+                    StatusCode::BAD_REQUEST,
+                    "InvalidArgument",
+                    format!("Invalid object key: {}", e.object_store_error()),
+                ),
+                (_, object_store::Error::Generic { store: WRITE_ONCE_STORE, .. }) => (
+                    StatusCode::FORBIDDEN,
+                    "AccessDenied",
+                    "Objects are write-once and cannot be deleted".to_string(),
+                ),
+                (_, object_store::Error::Generic { store: REQUESTER_PAYS_STORE, .. }) => (
+                    StatusCode::FORBIDDEN,
+                    "AccessDenied",
+                    "The S3 bucket denied access; if it is a requester-pays bucket, set request_payer = true on the backend"
+                        .to_string(),
+                ),
+                (_, object_store::Error::Generic { store: QUOTA_STORE, source }) => {
+                    (StatusCode::FORBIDDEN, "QuotaExceeded", source.to_string())
                 }
-            }
+                (_, object_store::Error::Generic { store: SOFT_DELETE_STORE, source }) => {
+                    (StatusCode::FORBIDDEN, "AccessDenied", source.to_string())
+                }
+                (_, object_store::Error::Generic { store: TOO_LARGE_STORE, source }) => {
+                    (StatusCode::BAD_REQUEST, "EntityTooLarge", source.to_string())
+                }
+                (StorageErrorClass::NotFound, _) => (
+                    StatusCode::NOT_FOUND,
+                    "NoSuchKey",
+                    "The specified key does not exist".to_string(),
+                ),
+                (StorageErrorClass::Permission, _) => (
+                    StatusCode::FORBIDDEN,
+                    "AccessDenied",
+                    "Access to the storage backend was denied".to_string(),
+                ),
+                (StorageErrorClass::Precondition, _) => (
+                    StatusCode::PRECONDITION_FAILED,
+                    "PreconditionFailed",
+                    "At least one of the preconditions you specified did not hold".to_string(),
+                ),
+                (StorageErrorClass::Throttled, _) => (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "SlowDown",
+                    "Please reduce your request rate".to_string(),
+                ),
+                (StorageErrorClass::Timeout, _) => (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "SlowDown",
+                    "The storage backend did not respond in time, please retry".to_string(),
+                ),
+                (StorageErrorClass::Proxy, _) => (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "ServiceUnavailable",
+                    "The storage backend could not be reached through the outbound proxy".to_string(),
+                ),
+                (StorageErrorClass::Other, _) => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "InternalError",
+                    format!("Storage operation failed: {}", e.object_store_error()),
+                ),
+            },
             _ => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "InternalError",
@@ -87,22 +510,212 @@ impl IntoResponse for S3ProxyError {
             ),
         };
 
-        // Return S3-compatible XML error response
-        let xml = format!(
-            r#"<?xml version="1.0" encoding="UTF-8"?>
-<Error>
-    <Code>{}</Code>
-    <Message>{}</Message>
-    <Resource></Resource>
-    <RequestId></RequestId>
-</Error>"#,
-            error_code, message
-        );
+        // Return S3-compatible XML error response, identifying the request
+        // when rendered while serving one. Messages may quote keys and
+        // backend errors, so the serializer escapes them.
+        let (request_id, host_id) = crate::request_id::RequestId::current()
+            .map(|id| (id.to_string(), id.host_id()))
+            .unwrap_or_default();
+        let error = S3Error {
+            code: error_code.to_string(),
+            message,
+            request_id,
+            host_id,
+            ..details
+        };
+        // Strings always serialize
+        let xml = error.to_xml().unwrap_or_default();
 
-        (status, [("content-type", "application/xml")], xml).into_response()
+        let mut response = (status, [("content-type", "application/xml")], xml).into_response();
+        if let Some(retry_after) = retry_after {
+            // Whole seconds, rounded up
+            let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response.headers_mut().insert(axum::http::header::RETRY_AFTER, secs.max(1).into());
+        }
+        response.extensions_mut().insert(ErrorCode(error_code));
+        response
     }
 }
 
 /// Result type alias for convenience
 pub type Result<T> = std::result::Result<T, S3ProxyError>;
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generic(message: &str) -> object_store::Error {
+        object_store::Error::Generic {
+            store: "S3",
+            source: message.into(),
+        }
+    }
+
+    #[test]
+    fn test_storage_error_classes() {
+        let cases = [
+            (
+                object_store::Error::NotFound {
+                    path: "a".into(),
+                    source: "missing".into(),
+                },
+                StorageErrorClass::NotFound,
+            ),
+            (
+                object_store::Error::Precondition {
+                    path: "a".into(),
+                    source: "etag mismatch".into(),
+                },
+                StorageErrorClass::Precondition,
+            ),
+            (
+                object_store::Error::AlreadyExists {
+                    path: "a".into(),
+                    source: "exists".into(),
+                },
+                StorageErrorClass::Precondition,
+            ),
+            (
+                object_store::Error::PermissionDenied {
+                    path: "a".into(),
+                    source: "denied".into(),
+                },
+                StorageErrorClass::Permission,
+            ),
+            (
+                object_store::Error::Unauthenticated {
+                    path: "a".into(),
+                    source: "expired".into(),
+                },
+                StorageErrorClass::Permission,
+            ),
+            (generic("Server returned non-2xx status code: 429 Too Many Requests"), StorageErrorClass::Throttled),
+            (generic("<Code>SlowDown</Code>"), StorageErrorClass::Throttled),
+            (generic("Server returned non-2xx status code: 403 Forbidden"), StorageErrorClass::Permission),
+            (
+                generic("Client error with status 403 Forbidden: <Code>AuthenticationFailed</Code>Signed expiry time"),
+                StorageErrorClass::Permission,
+            ),
+            (generic("error sending request: operation timed out"), StorageErrorClass::Timeout),
+            (generic("connection refused"), StorageErrorClass::Other),
+            (
+                generic("client error (Connect): tunnel error: failed to create underlying connection"),
+                StorageErrorClass::Proxy,
+            ),
+            (generic("tunnel error: proxy authorization required"), StorageErrorClass::Proxy),
+            (object_store::Error::NotImplemented, StorageErrorClass::Other),
+        ];
+        for (error, class) in cases {
+            assert_eq!(StorageErrorClass::of(&error), class, "{}", error);
+        }
+
+        let io = object_store::Error::Generic {
+            store: "S3",
+            source: Box::new(std::io::Error::from(std::io::ErrorKind::TimedOut)),
+        };
+        assert_eq!(StorageErrorClass::of(&io), StorageErrorClass::Timeout);
+    }
+
+    #[test]
+    fn test_storage_error_status_matches_class() {
+        let status = |error: object_store::Error| S3ProxyError::from(error).into_response().status();
+        assert_eq!(status(generic("429 Too Many Requests")), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(status(generic("403 Forbidden")), StatusCode::FORBIDDEN);
+        assert_eq!(status(generic("connection refused")), StatusCode::INTERNAL_SERVER_ERROR);
+        let not_modified = object_store::Error::NotModified {
+            path: "a".into(),
+            source: "etag matches".into(),
+        };
+        assert_eq!(status(not_modified), StatusCode::NOT_MODIFIED);
+        assert_eq!(status(object_store::Error::NotImplemented), StatusCode::NOT_IMPLEMENTED);
+    }
+
+    #[test]
+    fn test_storage_error_context() {
+        let error = StorageError::from(generic("503 Service Unavailable")).with_context("aws", "get", "dir/a");
+        assert_eq!(error.backend(), Some("aws"));
+        assert_eq!(error.operation(), Some("get"));
+        assert_eq!(error.path(), Some("dir/a"));
+        assert_eq!(
+            error.to_string(),
+            "aws get of \"dir/a\" failed: Generic S3 error: 503 Service Unavailable"
+        );
+        assert_eq!(StorageError::from(generic("oops")).to_string(), "Generic S3 error: oops");
+    }
+
+    /// Parse an error response body as strictly as quick-xml allows,
+    /// returning its code and message unescaped
+    async fn parse_error(response: Response) -> (String, String) {
+        use quick_xml::events::Event;
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let xml = String::from_utf8(body.to_vec()).unwrap();
+        let mut reader = quick_xml::Reader::from_str(&xml);
+        let (mut element, mut code, mut message) = (String::new(), String::new(), String::new());
+        loop {
+            match reader.read_event().unwrap_or_else(|e| panic!("{e}: {xml}")) {
+                Event::Start(start) => element = String::from_utf8(start.name().as_ref().to_vec()).unwrap(),
+                Event::Text(text) => {
+                    let text = text.unescape().unwrap_or_else(|e| panic!("{e}: {xml}")).into_owned();
+                    match element.as_str() {
+                        "Code" => code = text,
+                        "Message" => message = text,
+                        _ => {}
+                    }
+                }
+                Event::End(_) => element.clear(),
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        (code, message)
+    }
+
+    #[tokio::test]
+    async fn test_error_xml_escapes_messages() {
+        let key = "reports/P&L <2024>.csv";
+        let (code, message) = parse_error(S3ProxyError::NotFound { path: key.to_string() }.into_response()).await;
+        assert_eq!(code, "NoSuchKey");
+        assert_eq!(message, format!("The specified key does not exist: {}", key));
+
+        let text = "it's \"quoted\" & <tagged>\non two lines";
+        let (code, message) = parse_error(S3ProxyError::InvalidArgument(text.to_string()).into_response()).await;
+        assert_eq!(code, "InvalidArgument");
+        assert_eq!(message, text);
+
+        let error = S3ProxyError::MethodNotAllowed {
+            method: "PATCH".to_string(),
+            resource_type: "OBJECT",
+        };
+        let body = axum::body::to_bytes(error.into_response().into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("<Method>PATCH</Method><ResourceType>OBJECT</ResourceType>"), "{body}");
+
+        let error = S3ProxyError::InvalidParameter {
+            name: "max-keys",
+            value: "<1>".to_string(),
+            message: "Provided max-keys not an integer or within integer range".to_string(),
+        };
+        let body = axum::body::to_bytes(error.into_response().into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let argument = "<ArgumentName>max-keys</ArgumentName><ArgumentValue>&lt;1&gt;</ArgumentValue>";
+        assert!(body.contains(argument), "{body}");
+
+        let xml = crate::s3::error_xml("InvalidArgument", "a < b");
+        assert!(xml.contains("<Message>a &lt; b</Message>"), "{xml}");
+    }
+
+    #[tokio::test]
+    async fn test_requester_pays_denial_names_setting() {
+        let requester_pays = object_store::Error::Generic {
+            store: REQUESTER_PAYS_STORE,
+            source: "denied".into(),
+        };
+        let response = S3ProxyError::from(requester_pays).into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let (code, message) = parse_error(response).await;
+        assert_eq!(code, "AccessDenied");
+        assert!(message.contains("request_payer = true"), "{message}");
+    }
+}
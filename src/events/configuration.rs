@@ -0,0 +1,214 @@
+//! Bucket notification configurations
+//!
+//! `PUT /{bucket}?notification` gives a bucket rules of its own, which
+//! route its events instead of the filters of the configured destinations.
+//! Each topic, queue or cloud function configuration names a configured
+//! destination by the last field of its ARN, e.g. `ingest` in
+//! `arn:aws:sqs:us-east-1:123456789012:ingest` or `webhook` in MinIO's
+//! `arn:minio:sqs::primary:webhook`, and selects events by type and key
+//! prefix and suffix. As in S3, an empty configuration turns the bucket's
+//! notifications off.
+//!
+//! A configuration is stored in the bucket's backend under a reserved key,
+//! so it survives restarts, and read again on the bucket's first event.
+
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use tracing::warn;
+
+use crate::config::NOTIFICATION_EVENTS;
+use crate::errors::{Result, S3ProxyError, StorageErrorClass};
+use crate::events::{EventFilter, Notifier};
+use crate::s3::{
+    FilterRule, KeyFilter, NotificationConfiguration, NotificationDestination, NotificationFilter, NotificationRule,
+    XmlRequest, NOTIFICATION_CONFIGURATION_KEY,
+};
+use crate::storage::BucketRegistry;
+
+/// Rule routing the matching events of a bucket to a destination
+pub(super) struct Rule {
+    /// Sent as the `configurationId` of the events
+    pub id: String,
+    /// Index of the destination in the notifier
+    pub destination: usize,
+    pub filter: EventFilter,
+}
+
+/// Notification configuration a bucket was given, and its rules
+pub(super) struct BucketConfiguration {
+    configuration: NotificationConfiguration,
+    pub rules: Vec<Rule>,
+}
+
+/// Configurations of the buckets, read from their backends on first use
+pub(super) struct BucketConfigurations {
+    registry: Arc<BucketRegistry>,
+    /// None for buckets without a configuration
+    loaded: RwLock<HashMap<String, Option<Arc<BucketConfiguration>>>>,
+}
+
+impl BucketConfigurations {
+    pub fn new(registry: Arc<BucketRegistry>) -> Self {
+        Self {
+            registry,
+            loaded: RwLock::default(),
+        }
+    }
+}
+
+/// ARN naming the destination `id` in notification configurations
+fn arn(id: &str) -> String {
+    format!("arn:s3proxy:sqs:::{}", id)
+}
+
+/// Key filter of the given prefix and suffix, if any
+fn key_filter(prefix: &str, suffix: &str) -> Option<NotificationFilter> {
+    let rules: Vec<FilterRule> = [("prefix", prefix), ("suffix", suffix)]
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| FilterRule {
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+        .collect();
+    (!rules.is_empty()).then_some(NotificationFilter {
+        key: KeyFilter { rules },
+    })
+}
+
+impl Notifier {
+    /// Notification configuration of `bucket`: the one it was given, or
+    /// else a queue configuration per destination receiving its events
+    pub async fn configuration(&self, bucket: &str) -> Result<NotificationConfiguration> {
+        if let Some(configured) = self.bucket_configuration(bucket).await? {
+            return Ok(configured.configuration.clone());
+        }
+        let destinations = self
+            .destinations
+            .iter()
+            .filter(|d| d.filter.buckets.is_empty() || d.filter.buckets.iter().any(|b| b == bucket))
+            .map(|d| {
+                NotificationDestination::Queue(NotificationRule {
+                    id: Some(d.id.clone()),
+                    queue: Some(arn(&d.id)),
+                    events: d.filter.events.clone(),
+                    filter: key_filter(&d.filter.prefix, &d.filter.suffix),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        Ok(NotificationConfiguration { destinations })
+    }
+
+    /// Route the events of `bucket` by `configuration` from now on,
+    /// storing it in the bucket's backend
+    pub async fn set_configuration(&self, bucket: &str, mut configuration: NotificationConfiguration) -> Result<()> {
+        let mut ids = HashSet::new();
+        for destination in &mut configuration.destinations {
+            let id = destination
+                .rule_mut()
+                .id
+                .get_or_insert_with(|| uuid::Uuid::new_v4().to_string());
+            if !ids.insert(id.clone()) {
+                return Err(S3ProxyError::InvalidArgument(format!("Configuration ID {} is used twice", id)));
+            }
+        }
+        let rules = configuration
+            .destinations
+            .iter()
+            .map(|destination| self.rule(destination))
+            .collect::<Result<Vec<_>>>()?;
+
+        let xml = configuration
+            .to_xml()
+            .map_err(|e| S3ProxyError::Internal(format!("XML serialization failed: {}", e)))?;
+        let storage = self.configurations.registry.resolve(bucket)?;
+        storage.put(NOTIFICATION_CONFIGURATION_KEY, Bytes::from(xml)).await?;
+
+        let configured = BucketConfiguration { configuration, rules };
+        let mut loaded = self.configurations.loaded.write().unwrap_or_else(|e| e.into_inner());
+        loaded.insert(bucket.to_string(), Some(Arc::new(configured)));
+        Ok(())
+    }
+
+    /// Configuration `bucket` was given, read from its backend the first
+    /// time
+    pub(super) async fn bucket_configuration(&self, bucket: &str) -> Result<Option<Arc<BucketConfiguration>>> {
+        let cached = {
+            let loaded = self.configurations.loaded.read().unwrap_or_else(|e| e.into_inner());
+            loaded.get(bucket).cloned()
+        };
+        if let Some(configured) = cached {
+            return Ok(configured);
+        }
+
+        let storage = self.configurations.registry.resolve(bucket)?;
+        let configured = match storage.get(NOTIFICATION_CONFIGURATION_KEY).await {
+            Ok(xml) => {
+                let configuration = NotificationConfiguration::from_xml(&String::from_utf8_lossy(&xml))?;
+                // Destinations may have been removed since
+                let rules = configuration
+                    .destinations
+                    .iter()
+                    .filter_map(|destination| match self.rule(destination) {
+                        Ok(rule) => Some(rule),
+                        Err(e) => {
+                            warn!(bucket, error = %e, "Ignoring notification rule");
+                            None
+                        }
+                    })
+                    .collect();
+                Some(Arc::new(BucketConfiguration { configuration, rules }))
+            }
+            Err(e) if e.class() == StorageErrorClass::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        // A configuration set meanwhile wins over the one read
+        let mut loaded = self.configurations.loaded.write().unwrap_or_else(|e| e.into_inner());
+        Ok(loaded.entry(bucket.to_string()).or_insert(configured).clone())
+    }
+
+    /// Rule of one destination of a configuration
+    fn rule(&self, destination: &NotificationDestination) -> Result<Rule> {
+        let invalid = |message: String| Err(S3ProxyError::InvalidArgument(message));
+        let rule = destination.rule();
+        let Some(arn) = destination.arn() else {
+            return invalid("A notification configuration names no destination".to_string());
+        };
+        let id = arn.rsplit(':').next().unwrap_or(arn);
+        let Some(index) = self.destinations.iter().position(|d| d.id == id) else {
+            return invalid(format!("{} does not name a configured destination", arn));
+        };
+        if rule.events.is_empty() {
+            return invalid(format!("The configuration of {} selects no events", arn));
+        }
+        if let Some(event) = rule.events.iter().find(|event| !NOTIFICATION_EVENTS.contains(&event.as_str())) {
+            return invalid(format!("Unsupported event: {}", event));
+        }
+
+        let mut filter = EventFilter {
+            events: rule.events.clone(),
+            ..Default::default()
+        };
+        let filter_rules = rule.filter.iter().flat_map(|filter| &filter.key.rules);
+        let mut names = HashSet::new();
+        for FilterRule { name, value } in filter_rules {
+            let name = name.to_lowercase();
+            let field = match name.as_str() {
+                "prefix" => &mut filter.prefix,
+                "suffix" => &mut filter.suffix,
+                _ => return invalid(format!("Unsupported filter rule name: {}", name)),
+            };
+            if !names.insert(name.clone()) {
+                return invalid(format!("The filter rule {} is given twice", name));
+            }
+            *field = value.clone();
+        }
+        Ok(Rule {
+            id: rule.id.clone().unwrap_or_default(),
+            destination: index,
+            filter,
+        })
+    }
+}
@@ -0,0 +1,150 @@
+//! Kafka event destination
+//!
+//! Publishes each notification as JSON to a topic, keyed by object key so
+//! the events of a key land on one partition in order. A publish counts as
+//! delivered once the brokers acknowledge it as `acks` requires; failures
+//! are retried by the dispatcher. Built with the `events-kafka` feature.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use rdkafka::config::ClientConfig;
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::KafkaConfig;
+use crate::events::{Event, EventSink};
+use crate::health::HealthCheck;
+
+/// Destination publishing events to a Kafka topic
+pub struct KafkaSink {
+    id: String,
+    producer: FutureProducer,
+    topic: String,
+    timeout: Duration,
+}
+
+impl KafkaSink {
+    pub fn new(config: &KafkaConfig) -> Result<Self, String> {
+        let timeout = Duration::from_millis(config.timeout_ms);
+        let mut client = ClientConfig::new();
+        client
+            .set("bootstrap.servers", config.brokers.join(","))
+            .set("acks", &config.acks)
+            .set("security.protocol", &config.security_protocol)
+            .set("message.timeout.ms", config.timeout_ms.to_string());
+        let optional = [
+            ("sasl.mechanism", config.sasl_mechanism.as_deref()),
+            ("sasl.username", config.sasl_username.as_deref()),
+            ("sasl.password", config.sasl_password.as_ref().map(|password| password.expose())),
+            ("ssl.ca.location", config.ssl_ca_location.as_deref()),
+            ("ssl.certificate.location", config.ssl_certificate_location.as_deref()),
+            ("ssl.key.location", config.ssl_key_location.as_deref()),
+        ];
+        for (key, value) in optional {
+            if let Some(value) = value {
+                client.set(key, value);
+            }
+        }
+        let producer = client
+            .create()
+            .map_err(|e| format!("Failed to create the producer of Kafka destination {}: {}", config.id, e))?;
+        Ok(Self {
+            id: config.id.clone(),
+            producer,
+            topic: config.topic.clone(),
+            timeout,
+        })
+    }
+}
+
+#[async_trait]
+impl EventSink for KafkaSink {
+    async fn send(&self, event: &Event, payload: Bytes) -> Result<(), String> {
+        let record = FutureRecord::to(&self.topic).key(&event.key).payload(payload.as_ref());
+        self.producer
+            .send(record, self.timeout)
+            .await
+            .map(|_| ())
+            .map_err(|(e, _)| e.to_string())
+    }
+
+    fn health_check(&self) -> Option<Arc<dyn HealthCheck>> {
+        Some(Arc::new(KafkaHealth {
+            id: self.id.clone(),
+            producer: self.producer.clone(),
+            topic: self.topic.clone(),
+            timeout: self.timeout,
+        }))
+    }
+}
+
+/// Connectivity of a Kafka destination
+///
+/// Fetches the metadata of its topic from the brokers. Not critical, since
+/// events are delivered off the request path.
+struct KafkaHealth {
+    id: String,
+    producer: FutureProducer,
+    topic: String,
+    timeout: Duration,
+}
+
+#[async_trait]
+impl HealthCheck for KafkaHealth {
+    fn name(&self) -> String {
+        format!("kafka:{}", self.id)
+    }
+
+    fn critical(&self) -> bool {
+        false
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        let producer = self.producer.clone();
+        let topic = self.topic.clone();
+        let timeout = self.timeout;
+        // Fetching metadata blocks until the brokers answer
+        let metadata = tokio::task::spawn_blocking(move || producer.client().fetch_metadata(Some(&topic), timeout))
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string())?;
+        match metadata.topics().first().and_then(|topic| topic.error()) {
+            Some(error) => Err(format!("topic {}: {:?}", self.topic, error)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    use crate::events::EventName;
+
+    #[tokio::test]
+    async fn test_unreachable_brokers_fail() {
+        let config: KafkaConfig =
+            toml::from_str("id = 'events'\nbrokers = ['127.0.0.1:1']\ntopic = 'objects'\ntimeout_ms = 200").unwrap();
+        let sink = KafkaSink::new(&config).unwrap();
+        let event = Event {
+            name: EventName::Put,
+            time: Utc::now(),
+            bucket: "photos".to_string(),
+            key: "cat.jpg".to_string(),
+            size: Some(5),
+            etag: None,
+            sequencer: "0".to_string(),
+            principal: "anonymous".to_string(),
+            source_ip: None,
+            request_id: None,
+        };
+
+        assert!(sink.send(&event, event.payload("events")).await.is_err());
+        let check = sink.health_check().unwrap();
+        assert_eq!(check.name(), "kafka:events");
+        assert!(!check.critical());
+        assert!(check.check().await.is_err());
+    }
+}
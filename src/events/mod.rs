@@ -0,0 +1,860 @@
+//! Object event notifications
+//!
+//! After a successful PutObject, CopyObject or DeleteObject, builds an
+//! event in the JSON format of S3 event notifications and hands it to every
+//! destination whose filter matches: the event types, buckets and key
+//! prefix and suffix it selects, as in S3 notification configurations. The
+//! proxy does not serve multipart uploads, so the
+//! `s3:ObjectCreated:CompleteMultipartUpload` type, accepted for
+//! configurations written for S3, selects no events.
+//!
+//! Delivery is off the request path. Each destination has a bounded queue,
+//! drained by a task delivering a limited number of events at a time.
+//! Events arriving while the queue is full are dropped and counted, or
+//! with the `block` overflow policy hold their response until they fit.
+//! Failed deliveries are retried with jittered exponential backoff, and
+//! events that exhaust their attempts are written to the dead-letter log.
+//! Events may arrive out of order; their `sequencer` orders the events of
+//! a key.
+//!
+//! Buckets given a notification configuration through the `?notification`
+//! subresource route their events by its rules instead, see
+//! [`configuration`].
+
+mod configuration;
+#[cfg(feature = "events-kafka")]
+pub mod kafka;
+pub mod webhook;
+
+use async_trait::async_trait;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap};
+use axum::middleware::Next;
+use axum::response::Response;
+use bytes::Bytes;
+use chrono::{DateTime, SecondsFormat, Utc};
+use http_body::Body as _;
+use object_store::ObjectMeta;
+use percent_encoding::percent_decode_str;
+use serde_json::{json, Value};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
+
+use crate::auth::{Principal, ANONYMOUS};
+use crate::config::{Config, KafkaConfig, NotificationsConfig, QueueOverflow, WebhookConfig};
+use crate::health::HealthCheck;
+use crate::metrics::EVENT_NOTIFICATIONS;
+use crate::request_id::RequestId;
+use crate::routes::{self, ip_filter};
+use crate::s3;
+use crate::storage::{jitter, BucketRegistry};
+
+use configuration::BucketConfigurations;
+
+#[cfg(feature = "events-kafka")]
+pub use kafka::KafkaSink;
+pub use webhook::WebhookSink;
+
+/// Kind of object event, named as in S3 event notifications
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventName {
+    Put,
+    Copy,
+    Delete,
+}
+
+impl EventName {
+    /// Event type of the S3 operation `operation`, if it notifies
+    fn for_operation(operation: &str) -> Option<Self> {
+        match operation {
+            "PutObject" => Some(EventName::Put),
+            "CopyObject" => Some(EventName::Copy),
+            "DeleteObject" => Some(EventName::Delete),
+            _ => None,
+        }
+    }
+
+    /// `eventName` of the notification
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventName::Put => "ObjectCreated:Put",
+            EventName::Copy => "ObjectCreated:Copy",
+            EventName::Delete => "ObjectRemoved:Delete",
+        }
+    }
+
+    /// Whether a configured event type such as `s3:ObjectCreated:*`
+    /// selects this event
+    fn matches(&self, pattern: &str) -> bool {
+        let Some(pattern) = pattern.strip_prefix("s3:") else {
+            return false;
+        };
+        match pattern.strip_suffix('*') {
+            Some(prefix) => self.as_str().starts_with(prefix),
+            None => self.as_str() == pattern,
+        }
+    }
+}
+
+/// Change to one object
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub name: EventName,
+    pub time: DateTime<Utc>,
+    pub bucket: String,
+    pub key: String,
+    /// Size of a created object, when known
+    pub size: Option<u64>,
+    /// ETag of a created object, without quotes
+    pub etag: Option<String>,
+    /// Hexadecimal value increasing with each event, ordering the events
+    /// of a key
+    pub sequencer: String,
+    pub principal: String,
+    pub source_ip: Option<String>,
+    pub request_id: Option<String>,
+}
+
+/// Last sequencer handed out, in nanoseconds since the epoch
+static SEQUENCER: AtomicU64 = AtomicU64::new(0);
+
+/// Next sequencer: the current time, or just past the previous one
+fn next_sequencer() -> String {
+    let now = Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64;
+    let previous = SEQUENCER
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(now.max(last + 1)))
+        .unwrap_or_default();
+    format!("{:016X}", now.max(previous + 1))
+}
+
+impl Event {
+    /// S3 event notification of the event, sent by the destination
+    /// `configuration_id`
+    pub fn payload(&self, configuration_id: &str) -> Bytes {
+        let mut object = json!({
+            "key": url::form_urlencoded::byte_serialize(self.key.as_bytes()).collect::<String>(),
+            "sequencer": self.sequencer,
+        });
+        if let Some(size) = self.size {
+            object["size"] = json!(size);
+        }
+        if let Some(etag) = &self.etag {
+            object["eTag"] = json!(etag);
+        }
+        let record = json!({
+            "eventVersion": "2.1",
+            "eventSource": "aws:s3",
+            "awsRegion": "",
+            "eventTime": self.time.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "eventName": self.name.as_str(),
+            "userIdentity": { "principalId": self.principal },
+            "requestParameters": { "sourceIPAddress": self.source_ip.as_deref().unwrap_or_default() },
+            "responseElements": { "x-amz-request-id": self.request_id.as_deref().unwrap_or_default() },
+            "s3": {
+                "s3SchemaVersion": "1.0",
+                "configurationId": configuration_id,
+                "bucket": {
+                    "name": self.bucket,
+                    "ownerIdentity": { "principalId": "" },
+                    "arn": format!("arn:aws:s3:::{}", self.bucket),
+                },
+                "object": object,
+            },
+        });
+        Bytes::from(json!({ "Records": [record] }).to_string())
+    }
+}
+
+/// Events a destination receives
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    /// Event types such as `s3:ObjectCreated:*`
+    pub events: Vec<String>,
+    /// Buckets, or empty for all
+    pub buckets: Vec<String>,
+    pub prefix: String,
+    pub suffix: String,
+}
+
+impl EventFilter {
+    fn matches(&self, event: &Event) -> bool {
+        self.events.iter().any(|pattern| event.name.matches(pattern))
+            && (self.buckets.is_empty() || self.buckets.contains(&event.bucket))
+            && event.key.starts_with(&self.prefix)
+            && event.key.ends_with(&self.suffix)
+    }
+}
+
+impl From<&WebhookConfig> for EventFilter {
+    fn from(config: &WebhookConfig) -> Self {
+        Self {
+            events: config.events.clone(),
+            buckets: config.buckets.clone(),
+            prefix: config.prefix.clone(),
+            suffix: config.suffix.clone(),
+        }
+    }
+}
+
+impl From<&KafkaConfig> for EventFilter {
+    fn from(config: &KafkaConfig) -> Self {
+        Self {
+            events: config.events.clone(),
+            buckets: config.buckets.clone(),
+            prefix: config.prefix.clone(),
+            suffix: config.suffix.clone(),
+        }
+    }
+}
+
+/// Destination type events are delivered to
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    /// Deliver `payload`, the notification of `event`, once
+    async fn send(&self, event: &Event, payload: Bytes) -> Result<(), String>;
+
+    /// Check of the connection to the destination reported by the deep
+    /// health endpoint, if it keeps one
+    fn health_check(&self) -> Option<Arc<dyn HealthCheck>> {
+        None
+    }
+}
+
+/// Sink publishing to the Kafka topic of `config`
+#[cfg(feature = "events-kafka")]
+fn kafka_sink(config: &KafkaConfig) -> Result<Arc<dyn EventSink>, String> {
+    Ok(Arc::new(KafkaSink::new(config)?))
+}
+
+#[cfg(not(feature = "events-kafka"))]
+fn kafka_sink(config: &KafkaConfig) -> Result<Arc<dyn EventSink>, String> {
+    Err(format!(
+        "Kafka destination {} requires a build with the events-kafka feature",
+        config.id
+    ))
+}
+
+/// Log of events that could not be delivered
+struct DeadLetters(Option<Mutex<File>>);
+
+impl DeadLetters {
+    /// Append to the file at `path`, or log without one
+    fn open(path: Option<&str>) -> Result<Self, String> {
+        let Some(path) = path else {
+            return Ok(Self(None));
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open dead-letter log {}: {}", path, e))?;
+        Ok(Self(Some(Mutex::new(file))))
+    }
+
+    fn write(&self, destination: &str, attempts: u32, error: &str, payload: &Bytes) {
+        let Some(file) = &self.0 else {
+            let event = String::from_utf8_lossy(payload);
+            warn!(destination, attempts, error, %event, "Undeliverable event notification");
+            return;
+        };
+        let entry = json!({
+            "time": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            "destination": destination,
+            "attempts": attempts,
+            "error": error,
+            "event": serde_json::from_slice::<Value>(payload).unwrap_or_default(),
+        });
+        let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = writeln!(file, "{}", entry) {
+            warn!(destination, error = %e, "Failed to write dead-letter log");
+        }
+    }
+}
+
+/// Delivery of queued events to one destination
+struct Delivery {
+    id: String,
+    sink: Arc<dyn EventSink>,
+    concurrency: usize,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    dead_letters: Arc<DeadLetters>,
+}
+
+impl Delivery {
+    /// Deliver events from `events`, `concurrency` at a time, until every
+    /// sender is gone
+    async fn run(self, mut events: mpsc::Receiver<Queued>) {
+        let delivery = Arc::new(self);
+        let permits = Arc::new(Semaphore::new(delivery.concurrency));
+        while let Some(queued) = events.recv().await {
+            let Ok(permit) = permits.clone().acquire_owned().await else {
+                return;
+            };
+            let delivery = delivery.clone();
+            tokio::spawn(async move {
+                delivery.deliver(queued).await;
+                drop(permit);
+            });
+        }
+    }
+
+    async fn deliver(&self, Queued { event, configuration_id }: Queued) {
+        let payload = event.payload(&configuration_id);
+        let mut attempts = 1;
+        loop {
+            match self.sink.send(&event, payload.clone()).await {
+                Ok(()) => {
+                    EVENT_NOTIFICATIONS.with_label_values(&[&self.id, "delivered"]).inc();
+                    return;
+                }
+                Err(e) if attempts >= self.max_attempts => {
+                    EVENT_NOTIFICATIONS.with_label_values(&[&self.id, "failed"]).inc();
+                    self.dead_letters.write(&self.id, attempts, &e, &payload);
+                    return;
+                }
+                Err(e) => {
+                    debug!(destination = %self.id, key = %event.key, attempts, error = %e, "Retrying event");
+                    tokio::time::sleep(jitter(self.backoff(attempts))).await;
+                    attempts += 1;
+                }
+            }
+        }
+    }
+
+    /// Backoff ceiling after `attempts` failed attempts
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts - 1);
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+/// Event waiting for delivery, with the configuration that selected it
+struct Queued {
+    event: Event,
+    configuration_id: String,
+}
+
+/// Destination with the filter of the events queued for it
+struct Destination {
+    id: String,
+    filter: EventFilter,
+    queue: mpsc::Sender<Queued>,
+}
+
+/// Dispatches events to the configured destinations
+pub struct Notifier {
+    destinations: Vec<Destination>,
+    overflow: QueueOverflow,
+    health_checks: Vec<Arc<dyn HealthCheck>>,
+    configurations: BucketConfigurations,
+    trust_forwarded_for: bool,
+}
+
+impl Notifier {
+    /// Start delivery to the configured destinations, or `None` without
+    /// any
+    pub fn from_config(config: &Config, registry: &Arc<BucketRegistry>) -> Result<Option<Self>, String> {
+        let notifications = &config.notifications;
+        if notifications.webhooks.is_empty() && notifications.kafka.is_empty() {
+            return Ok(None);
+        }
+        let dead_letters = Arc::new(DeadLetters::open(notifications.dead_letter_path.as_deref())?);
+        let mut notifier = Self {
+            destinations: Vec::new(),
+            overflow: notifications.overflow,
+            health_checks: Vec::new(),
+            configurations: BucketConfigurations::new(registry.clone()),
+            trust_forwarded_for: config.ip_filter.trust_forwarded_for,
+        };
+        for webhook in &notifications.webhooks {
+            let sink = Arc::new(WebhookSink::new(webhook)?);
+            notifier.add(&webhook.id, webhook.into(), sink, notifications, dead_letters.clone());
+        }
+        for kafka in &notifications.kafka {
+            let sink = kafka_sink(kafka)?;
+            notifier.add(&kafka.id, kafka.into(), sink, notifications, dead_letters.clone());
+        }
+        Ok(Some(notifier))
+    }
+
+    /// Checks of the destinations keeping a connection
+    pub fn health_checks(&self) -> &[Arc<dyn HealthCheck>] {
+        &self.health_checks
+    }
+
+    /// Deliver the events `filter` selects to `sink` in the background
+    fn add(
+        &mut self,
+        id: &str,
+        filter: EventFilter,
+        sink: Arc<dyn EventSink>,
+        config: &NotificationsConfig,
+        dead_letters: Arc<DeadLetters>,
+    ) {
+        if let Some(check) = sink.health_check() {
+            self.health_checks.push(check);
+        }
+        let (queue, events) = mpsc::channel(config.queue_size.max(1));
+        let delivery = Delivery {
+            id: id.to_string(),
+            sink,
+            concurrency: config.concurrency.max(1),
+            max_attempts: config.max_attempts.max(1),
+            initial_backoff: Duration::from_millis(config.initial_backoff_ms),
+            max_backoff: Duration::from_millis(config.max_backoff_ms),
+            dead_letters,
+        };
+        tokio::spawn(delivery.run(events));
+        self.destinations.push(Destination {
+            id: id.to_string(),
+            filter,
+            queue,
+        });
+    }
+
+    /// Queue `event` for every destination it matches, by the rules of
+    /// its bucket when it has a configuration, waiting for room in full
+    /// queues only with the `block` overflow policy
+    pub async fn publish(&self, event: Event) {
+        match self.bucket_configuration(&event.bucket).await {
+            Ok(Some(configured)) => {
+                for rule in configured.rules.iter().filter(|rule| rule.filter.matches(&event)) {
+                    self.queue(&self.destinations[rule.destination], &event, &rule.id).await;
+                }
+            }
+            Ok(None) => {
+                for destination in self.destinations.iter().filter(|d| d.filter.matches(&event)) {
+                    self.queue(destination, &event, &destination.id).await;
+                }
+            }
+            Err(e) => {
+                let (bucket, key) = (&event.bucket, &event.key);
+                warn!(bucket, key, error = %e, "Event dropped: notification configuration unavailable");
+            }
+        }
+    }
+
+    async fn queue(&self, destination: &Destination, event: &Event, configuration_id: &str) {
+        let queued = Queued {
+            event: event.clone(),
+            configuration_id: configuration_id.to_string(),
+        };
+        let queued = match self.overflow {
+            QueueOverflow::Drop => destination.queue.try_send(queued).map_err(|e| match e {
+                TrySendError::Full(queued) | TrySendError::Closed(queued) => queued,
+            }),
+            QueueOverflow::Block => destination.queue.send(queued).await.map_err(|e| e.0),
+        };
+        if let Err(Queued { event, .. }) = queued {
+            warn!(destination = %destination.id, bucket = %event.bucket, key = %event.key, "Event dropped");
+            EVENT_NOTIFICATIONS.with_label_values(&[&destination.id, "dropped"]).inc();
+        }
+    }
+}
+
+/// Event a request causes once it succeeds
+fn pending_event(request: &Request, trust_forwarded_for: bool) -> Option<Event> {
+    let uri = request.uri();
+    let headers = request.headers();
+    let name = match routes::operation_name(request.method(), uri.path(), uri.query()) {
+        // A PUT naming its source in a header
+        "PutObject" if headers.contains_key(s3::COPY_SOURCE) => EventName::for_operation("CopyObject"),
+        operation => EventName::for_operation(operation),
+    }?;
+    let (bucket, key) = uri.path().trim_start_matches('/').split_once('/')?;
+    let size = (name == EventName::Put)
+        .then(|| {
+            content_length(headers, "x-amz-decoded-content-length")
+                .or_else(|| content_length(headers, header::CONTENT_LENGTH.as_str()))
+                .or_else(|| request.body().size_hint().exact())
+        })
+        .flatten();
+
+    Some(Event {
+        name,
+        time: Utc::now(),
+        bucket: bucket.to_string(),
+        key: percent_decode_str(key).decode_utf8_lossy().into_owned(),
+        size,
+        etag: None,
+        sequencer: String::new(),
+        principal: ANONYMOUS.to_string(),
+        source_ip: ip_filter::client_ip(request, trust_forwarded_for).map(|ip| ip.to_canonical().to_string()),
+        request_id: request.extensions().get::<RequestId>().map(|id| id.to_string()),
+    })
+}
+
+fn content_length(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+/// Middleware publishing the event of every successful object write and
+/// delete
+///
+/// Must run inside the request ID middleware and outside authorization, so
+/// the event names its principal. Does nothing without destinations.
+pub async fn publish(State(notifier): State<Option<Arc<Notifier>>>, request: Request, next: Next) -> Response {
+    let Some(notifier) = notifier else {
+        return next.run(request).await;
+    };
+    let Some(mut event) = pending_event(&request, notifier.trust_forwarded_for) else {
+        return next.run(request).await;
+    };
+
+    let response = next.run(request).await;
+    if response.status().is_success() {
+        event.time = Utc::now();
+        event.sequencer = next_sequencer();
+        event.etag = response
+            .headers()
+            .get(header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(|etag| etag.trim_matches('"').to_string());
+        // A copy reports what it wrote in its body, and to us as the
+        // destination's metadata
+        if let Some(meta) = response.extensions().get::<ObjectMeta>() {
+            event.size = Some(meta.size as u64);
+            event.etag = meta.e_tag.as_ref().map(|etag| etag.trim_matches('"').to_string());
+        }
+        if let Some(principal) = response.extensions().get::<Principal>() {
+            event.principal = principal.name.clone();
+        }
+        notifier.publish(event).await;
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::middleware::from_fn_with_state;
+    use axum::{Extension, Router};
+    use tower::ServiceExt;
+
+    use crate::s3::{self, NotificationConfiguration, XmlRequest};
+    use crate::storage::{MemoryBackend, StorageBackend};
+
+    /// Sink failing its first `failures` sends and recording the rest
+    #[derive(Default)]
+    struct Capture {
+        failures: Mutex<u32>,
+        sent: Mutex<Vec<Value>>,
+        delivered: tokio::sync::Notify,
+    }
+
+    #[async_trait]
+    impl EventSink for Capture {
+        async fn send(&self, _event: &Event, payload: Bytes) -> Result<(), String> {
+            {
+                let mut failures = self.failures.lock().unwrap();
+                if *failures > 0 {
+                    *failures -= 1;
+                    return Err("unavailable".to_string());
+                }
+            }
+            self.sent.lock().unwrap().push(serde_json::from_slice(&payload).unwrap());
+            self.delivered.notify_one();
+            Ok(())
+        }
+    }
+
+    fn notifier(sink: Arc<Capture>, filter: EventFilter, config: &NotificationsConfig) -> Notifier {
+        notifier_over(registry(), sink, filter, config)
+    }
+
+    fn notifier_over(
+        registry: Arc<BucketRegistry>,
+        sink: Arc<Capture>,
+        filter: EventFilter,
+        config: &NotificationsConfig,
+    ) -> Notifier {
+        let mut notifier = Notifier {
+            destinations: Vec::new(),
+            overflow: config.overflow,
+            health_checks: Vec::new(),
+            configurations: BucketConfigurations::new(registry),
+            trust_forwarded_for: true,
+        };
+        notifier.add("capture", filter, sink, config, Arc::new(DeadLetters(None)));
+        notifier
+    }
+
+    fn registry() -> Arc<BucketRegistry> {
+        let backend: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new(&Default::default()));
+        Arc::new(BucketRegistry::single(backend))
+    }
+
+    fn all_events() -> EventFilter {
+        EventFilter {
+            events: vec!["s3:ObjectCreated:*".to_string(), "s3:ObjectRemoved:*".to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn event(name: EventName, bucket: &str, key: &str) -> Event {
+        Event {
+            name,
+            time: Utc::now(),
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            size: None,
+            etag: None,
+            sequencer: next_sequencer(),
+            principal: ANONYMOUS.to_string(),
+            source_ip: None,
+            request_id: None,
+        }
+    }
+
+    async fn call(router: &Router, method: &str, uri: &str, body: &str) -> (StatusCode, String) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("x-forwarded-for", "203.0.113.7")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    async fn send(router: &Router, method: &str, uri: &str) -> StatusCode {
+        call(router, method, uri, "hello").await.0
+    }
+
+    #[tokio::test]
+    async fn test_writes_and_deletes_publish_events() {
+        let sink = Arc::new(Capture::default());
+        let notifier = notifier(sink.clone(), all_events(), &NotificationsConfig::default());
+        let router = routes::create_router(registry()).layer(from_fn_with_state(Some(Arc::new(notifier)), publish));
+
+        assert_eq!(send(&router, "PUT", "/photos/2024/cat%20pic.jpg").await, StatusCode::OK);
+        sink.delivered.notified().await;
+        assert_eq!(send(&router, "GET", "/photos/2024/cat%20pic.jpg").await, StatusCode::OK);
+        let copy = || {
+            let request = Request::builder()
+                .method("PUT")
+                .uri("/photos/copy.jpg")
+                .header(s3::COPY_SOURCE, "/photos/2024/cat%20pic.jpg")
+                .body(Body::empty())
+                .unwrap();
+            router.clone().oneshot(request)
+        };
+        assert_eq!(copy().await.unwrap().status(), StatusCode::OK);
+        sink.delivered.notified().await;
+        assert_eq!(send(&router, "DELETE", "/photos/2024/cat%20pic.jpg").await, StatusCode::NO_CONTENT);
+        sink.delivered.notified().await;
+        // Failed requests publish nothing
+        assert_eq!(send(&router, "PUT", "/photos/bad/../key").await, StatusCode::BAD_REQUEST);
+        assert_eq!(copy().await.unwrap().status(), StatusCode::NOT_FOUND);
+
+        let sent = sink.sent.lock().unwrap().clone();
+        assert_eq!(sent.len(), 3, "{sent:?}");
+        let put = &sent[0]["Records"][0];
+        assert_eq!(put["eventSource"], "aws:s3");
+        assert_eq!(put["eventName"], "ObjectCreated:Put");
+        assert_eq!(put["userIdentity"]["principalId"], ANONYMOUS);
+        assert_eq!(put["requestParameters"]["sourceIPAddress"], "203.0.113.7");
+        assert_eq!(put["s3"]["configurationId"], "capture");
+        assert_eq!(put["s3"]["bucket"]["name"], "photos");
+        assert_eq!(put["s3"]["bucket"]["arn"], "arn:aws:s3:::photos");
+        let object = &put["s3"]["object"];
+        assert_eq!(object["key"], "2024%2Fcat+pic.jpg");
+        assert_eq!(object["size"], 5);
+        assert!(!object["eTag"].as_str().unwrap().contains('"'), "{object}");
+        let copied = &sent[1]["Records"][0];
+        assert_eq!(copied["eventName"], "ObjectCreated:Copy");
+        assert_eq!(copied["s3"]["object"]["key"], "copy.jpg");
+        // Reported from the copy, not the empty request body
+        assert_eq!(copied["s3"]["object"]["size"], 5);
+        assert!(!copied["s3"]["object"]["eTag"].as_str().unwrap().contains('"'), "{copied}");
+        let delete = &sent[2]["Records"][0];
+        assert_eq!(delete["eventName"], "ObjectRemoved:Delete");
+        assert!(delete["s3"]["object"].get("size").is_none());
+        assert!(delete["s3"]["object"]["sequencer"].as_str() > object["sequencer"].as_str());
+    }
+
+    #[test]
+    fn test_filters() {
+        let filter = EventFilter {
+            events: vec!["s3:ObjectCreated:*".to_string(), "s3:ObjectRemoved:Delete".to_string()],
+            buckets: vec!["photos".to_string()],
+            prefix: "uploads/".to_string(),
+            suffix: ".jpg".to_string(),
+        };
+        assert!(filter.matches(&event(EventName::Put, "photos", "uploads/cat.jpg")));
+        assert!(filter.matches(&event(EventName::Copy, "photos", "uploads/cat.jpg")));
+        assert!(filter.matches(&event(EventName::Delete, "photos", "uploads/cat.jpg")));
+        assert!(!filter.matches(&event(EventName::Put, "videos", "uploads/cat.jpg")));
+        assert!(!filter.matches(&event(EventName::Put, "photos", "cat.jpg")));
+        assert!(!filter.matches(&event(EventName::Put, "photos", "uploads/cat.png")));
+
+        let copies = EventFilter {
+            events: vec!["s3:ObjectCreated:Copy".to_string()],
+            ..Default::default()
+        };
+        assert!(copies.matches(&event(EventName::Copy, "photos", "cat.jpg")));
+        assert!(!copies.matches(&event(EventName::Put, "photos", "cat.jpg")));
+    }
+
+    #[tokio::test]
+    async fn test_failed_deliveries_retried_then_dead_lettered() {
+        let config = NotificationsConfig {
+            max_attempts: 3,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 5,
+            ..Default::default()
+        };
+        let sink = Arc::new(Capture::default());
+        *sink.failures.lock().unwrap() = 2;
+        notifier(sink.clone(), all_events(), &config)
+            .publish(event(EventName::Put, "photos", "a.jpg"))
+            .await;
+        sink.delivered.notified().await;
+        assert_eq!(sink.sent.lock().unwrap().len(), 1);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dead-letters.log");
+        let sink = Arc::new(Capture::default());
+        *sink.failures.lock().unwrap() = 3;
+        let delivery = Delivery {
+            id: "capture".to_string(),
+            sink: sink.clone(),
+            concurrency: 1,
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+            dead_letters: Arc::new(DeadLetters::open(path.to_str()).unwrap()),
+        };
+        let queued = Queued {
+            event: event(EventName::Delete, "photos", "b.jpg"),
+            configuration_id: "capture".to_string(),
+        };
+        delivery.deliver(queued).await;
+
+        assert!(sink.sent.lock().unwrap().is_empty());
+        let line = std::fs::read_to_string(&path).unwrap();
+        let entry: Value = serde_json::from_str(line.trim()).unwrap();
+        assert_eq!(entry["destination"], "capture");
+        assert_eq!(entry["attempts"], 3);
+        assert_eq!(entry["error"], "unavailable");
+        assert_eq!(entry["event"]["Records"][0]["s3"]["object"]["key"], "b.jpg");
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_drops_events() {
+        let config = NotificationsConfig {
+            queue_size: 1,
+            concurrency: 1,
+            ..Default::default()
+        };
+        let sink = Arc::new(Capture::default());
+        let notifier = notifier(sink.clone(), all_events(), &config);
+        let dropped = EVENT_NOTIFICATIONS.with_label_values(&["capture", "dropped"]).get();
+
+        // Published before the delivery task runs, so only one fits
+        for key in ["a", "b", "c"] {
+            notifier.publish(event(EventName::Put, "photos", key)).await;
+        }
+        assert_eq!(EVENT_NOTIFICATIONS.with_label_values(&["capture", "dropped"]).get(), dropped + 2);
+        sink.delivered.notified().await;
+        assert_eq!(sink.sent.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_blocks_events() {
+        let config = NotificationsConfig {
+            queue_size: 1,
+            concurrency: 1,
+            overflow: QueueOverflow::Block,
+            ..Default::default()
+        };
+        let sink = Arc::new(Capture::default());
+        let notifier = notifier(sink.clone(), all_events(), &config);
+
+        // Each publish waits for the delivery task to make room
+        for key in ["a", "b", "c"] {
+            notifier.publish(event(EventName::Put, "blocking", key)).await;
+        }
+        while sink.sent.lock().unwrap().len() < 3 {
+            sink.delivered.notified().await;
+        }
+        let sent = sink.sent.lock().unwrap().clone();
+        let keys: Vec<_> = sent.iter().map(|s| s["Records"][0]["s3"]["object"]["key"].clone()).collect();
+        assert_eq!(keys, ["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_bucket_notification_configuration() {
+        let registry = registry();
+        let sink = Arc::new(Capture::default());
+        let config = NotificationsConfig::default();
+        let notifier = Arc::new(notifier_over(registry.clone(), sink.clone(), all_events(), &config));
+        let router = routes::create_router(registry.clone())
+            .layer(Extension(notifier.clone()))
+            .layer(from_fn_with_state(Some(notifier.clone()), publish));
+
+        // Without a configuration of its own, the bucket reports the
+        // destinations receiving its events
+        let (status, body) = call(&router, "GET", "/photos?notification", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let reported = NotificationConfiguration::from_xml(&body).unwrap();
+        assert_eq!(reported.destinations.len(), 1);
+        assert_eq!(reported.destinations[0].arn(), Some("arn:s3proxy:sqs:::capture"));
+
+        let xml = "<NotificationConfiguration><QueueConfiguration><Id>jpegs</Id>\
+                   <Queue>arn:minio:sqs::primary:capture</Queue><Event>s3:ObjectCreated:*</Event>\
+                   <Filter><S3Key><FilterRule><Name>suffix</Name><Value>.jpg</Value></FilterRule></S3Key></Filter>\
+                   </QueueConfiguration></NotificationConfiguration>";
+        assert_eq!(call(&router, "PUT", "/photos?notification", xml).await.0, StatusCode::OK);
+        assert_eq!(send(&router, "PUT", "/photos/cat.png").await, StatusCode::OK);
+        assert_eq!(send(&router, "PUT", "/photos/cat.jpg").await, StatusCode::OK);
+        sink.delivered.notified().await;
+        let sent = sink.sent.lock().unwrap().clone();
+        assert_eq!(sent.len(), 1, "{sent:?}");
+        assert_eq!(sent[0]["Records"][0]["s3"]["object"]["key"], "cat.jpg");
+        assert_eq!(sent[0]["Records"][0]["s3"]["configurationId"], "jpegs");
+
+        // Stored in the bucket, out of listings, and read after a restart
+        let (_, listing) = call(&router, "GET", "/photos?list-type=2", "").await;
+        assert!(listing.contains("cat.jpg") && !listing.contains(s3::NOTIFICATION_CONFIGURATION_KEY), "{listing}");
+        let uri = format!("/photos/{}", s3::NOTIFICATION_CONFIGURATION_KEY);
+        assert_eq!(send(&router, "GET", &uri).await, StatusCode::BAD_REQUEST);
+        let restarted = notifier_over(registry.clone(), Arc::new(Capture::default()), all_events(), &config);
+        let expected = NotificationConfiguration::from_xml(xml).unwrap();
+        assert_eq!(restarted.configuration("photos").await.unwrap(), expected);
+        let (_, body) = call(&router, "GET", "/photos?notification", "").await;
+        assert_eq!(NotificationConfiguration::from_xml(&body).unwrap(), expected);
+
+        let rejected = [
+            ("<QueueConfiguration><Queue>arn:aws:sqs:::elsewhere</Queue>", "InvalidArgument"),
+            ("<QueueConfiguration><Queue>capture</Queue><Event>s3:ObjectRestore:*</Event>", "InvalidArgument"),
+            ("<LambdaFunctionConfiguration><LambdaFunction>capture</LambdaFunction>", "LambdaFunctionConfiguration"),
+            ("<QueueConfiguration><Queue>capture", "MalformedXML"),
+        ];
+        for (destination, code) in rejected {
+            let element = destination[1..destination.find('>').unwrap()].to_string();
+            let xml = format!("<NotificationConfiguration>{}</{}></NotificationConfiguration>", destination, element);
+            let (status, body) = call(&router, "PUT", "/photos?notification", &xml).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{xml}");
+            assert!(body.contains(code), "{xml}: {body}");
+        }
+        assert_eq!(restarted.configuration("photos").await.unwrap(), expected);
+
+        // An empty configuration turns the bucket's notifications off
+        let empty = "<NotificationConfiguration/>";
+        assert_eq!(call(&router, "PUT", "/photos?notification", empty).await.0, StatusCode::OK);
+        assert!(notifier.bucket_configuration("photos").await.unwrap().unwrap().rules.is_empty());
+    }
+}
@@ -0,0 +1,125 @@
+//! Webhook event destination
+//!
+//! POSTs each notification as JSON to a configured URL, with a bearer
+//! token when one is set. Any 2xx response counts as delivered; other
+//! responses and connection failures are retried by the dispatcher.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use reqwest::header::CONTENT_TYPE;
+use std::time::Duration;
+
+use crate::config::{Secret, WebhookConfig};
+use crate::events::{Event, EventSink};
+
+/// Destination POSTing events to a URL
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url: String,
+    auth_token: Option<Secret>,
+}
+
+impl WebhookSink {
+    pub fn new(config: &WebhookConfig) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()
+            .map_err(|e| format!("Failed to create the client of webhook {}: {}", config.id, e))?;
+        Ok(Self {
+            client,
+            url: config.url.clone(),
+            auth_token: config.auth_token.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl EventSink for WebhookSink {
+    async fn send(&self, _event: &Event, payload: Bytes) -> Result<(), String> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(payload);
+        if let Some(token) = &self.auth_token {
+            request = request.bearer_auth(token.expose());
+        }
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("webhook answered {}", response.status()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::Router;
+    use chrono::Utc;
+    use std::sync::{Arc, Mutex};
+
+    use crate::events::EventName;
+
+    #[tokio::test]
+    async fn test_posts_events() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let app = {
+            let received = received.clone();
+            Router::new().route(
+                "/hook",
+                post(move |headers: HeaderMap, body: Bytes| async move {
+                    let header = |name| headers.get(name).map(|v| v.to_str().unwrap().to_string());
+                    received
+                        .lock()
+                        .unwrap()
+                        .push((header("authorization"), header("content-type"), body));
+                    StatusCode::NO_CONTENT
+                }),
+            )
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let config = |path: &str| WebhookConfig {
+            id: "hook".to_string(),
+            url: format!("http://{}{}", address, path),
+            events: Vec::new(),
+            buckets: Vec::new(),
+            prefix: String::new(),
+            suffix: String::new(),
+            auth_token: Some("token".into()),
+            timeout_ms: 5000,
+        };
+        let event = Event {
+            name: EventName::Put,
+            time: Utc::now(),
+            bucket: "photos".to_string(),
+            key: "cat.jpg".to_string(),
+            size: Some(5),
+            etag: None,
+            sequencer: "0".to_string(),
+            principal: "anonymous".to_string(),
+            source_ip: None,
+            request_id: None,
+        };
+
+        let sink = WebhookSink::new(&config("/hook")).unwrap();
+        sink.send(&event, event.payload("hook")).await.unwrap();
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 1);
+        let (authorization, content_type, body) = &received[0];
+        assert_eq!(authorization.as_deref(), Some("Bearer token"));
+        assert_eq!(content_type.as_deref(), Some("application/json"));
+        assert_eq!(body, &event.payload("hook"));
+
+        // Other responses fail the delivery
+        let sink = WebhookSink::new(&config("/missing")).unwrap();
+        let error = sink.send(&event, event.payload("hook")).await.unwrap_err();
+        assert!(error.contains("404"), "{error}");
+    }
+}
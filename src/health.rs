@@ -0,0 +1,226 @@
+//! Component health checks for the deep health endpoint
+//!
+//! Each subsystem worth reporting on implements `HealthCheck`. The deep
+//! health endpoint runs every registered check concurrently and reports
+//! the status and latency of each one. Only critical components decide the
+//! overall result; non-critical ones are reported but cannot fail it.
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::s3;
+use crate::storage::{BucketRegistry, StorageBackend};
+
+/// A component reported by the deep health endpoint
+#[async_trait]
+pub trait HealthCheck: Send + Sync {
+    /// Component name shown in the report
+    fn name(&self) -> String;
+
+    /// Whether a failure of this component fails the overall check
+    fn critical(&self) -> bool {
+        true
+    }
+
+    /// Check the component, describing the problem on failure
+    async fn check(&self) -> Result<(), String>;
+}
+
+/// Connectivity of the backend serving a bucket
+///
+/// Lists at most one object, or HEADs the sentinel key when one is set.
+pub struct BackendHealth {
+    bucket: String,
+    backend: Arc<dyn StorageBackend>,
+    sentinel_key: Option<String>,
+}
+
+impl BackendHealth {
+    pub fn new(bucket: impl Into<String>, backend: Arc<dyn StorageBackend>, sentinel_key: Option<String>) -> Self {
+        Self {
+            bucket: bucket.into(),
+            backend,
+            sentinel_key,
+        }
+    }
+
+    /// Checks for every backend in `registry`
+    pub fn for_registry(registry: &BucketRegistry, sentinel_key: Option<&str>) -> Vec<Self> {
+        registry
+            .backends()
+            .into_iter()
+            .map(|(bucket, backend)| Self::new(bucket, backend, sentinel_key.map(str::to_string)))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl HealthCheck for BackendHealth {
+    fn name(&self) -> String {
+        format!("backend:{}", self.bucket)
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        let result = match &self.sentinel_key {
+            Some(key) => self.backend.head(&s3::to_storage_key(key)).await.map(|_| ()),
+            None => match self.backend.object_store().list(None).next().await {
+                Some(Err(e)) => Err(e.into()),
+                _ => Ok(()),
+            },
+        };
+        result.map_err(|e| e.to_string())
+    }
+}
+
+/// Result of one component check
+#[derive(Debug, Serialize)]
+pub struct ComponentStatus {
+    pub name: String,
+    pub status: &'static str,
+    pub critical: bool,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Deep health report
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    /// `ok`, `degraded` when only non-critical components fail, or `fail`
+    pub status: &'static str,
+    pub components: Vec<ComponentStatus>,
+}
+
+impl HealthReport {
+    /// Whether every critical component passed
+    pub fn healthy(&self) -> bool {
+        self.status != "fail"
+    }
+}
+
+/// The set of checks reported by the deep health endpoint
+pub struct HealthChecks {
+    checks: Vec<Arc<dyn HealthCheck>>,
+    timeout: Duration,
+}
+
+impl HealthChecks {
+    /// Create an empty set, limiting each check to `timeout`
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            checks: Vec::new(),
+            timeout,
+        }
+    }
+
+    pub fn register(&mut self, check: Arc<dyn HealthCheck>) {
+        self.checks.push(check);
+    }
+
+    /// Run every check concurrently
+    pub async fn report(&self) -> HealthReport {
+        let components =
+            futures::future::join_all(self.checks.iter().map(|check| self.run(check.as_ref()))).await;
+
+        let failed = |critical| {
+            components
+                .iter()
+                .any(|c| c.critical == critical && c.error.is_some())
+        };
+        let status = if failed(true) {
+            "fail"
+        } else if failed(false) {
+            "degraded"
+        } else {
+            "ok"
+        };
+        HealthReport { status, components }
+    }
+
+    async fn run(&self, check: &dyn HealthCheck) -> ComponentStatus {
+        let start = Instant::now();
+        let result = match tokio::time::timeout(self.timeout, check.check()).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("Timed out after {:?}", self.timeout)),
+        };
+        ComponentStatus {
+            name: check.name(),
+            status: if result.is_ok() { "ok" } else { "fail" },
+            critical: check.critical(),
+            latency_ms: start.elapsed().as_secs_f64() * 1000.0,
+            error: result.err(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed {
+        critical: bool,
+        result: Result<(), String>,
+    }
+
+    #[async_trait]
+    impl HealthCheck for Fixed {
+        fn name(&self) -> String {
+            "fixed".to_string()
+        }
+        fn critical(&self) -> bool {
+            self.critical
+        }
+        async fn check(&self) -> Result<(), String> {
+            self.result.clone()
+        }
+    }
+
+    fn checks(components: &[(bool, bool)]) -> HealthChecks {
+        let mut checks = HealthChecks::new(Duration::from_secs(1));
+        for &(critical, ok) in components {
+            let result = if ok { Ok(()) } else { Err("down".to_string()) };
+            checks.register(Arc::new(Fixed { critical, result }));
+        }
+        checks
+    }
+
+    #[tokio::test]
+    async fn test_overall_status() {
+        assert_eq!(checks(&[]).report().await.status, "ok");
+        assert_eq!(checks(&[(true, true), (false, true)]).report().await.status, "ok");
+
+        let degraded = checks(&[(true, true), (false, false)]).report().await;
+        assert_eq!(degraded.status, "degraded");
+        assert!(degraded.healthy());
+        assert_eq!(degraded.components[1].error.as_deref(), Some("down"));
+
+        let failed = checks(&[(true, false), (false, true)]).report().await;
+        assert_eq!(failed.status, "fail");
+        assert!(!failed.healthy());
+    }
+
+    #[tokio::test]
+    async fn test_slow_check_times_out() {
+        struct Slow;
+
+        #[async_trait]
+        impl HealthCheck for Slow {
+            fn name(&self) -> String {
+                "slow".to_string()
+            }
+            async fn check(&self) -> Result<(), String> {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Ok(())
+            }
+        }
+
+        let mut checks = HealthChecks::new(Duration::from_millis(10));
+        checks.register(Arc::new(Slow));
+        let report = checks.report().await;
+        assert_eq!(report.status, "fail");
+        assert!(report.components[0].error.as_ref().unwrap().starts_with("Timed out"));
+    }
+}
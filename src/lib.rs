@@ -0,0 +1,52 @@
+//! S3Proxy - Production-grade S3-compatible proxy for cloud object stores
+//!
+//! Provides an S3-compatible HTTP API that proxies requests to backend
+//! object stores (AWS S3, Azure Blob Storage, Google Cloud Storage) using
+//! managed identity/workload identity for authentication. The `s3proxy-rs`
+//! binary is a thin wrapper around [`run`]; other applications can embed the
+//! proxy with [`S3Proxy::builder`], for example in integration tests:
+//!
+//! ```
+//! use s3proxy_rs::config::{BackendConfig, MemoryConfig};
+//! use s3proxy_rs::S3Proxy;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let proxy = S3Proxy::builder()
+//!     .bind_address("127.0.0.1:0".parse()?)
+//!     .backend_config(BackendConfig::Memory(MemoryConfig::default()))
+//!     .build()
+//!     .await?;
+//!
+//! // Serve on an ephemeral port until shut down
+//! let handle = proxy.bind().await?;
+//! println!("listening on {}", handle.local_addr());
+//! handle.shutdown().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod access_log;
+mod audit_log;
+mod auth;
+pub mod cli;
+pub mod config;
+pub mod errors;
+mod events;
+mod health;
+mod logging;
+mod metrics;
+mod proxy;
+mod request_id;
+mod routes;
+mod s3;
+pub mod server;
+pub mod storage;
+mod telemetry;
+mod version;
+
+pub use config::Config;
+pub use errors::{S3ProxyError, StorageError, StorageErrorClass};
+pub use proxy::{run, S3Proxy, S3ProxyBuilder};
+pub use server::{Server, ServerHandle};
+pub use storage::{create_backend, StorageBackend};
@@ -0,0 +1,67 @@
+//! Runtime-adjustable log filter
+//!
+//! The tracing subscriber's filter sits behind a reload layer so the
+//! admin endpoint can raise verbosity during an incident without a restart.
+
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+/// Handle for replacing the active log filter
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+}
+
+impl LogFilter {
+    pub fn new(handle: reload::Handle<EnvFilter, Registry>) -> Self {
+        Self { handle }
+    }
+
+    /// The active filter directives
+    pub fn current(&self) -> Result<String, String> {
+        self.handle
+            .with_current(|filter| filter.to_string())
+            .map_err(|e| format!("Failed to read log filter: {}", e))
+    }
+
+    /// Replace the active filter, returning the previous directives
+    ///
+    /// Invalid directives are rejected and leave the active filter in place.
+    pub fn set(&self, directives: &str) -> Result<String, SetFilterError> {
+        let filter = EnvFilter::try_new(directives.trim())
+            .map_err(|e| SetFilterError::Invalid(format!("Invalid log filter '{}': {}", directives.trim(), e)))?;
+        let mut previous = String::new();
+        self.handle
+            .modify(|current| {
+                previous = current.to_string();
+                *current = filter;
+            })
+            .map_err(|e| SetFilterError::Reload(format!("Failed to reload log filter: {}", e)))?;
+        Ok(previous)
+    }
+}
+
+/// Why a log filter could not be applied
+#[derive(Debug)]
+pub enum SetFilterError {
+    /// The directives do not parse
+    Invalid(String),
+    /// The subscriber is gone
+    Reload(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_filter() {
+        let (_layer, handle) = reload::Layer::<EnvFilter, Registry>::new(EnvFilter::new("info"));
+        let filter = LogFilter::new(handle);
+
+        assert_eq!(filter.set("s3proxy=debug,object_store=trace").unwrap(), "info");
+        // Directives are reported in the filter's normalized order
+        assert_eq!(filter.current().unwrap(), "object_store=trace,s3proxy=debug");
+
+        assert!(matches!(filter.set("s3proxy=loud"), Err(SetFilterError::Invalid(_))));
+        assert_eq!(filter.current().unwrap(), "object_store=trace,s3proxy=debug");
+    }
+}
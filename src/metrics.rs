@@ -1,22 +1,73 @@
 //! Prometheus metrics for S3Proxy
 //!
 //! Defines metrics for:
-//! - Request counts by method and status
-//! - Request latency
+//! - Build information
+//! - Read-only mode
+//! - Request counts by method, S3 operation, status and optionally bucket
+//! - Request latency, overall and per S3 operation
 //! - Storage operation duration
+//! - Storage errors by backend and error class
+//! - Storage retries by operation and error class
+//! - Circuit breaker state transitions
+//! - Failover backend members serving operations
+//! - Object cache hits, misses, evictions and size by tier
+//! - Objects moved to the trash and purged from it by soft deletes
+//! - Audit records written and lost
+//! - Event notifications delivered, failed and dropped
 //! - Error counts
+//! - Authentication failures
+//! - In-flight requests
+//! - Bytes received and sent
+//! - Backend readiness probes
+//! - Client retries reported by AWS SDKs
+//! - Requests aborted by clients
 
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+use bytes::Bytes;
+use http_body::{Body as _, Frame, SizeHint};
 use lazy_static::lazy_static;
-use prometheus::{Histogram, HistogramOpts, IntCounterVec, Opts, Registry};
+use prometheus::{
+    CounterVec, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry,
+};
+use std::collections::BTreeSet;
+use std::pin::Pin;
+use std::sync::{Arc, Once};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tracing::info;
+
+use crate::routes;
+use crate::version;
 
 lazy_static! {
     /// Registry for all metrics
     pub static ref REGISTRY: Registry = Registry::new();
 
-    /// HTTP request counter by method and status
+    /// Constant 1, labeled with the running build's version and git commit
+    pub static ref BUILD_INFO: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("s3proxy_build_info", "Build information of the running proxy"),
+        &["version", "commit"]
+    )
+    .expect("Failed to create BUILD_INFO metric");
+
+    /// 1 while the proxy rejects S3 writes, otherwise 0
+    pub static ref READ_ONLY: IntGauge = IntGauge::new(
+        "s3proxy_read_only",
+        "Whether the proxy is in read-only mode"
+    )
+    .expect("Failed to create READ_ONLY metric");
+
+    /// HTTP request counter by method, S3 operation, status and bucket
     pub static ref HTTP_REQUESTS: IntCounterVec = IntCounterVec::new(
         Opts::new("s3proxy_http_requests_total", "Total HTTP requests"),
-        &["method", "status"]
+        &["method", "operation", "status", "bucket"]
     )
     .expect("Failed to create HTTP_REQUESTS metric");
 
@@ -30,29 +81,423 @@ lazy_static! {
     )
     .expect("Failed to create HTTP_REQUEST_DURATION metric");
 
-    /// Storage operation counter by operation and status
+    /// HTTP request latency histogram by S3 operation
+    pub static ref HTTP_OPERATION_DURATION: HistogramVec = HistogramVec::new(
+        HistogramOpts::new(
+            "s3proxy_http_operation_duration_seconds",
+            "HTTP request duration in seconds by S3 operation"
+        )
+        .buckets(vec![0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]),
+        &["operation"]
+    )
+    .expect("Failed to create HTTP_OPERATION_DURATION metric");
+
+    /// Storage operation counter by operation, outcome (ok, not_found, error)
+    /// and bucket
     pub static ref STORAGE_OPERATIONS: IntCounterVec = IntCounterVec::new(
         Opts::new("s3proxy_storage_operations_total", "Total storage operations"),
-        &["operation", "status"]
+        &["operation", "status", "bucket"]
     )
     .expect("Failed to create STORAGE_OPERATIONS metric");
 
-    /// Storage operation duration histogram
-    pub static ref STORAGE_OPERATION_DURATION: Histogram = Histogram::with_opts(
+    /// Storage operation duration histogram by operation
+    pub static ref STORAGE_OPERATION_DURATION: HistogramVec = HistogramVec::new(
         HistogramOpts::new(
             "s3proxy_storage_operation_duration_seconds",
             "Storage operation duration in seconds"
         )
-        .buckets(vec![0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0])
+        .buckets(vec![0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]),
+        &["operation"]
     )
     .expect("Failed to create STORAGE_OPERATION_DURATION metric");
+
+    /// Storage backend errors by backend type and error class (not_found,
+    /// permission, precondition, throttled, timeout, other)
+    pub static ref STORAGE_ERRORS: IntCounterVec = IntCounterVec::new(
+        Opts::new("s3proxy_storage_errors_total", "Total storage backend errors"),
+        &["backend", "class"]
+    )
+    .expect("Failed to create STORAGE_ERRORS metric");
+
+    /// Storage operations retried after a transient failure, by operation
+    /// and the error class that was retried
+    pub static ref STORAGE_RETRIES: IntCounterVec = IntCounterVec::new(
+        Opts::new("s3proxy_storage_retries_total", "Total retried storage operations"),
+        &["operation", "class"]
+    )
+    .expect("Failed to create STORAGE_RETRIES metric");
+
+    /// Circuit breaker state transitions by backend type, operation class
+    /// (read, write) and new state (open, half_open, closed)
+    pub static ref CIRCUIT_BREAKER_TRANSITIONS: IntCounterVec = IntCounterVec::new(
+        Opts::new("s3proxy_circuit_breaker_transitions_total", "Total circuit breaker state transitions"),
+        &["backend", "class", "state"]
+    )
+    .expect("Failed to create CIRCUIT_BREAKER_TRANSITIONS metric");
+
+    /// Failover backend operations by operation and the member that served
+    /// them (primary, secondary)
+    pub static ref FAILOVER_OPERATIONS: IntCounterVec = IntCounterVec::new(
+        Opts::new("s3proxy_failover_operations_total", "Total failover backend operations"),
+        &["operation", "served_by"]
+    )
+    .expect("Failed to create FAILOVER_OPERATIONS metric");
+
+    /// Object cache lookups by tier (memory, disk), operation (get, head)
+    /// and result (hit, miss)
+    pub static ref CACHE_REQUESTS: IntCounterVec = IntCounterVec::new(
+        Opts::new("s3proxy_cache_requests_total", "Total object cache lookups"),
+        &["tier", "operation", "result"]
+    )
+    .expect("Failed to create CACHE_REQUESTS metric");
+
+    /// Objects evicted from a cache tier to make room for others
+    pub static ref CACHE_EVICTIONS: IntCounterVec = IntCounterVec::new(
+        Opts::new("s3proxy_cache_evictions_total", "Total objects evicted from the object cache"),
+        &["tier"]
+    )
+    .expect("Failed to create CACHE_EVICTIONS metric");
+
+    /// Objects soft deletes moved to the trash (`trashed`) or deleted from
+    /// it (`purged`)
+    pub static ref SOFT_DELETES: IntCounterVec = IntCounterVec::new(
+        Opts::new("s3proxy_soft_delete_objects_total", "Total objects moved to or purged from the trash"),
+        &["action"]
+    )
+    .expect("Failed to create SOFT_DELETES metric");
+
+    /// Audit records by outcome (`written`, `failed`)
+    pub static ref AUDIT_RECORDS: IntCounterVec = IntCounterVec::new(
+        Opts::new("s3proxy_audit_records_total", "Total audit records written or failed"),
+        &["outcome"]
+    )
+    .expect("Failed to create AUDIT_RECORDS metric");
+
+    /// Object event notifications by destination and outcome (`delivered`,
+    /// `failed` once retries are exhausted, `dropped` when the queue is full)
+    pub static ref EVENT_NOTIFICATIONS: IntCounterVec = IntCounterVec::new(
+        Opts::new("s3proxy_event_notifications_total", "Total object event notifications by outcome"),
+        &["destination", "outcome"]
+    )
+    .expect("Failed to create EVENT_NOTIFICATIONS metric");
+
+    /// Size of the objects currently cached by tier, across all backends
+    pub static ref CACHE_BYTES: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("s3proxy_cache_bytes", "Bytes currently held by the object cache"),
+        &["tier"]
+    )
+    .expect("Failed to create CACHE_BYTES metric");
+
+    /// Rejected authentication attempts by auth mode
+    pub static ref AUTH_FAILURES: IntCounterVec = IntCounterVec::new(
+        Opts::new("s3proxy_auth_failures_total", "Total rejected authentication attempts"),
+        &["mode"]
+    )
+    .expect("Failed to create AUTH_FAILURES metric");
+
+    /// Requests currently being served, including their response bodies and
+    /// while draining on shutdown, by operation class (data, metadata)
+    pub static ref INFLIGHT_REQUESTS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("s3proxy_inflight_requests", "Requests currently being served"),
+        &["class"]
+    )
+    .expect("Failed to create INFLIGHT_REQUESTS metric");
+
+    /// Concurrency slots held by S3 requests, by pool (global, data, metadata)
+    pub static ref CONCURRENCY_IN_USE: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("s3proxy_concurrency_in_use", "Concurrency slots held by requests"),
+        &["pool"]
+    )
+    .expect("Failed to create CONCURRENCY_IN_USE metric");
+
+    /// Requests shed with 503 SlowDown by the pool that was full
+    pub static ref REQUESTS_SHED: IntCounterVec = IntCounterVec::new(
+        Opts::new("s3proxy_requests_shed_total", "Total requests shed over a concurrency limit"),
+        &["pool"]
+    )
+    .expect("Failed to create REQUESTS_SHED metric");
+
+    /// Bandwidth limit in effect by direction (download, upload) and scope
+    /// (global, per_connection); 0 is unlimited
+    pub static ref BANDWIDTH_LIMIT: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("s3proxy_bandwidth_limit_bytes_per_second", "Bandwidth limit in effect"),
+        &["direction", "scope"]
+    )
+    .expect("Failed to create BANDWIDTH_LIMIT metric");
+
+    /// Time bodies waited for the bandwidth limits by direction
+    pub static ref THROTTLED_SECONDS: CounterVec = CounterVec::new(
+        Opts::new("s3proxy_throttled_seconds_total", "Total time bodies waited for bandwidth limits"),
+        &["direction"]
+    )
+    .expect("Failed to create THROTTLED_SECONDS metric");
+
+    /// Usage of the storage quotas by bucket, prefix and unit (bytes, objects)
+    pub static ref QUOTA_USAGE: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("s3proxy_quota_usage", "Usage of storage quotas"),
+        &["bucket", "prefix", "unit"]
+    )
+    .expect("Failed to create QUOTA_USAGE metric");
+
+    /// Storage quotas by bucket, prefix and unit (bytes, objects); 0 is
+    /// unlimited
+    pub static ref QUOTA_LIMIT: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("s3proxy_quota_limit", "Storage quotas"),
+        &["bucket", "prefix", "unit"]
+    )
+    .expect("Failed to create QUOTA_LIMIT metric");
+
+    /// Request body bytes received from clients by S3 operation
+    pub static ref BYTES_RECEIVED: IntCounterVec = IntCounterVec::new(
+        Opts::new("s3proxy_bytes_received_total", "Total request body bytes received"),
+        &["operation"]
+    )
+    .expect("Failed to create BYTES_RECEIVED metric");
+
+    /// Response body bytes sent to clients by S3 operation
+    pub static ref BYTES_SENT: IntCounterVec = IntCounterVec::new(
+        Opts::new("s3proxy_bytes_sent_total", "Total response body bytes sent"),
+        &["operation"]
+    )
+    .expect("Failed to create BYTES_SENT metric");
+
+    /// Backend readiness probes by outcome (ok, error, timeout); cached
+    /// results are not counted
+    pub static ref READINESS_PROBES: IntCounterVec = IntCounterVec::new(
+        Opts::new("s3proxy_readiness_probes_total", "Total backend readiness probes"),
+        &["outcome"]
+    )
+    .expect("Failed to create READINESS_PROBES metric");
+
+    /// Backend readiness probe latency histogram
+    pub static ref READINESS_PROBE_DURATION: Histogram = Histogram::with_opts(
+        HistogramOpts::new(
+            "s3proxy_readiness_probe_duration_seconds",
+            "Backend readiness probe duration in seconds"
+        )
+        .buckets(vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0])
+    )
+    .expect("Failed to create READINESS_PROBE_DURATION metric");
+
+    /// Requests that AWS SDKs mark as a retry of an earlier attempt, by S3
+    /// operation
+    pub static ref CLIENT_RETRIES: IntCounterVec = IntCounterVec::new(
+        Opts::new("s3proxy_client_retries_total", "Total requests retried by clients"),
+        &["operation"]
+    )
+    .expect("Failed to create CLIENT_RETRIES metric");
+
+    /// Requests whose client went away before the response was complete,
+    /// by S3 operation
+    pub static ref CLIENT_ABORTS: IntCounterVec = IntCounterVec::new(
+        Opts::new("s3proxy_client_aborted_requests_total", "Total requests aborted by the client"),
+        &["operation"]
+    )
+    .expect("Failed to create CLIENT_ABORTS metric");
+
+    /// Refreshes of temporary backend credentials, e.g. of an assumed role,
+    /// by outcome (ok, error)
+    pub static ref CREDENTIAL_REFRESHES: IntCounterVec = IntCounterVec::new(
+        Opts::new("s3proxy_credential_refreshes_total", "Total refreshes of temporary backend credentials"),
+        &["outcome"]
+    )
+    .expect("Failed to create CREDENTIAL_REFRESHES metric");
 }
 
 /// Initialize metrics and register with the global registry
+///
+/// Only the first call registers; an embedding application may start
+/// several proxies in one process.
 pub fn init_metrics() {
-    REGISTRY.register(Box::new(HTTP_REQUESTS.clone())).unwrap();
-    REGISTRY.register(Box::new(HTTP_REQUEST_DURATION.clone())).unwrap();
-    REGISTRY.register(Box::new(STORAGE_OPERATIONS.clone())).unwrap();
-    REGISTRY.register(Box::new(STORAGE_OPERATION_DURATION.clone())).unwrap();
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        BUILD_INFO.with_label_values(&[version::VERSION, version::COMMIT]).set(1);
+        REGISTRY.register(Box::new(BUILD_INFO.clone())).unwrap();
+        REGISTRY.register(Box::new(READ_ONLY.clone())).unwrap();
+        REGISTRY.register(Box::new(HTTP_REQUESTS.clone())).unwrap();
+        REGISTRY.register(Box::new(HTTP_REQUEST_DURATION.clone())).unwrap();
+        REGISTRY.register(Box::new(HTTP_OPERATION_DURATION.clone())).unwrap();
+        REGISTRY.register(Box::new(STORAGE_OPERATIONS.clone())).unwrap();
+        REGISTRY.register(Box::new(STORAGE_OPERATION_DURATION.clone())).unwrap();
+        REGISTRY.register(Box::new(STORAGE_ERRORS.clone())).unwrap();
+        REGISTRY.register(Box::new(STORAGE_RETRIES.clone())).unwrap();
+        REGISTRY.register(Box::new(CIRCUIT_BREAKER_TRANSITIONS.clone())).unwrap();
+        REGISTRY.register(Box::new(FAILOVER_OPERATIONS.clone())).unwrap();
+        REGISTRY.register(Box::new(CACHE_REQUESTS.clone())).unwrap();
+        REGISTRY.register(Box::new(CACHE_EVICTIONS.clone())).unwrap();
+        REGISTRY.register(Box::new(CACHE_BYTES.clone())).unwrap();
+        REGISTRY.register(Box::new(SOFT_DELETES.clone())).unwrap();
+        REGISTRY.register(Box::new(AUDIT_RECORDS.clone())).unwrap();
+        REGISTRY.register(Box::new(EVENT_NOTIFICATIONS.clone())).unwrap();
+        REGISTRY.register(Box::new(AUTH_FAILURES.clone())).unwrap();
+        REGISTRY.register(Box::new(INFLIGHT_REQUESTS.clone())).unwrap();
+        REGISTRY.register(Box::new(CONCURRENCY_IN_USE.clone())).unwrap();
+        REGISTRY.register(Box::new(REQUESTS_SHED.clone())).unwrap();
+        REGISTRY.register(Box::new(BANDWIDTH_LIMIT.clone())).unwrap();
+        REGISTRY.register(Box::new(THROTTLED_SECONDS.clone())).unwrap();
+        REGISTRY.register(Box::new(QUOTA_USAGE.clone())).unwrap();
+        REGISTRY.register(Box::new(QUOTA_LIMIT.clone())).unwrap();
+        REGISTRY.register(Box::new(BYTES_RECEIVED.clone())).unwrap();
+        REGISTRY.register(Box::new(BYTES_SENT.clone())).unwrap();
+        REGISTRY.register(Box::new(READINESS_PROBES.clone())).unwrap();
+        REGISTRY.register(Box::new(READINESS_PROBE_DURATION.clone())).unwrap();
+        REGISTRY.register(Box::new(CLIENT_RETRIES.clone())).unwrap();
+        REGISTRY.register(Box::new(CLIENT_ABORTS.clone())).unwrap();
+        REGISTRY.register(Box::new(CREDENTIAL_REFRESHES.clone())).unwrap();
+    });
+}
+
+/// Label value for buckets that are not configured
+pub const UNKNOWN_BUCKET: &str = "unknown";
+
+/// Bucket label values for per-bucket metrics
+///
+/// Label values are limited to the configured bucket names, so scanners
+/// probing random bucket names cannot create new series. When per-bucket
+/// labels are disabled the label is empty.
+#[derive(Debug, Clone, Default)]
+pub struct BucketLabels {
+    enabled: bool,
+    known: BTreeSet<String>,
+}
+
+impl BucketLabels {
+    pub fn new<I, S>(enabled: bool, known: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            enabled,
+            known: known.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Label value for a bucket name
+    pub fn label<'a>(&'a self, bucket: &'a str) -> &'a str {
+        if !self.enabled || bucket.is_empty() {
+            ""
+        } else if self.known.contains(bucket) {
+            bucket
+        } else {
+            UNKNOWN_BUCKET
+        }
+    }
+}
+
+/// Middleware recording HTTP_REQUESTS, the request duration histograms,
+/// the byte counters and CLIENT_ABORTS
+///
+/// Health, readiness and metrics requests are not recorded, so probes and
+/// scrapes don't drown out S3 traffic. Error responses are recorded with
+/// their final status. Bytes are counted as the bodies are read and
+/// written, so aborted transfers count what was actually moved. A request
+/// dropped before its response body ended, because the client went away,
+/// counts as aborted instead.
+pub async fn record_http(
+    State(buckets): State<Arc<BucketLabels>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if routes::is_system_path(request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    let method = request.method().clone();
+    let operation = routes::operation_name(&method, request.uri().path(), request.uri().query());
+    let bucket = request.uri().path().trim_start_matches('/').split('/').next().unwrap_or("");
+    let bucket = buckets.label(bucket).to_string();
+    let received = BYTES_RECEIVED.with_label_values(&[operation]);
+    let request = request.map(|body| Body::new(CountingBody::new(body, received)));
+    let start = Instant::now();
+    let mut abort = AbortGuard(Some(operation));
+    let response = next.run(request).await;
+
+    // No body is sent for these, so the response is complete
+    if method == Method::HEAD || response.body().is_end_stream() {
+        abort.disarm();
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    HTTP_REQUEST_DURATION.observe(elapsed);
+    HTTP_OPERATION_DURATION.with_label_values(&[operation]).observe(elapsed);
+    HTTP_REQUESTS
+        .with_label_values(&[method.as_str(), operation, response.status().as_str(), &bucket])
+        .inc();
+    let sent = BYTES_SENT.with_label_values(&[operation]);
+    response.map(|body| {
+        Body::new(CountingBody {
+            abort: Some(abort),
+            ..CountingBody::new(body, sent)
+        })
+    })
+}
+
+/// Counts the request of an operation as aborted by the client when
+/// dropped before being disarmed
+struct AbortGuard(Option<&'static str>);
+
+impl AbortGuard {
+    fn disarm(&mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for AbortGuard {
+    fn drop(&mut self) {
+        if let Some(operation) = self.0 {
+            CLIENT_ABORTS.with_label_values(&[operation]).inc();
+            info!(operation, "Client disconnected before the response was complete");
+        }
+    }
+}
+
+/// Body wrapper adding the size of each data frame to a counter, and
+/// disarming the abort guard of a response once it has been sent
+struct CountingBody {
+    inner: Body,
+    counter: IntCounter,
+    abort: Option<AbortGuard>,
+}
+
+impl CountingBody {
+    fn new(inner: Body, counter: IntCounter) -> Self {
+        Self {
+            inner,
+            counter,
+            abort: None,
+        }
+    }
+}
+
+impl http_body::Body for CountingBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let frame = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &frame {
+            if let Some(data) = frame.data_ref() {
+                self.counter.inc_by(data.len() as u64);
+            }
+        }
+        // Failing bodies are the server's doing, not the client's
+        if matches!(frame, Poll::Ready(None | Some(Err(_)))) || self.inner.is_end_stream() {
+            if let Some(abort) = &mut self.abort {
+                abort.disarm();
+            }
+        }
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
 }
 
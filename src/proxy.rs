@@ -0,0 +1,284 @@
+//! Embedding API
+//!
+//! [`S3Proxy::builder`] assembles a proxy from a [`Config`], individual
+//! settings, and either a constructed backend or the configuration to build
+//! one. [`run`] is what the `s3proxy-rs` binary does with its configuration:
+//! it also installs the global tracing subscriber and serves until SIGTERM
+//! or Ctrl+C, so it is only suited to owning the whole process.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::{error, info};
+use tracing_subscriber::{layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Layer};
+
+use crate::config::{BackendConfig, Config};
+use crate::logging::LogFilter;
+use crate::metrics::{self, UNKNOWN_BUCKET};
+use crate::server::{self, Server, ServerHandle};
+use crate::storage::{self, BucketRegistry, MetricsBackend, PrefixedBackend, StorageBackend};
+use crate::{telemetry, version};
+
+/// Backend type label of storage metrics for a backend passed to
+/// [`S3ProxyBuilder::backend`]
+const CUSTOM_BACKEND: &str = "custom";
+
+/// An S3-compatible proxy ready to serve
+pub struct S3Proxy {
+    server: Server,
+}
+
+impl S3Proxy {
+    /// Start building a proxy
+    pub fn builder() -> S3ProxyBuilder {
+        S3ProxyBuilder::default()
+    }
+
+    /// Serve until `shutdown` completes, then drain in-flight requests for
+    /// up to `server.shutdown_grace_secs`
+    pub async fn start<F>(&self, shutdown: F) -> Result<(), Box<dyn std::error::Error>>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.server.start(shutdown).await
+    }
+
+    /// Bind the listener and serve in the background; see [`Server::bind`]
+    pub async fn bind(&self) -> Result<ServerHandle, Box<dyn std::error::Error>> {
+        self.server.bind().await
+    }
+
+    /// Switch read-only mode; see [`Server::set_read_only`]
+    pub fn set_read_only(&self, enabled: bool) {
+        self.server.set_read_only(enabled);
+    }
+}
+
+/// Builder for [`S3Proxy`]
+///
+/// Settings given individually override the corresponding [`Config`]
+/// fields. A backend given with [`backend`](Self::backend) serves every
+/// bucket name and takes precedence over configured backends.
+#[derive(Default)]
+pub struct S3ProxyBuilder {
+    config: Option<Config>,
+    bind_address: Option<SocketAddr>,
+    prefix: Option<String>,
+    backend_config: Option<BackendConfig>,
+    backend: Option<Arc<dyn StorageBackend>>,
+    log_filter: Option<LogFilter>,
+}
+
+impl S3ProxyBuilder {
+    /// Start from `config` instead of the defaults
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Address to listen on (default: 0.0.0.0:8080)
+    pub fn bind_address(mut self, bind_address: SocketAddr) -> Self {
+        self.bind_address = Some(bind_address);
+        self
+    }
+
+    /// Path prefix for all objects
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Build the default backend from `backend_config`
+    pub fn backend_config(mut self, backend_config: BackendConfig) -> Self {
+        self.backend_config = Some(backend_config);
+        self
+    }
+
+    /// Serve every bucket name from `backend`
+    pub fn backend(mut self, backend: Arc<dyn StorageBackend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Allow changing the log filter through `PUT /admin/loglevel`
+    pub(crate) fn log_filter(mut self, log_filter: LogFilter) -> Self {
+        self.log_filter = Some(log_filter);
+        self
+    }
+
+    /// Build the backends and the server
+    ///
+    /// Fails when the configuration is invalid or a backend cannot be
+    /// created.
+    pub async fn build(self) -> Result<S3Proxy, Box<dyn std::error::Error>> {
+        let mut config = self.config.unwrap_or_default();
+        if let Some(bind_address) = self.bind_address {
+            config.server.bind_address = bind_address.into();
+        }
+        if let Some(prefix) = self.prefix {
+            config.prefix = Some(prefix);
+        }
+        if let Some(backend_config) = self.backend_config {
+            config.backend = Some(backend_config);
+        }
+
+        metrics::init_metrics();
+        let registry = match self.backend {
+            Some(mut backend) => {
+                if let Some(prefix) = &config.prefix {
+                    backend = Arc::new(PrefixedBackend::new(backend, prefix));
+                }
+                backend = storage::protect_objects(backend, &config);
+                BucketRegistry::single(Arc::new(MetricsBackend::new(backend, CUSTOM_BACKEND, UNKNOWN_BUCKET)))
+            }
+            None => storage::create_registry(&config).await?,
+        };
+
+        let mut server = Server::new(config, Arc::new(registry))?;
+        if let Some(log_filter) = self.log_filter {
+            server = server.with_log_filter(log_filter);
+        }
+        Ok(S3Proxy { server })
+    }
+}
+
+/// Run the proxy process with `config`
+///
+/// Installs the global tracing subscriber (JSON output, filtered by
+/// `log_level` and, when configured, exported over OTLP), serves until
+/// SIGTERM or Ctrl+C and flushes pending spans on the way out.
+pub async fn run(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    // Initialize tracing with JSON output for structured logging; the
+    // filter can be replaced at runtime through the admin endpoint. Spans
+    // are also exported over OTLP when an endpoint is configured.
+    let filter = EnvFilter::try_new(&config.log_level).map_err(|e| format!("Invalid log_level: {e}"))?;
+    let (filter, filter_handle) = reload::Layer::new(filter);
+    let otel = telemetry::init_tracer(&config.telemetry)?.map(|tracer| {
+        tracing_opentelemetry::layer()
+            .with_tracer(tracer)
+            .with_filter(telemetry::export_filter())
+    });
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().json().with_filter(filter))
+        .with(otel)
+        .init();
+
+    let build = version::build_info();
+    info!(
+        version = build.version,
+        commit = build.commit,
+        build_timestamp = %build.build_timestamp,
+        features = ?build.features,
+        "Starting S3Proxy"
+    );
+
+    info!(config = %config.redacted(), "Configuration loaded");
+
+    let bind_address = config.server.bind_address.clone();
+    let export_spans = config.telemetry.otlp_endpoint.is_some();
+    let proxy = S3Proxy::builder()
+        .config(config)
+        .log_filter(LogFilter::new(filter_handle))
+        .build()
+        .await?;
+    info!("Storage backends initialized");
+
+    // Handle graceful shutdown on SIGTERM (Kubernetes) or Ctrl+C
+    let shutdown_signal = async {
+        server::shutdown::signal().await;
+    };
+
+    info!("Server starting on {}", bind_address);
+    let result = proxy.start(shutdown_signal).await;
+    if export_spans {
+        telemetry::shutdown().await;
+    }
+    if let Err(e) = result {
+        error!(error = %e, "Server error");
+        return Err(e);
+    }
+
+    info!("Server shutdown complete");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MockBackend, MockOperation};
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_builder_serves_given_backend_under_prefix() {
+        let mock = Arc::new(MockBackend::new());
+        let proxy = S3Proxy::builder()
+            .backend(mock.clone())
+            .prefix("tenant")
+            .build()
+            .await
+            .unwrap();
+
+        let response = proxy
+            .server
+            .build_router()
+            .oneshot(Request::put("/bucket/a.txt").body(Body::from("data")).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(mock.calls_of(MockOperation::Put)[0].path, "tenant/a.txt");
+    }
+
+    #[tokio::test]
+    async fn test_write_once_refuses_overwrites_and_deletes() {
+        let config = Config {
+            write_once: true,
+            ..Config::default()
+        };
+        let proxy = S3Proxy::builder()
+            .config(config)
+            .backend(Arc::new(MockBackend::new()))
+            .build()
+            .await
+            .unwrap();
+        let router = proxy.server.build_router();
+        let send = |method: &str, body: &'static str| {
+            let request = Request::builder().method(method).uri("/bucket/a.txt").body(Body::from(body)).unwrap();
+            router.clone().oneshot(request)
+        };
+
+        assert_eq!(send("PUT", "first").await.unwrap().status(), StatusCode::OK);
+        let response = send("PUT", "second").await.unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<Code>PreconditionFailed</Code>"));
+        assert_eq!(send("DELETE", "").await.unwrap().status(), StatusCode::FORBIDDEN);
+
+        // Streamed uploads and copies only create objects as well
+        let chunks = futures::stream::iter([Ok::<_, std::io::Error>("third")]);
+        let request = Request::put("/bucket/a.txt").body(Body::from_stream(chunks)).unwrap();
+        assert_eq!(router.clone().oneshot(request).await.unwrap().status(), StatusCode::PRECONDITION_FAILED);
+        let copy = |key: &str| {
+            let request = Request::put(format!("/bucket/{key}"))
+                .header(crate::s3::COPY_SOURCE, "/bucket/a.txt")
+                .body(Body::empty())
+                .unwrap();
+            router.clone().oneshot(request)
+        };
+        let response = copy("b.txt").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<CopyObjectResult>"));
+        assert_eq!(copy("b.txt").await.unwrap().status(), StatusCode::PRECONDITION_FAILED);
+        let request = Request::get("/bucket/b.txt").body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, "first");
+    }
+
+    #[tokio::test]
+    async fn test_builder_requires_a_backend() {
+        assert!(S3Proxy::builder().build().await.is_err());
+    }
+}
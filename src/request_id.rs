@@ -0,0 +1,168 @@
+//! S3 request IDs
+//!
+//! Every request gets an ID returned in `x-amz-request-id`, recorded on the
+//! request span and carried in the `<RequestId>` element of error
+//! responses. The extended ID in `x-amz-id-2` and `<HostId>` additionally
+//! names the proxy instance that served the request. Errors are rendered
+//! without access to the request, so the middleware also makes the ID
+//! available to the task serving it.
+//!
+//! AWS SDKs identify retries of one logical call with the
+//! `amz-sdk-invocation-id` and `amz-sdk-request` headers; both are recorded
+//! on the request span, and retried attempts are counted in
+//! `s3proxy_client_retries_total`.
+
+use axum::body::Body;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Request, Response};
+use axum::middleware::Next;
+use base64::Engine;
+use lazy_static::lazy_static;
+use tracing::Span;
+use uuid::Uuid;
+
+use crate::metrics::CLIENT_RETRIES;
+use crate::routes;
+
+/// Response header carrying the request ID
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-amz-request-id");
+
+/// Response header carrying the extended request ID
+pub static HOST_ID_HEADER: HeaderName = HeaderName::from_static("x-amz-id-2");
+
+lazy_static! {
+    /// Name of this proxy instance: the pod or host name when known,
+    /// otherwise generated at startup
+    static ref INSTANCE: String = std::env::var("HOSTNAME")
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| Uuid::new_v4().simple().to_string());
+}
+
+tokio::task_local! {
+    static CURRENT: RequestId;
+}
+
+/// Unique ID of one request, in the 16 hex digit form S3 uses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    pub fn new() -> Self {
+        let mut id = Uuid::new_v4().simple().to_string();
+        id.truncate(16);
+        Self(id.to_uppercase())
+    }
+
+    /// ID of the request being served by the current task, if any
+    pub fn current() -> Option<Self> {
+        CURRENT.try_with(Clone::clone).ok()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Extended ID naming the instance that served the request
+    ///
+    /// Base64 of `<instance>:<request id>`, opaque to clients like S3's.
+    pub fn host_id(&self) -> String {
+        base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", *INSTANCE, self.0))
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Assign the request its ID and return it on the response
+///
+/// Must run inside the request span so the ID is recorded on it.
+pub async fn assign(mut request: Request<Body>, next: Next) -> Response<Body> {
+    let id = RequestId::new();
+    let span = Span::current();
+    span.record("request_id", id.as_str());
+    record_sdk_headers(&request, &span);
+    request.extensions_mut().insert(id.clone());
+
+    let mut response = CURRENT.scope(id.clone(), next.run(request)).await;
+    let headers = response.headers_mut();
+    headers.insert(
+        REQUEST_ID_HEADER.clone(),
+        HeaderValue::from_str(id.as_str()).expect("hex request ID is a valid header value"),
+    );
+    headers.insert(
+        HOST_ID_HEADER.clone(),
+        HeaderValue::from_str(&id.host_id()).expect("base64 host ID is a valid header value"),
+    );
+    response
+}
+
+/// Record the SDK's invocation ID and attempt number, counting retries
+fn record_sdk_headers(request: &Request<Body>, span: &Span) {
+    let headers = request.headers();
+    if let Some(invocation) = header_str(headers, "amz-sdk-invocation-id") {
+        span.record("sdk_invocation_id", invocation);
+    }
+    let Some(attempt) = header_str(headers, "amz-sdk-request").and_then(sdk_attempt) else {
+        return;
+    };
+    span.record("sdk_attempt", attempt);
+    if attempt > 1 {
+        let uri = request.uri();
+        CLIENT_RETRIES
+            .with_label_values(&[routes::operation_name(request.method(), uri.path(), uri.query())])
+            .inc();
+    }
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Attempt number from an `amz-sdk-request` header (`attempt=2; max=3`)
+fn sdk_attempt(value: &str) -> Option<u32> {
+    value
+        .split(';')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == "attempt")
+        .and_then(|(_, attempt)| attempt.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_current_only_inside_scope() {
+        assert!(RequestId::current().is_none());
+
+        let id = RequestId::new();
+        assert_eq!(id.as_str().len(), 16);
+        assert!(id.as_str().chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_lowercase()));
+        let current = CURRENT.scope(id.clone(), async { RequestId::current() }).await;
+        assert_eq!(current, Some(id));
+    }
+
+    #[test]
+    fn test_host_id_names_instance_and_request() {
+        let id = RequestId::new();
+        let decoded = base64::engine::general_purpose::STANDARD.decode(id.host_id()).unwrap();
+        assert_eq!(String::from_utf8(decoded).unwrap(), format!("{}:{}", *INSTANCE, id));
+    }
+
+    #[test]
+    fn test_sdk_attempt() {
+        assert_eq!(sdk_attempt("attempt=1; max=3"), Some(1));
+        assert_eq!(sdk_attempt("ttl=20240101T000000Z; attempt=3; max=3"), Some(3));
+        assert_eq!(sdk_attempt("max=3"), None);
+        assert_eq!(sdk_attempt("attempt=x"), None);
+    }
+}
@@ -0,0 +1,278 @@
+//! Query subresource dispatching
+//!
+//! S3 multiplexes operations onto the bucket and object paths through query
+//! subresources such as `?tagging` or `?uploadId=`, so the handlers routed
+//! here pick the operation by subresource. Subresources no operation serves
+//! answer `NotImplemented` rather than being taken for a plain GetObject,
+//! PutObject or ListObjects. CopyObject requests are PUTs told apart by
+//! their `x-amz-copy-source` header.
+
+use axum::{
+    body::Body,
+    extract::{rejection::QueryRejection, Extension, Path, Query, RawQuery, State},
+    http::{HeaderMap, Method},
+    response::Response,
+};
+use std::sync::Arc;
+
+use super::handlers;
+use crate::auth::Principal;
+use crate::config::Config;
+use crate::errors::{Result, S3ProxyError};
+use crate::events::Notifier;
+use crate::routes::operation::has_param;
+use crate::routes::ListObjectsQuery;
+use crate::s3;
+use crate::storage::BucketRegistry;
+
+/// Query parameters selecting an S3 subresource, by precedence: `uploadId`
+/// goes first since `partNumber` only picks a part within the upload
+///
+/// `versionId` is left out, as some clients send `versionId=null` for the
+/// current version.
+const SUBRESOURCES: &[&str] = &[
+    "uploadId",
+    "uploads",
+    "partNumber",
+    "accelerate",
+    "acl",
+    "analytics",
+    "attributes",
+    "cors",
+    "delete",
+    "encryption",
+    "intelligent-tiering",
+    "inventory",
+    "legal-hold",
+    "lifecycle",
+    "location",
+    "logging",
+    "metrics",
+    "notification",
+    "object-lock",
+    "ownershipControls",
+    "policy",
+    "policyStatus",
+    "publicAccessBlock",
+    "replication",
+    "requestPayment",
+    "restore",
+    "retention",
+    "select",
+    "tagging",
+    "torrent",
+    "versioning",
+    "versions",
+    "website",
+];
+
+/// The subresource the query string selects, if any
+pub fn subresource(query: Option<&str>) -> Option<&'static str> {
+    SUBRESOURCES.iter().copied().find(|name| has_param(query, name))
+}
+
+fn not_implemented(subresource: &str) -> S3ProxyError {
+    S3ProxyError::NotImplemented(format!("The {} subresource is not implemented", subresource))
+}
+
+/// GET /{bucket}, and HEAD
+pub async fn get_bucket(
+    State(registry): State<Arc<BucketRegistry>>,
+    Path(bucket): Path<String>,
+    RawQuery(query): RawQuery,
+    params: std::result::Result<Query<ListObjectsQuery>, QueryRejection>,
+    principal: Option<Extension<Principal>>,
+    notifier: Option<Extension<Arc<Notifier>>>,
+    config: Option<Extension<Arc<Config>>>,
+) -> Result<Response> {
+    match subresource(query.as_deref()) {
+        None => handlers::list_objects(State(registry), Path(bucket), params, principal, config).await,
+        Some("notification") => handlers::get_bucket_notification(State(registry), Path(bucket), notifier).await,
+        Some(other) => Err(not_implemented(other)),
+    }
+}
+
+/// PUT /{bucket}
+pub async fn put_bucket(
+    State(registry): State<Arc<BucketRegistry>>,
+    Path(bucket): Path<String>,
+    RawQuery(query): RawQuery,
+    notifier: Option<Extension<Arc<Notifier>>>,
+    body: Body,
+) -> Result<Response> {
+    match subresource(query.as_deref()) {
+        None => handlers::create_bucket(State(registry), Path(bucket)).await,
+        Some("notification") => {
+            handlers::put_bucket_notification(State(registry), Path(bucket), notifier, body).await
+        }
+        Some(other) => Err(not_implemented(other)),
+    }
+}
+
+/// DELETE /{bucket}
+pub async fn delete_bucket(
+    State(registry): State<Arc<BucketRegistry>>,
+    Path(bucket): Path<String>,
+    RawQuery(query): RawQuery,
+) -> Result<Response> {
+    match subresource(query.as_deref()) {
+        None => handlers::delete_bucket(State(registry), Path(bucket)).await,
+        Some(other) => Err(not_implemented(other)),
+    }
+}
+
+/// GET /{bucket}/{key}
+pub async fn get_object(
+    State(registry): State<Arc<BucketRegistry>>,
+    Path(path): Path<(String, String)>,
+    RawQuery(query): RawQuery,
+) -> Result<Response> {
+    match subresource(query.as_deref()) {
+        None => handlers::get_object(State(registry), Path(path)).await,
+        Some(other) => Err(not_implemented(other)),
+    }
+}
+
+/// PUT /{bucket}/{key}
+pub async fn put_object(
+    State(registry): State<Arc<BucketRegistry>>,
+    Path(path): Path<(String, String)>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+    config: Option<Extension<Arc<Config>>>,
+    body: Body,
+) -> Result<Response> {
+    match subresource(query.as_deref()) {
+        // A copy has no body: served as a PutObject, it would empty the key
+        None if headers.contains_key(s3::COPY_SOURCE) => {
+            handlers::copy_object(State(registry), Path(path), headers).await
+        }
+        None => handlers::put_object(State(registry), Path(path), headers, config, body).await,
+        Some(other) => Err(not_implemented(other)),
+    }
+}
+
+/// DELETE /{bucket}/{key}
+pub async fn delete_object(
+    State(registry): State<Arc<BucketRegistry>>,
+    Path(path): Path<(String, String)>,
+    RawQuery(query): RawQuery,
+) -> Result<Response> {
+    match subresource(query.as_deref()) {
+        None => handlers::delete_object(State(registry), Path(path)).await,
+        Some(other) => Err(not_implemented(other)),
+    }
+}
+
+/// HEAD /{bucket}/{key}
+pub async fn head_object(
+    State(registry): State<Arc<BucketRegistry>>,
+    Path(path): Path<(String, String)>,
+    RawQuery(query): RawQuery,
+) -> Result<Response> {
+    match subresource(query.as_deref()) {
+        None => handlers::head_object(State(registry), Path(path)).await,
+        Some(other) => Err(not_implemented(other)),
+    }
+}
+
+/// Answer a method the route does not support
+///
+/// S3 operations sent with POST, such as DeleteObjects and multipart
+/// uploads, are recognized by subresource and answer `NotImplemented`. The
+/// router adds the `Allow` header listing the methods it does support.
+pub async fn method_not_allowed(method: Method, query: Option<String>, resource_type: &'static str) -> S3ProxyError {
+    match subresource(query.as_deref()) {
+        Some(name) if method == Method::POST => not_implemented(name),
+        _ => S3ProxyError::MethodNotAllowed {
+            method: method.to_string(),
+            resource_type,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    use crate::routes::create_router;
+    use crate::storage::LocalBackend;
+
+    #[test]
+    fn test_subresource_precedence() {
+        assert_eq!(subresource(None), None);
+        assert_eq!(subresource(Some("list-type=2&prefix=a")), None);
+        assert_eq!(subresource(Some("tagging")), Some("tagging"));
+        assert_eq!(subresource(Some("partNumber=1&uploadId=2")), Some("uploadId"));
+        assert_eq!(subresource(Some("versionId=null")), None);
+        assert_eq!(subresource(Some("taggingx&x=tagging")), None);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_by_subresource() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("key"), "data").unwrap();
+        let router = create_router(Arc::new(BucketRegistry::single(Arc::new(
+            LocalBackend::new(root.path()).unwrap(),
+        ))));
+
+        // Method, URI, expected status and a body marker of the handler or
+        // error answering
+        let cases = [
+            ("GET", "/bucket", StatusCode::OK, "<ListBucketResult"),
+            ("GET", "/bucket?list-type=2&prefix=k", StatusCode::OK, "<ListBucketResult"),
+            ("GET", "/bucket?notification", StatusCode::OK, "<NotificationConfiguration"),
+            ("GET", "/bucket?acl", StatusCode::NOT_IMPLEMENTED, "The acl subresource"),
+            ("GET", "/bucket?location", StatusCode::NOT_IMPLEMENTED, "The location subresource"),
+            ("GET", "/bucket?uploads", StatusCode::NOT_IMPLEMENTED, "The uploads subresource"),
+            ("GET", "/bucket?versioning", StatusCode::NOT_IMPLEMENTED, "The versioning subresource"),
+            ("PUT", "/bucket?notification", StatusCode::OK, ""),
+            ("PUT", "/bucket?lifecycle", StatusCode::NOT_IMPLEMENTED, "The lifecycle subresource"),
+            ("DELETE", "/bucket?cors", StatusCode::NOT_IMPLEMENTED, "The cors subresource"),
+            ("POST", "/bucket?delete", StatusCode::NOT_IMPLEMENTED, "The delete subresource"),
+            ("POST", "/bucket", StatusCode::METHOD_NOT_ALLOWED, "<Code>MethodNotAllowed</Code>"),
+            ("GET", "/bucket/key", StatusCode::OK, "data"),
+            ("GET", "/bucket/key?versionId=null", StatusCode::OK, "data"),
+            ("GET", "/bucket/key?tagging", StatusCode::NOT_IMPLEMENTED, "The tagging subresource"),
+            ("GET", "/bucket/key?partNumber=1", StatusCode::NOT_IMPLEMENTED, "The partNumber subresource"),
+            ("GET", "/bucket/key?uploadId=1", StatusCode::NOT_IMPLEMENTED, "The uploadId subresource"),
+            ("HEAD", "/bucket/key?acl", StatusCode::NOT_IMPLEMENTED, ""),
+            ("PUT", "/bucket/key?tagging", StatusCode::NOT_IMPLEMENTED, "The tagging subresource"),
+            ("PUT", "/bucket/key?partNumber=1&uploadId=1", StatusCode::NOT_IMPLEMENTED, "The uploadId subresource"),
+            ("DELETE", "/bucket/key?uploadId=1", StatusCode::NOT_IMPLEMENTED, "The uploadId subresource"),
+            ("POST", "/bucket/key?uploads", StatusCode::NOT_IMPLEMENTED, "The uploads subresource"),
+            ("POST", "/bucket/key?restore", StatusCode::NOT_IMPLEMENTED, "The restore subresource"),
+            ("POST", "/bucket/key", StatusCode::METHOD_NOT_ALLOWED, "<Code>MethodNotAllowed</Code>"),
+            ("PATCH", "/bucket/key?tagging", StatusCode::METHOD_NOT_ALLOWED, "<Code>MethodNotAllowed</Code>"),
+        ];
+        for (method, uri, status, marker) in cases {
+            // Valid for PutBucketNotificationConfiguration, anything for the rest
+            let body = if method == "PUT" { "<NotificationConfiguration/>" } else { "" };
+            let request = Request::builder().method(method).uri(uri).body(Body::from(body)).unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), status, "{method} {uri}");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body = String::from_utf8_lossy(&body);
+            assert!(body.contains(marker), "{method} {uri}: {body}");
+        }
+
+        // CopyObject is a PUT without a subresource, told apart by header:
+        // not taken for a PutObject, even when it asks for what it can't do
+        let request = Request::builder()
+            .method("PUT")
+            .uri("/bucket/key")
+            .header(s3::COPY_SOURCE, "/bucket/other?versionId=3HL4kqtJlcpXroDTDmJ")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("Copying an object version is not implemented"));
+
+        // None of the unsupported writes touched the object
+        assert_eq!(std::fs::read_to_string(root.path().join("key")).unwrap(), "data");
+    }
+}
@@ -2,18 +2,30 @@
 
 use axum::{
     body::Body,
-    extract::{Path, Query, State},
-    http::{HeaderMap, StatusCode},
+    extract::{rejection::QueryRejection, Extension, Path, Query, State},
+    http::{header, HeaderMap, StatusCode, Uri},
     response::{IntoResponse, Response},
+    Json,
 };
-use bytes::Bytes;
+use base64::Engine as _;
+use futures::{StreamExt, TryStreamExt};
+use http_body::Body as _;
+use object_store::{GetOptions, ObjectMeta};
 use prometheus::{Encoder, TextEncoder};
 use std::sync::Arc;
-use tracing::{error, info, instrument};
+use tracing::{error, info, instrument, warn};
 
+use crate::auth::Principal;
+use crate::config::Config;
 use crate::errors::{Result, S3ProxyError};
+use crate::events::Notifier;
+use crate::health::{BackendHealth, HealthChecks};
+use crate::logging::{LogFilter, SetFilterError};
+use crate::routes::read_only::ReadOnly;
 use crate::s3;
-use crate::storage::StorageBackend;
+use crate::server::{BackendProbe, Readiness};
+use crate::storage::{BucketRegistry, StorageBackend, DEFAULT_PART_SIZE};
+use crate::version::BuildInfo;
 
 /// Health check endpoint
 #[instrument]
@@ -21,13 +33,123 @@ pub async fn health() -> impl IntoResponse {
     (StatusCode::OK, "OK")
 }
 
+/// Deep health endpoint
+///
+/// Reports the status and latency of every component as JSON. Returns 503
+/// when a critical component fails.
+#[instrument(skip_all)]
+pub async fn deep_health(
+    State(registry): State<Arc<BucketRegistry>>,
+    checks: Option<Extension<Arc<HealthChecks>>>,
+) -> impl IntoResponse {
+    let report = match checks {
+        Some(Extension(checks)) => checks.report().await,
+        None => {
+            // Not wired up by the server: report the backends only
+            let mut checks = HealthChecks::new(std::time::Duration::from_secs(2));
+            for check in BackendHealth::for_registry(&registry, None) {
+                checks.register(Arc::new(check));
+            }
+            checks.report().await
+        }
+    };
+    let status = if report.healthy() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
 /// Readiness probe endpoint
-#[instrument]
-pub async fn ready() -> impl IntoResponse {
-    // TODO: Add backend connectivity check
+///
+/// Reports unready once shutdown has started, and while the storage
+/// backends fail the connectivity probe when one is configured.
+#[instrument(skip_all)]
+pub async fn ready(
+    State(registry): State<Arc<BucketRegistry>>,
+    readiness: Option<Extension<Readiness>>,
+    probe: Option<Extension<Arc<BackendProbe>>>,
+) -> impl IntoResponse {
+    if readiness.is_some_and(|Extension(readiness)| !readiness.is_ready()) {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Shutting down");
+    }
+    if let Some(Extension(probe)) = probe {
+        if probe.check(&registry).await.is_err() {
+            return (StatusCode::SERVICE_UNAVAILABLE, "Backend unavailable");
+        }
+    }
     (StatusCode::OK, "Ready")
 }
 
+/// Response of the version endpoint: build information and the modes
+/// that change what the proxy accepts
+#[derive(serde::Serialize)]
+struct VersionResponse {
+    #[serde(flatten)]
+    build: BuildInfo,
+    read_only: bool,
+}
+
+/// Build information endpoint
+#[instrument(skip_all)]
+pub async fn version(read_only: Option<Extension<ReadOnly>>) -> impl IntoResponse {
+    Json(VersionResponse {
+        build: crate::version::build_info(),
+        read_only: read_only.is_some_and(|Extension(read_only)| read_only.is_enabled()),
+    })
+}
+
+/// Current log filter - GET /admin/loglevel
+#[instrument(skip_all)]
+pub async fn get_log_level(log_filter: Option<Extension<Arc<LogFilter>>>) -> Response {
+    let Some(Extension(log_filter)) = log_filter else {
+        return (StatusCode::NOT_FOUND, "Log level changes are not available").into_response();
+    };
+    match log_filter.current() {
+        Ok(current) => Json(serde_json::json!({ "filter": current })).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+/// Replace the log filter - PUT /admin/loglevel
+///
+/// The body is a filter such as `s3proxy=debug,object_store=trace`. Returns
+/// the previous filter; invalid filters are rejected with 400.
+#[instrument(skip(log_filter))]
+pub async fn set_log_level(log_filter: Option<Extension<Arc<LogFilter>>>, body: String) -> Response {
+    let Some(Extension(log_filter)) = log_filter else {
+        return (StatusCode::NOT_FOUND, "Log level changes are not available").into_response();
+    };
+    match log_filter.set(&body) {
+        Ok(previous) => {
+            let filter = log_filter.current().unwrap_or_else(|_| body.trim().to_string());
+            warn!(previous = %previous, filter = %filter, "Log filter changed");
+            Json(serde_json::json!({ "previous": previous, "filter": filter })).into_response()
+        }
+        Err(SetFilterError::Invalid(e)) => (StatusCode::BAD_REQUEST, e).into_response(),
+        Err(SetFilterError::Reload(e)) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+/// Effective configuration with secrets redacted - GET /admin/config
+#[instrument(skip_all)]
+pub async fn get_config(config: Option<Extension<Arc<Config>>>) -> Response {
+    match config {
+        Some(Extension(config)) => Json(config.redacted()).into_response(),
+        None => (StatusCode::NOT_FOUND, "Configuration is not available").into_response(),
+    }
+}
+
+/// Storage quotas with their usage - GET /admin/quotas
+#[instrument(skip_all)]
+pub async fn get_quotas(State(registry): State<Arc<BucketRegistry>>) -> Response {
+    match registry.quotas() {
+        Some(quotas) => Json(quotas.report()).into_response(),
+        None => (StatusCode::NOT_FOUND, "Storage quotas are not configured").into_response(),
+    }
+}
+
 /// Prometheus metrics endpoint
 #[instrument]
 pub async fn metrics() -> impl IntoResponse {
@@ -40,65 +162,205 @@ pub async fn metrics() -> impl IntoResponse {
 }
 
 /// GetObject - GET /{bucket}/{key}
-#[instrument(skip(storage))]
+#[instrument(skip(registry))]
 pub async fn get_object(
-    State(storage): State<Arc<dyn StorageBackend>>,
+    State(registry): State<Arc<BucketRegistry>>,
     Path((bucket, key)): Path<(String, String)>,
 ) -> Result<Response> {
     info!(bucket = %bucket, key = %key, "GetObject request");
-
-    let data = storage.get(&key).await.map_err(|e| {
-        error!(error = %e, "Storage get failed");
-        S3ProxyError::Storage(e)
-    })?;
-
-    // TODO: Add content-type detection based on file extension
-    let response = Response::builder()
+    s3::validate_key(&key)?;
+    let storage = registry.resolve(&bucket)?;
+
+    let result = storage
+        .get_opts(&s3::to_storage_key(&key), GetOptions::default())
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Storage get failed");
+            S3ProxyError::Storage(e)
+        })?;
+
+    // Streamed, so large objects are never held in memory whole
+    let mut response = Response::builder()
         .status(StatusCode::OK)
-        .header("content-type", "application/octet-stream")
-        .header("content-length", data.len())
-        .body(Body::from(data))
+        .header("content-length", result.range.len())
+        .header("last-modified", format_http_date(&result.meta));
+    if let Some(etag) = result.meta.e_tag.as_deref() {
+        response = response.header("etag", s3::quote_etag(etag));
+    }
+    let headers = s3::attribute_headers(&result.attributes);
+    if !headers.iter().any(|(name, _)| name == header::CONTENT_TYPE) {
+        response = response.header(header::CONTENT_TYPE, "application/octet-stream");
+    }
+    for (name, value) in headers {
+        response = response.header(name, value);
+    }
+    let response = response
+        .body(Body::from_stream(result.into_stream()))
         .map_err(|e| S3ProxyError::Internal(format!("Failed to build response: {}", e)))?;
 
     Ok(response)
 }
 
 /// PutObject - PUT /{bucket}/{key}
-#[instrument(skip(storage))]
+///
+/// Bodies of a known length up to one multipart part are buffered and put
+/// in one request; longer ones, and those of unknown length, are streamed
+/// to the backend as a multipart upload.
+#[instrument(skip(registry, config, body))]
 pub async fn put_object(
-    State(storage): State<Arc<dyn StorageBackend>>,
+    State(registry): State<Arc<BucketRegistry>>,
     Path((bucket, key)): Path<(String, String)>,
     headers: HeaderMap,
-    body: Bytes,
+    config: Option<Extension<Arc<Config>>>,
+    body: Body,
 ) -> Result<Response> {
-    info!(bucket = %bucket, key = %key, size = body.len(), "PutObject request");
+    let size = content_length(&headers).or_else(|| body.size_hint().exact());
+    info!(bucket = %bucket, key = %key, size = ?size, "PutObject request");
+    s3::validate_key(&key)?;
+    let storage = registry.resolve(&bucket)?;
+
+    let part_size = config.map_or(DEFAULT_PART_SIZE, |Extension(config)| {
+        config.multipart.part_size_bytes
+    });
+    let path = s3::to_storage_key(&key);
+    let attributes = s3::object_attributes(&headers);
+    let put = match size {
+        Some(size) if size <= part_size as u64 => {
+            let data = axum::body::to_bytes(body, part_size)
+                .await
+                .map_err(|e| S3ProxyError::InvalidRequest(format!("Failed to read body: {}", e)))?;
+            storage.put_with_attributes(&path, data, attributes).await
+        }
+        _ => {
+            let stream = body.into_data_stream().map_err(std::io::Error::other).boxed();
+            storage.put_stream(&path, stream, attributes).await
+        }
+    }
+    .map_err(|e| {
+        error!(error = %e, "Storage put failed");
+        S3ProxyError::Storage(e)
+    })?;
 
-    // TODO: Extract and store metadata from x-amz-meta-* headers
-    let _metadata = s3::extract_metadata(&headers);
+    // The ETag the backend assigned, if any
+    let mut response = Response::builder().status(StatusCode::OK);
+    if let Some(etag) = put.e_tag.as_deref() {
+        response = response.header("etag", s3::quote_etag(etag));
+    }
+    let response = response
+        .body(Body::empty())
+        .map_err(|e| S3ProxyError::Internal(format!("Failed to build response: {}", e)))?;
 
-    storage.put(&key, body).await.map_err(|e| {
-        error!(error = %e, "Storage put failed");
+    Ok(response)
+}
+
+/// CopyObject - PUT /{bucket}/{key} with `x-amz-copy-source`
+///
+/// Copies within a bucket, in the backend. The copy keeps the source's
+/// attributes; replacing them, copies between buckets and copies
+/// conditional on the source are not implemented.
+#[instrument(skip(registry, headers))]
+pub async fn copy_object(
+    State(registry): State<Arc<BucketRegistry>>,
+    Path((bucket, key)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let source = headers
+        .get(s3::COPY_SOURCE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let (source_bucket, source_key) = s3::parse_copy_source(source)?;
+    info!(bucket = %bucket, key = %key, source_bucket = %source_bucket, source_key = %source_key, "CopyObject request");
+    s3::validate_key(&key)?;
+    s3::validate_key(&source_key)?;
+    let storage = registry.resolve(&bucket)?;
+    registry.resolve(&source_bucket)?;
+
+    if source_bucket != bucket {
+        return Err(S3ProxyError::NotImplemented(
+            "Copying objects between buckets is not implemented".to_string(),
+        ));
+    }
+    let replaces = |directive: &str| {
+        headers
+            .get(directive)
+            .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"REPLACE"))
+    };
+    if replaces("x-amz-metadata-directive") || replaces("x-amz-tagging-directive") {
+        return Err(S3ProxyError::NotImplemented(
+            "Replacing the metadata or tags of a copied object is not implemented".to_string(),
+        ));
+    }
+    if headers.keys().any(|name| name.as_str().starts_with("x-amz-copy-source-if-")) {
+        return Err(S3ProxyError::NotImplemented(
+            "Conditional copies are not implemented".to_string(),
+        ));
+    }
+    if source_key == key {
+        return Err(S3ProxyError::InvalidRequest(
+            "This copy request is illegal because it is trying to copy an object to itself without changing the object's metadata"
+                .to_string(),
+        ));
+    }
+
+    let to = s3::to_storage_key(&key);
+    storage
+        .copy(&s3::to_storage_key(&source_key), &to)
+        .await
+        .map_err(|e| {
+            error!(error = %e, "Storage copy failed");
+            S3ProxyError::Storage(e)
+        })?;
+    let meta = storage.head(&to).await.map_err(|e| {
+        error!(error = %e, "Storage head failed");
         S3ProxyError::Storage(e)
     })?;
 
-    let response = Response::builder()
+    let result = s3::CopyObjectResult {
+        last_modified: meta.last_modified.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+        etag: meta.e_tag.as_deref().map(s3::quote_etag),
+    };
+    let xml = result.to_xml().map_err(|e| {
+        error!(error = %e, "XML serialization failed");
+        S3ProxyError::Internal(format!("XML serialization failed: {}", e))
+    })?;
+
+    let mut response = Response::builder()
         .status(StatusCode::OK)
-        .header("etag", format!("\"{}\"", uuid::Uuid::new_v4()))
-        .body(Body::empty())
+        .header("content-type", "application/xml")
+        .body(Body::from(xml))
         .map_err(|e| S3ProxyError::Internal(format!("Failed to build response: {}", e)))?;
+    // Size and ETag of the copy, for its event notification
+    response.extensions_mut().insert(meta);
 
     Ok(response)
 }
 
+/// Length of the body the Content-Length header declares
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Last-Modified header value of an object
+fn format_http_date(meta: &ObjectMeta) -> String {
+    meta.last_modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
 /// DeleteObject - DELETE /{bucket}/{key}
-#[instrument(skip(storage))]
+#[instrument(skip(registry))]
 pub async fn delete_object(
-    State(storage): State<Arc<dyn StorageBackend>>,
+    State(registry): State<Arc<BucketRegistry>>,
     Path((bucket, key)): Path<(String, String)>,
 ) -> Result<Response> {
     info!(bucket = %bucket, key = %key, "DeleteObject request");
+    s3::validate_key(&key)?;
+    let storage = registry.resolve(&bucket)?;
 
-    storage.delete(&key).await.map_err(|e| {
+    storage.delete(&s3::to_storage_key(&key)).await.map_err(|e| {
         error!(error = %e, "Storage delete failed");
         S3ProxyError::Storage(e)
     })?;
@@ -112,27 +374,92 @@ pub async fn delete_object(
 }
 
 /// HeadObject - HEAD /{bucket}/{key}
-#[instrument(skip(storage))]
+#[instrument(skip(registry))]
 pub async fn head_object(
-    State(storage): State<Arc<dyn StorageBackend>>,
+    State(registry): State<Arc<BucketRegistry>>,
     Path((bucket, key)): Path<(String, String)>,
 ) -> Result<Response> {
     info!(bucket = %bucket, key = %key, "HeadObject request");
+    s3::validate_key(&key)?;
+    let storage = registry.resolve(&bucket)?;
 
-    let meta = storage.head(&key).await.map_err(|e| {
+    let meta = storage.head(&s3::to_storage_key(&key)).await.map_err(|e| {
         error!(error = %e, "Storage head failed");
         S3ProxyError::Storage(e)
     })?;
 
-    // ObjectMeta in object_store 0.10 doesn't have etag field directly
-    // We'll generate a simple etag or leave it empty
-    let etag = format!("\"{}\"", uuid::Uuid::new_v4());
-    
-    let response = Response::builder()
+    let mut response = Response::builder()
         .status(StatusCode::OK)
         .header("content-length", meta.size)
-        .header("last-modified", format!("{}", meta.last_modified.format("%a, %d %b %Y %H:%M:%S GMT")))
-        .header("etag", etag)
+        .header("last-modified", format_http_date(&meta));
+    if let Some(etag) = meta.e_tag.as_deref() {
+        response = response.header("etag", s3::quote_etag(etag));
+    }
+    let response = response
+        .body(Body::empty())
+        .map_err(|e| S3ProxyError::Internal(format!("Failed to build response: {}", e)))?;
+
+    Ok(response)
+}
+
+/// GetBucketNotificationConfiguration - GET /{bucket}?notification
+///
+/// Returns the configuration the bucket was given, or else the configured
+/// destinations receiving its events.
+#[instrument(skip(registry, notifier))]
+pub async fn get_bucket_notification(
+    State(registry): State<Arc<BucketRegistry>>,
+    Path(bucket): Path<String>,
+    notifier: Option<Extension<Arc<Notifier>>>,
+) -> Result<Response> {
+    info!(bucket = %bucket, "GetBucketNotificationConfiguration request");
+    registry.resolve(&bucket)?;
+
+    let configuration = match notifier {
+        Some(Extension(notifier)) => notifier.configuration(&bucket).await?,
+        None => s3::NotificationConfiguration::default(),
+    };
+    let xml = configuration.to_xml().map_err(|e| {
+        error!(error = %e, "XML serialization failed");
+        S3ProxyError::Internal(format!("XML serialization failed: {}", e))
+    })?;
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/xml")
+        .body(Body::from(xml))
+        .map_err(|e| S3ProxyError::Internal(format!("Failed to build response: {}", e)))?;
+
+    Ok(response)
+}
+
+/// PutBucketNotificationConfiguration - PUT /{bucket}?notification
+///
+/// Routes the bucket's events by the configuration, whose destinations
+/// must name configured ones.
+#[instrument(skip(registry, notifier, body))]
+pub async fn put_bucket_notification(
+    State(registry): State<Arc<BucketRegistry>>,
+    Path(bucket): Path<String>,
+    notifier: Option<Extension<Arc<Notifier>>>,
+    body: Body,
+) -> Result<Response> {
+    info!(bucket = %bucket, "PutBucketNotificationConfiguration request");
+    registry.resolve(&bucket)?;
+
+    let configuration: s3::NotificationConfiguration = s3::read_xml(body).await?;
+    match notifier {
+        Some(Extension(notifier)) => notifier.set_configuration(&bucket, configuration).await?,
+        None if configuration.destinations.is_empty() => {}
+        None => {
+            return Err(S3ProxyError::InvalidArgument(
+                "No notification destinations are configured".to_string(),
+            ))
+        }
+    }
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
         .body(Body::empty())
         .map_err(|e| S3ProxyError::Internal(format!("Failed to build response: {}", e)))?;
 
@@ -140,43 +467,334 @@ pub async fn head_object(
 }
 
 /// ListObjectsV2 - GET /{bucket}?prefix=...
-#[instrument(skip(storage))]
+#[instrument(skip(registry, principal, config))]
 pub async fn list_objects(
-    State(storage): State<Arc<dyn StorageBackend>>,
+    State(registry): State<Arc<BucketRegistry>>,
     Path(bucket): Path<String>,
-    Query(params): Query<crate::routes::ListObjectsQuery>,
+    params: std::result::Result<Query<crate::routes::ListObjectsQuery>, QueryRejection>,
+    principal: Option<Extension<Principal>>,
+    config: Option<Extension<Arc<Config>>>,
 ) -> Result<Response> {
+    let Query(params) = params?;
     info!(bucket = %bucket, prefix = ?params.prefix, "ListObjects request");
+    let storage = registry.resolve(&bucket)?;
 
     let prefix = params.prefix.as_deref().unwrap_or("");
-    let max_keys = params.max_keys.unwrap_or(1000);
+    let delimiter = params.delimiter.as_deref().filter(|d| !d.is_empty());
+    let max_keys = parse_max_keys(params.max_keys.as_deref())?;
+    if let Some(list_type) = params.list_type.filter(|list_type| *list_type != 2) {
+        return Err(S3ProxyError::InvalidParameter {
+            name: "list-type",
+            value: list_type.to_string(),
+            message: "Invalid List Type specified".to_string(),
+        });
+    }
+    let url_encoded = match params.encoding_type.as_deref() {
+        None => false,
+        Some("url") => true,
+        Some(encoding_type) => {
+            return Err(S3ProxyError::InvalidParameter {
+                name: "encoding-type",
+                value: encoding_type.to_string(),
+                message: "Invalid Encoding Method specified in Request".to_string(),
+            })
+        }
+    };
+    // A continuation token carries on from its page, regardless of start-after
+    let after = match params.continuation_token.as_deref() {
+        Some(token) => Some(decode_continuation_token(token)?),
+        None => params
+            .start_after
+            .as_deref()
+            .filter(|key| !key.is_empty())
+            .map(|key| object_store::path::Path::from(s3::to_storage_key(key)).to_string()),
+    };
 
-    let objects = storage.list(prefix).await.map_err(|e| {
-        error!(error = %e, "Storage list failed");
-        S3ProxyError::Storage(e)
-    })?;
+    // Principals confined to key prefixes only see keys inside them
+    let list_prefixes = match &principal {
+        Some(Extension(principal)) => principal.policy.list_prefixes(prefix),
+        None => vec![prefix.to_string()],
+    };
+
+    // Up to one entry more than requested, to tell whether the listing is
+    // truncated, from each listed prefix
+    let mut entries: Vec<ListEntry> = Vec::new();
+    for list_prefix in &list_prefixes {
+        let page = ListPage {
+            prefix,
+            list_prefix,
+            delimiter,
+            after: after.as_deref(),
+            limit: max_keys as usize + 1,
+        };
+        entries.extend(page.fetch(storage.as_ref()).await?);
+    }
+    entries.sort_by(|a, b| a.resume.cmp(&b.resume));
+    entries.dedup_by(|a, b| a.resume == b.resume);
+
+    let is_truncated = entries.len() > max_keys as usize;
+    entries.truncate(max_keys as usize);
+    // A page of no keys, asked for with max-keys=0, resumes where it started
+    let next_continuation_token = match (is_truncated, entries.last()) {
+        (false, _) => None,
+        (true, Some(entry)) => Some(base64::engine::general_purpose::STANDARD.encode(&entry.resume)),
+        (true, None) => params.continuation_token.clone(),
+    };
+
+    // ListObjects always reports owners, ListObjectsV2 only when asked to
+    let owner = (params.list_type != Some(2) || params.fetch_owner == Some(true)).then(|| {
+        let owner = config.map(|Extension(config)| config.owner.clone()).unwrap_or_default();
+        s3::Owner {
+            id: owner.id,
+            display_name: owner.display_name,
+        }
+    });
 
     // Convert object_store::ObjectMeta to S3 Object format
     let mut s3_objects = Vec::new();
-    for meta in objects.iter().take(max_keys as usize) {
-        // Generate a simple etag since ObjectMeta doesn't expose it directly
-        let etag = format!("\"{}\"", uuid::Uuid::new_v4());
-        s3_objects.push(s3::Object {
-            key: meta.location.to_string(),
-            last_modified: meta.last_modified.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
-            etag,
-            size: meta.size as u64,
-            storage_class: "STANDARD".to_string(),
-        });
+    let mut common_prefixes: Vec<s3::CommonPrefix> = Vec::new();
+    for ListEntry { key, meta, .. } in entries {
+        match meta {
+            None => common_prefixes.push(s3::CommonPrefix { prefix: key }),
+            Some(meta) => {
+                s3_objects.push(s3::Object {
+                    key,
+                    last_modified: meta.last_modified.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+                    etag: meta.e_tag.as_deref().map(s3::quote_etag),
+                    size: meta.size as u64,
+                    owner: owner.clone(),
+                    storage_class: "STANDARD".to_string(),
+                });
+            }
+        }
     }
 
+    let encode = |value: String| if url_encoded { s3::url_encode_list_key(&value) } else { value };
+    for object in &mut s3_objects {
+        object.key = encode(std::mem::take(&mut object.key));
+    }
+    for common_prefix in &mut common_prefixes {
+        common_prefix.prefix = encode(std::mem::take(&mut common_prefix.prefix));
+    }
+    let v2 = params.list_type == Some(2);
     let result = s3::ListObjectsV2Result {
+        xmlns: s3::S3_NAMESPACE,
         name: bucket,
-        prefix: params.prefix,
+        prefix: params.prefix.map(encode),
+        start_after: params.start_after.filter(|_| v2).map(encode),
+        continuation_token: params.continuation_token,
+        next_continuation_token,
+        key_count: v2.then_some((s3_objects.len() + common_prefixes.len()) as u32),
         max_keys,
-        is_truncated: objects.len() > max_keys as usize,
+        delimiter: params.delimiter.map(encode),
+        encoding_type: url_encoded.then(|| "url".to_string()),
+        is_truncated,
         contents: s3_objects,
-        common_prefixes: None, // TODO: Implement delimiter support
+        common_prefixes: (!common_prefixes.is_empty()).then_some(common_prefixes),
+    };
+
+    let xml = result.to_xml().map_err(|e| {
+        error!(error = %e, "XML serialization failed");
+        S3ProxyError::Internal(format!("XML serialization failed: {}", e))
+    })?;
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/xml")
+        .body(Body::from(xml))
+        .map_err(|e| S3ProxyError::Internal(format!("Failed to build response: {}", e)))?;
+
+    Ok(response)
+}
+
+/// Most keys a listing page holds, as in S3
+const MAX_LIST_KEYS: u32 = 1000;
+
+/// Page size of a listing, with max-keys capped at 1000
+///
+/// Zero lists no keys but still tells whether any match.
+fn parse_max_keys(max_keys: Option<&str>) -> Result<u32> {
+    let Some(value) = max_keys else {
+        return Ok(MAX_LIST_KEYS);
+    };
+    value
+        .parse::<u32>()
+        .map(|max_keys| max_keys.min(MAX_LIST_KEYS))
+        .map_err(|_| S3ProxyError::InvalidParameter {
+            name: "max-keys",
+            value: value.to_string(),
+            message: "Provided max-keys not an integer or within integer range".to_string(),
+        })
+}
+
+/// Listing position encoded in a continuation token
+fn decode_continuation_token(token: &str) -> Result<String> {
+    base64::engine::general_purpose::STANDARD
+        .decode(token)
+        .ok()
+        .and_then(|after| String::from_utf8(after).ok())
+        .filter(|after| object_store::path::Path::parse(after).is_ok())
+        .ok_or_else(|| S3ProxyError::InvalidParameter {
+            name: "continuation-token",
+            value: token.to_string(),
+            message: "The continuation token provided is incorrect".to_string(),
+        })
+}
+
+/// Object or common prefix of a ListObjectsV2 page
+struct ListEntry {
+    key: String,
+    /// Metadata of an object, none for a common prefix
+    meta: Option<ObjectMeta>,
+    /// Storage path a listing continuing after this entry resumes from
+    resume: String,
+}
+
+impl ListEntry {
+    fn object(meta: ObjectMeta) -> Self {
+        Self {
+            key: s3::from_storage_key(meta.location.as_ref()),
+            resume: meta.location.to_string(),
+            meta: Some(meta),
+        }
+    }
+
+    /// Whether the entry is a key the proxy keeps for itself
+    fn is_reserved(&self) -> bool {
+        self.meta.is_some() && self.key == s3::NOTIFICATION_CONFIGURATION_KEY
+    }
+
+    fn common_prefix(key: String) -> Self {
+        // Locations are encoded a segment at a time, so the common prefix
+        // of the keys is encoded as a path; followed by a character that
+        // encoding never produces, it sorts after every location inside
+        let trailing = if key.ends_with('/') { "/" } else { "" };
+        let resume = format!("{}{}{}", object_store::path::Path::from(key.as_str()), trailing, char::MAX);
+        Self { key, meta: None, resume }
+    }
+}
+
+/// One list prefix's share of a ListObjectsV2 page
+struct ListPage<'a> {
+    /// Prefix requested by the client
+    prefix: &'a str,
+    /// Prefix listed, inside the requested one for confined principals
+    list_prefix: &'a str,
+    delimiter: Option<&'a str>,
+    /// Location the previous page ended at
+    after: Option<&'a str>,
+    /// Entries to return at most
+    limit: usize,
+}
+
+impl ListPage<'_> {
+    /// Group an entry whose key contains the delimiter after the prefix
+    /// into its CommonPrefix
+    fn group(&self, entry: ListEntry) -> ListEntry {
+        let common_prefix = self.delimiter.and_then(|d| {
+            entry.key[self.prefix.len()..]
+                .find(d)
+                .map(|i| entry.key[..self.prefix.len() + i + d.len()].to_string())
+        });
+        match common_prefix {
+            Some(common_prefix) => ListEntry::common_prefix(common_prefix),
+            None => entry,
+        }
+    }
+
+    async fn fetch(&self, storage: &dyn StorageBackend) -> Result<Vec<ListEntry>> {
+        // object_store lists whole path segments, so list from the last complete
+        // segment of the prefix and apply S3's plain string-prefix match afterwards
+        let list_root = self.list_prefix.rfind('/').map(|i| &self.list_prefix[..i]).unwrap_or("");
+        let storage_error = |e| {
+            error!(error = %e, "Storage list failed");
+            S3ProxyError::Storage(e)
+        };
+
+        if self.delimiter == Some("/") {
+            // The backend groups by `/` itself, without a recursive listing,
+            // but cannot start from an offset: the page is taken here
+            let listing = storage.list_with_delimiter(list_root).await.map_err(storage_error)?;
+            let common_prefixes = listing
+                .common_prefixes
+                .into_iter()
+                .map(|path| ListEntry::common_prefix(s3::from_storage_key(&format!("{}/", path))));
+            let objects = listing.objects.into_iter().map(ListEntry::object);
+            let mut entries: Vec<ListEntry> = common_prefixes
+                .chain(objects)
+                .filter(|entry| entry.key.starts_with(self.list_prefix) && !entry.is_reserved())
+                .map(|entry| self.group(entry))
+                .filter(|entry| self.after.is_none_or(|after| entry.resume.as_str() > after))
+                .collect();
+            entries.sort_by(|a, b| a.resume.cmp(&b.resume));
+            entries.dedup_by(|a, b| a.resume == b.resume);
+            entries.truncate(self.limit);
+            return Ok(entries);
+        }
+
+        // Read the listing a page at a time, from where the previous page
+        // left off, so no call returns more than `limit` objects
+        let mut entries: Vec<ListEntry> = Vec::new();
+        let mut cursor = self.after.map(str::to_string);
+        loop {
+            let objects = storage
+                .list(list_root, cursor.as_deref(), Some(self.limit))
+                .await
+                .map_err(storage_error)?;
+            let exhausted = objects.len() < self.limit;
+            for meta in objects {
+                let entry = ListEntry::object(meta);
+                cursor = Some(entry.resume.clone());
+                if entry.is_reserved() {
+                    continue;
+                }
+                if !entry.key.starts_with(self.list_prefix) {
+                    if entry.key.as_str() > self.list_prefix {
+                        // Past the prefix
+                        return Ok(entries);
+                    }
+                    continue;
+                }
+
+                let entry = self.group(entry);
+                if entries.last().is_some_and(|last| last.resume == entry.resume) {
+                    continue;
+                }
+                entries.push(entry);
+                if entries.len() == self.limit {
+                    return Ok(entries);
+                }
+            }
+            if exhausted {
+                return Ok(entries);
+            }
+            // Skip the rest of a common prefix the batch ended in
+            if let Some(last) = entries.last().filter(|last| last.meta.is_none()) {
+                cursor = Some(last.resume.clone());
+            }
+        }
+    }
+}
+
+/// ListBuckets - GET /
+#[instrument(skip(registry))]
+pub async fn list_buckets(State(registry): State<Arc<BucketRegistry>>) -> Result<Response> {
+    info!("ListBuckets request");
+
+    let creation_date = registry
+        .created()
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string();
+    let result = s3::ListAllMyBucketsResult {
+        buckets: s3::Buckets {
+            bucket: registry
+                .bucket_names()
+                .map(|name| s3::Bucket {
+                    name: name.to_string(),
+                    creation_date: creation_date.clone(),
+                })
+                .collect(),
+        },
     };
 
     let xml = result.to_xml().map_err(|e| {
@@ -194,10 +812,14 @@ pub async fn list_objects(
 }
 
 /// CreateBucket - PUT /{bucket}
-#[instrument]
-pub async fn create_bucket(Path(bucket): Path<String>) -> Result<Response> {
+#[instrument(skip(registry))]
+pub async fn create_bucket(
+    State(registry): State<Arc<BucketRegistry>>,
+    Path(bucket): Path<String>,
+) -> Result<Response> {
     info!(bucket = %bucket, "CreateBucket request (noop)");
-    
+    registry.resolve(&bucket)?;
+
     // Bucket creation is a noop - the bucket/container should already exist
     // in the backend storage system
     let response = Response::builder()
@@ -209,10 +831,14 @@ pub async fn create_bucket(Path(bucket): Path<String>) -> Result<Response> {
 }
 
 /// DeleteBucket - DELETE /{bucket}
-#[instrument]
-pub async fn delete_bucket(Path(bucket): Path<String>) -> Result<Response> {
+#[instrument(skip(registry))]
+pub async fn delete_bucket(
+    State(registry): State<Arc<BucketRegistry>>,
+    Path(bucket): Path<String>,
+) -> Result<Response> {
     info!(bucket = %bucket, "DeleteBucket request (noop)");
-    
+    registry.resolve(&bucket)?;
+
     // Bucket deletion is a noop - buckets/containers are managed externally
     let response = Response::builder()
         .status(StatusCode::NO_CONTENT)
@@ -222,3 +848,13 @@ pub async fn delete_bucket(Path(bucket): Path<String>) -> Result<Response> {
     Ok(response)
 }
 
+
+/// Answer a path no route matches, such as `/bucket/` with an empty key
+pub async fn no_such_resource(State(registry): State<Arc<BucketRegistry>>, uri: Uri) -> S3ProxyError {
+    let path = uri.path().trim_start_matches('/');
+    let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+    match registry.resolve(bucket) {
+        Ok(_) => S3ProxyError::NotFound { path: key.to_string() },
+        Err(e) => e,
+    }
+}
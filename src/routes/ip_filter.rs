@@ -0,0 +1,208 @@
+//! Client IP allow and deny lists
+//!
+//! Rejects requests from clients outside the configured networks with a
+//! bare 403 before any authentication or S3 handling. Rules are evaluated
+//! against the TCP peer address, or the last `X-Forwarded-For` entry when
+//! the proxy sits behind a trusted load balancer:
+//! 1. a matching `deny` network rejects the request
+//! 2. for health, readiness and metrics endpoints a matching `system_allow`
+//!    network accepts it
+//! 3. if `allow` is non-empty the client must match one of its networks
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+use crate::config::IpFilterConfig;
+use crate::routes;
+
+/// Outcome of evaluating the rules for one client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Allowed by the given rule, or because no allow list is configured
+    Allow(Option<IpNet>),
+    /// Rejected by the given deny rule, or because no allow rule matched
+    Deny(Option<IpNet>),
+}
+
+/// Compiled IP filter rules
+pub struct IpFilter {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+    system_allow: Vec<IpNet>,
+    trust_forwarded_for: bool,
+}
+
+impl IpFilter {
+    pub fn new(config: &IpFilterConfig) -> Self {
+        Self {
+            allow: config.allow.iter().map(IpNet::trunc).collect(),
+            deny: config.deny.iter().map(IpNet::trunc).collect(),
+            system_allow: config.system_allow.iter().map(IpNet::trunc).collect(),
+            trust_forwarded_for: config.trust_forwarded_for,
+        }
+    }
+
+    /// Whether any rule is configured
+    pub fn is_enabled(&self) -> bool {
+        !self.allow.is_empty() || !self.deny.is_empty() || !self.system_allow.is_empty()
+    }
+
+    /// Evaluate the rules for a client address
+    pub fn decide(&self, ip: IpAddr, system_path: bool) -> Decision {
+        // Dual-stack listeners report IPv4 clients as IPv4-mapped IPv6
+        let ip = ip.to_canonical();
+        let matching = |networks: &[IpNet]| networks.iter().find(|n| n.contains(&ip)).copied();
+
+        if let Some(rule) = matching(&self.deny) {
+            return Decision::Deny(Some(rule));
+        }
+        if system_path {
+            if let Some(rule) = matching(&self.system_allow) {
+                return Decision::Allow(Some(rule));
+            }
+        }
+        if self.allow.is_empty() {
+            return Decision::Allow(None);
+        }
+        match matching(&self.allow) {
+            Some(rule) => Decision::Allow(Some(rule)),
+            None => Decision::Deny(None),
+        }
+    }
+
+    /// Address the rules apply to for this request
+    fn client_ip(&self, request: &Request) -> Option<IpAddr> {
+        client_ip(request, self.trust_forwarded_for)
+    }
+}
+
+/// Client address of a request: the peer address, or the last
+/// `X-Forwarded-For` entry when the proxy in front is trusted to set it
+pub fn client_ip(request: &Request, trust_forwarded_for: bool) -> Option<IpAddr> {
+    if trust_forwarded_for {
+        // The last entry is the one added by the trusted proxy itself
+        return request
+            .headers()
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .last()
+            .and_then(|ip| ip.trim().parse().ok());
+    }
+    request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
+
+/// Middleware rejecting clients outside the configured networks
+pub async fn filter(State(filter): State<Arc<IpFilter>>, request: Request, next: Next) -> Response {
+    if !filter.is_enabled() {
+        return next.run(request).await;
+    }
+
+    let system_path = routes::is_system_path(request.uri().path());
+    let Some(ip) = filter.client_ip(&request) else {
+        warn!("Rejected request with unknown client address");
+        return StatusCode::FORBIDDEN.into_response();
+    };
+
+    match filter.decide(ip, system_path) {
+        Decision::Allow(rule) => {
+            debug!(client_ip = %ip, rule = ?rule, "Client IP allowed");
+            next.run(request).await
+        }
+        Decision::Deny(rule) => {
+            let rule = rule.map_or_else(|| "no allow rule matched".to_string(), |r| format!("deny {}", r));
+            warn!(client_ip = %ip, rule = %rule, "Client IP rejected");
+            StatusCode::FORBIDDEN.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware::from_fn_with_state, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn filter_for(allow: &[&str], deny: &[&str], system_allow: &[&str]) -> IpFilter {
+        let nets = |list: &[&str]| list.iter().map(|n| n.parse().unwrap()).collect();
+        IpFilter::new(&IpFilterConfig {
+            allow: nets(allow),
+            deny: nets(deny),
+            system_allow: nets(system_allow),
+            trust_forwarded_for: false,
+        })
+    }
+
+    #[test]
+    fn test_decisions() {
+        let filter = filter_for(&["10.1.0.0/16", "2001:db8::/32"], &["10.1.99.0/24"], &["192.168.0.0/24"]);
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        assert_eq!(filter.decide(ip("10.1.2.3"), false), Decision::Allow(Some("10.1.0.0/16".parse().unwrap())));
+        assert_eq!(filter.decide(ip("::ffff:10.1.2.3"), false), Decision::Allow(Some("10.1.0.0/16".parse().unwrap())));
+        assert_eq!(filter.decide(ip("2001:db8::1"), false), Decision::Allow(Some("2001:db8::/32".parse().unwrap())));
+        assert_eq!(filter.decide(ip("10.1.99.7"), false), Decision::Deny(Some("10.1.99.0/24".parse().unwrap())));
+        assert_eq!(filter.decide(ip("10.2.0.1"), false), Decision::Deny(None));
+        assert_eq!(filter.decide(ip("2001:db9::1"), false), Decision::Deny(None));
+
+        // The load balancer range only reaches system endpoints
+        assert!(matches!(filter.decide(ip("192.168.0.10"), true), Decision::Allow(_)));
+        assert!(matches!(filter.decide(ip("192.168.0.10"), false), Decision::Deny(_)));
+
+        let deny_only = filter_for(&[], &["0.0.0.0/0"], &[]);
+        assert_eq!(deny_only.decide(ip("::1"), false), Decision::Allow(None));
+        assert!(matches!(deny_only.decide(ip("127.0.0.1"), false), Decision::Deny(_)));
+    }
+
+    async fn status(filter: IpFilter, peer: Option<&str>, forwarded: Option<&str>, uri: &str) -> StatusCode {
+        let router = Router::new()
+            .route("/healthz", get(|| async { "ok" }))
+            .route("/bucket/*key", get(|| async { "ok" }))
+            .layer(from_fn_with_state(Arc::new(filter), super::filter));
+        let mut request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        if let Some(peer) = peer {
+            request.extensions_mut().insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+        }
+        if let Some(forwarded) = forwarded {
+            request.headers_mut().insert("x-forwarded-for", forwarded.parse().unwrap());
+        }
+        router.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_middleware() {
+        let f = || filter_for(&["10.0.0.0/8"], &[], &["192.168.0.0/24"]);
+        assert_eq!(status(f(), Some("10.0.0.5:4000"), None, "/bucket/a").await, StatusCode::OK);
+        assert_eq!(status(f(), Some("172.16.0.5:4000"), None, "/bucket/a").await, StatusCode::FORBIDDEN);
+        assert_eq!(status(f(), Some("192.168.0.5:4000"), None, "/healthz").await, StatusCode::OK);
+        assert_eq!(status(f(), None, None, "/bucket/a").await, StatusCode::FORBIDDEN);
+        // X-Forwarded-For is ignored unless trusted
+        assert_eq!(status(f(), Some("172.16.0.5:4000"), Some("10.0.0.5"), "/bucket/a").await, StatusCode::FORBIDDEN);
+
+        let trusted = || {
+            let mut filter = f();
+            filter.trust_forwarded_for = true;
+            filter
+        };
+        assert_eq!(
+            status(trusted(), Some("172.16.0.5:4000"), Some("172.16.9.9, 10.0.0.5"), "/bucket/a").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(trusted(), Some("10.0.0.5:4000"), Some("10.0.0.5, 172.16.9.9"), "/bucket/a").await,
+            StatusCode::FORBIDDEN
+        );
+    }
+}
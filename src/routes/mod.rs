@@ -5,38 +5,954 @@
 //! - PUT /{bucket}/{key} - PutObject
 //! - DELETE /{bucket}/{key} - DeleteObject
 //! - HEAD /{bucket}/{key} - HeadObject
+//! - GET / - ListBuckets
 //! - GET /{bucket}?prefix=... - ListObjectsV2
+//! - GET /{bucket}?notification - GetBucketNotificationConfiguration
+//! - PUT /{bucket}?notification - PutBucketNotificationConfiguration
 //! - PUT /{bucket} - CreateBucket (noop)
 //! - DELETE /{bucket} - DeleteBucket (noop)
+//!
+//! Other query subresources answer `NotImplemented`.
 
+mod dispatch;
 mod handlers;
+pub mod ip_filter;
+mod operation;
+pub mod read_only;
+pub mod virtual_host;
 
 use axum::{
-    routing::get,
+    extract::RawQuery,
+    http::{Method, StatusCode},
+    routing::{any, get},
     Router,
 };
 use std::sync::Arc;
 
-use crate::storage::StorageBackend;
+use crate::storage::BucketRegistry;
+
+pub use operation::{access_log_operation, operation_class, operation_name};
 
 /// Query parameters for ListObjects operation
 #[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct ListObjectsQuery {
     pub prefix: Option<String>,
-    pub max_keys: Option<u32>,
-    #[allow(dead_code)] // Reserved for future pagination support
+    pub delimiter: Option<String>,
+    /// Validated by the handler, to report bad values as `InvalidArgument`
+    pub max_keys: Option<String>,
     pub continuation_token: Option<String>,
+    /// Key to list after, unless continuing from a token
+    pub start_after: Option<String>,
+    /// `url` to URL-encode keys in the response
+    pub encoding_type: Option<String>,
+    /// `2` for ListObjectsV2, absent for ListObjects
+    pub list_type: Option<u32>,
+    /// Report the owner of each object in ListObjectsV2
+    pub fetch_owner: Option<bool>,
+}
+
+/// Whether a path is served by the proxy itself rather than the S3 API
+///
+/// Health, readiness and metrics endpoints are exempt from S3 request
+/// authentication so orchestrators and scrapers can reach them.
+pub fn is_system_path(path: &str) -> bool {
+    matches!(path, "/healthz" | "/healthz/deep" | "/ready" | "/metrics" | "/version") || is_admin_path(path)
+}
+
+/// Whether a path is an admin endpoint, which needs the admin token
+///
+/// Matched exactly so objects in a bucket named `admin` stay reachable.
+pub fn is_admin_path(path: &str) -> bool {
+    matches!(path, "/admin/loglevel" | "/admin/config" | "/admin/quotas")
+}
+
+/// Create the router serving both the S3 API and the system endpoints
+pub fn create_router(registry: Arc<BucketRegistry>) -> Router {
+    system_routes().merge(s3_routes()).with_state(registry)
 }
 
-/// Create the S3 API router
-pub fn create_router(storage: Arc<dyn StorageBackend>) -> Router {
+/// Create the router serving only the S3 API
+///
+/// System paths answer 404 rather than being taken for bucket names, so
+/// they stay exempt from authentication without reaching any bucket.
+pub fn create_s3_router(registry: Arc<BucketRegistry>) -> Router {
+    let system_paths = ["/healthz", "/healthz/deep", "/ready", "/metrics", "/version"];
+    let admin_paths = ["/admin/loglevel", "/admin/config", "/admin/quotas"];
+    let not_served = any(|| async { StatusCode::NOT_FOUND });
+    system_paths
+        .into_iter()
+        .chain(admin_paths)
+        .fold(s3_routes(), |router, path| router.route(path, not_served.clone()))
+        .with_state(registry)
+}
+
+/// Create the router serving only the system endpoints
+pub fn create_system_router(registry: Arc<BucketRegistry>) -> Router {
+    system_routes().with_state(registry)
+}
+
+/// Health, readiness, metrics, version and admin endpoints
+fn system_routes() -> Router<Arc<BucketRegistry>> {
     use handlers;
     Router::new()
         .route("/healthz", get(handlers::health))
+        .route("/healthz/deep", get(handlers::deep_health))
         .route("/ready", get(handlers::ready))
         .route("/metrics", get(handlers::metrics))
-        .route("/:bucket", get(handlers::list_objects).put(handlers::create_bucket).delete(handlers::delete_bucket))
-        .route("/:bucket/*key", get(handlers::get_object).put(handlers::put_object).delete(handlers::delete_object).head(handlers::head_object))
-        .with_state(storage)
+        .route("/version", get(handlers::version))
+        .route("/admin/loglevel", get(handlers::get_log_level).put(handlers::set_log_level))
+        .route("/admin/config", get(handlers::get_config))
+        .route("/admin/quotas", get(handlers::get_quotas))
 }
 
+/// S3 API operations
+///
+/// Bucket and object requests are dispatched by query subresource.
+/// Unsupported methods get S3's `MethodNotAllowed` and unmatched paths
+/// `NoSuchKey` or `NoSuchBucket`, which SDKs can parse, rather than empty
+/// responses.
+fn s3_routes() -> Router<Arc<BucketRegistry>> {
+    let method_not_allowed = |resource_type| {
+        move |method: Method, RawQuery(query): RawQuery| dispatch::method_not_allowed(method, query, resource_type)
+    };
+    Router::new()
+        .route("/", get(handlers::list_buckets).fallback(method_not_allowed("SERVICE")))
+        .route(
+            "/:bucket",
+            get(dispatch::get_bucket)
+                .put(dispatch::put_bucket)
+                .delete(dispatch::delete_bucket)
+                .fallback(method_not_allowed("BUCKET")),
+        )
+        .route(
+            "/:bucket/*key",
+            get(dispatch::get_object)
+                .put(dispatch::put_object)
+                .delete(dispatch::delete_object)
+                .head(dispatch::head_object)
+                .fallback(method_not_allowed("OBJECT")),
+        )
+        .fallback(handlers::no_such_resource)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    use crate::config::AwsConfig;
+    use crate::storage::{AwsBackend, LocalBackend, MockBackend, MockOperation, PrefixedBackend, StorageBackend};
+
+    fn single(backend: impl StorageBackend + 'static) -> Router {
+        create_router(Arc::new(BucketRegistry::single(Arc::new(backend))))
+    }
+
+    async fn call(router: &Router, method: &str, uri: &str, body: &'static str) -> (StatusCode, String) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::from(body))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    async fn send(router: &Router, method: &str, uri: &str, body: &'static str) -> StatusCode {
+        call(router, method, uri, body).await.0
+    }
+
+    #[tokio::test]
+    async fn test_traversal_keys_cannot_escape_prefix() {
+        let root = tempfile::tempdir().unwrap();
+        let backend = LocalBackend::new(root.path())
+            .unwrap()
+            .with_prefix(Some("tenant".to_string()));
+        let router = single(backend);
+
+        for uri in [
+            "/bucket/../escaped",
+            "/bucket/a/../../escaped",
+            "/bucket/%2E%2E/escaped",
+            "/bucket/./escaped",
+        ] {
+            assert_eq!(send(&router, "PUT", uri, "x").await, StatusCode::BAD_REQUEST, "{uri}");
+            assert_eq!(send(&router, "GET", uri, "").await, StatusCode::BAD_REQUEST, "{uri}");
+            assert_eq!(send(&router, "DELETE", uri, "").await, StatusCode::BAD_REQUEST, "{uri}");
+        }
+
+        // Nothing may have been written anywhere under the backend root
+        assert!(!root.path().join("escaped").exists());
+        assert!(!root.path().join("tenant").exists());
+
+        // A well-formed key still lands inside the prefix
+        assert_eq!(send(&router, "PUT", "/bucket/ok/file", "x").await, StatusCode::OK);
+        assert!(root.path().join("tenant/ok/file").exists());
+    }
+
+    #[tokio::test]
+    async fn test_control_characters_and_long_keys_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        let router = single(LocalBackend::new(root.path()).unwrap());
+
+        assert_eq!(send(&router, "PUT", "/bucket/a%00b", "x").await, StatusCode::BAD_REQUEST);
+        assert_eq!(send(&router, "PUT", "/bucket/a%0Ab", "x").await, StatusCode::BAD_REQUEST);
+
+        let long_key = format!("/bucket/{}", "k".repeat(crate::s3::MAX_KEY_LENGTH + 1));
+        let (status, body) = call(&router, "PUT", &long_key, "x").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("<Code>KeyTooLongError</Code>"));
+    }
+
+    #[tokio::test]
+    async fn test_anonymous_aws_backend_refuses_writes_locally() {
+        // Stand-in for S3, recording the requests reaching it
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let s3 = Router::new().fallback({
+            let seen = seen.clone();
+            move |method: axum::http::Method, uri: axum::http::Uri| async move {
+                seen.lock().unwrap().push(format!("{method} {}", uri.path()));
+                StatusCode::NOT_FOUND
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, s3).await });
+
+        let config: AwsConfig = toml::from_str(&format!(
+            "bucket_name = \"public\"\nregion = \"us-east-1\"\nendpoint = \"http://{address}\"\n\
+             allow_http = true\nanonymous = true"
+        ))
+        .unwrap();
+        let router = single(AwsBackend::new(&config).await.unwrap());
+
+        let (status, body) = call(&router, "PUT", "/bucket/a.txt", "data").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(body.contains("<Code>AccessDenied</Code>"), "{body}");
+        assert!(seen.lock().unwrap().is_empty());
+
+        assert_eq!(send(&router, "GET", "/bucket/a.txt", "").await, StatusCode::NOT_FOUND);
+        assert_eq!(*seen.lock().unwrap(), ["GET /public/a.txt"]);
+    }
+
+    #[tokio::test]
+    async fn test_directory_markers_coexist_with_objects() {
+        let root = tempfile::tempdir().unwrap();
+        let router = single(LocalBackend::new(root.path()).unwrap());
+
+        assert_eq!(send(&router, "PUT", "/bucket/data/", "").await, StatusCode::OK);
+        assert_eq!(send(&router, "PUT", "/bucket/data/file.txt", "hello").await, StatusCode::OK);
+        assert_eq!(send(&router, "PUT", "/bucket/data/sub/", "").await, StatusCode::OK);
+
+        assert_eq!(send(&router, "HEAD", "/bucket/data/", "").await, StatusCode::OK);
+        assert_eq!(call(&router, "GET", "/bucket/data/", "").await, (StatusCode::OK, String::new()));
+        assert_eq!(send(&router, "HEAD", "/bucket/missing/", "").await, StatusCode::NOT_FOUND);
+
+        // Flat listing shows the markers as keys with a trailing slash
+        let (status, body) = call(&router, "GET", "/bucket?prefix=data/", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("<Key>data/</Key>"), "{body}");
+        assert!(body.contains("<Key>data/file.txt</Key>"), "{body}");
+        assert!(body.contains("<Key>data/sub/</Key>"), "{body}");
+        assert!(!body.contains(crate::s3::DIRECTORY_MARKER), "{body}");
+
+        // With a delimiter, the marker for the prefix itself is a key while
+        // the nested marker is grouped into CommonPrefixes
+        let (_, body) = call(&router, "GET", "/bucket?prefix=data/&delimiter=/", "").await;
+        assert!(body.contains("<Key>data/</Key>"), "{body}");
+        assert!(body.contains("<Key>data/file.txt</Key>"), "{body}");
+        assert!(body.contains("<CommonPrefixes><Prefix>data/sub/</Prefix></CommonPrefixes>"), "{body}");
+        assert!(!body.contains("<Key>data/sub/</Key>"), "{body}");
+
+        let (_, body) = call(&router, "GET", "/bucket?delimiter=/", "").await;
+        assert!(body.contains("<CommonPrefixes><Prefix>data/</Prefix></CommonPrefixes>"), "{body}");
+        assert!(!body.contains("<Key>"), "{body}");
+
+        // Deleting the marker leaves the real objects alone
+        assert_eq!(send(&router, "DELETE", "/bucket/data/", "").await, StatusCode::NO_CONTENT);
+        assert_eq!(send(&router, "HEAD", "/bucket/data/", "").await, StatusCode::NOT_FOUND);
+        assert_eq!(call(&router, "GET", "/bucket/data/file.txt", "").await, (StatusCode::OK, "hello".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_backend_prefix_is_hidden_from_listings() {
+        let root = tempfile::tempdir().unwrap();
+        let backend = LocalBackend::new(root.path())
+            .unwrap()
+            .with_prefix(Some("tenants/alpha".to_string()));
+        let router = single(backend);
+
+        assert_eq!(send(&router, "PUT", "/bucket/data/file.bin", "payload").await, StatusCode::OK);
+        assert_eq!(send(&router, "PUT", "/bucket/data/100%25.txt", "pct").await, StatusCode::OK);
+        assert_eq!(send(&router, "PUT", "/bucket/data/nested/", "").await, StatusCode::OK);
+        assert!(root.path().join("tenants/alpha/data/file.bin").exists());
+
+        let (status, body) = call(&router, "GET", "/bucket?prefix=data/", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("<Prefix>data/</Prefix>"), "{body}");
+        assert!(body.contains("<Key>data/file.bin</Key>"), "{body}");
+        assert!(body.contains("<Key>data/100%.txt</Key>"), "{body}");
+        assert!(!body.contains("tenants"), "{body}");
+
+        let (_, body) = call(&router, "GET", "/bucket?prefix=data/&delimiter=/", "").await;
+        assert!(body.contains("<CommonPrefixes><Prefix>data/nested/</Prefix></CommonPrefixes>"), "{body}");
+        assert!(!body.contains("tenants"), "{body}");
+
+        // Every listed key can be fetched back through the same namespace
+        assert_eq!(call(&router, "GET", "/bucket/data/file.bin", "").await, (StatusCode::OK, "payload".to_string()));
+        assert_eq!(call(&router, "GET", "/bucket/data/100%25.txt", "").await, (StatusCode::OK, "pct".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_slash_delimited_listing_grouped_by_backend() {
+        let mock = Arc::new(MockBackend::new());
+        for key in ["logs/2024/a.log", "logs/2025/b.log", "logs/2025.txt", "logs/other/c.log"] {
+            mock.put(key, bytes::Bytes::from("x")).await.unwrap();
+        }
+        let router = create_router(Arc::new(BucketRegistry::single(mock.clone())));
+
+        let (_, body) = call(&router, "GET", "/bucket?prefix=logs/2025&delimiter=/", "").await;
+        assert!(body.contains("<CommonPrefixes><Prefix>logs/2025/</Prefix></CommonPrefixes>"), "{body}");
+        assert!(body.contains("<Key>logs/2025.txt</Key>"), "{body}");
+        assert!(!body.contains("2024") && !body.contains("other"), "{body}");
+
+        // Never listed recursively
+        assert!(mock.calls_of(MockOperation::List).is_empty());
+        assert_eq!(mock.calls_of(MockOperation::ListWithDelimiter)[0].path, "logs");
+    }
+
+    /// Text of the first `tag` element in `body`
+    fn element<'a>(body: &'a str, tag: &str) -> Option<&'a str> {
+        let start = body.find(&format!("<{tag}>"))? + tag.len() + 2;
+        let end = start + body[start..].find(&format!("</{tag}>"))?;
+        Some(&body[start..end])
+    }
+
+    /// Follow continuation tokens through a whole listing, returning every
+    /// key and common prefix in order
+    async fn list_all(router: &Router, query: &str) -> Vec<String> {
+        let mut listed = vec![];
+        let mut token: Option<String> = None;
+        loop {
+            let uri = match &token {
+                Some(token) => format!("/bucket?{query}&continuation-token={}", percent_encoding::utf8_percent_encode(token, percent_encoding::NON_ALPHANUMERIC)),
+                None => format!("/bucket?{query}"),
+            };
+            let (status, body) = call(router, "GET", &uri, "").await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            let mut page: Vec<String> = body
+                .split("<Key>")
+                .skip(1)
+                .chain(body.split("<CommonPrefixes><Prefix>").skip(1))
+                .map(|entry| entry[..entry.find('<').unwrap()].to_string())
+                .collect();
+            page.sort();
+            listed.extend(page);
+            token = element(&body, "NextContinuationToken").map(str::to_string);
+            assert_eq!(token.is_some(), element(&body, "IsTruncated") == Some("true"), "{body}");
+            if token.is_none() {
+                return listed;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_listing_pages_bounded() {
+        let mock = Arc::new(MockBackend::new());
+        let mut keys: Vec<String> = (0..10_000).map(|i| format!("data/{i:05}")).collect();
+        for key in &keys {
+            mock.put(key, bytes::Bytes::from("x")).await.unwrap();
+        }
+        let router = create_router(Arc::new(BucketRegistry::single(mock.clone())));
+
+        let (_, body) = call(&router, "GET", "/bucket?prefix=data/&max-keys=10", "").await;
+        assert_eq!(body.matches("<Key>").count(), 10, "{body}");
+        assert_eq!(element(&body, "IsTruncated"), Some("true"));
+        let lists = mock.calls_of(MockOperation::List);
+        assert_eq!(lists.len(), 1);
+        assert_eq!(lists[0].size, Some(11));
+
+        let mut listed = list_all(&router, "prefix=data/&max-keys=250").await;
+        assert_eq!(listed, keys);
+        // Every call asked for one page
+        let lists = mock.calls_of(MockOperation::List);
+        assert!(lists.iter().all(|call| call.size <= Some(251)));
+        assert_eq!(lists.len(), 1 + 40);
+
+        keys.sort();
+        listed.sort();
+        listed.dedup();
+        assert_eq!(listed.len(), keys.len());
+    }
+
+    #[tokio::test]
+    async fn test_listing_pages_through_common_prefixes() {
+        let mock = Arc::new(MockBackend::new());
+        for key in ["a-1", "a-2", "a-3", "b", "c-1", "c-2", "d"] {
+            mock.put(key, bytes::Bytes::from("x")).await.unwrap();
+        }
+        let router = create_router(Arc::new(BucketRegistry::single(mock.clone())));
+
+        let listed = list_all(&router, "delimiter=-&max-keys=1").await;
+        assert_eq!(listed, ["a-", "b", "c-", "d"]);
+
+        let mock = Arc::new(MockBackend::new());
+        for key in ["logs/a/1", "logs/b/1", "logs/c", "logs/d/1"] {
+            mock.put(key, bytes::Bytes::from("x")).await.unwrap();
+        }
+        let router = create_router(Arc::new(BucketRegistry::single(mock)));
+        let listed = list_all(&router, "prefix=logs/&delimiter=/&max-keys=2").await;
+        assert_eq!(listed, ["logs/a/", "logs/b/", "logs/c", "logs/d/"]);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_continuation_token_rejected() {
+        let router = single(MockBackend::new());
+        let (status, body) = call(&router, "GET", "/bucket?continuation-token=%25%25", "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("<Code>InvalidArgument</Code>"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_list_prefix_matches_partial_segments() {
+        let root = tempfile::tempdir().unwrap();
+        let router = single(LocalBackend::new(root.path()).unwrap());
+
+        send(&router, "PUT", "/bucket/logs/app-1.log", "1").await;
+        send(&router, "PUT", "/bucket/logs/web-1.log", "2").await;
+
+        let (_, body) = call(&router, "GET", "/bucket?prefix=logs/app", "").await;
+        assert!(body.contains("<Key>logs/app-1.log</Key>"), "{body}");
+        assert!(!body.contains("web-1"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_named_buckets_route_to_their_backends() {
+        let logs_root = tempfile::tempdir().unwrap();
+        let data_root = tempfile::tempdir().unwrap();
+        let mut registry = BucketRegistry::new();
+        registry
+            .insert("logs", Arc::new(LocalBackend::new(logs_root.path()).unwrap()))
+            .unwrap();
+        registry
+            .insert("ml-data", Arc::new(LocalBackend::new(data_root.path()).unwrap()))
+            .unwrap();
+        let router = create_router(Arc::new(registry));
+
+        assert_eq!(send(&router, "PUT", "/logs/app.log", "log").await, StatusCode::OK);
+        assert_eq!(send(&router, "PUT", "/ml-data/model.bin", "model").await, StatusCode::OK);
+        assert!(logs_root.path().join("app.log").exists());
+        assert!(data_root.path().join("model.bin").exists());
+        assert_eq!(send(&router, "GET", "/ml-data/app.log", "").await, StatusCode::NOT_FOUND);
+
+        let (status, body) = call(&router, "GET", "/unknown/app.log", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.contains("<Code>NoSuchBucket</Code>"), "{body}");
+        let (status, body) = call(&router, "GET", "/unknown", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.contains("<Code>NoSuchBucket</Code>"), "{body}");
+
+        let (status, body) = call(&router, "GET", "/", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("<Bucket><Name>logs</Name>"), "{body}");
+        assert!(body.contains("<Bucket><Name>ml-data</Name>"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_methods_and_paths_answer_s3_errors() {
+        let root = tempfile::tempdir().unwrap();
+        let mut registry = BucketRegistry::new();
+        registry
+            .insert("logs", Arc::new(LocalBackend::new(root.path()).unwrap()))
+            .unwrap();
+        let router = create_router(Arc::new(registry));
+
+        for (method, uri, resource_type, allow) in [
+            ("PATCH", "/logs/app.log", "OBJECT", "GET,HEAD,PUT,DELETE"),
+            ("POST", "/logs/app.log", "OBJECT", "GET,HEAD,PUT,DELETE"),
+            ("PATCH", "/logs", "BUCKET", "GET,HEAD,PUT,DELETE"),
+            ("POST", "/", "SERVICE", "GET,HEAD"),
+            ("PUT", "/", "SERVICE", "GET,HEAD"),
+        ] {
+            let request = Request::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED, "{method} {uri}");
+            assert_eq!(response.headers()["allow"], allow, "{method} {uri}");
+            assert_eq!(response.headers()["content-type"], "application/xml", "{method} {uri}");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body = String::from_utf8_lossy(&body);
+            assert!(body.contains("<Code>MethodNotAllowed</Code>"), "{body}");
+            assert!(body.contains(&format!("<Method>{method}</Method>")), "{body}");
+            assert!(body.contains(&format!("<ResourceType>{resource_type}</ResourceType>")), "{body}");
+        }
+
+        // An empty key is no route's, but still an object that does not exist
+        let (status, body) = call(&router, "GET", "/logs/", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.contains("<Code>NoSuchKey</Code>"), "{body}");
+        let (status, body) = call(&router, "PATCH", "/unknown/", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.contains("<Code>NoSuchBucket</Code>"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_listing_escapes_keys() {
+        use quick_xml::events::Event;
+
+        let root = tempfile::tempdir().unwrap();
+        let router = single(LocalBackend::new(root.path()).unwrap());
+        assert_eq!(send(&router, "PUT", "/bucket/reports/P%26L%20%3C2024%3E.csv", "x").await, StatusCode::OK);
+        assert_eq!(send(&router, "PUT", "/bucket/reports/it%27s%20%22final%22.csv", "x").await, StatusCode::OK);
+
+        let (status, body) = call(&router, "GET", "/bucket?list-type=2&prefix=reports/", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let mut reader = quick_xml::Reader::from_str(&body);
+        let (mut in_key, mut keys) = (false, Vec::new());
+        loop {
+            match reader.read_event().unwrap_or_else(|e| panic!("{e}: {body}")) {
+                Event::Start(start) => in_key = start.name().as_ref() == b"Key",
+                Event::Text(text) if in_key => keys.push(text.unescape().unwrap().into_owned()),
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        keys.sort();
+        assert_eq!(keys, ["reports/P&L <2024>.csv", "reports/it's \"final\".csv"]);
+    }
+
+    #[tokio::test]
+    async fn test_owners_listed_by_list_type_and_fetch_owner() {
+        let root = tempfile::tempdir().unwrap();
+        let router = single(LocalBackend::new(root.path()).unwrap());
+        assert_eq!(send(&router, "PUT", "/bucket/a.txt", "x").await, StatusCode::OK);
+
+        let owner = "<Owner><ID>s3proxy</ID><DisplayName>s3proxy</DisplayName></Owner>";
+        for (uri, owned) in [
+            ("/bucket", true),
+            ("/bucket?list-type=2", false),
+            ("/bucket?list-type=2&fetch-owner=false", false),
+            ("/bucket?list-type=2&fetch-owner=true", true),
+        ] {
+            let (status, body) = call(&router, "GET", uri, "").await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body.contains(owner), owned, "{uri}: {body}");
+        }
+
+        // The identity comes from the configuration
+        let mut config = crate::config::Config::default();
+        config.owner.id = "tenant-a".to_string();
+        config.owner.display_name = "Tenant A".to_string();
+        let router = router.layer(axum::Extension(Arc::new(config)));
+        let (_, body) = call(&router, "GET", "/bucket?list-type=2&fetch-owner=true", "").await;
+        assert!(body.contains("<Owner><ID>tenant-a</ID><DisplayName>Tenant A</DisplayName></Owner>"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_list_parameters_validated() {
+        let root = tempfile::tempdir().unwrap();
+        let router = single(LocalBackend::new(root.path()).unwrap());
+        for key in ["a", "b", "c"] {
+            assert_eq!(send(&router, "PUT", &format!("/bucket/{key}"), "x").await, StatusCode::OK);
+        }
+
+        // max-keys: listed keys, reported MaxKeys and IsTruncated
+        for (max_keys, keys, reported, truncated) in [
+            ("0", 0, "0", "true"),
+            ("1", 1, "1", "true"),
+            ("2", 2, "2", "true"),
+            ("3", 3, "3", "false"),
+            ("1000", 3, "1000", "false"),
+            ("1001", 3, "1000", "false"),
+            ("100000", 3, "1000", "false"),
+            ("4294967295", 3, "1000", "false"),
+        ] {
+            let (status, body) = call(&router, "GET", &format!("/bucket?list-type=2&max-keys={max_keys}"), "").await;
+            assert_eq!(status, StatusCode::OK, "{max_keys}: {body}");
+            assert_eq!(body.matches("<Key>").count(), keys, "{max_keys}: {body}");
+            assert_eq!(element(&body, "MaxKeys"), Some(reported), "{max_keys}: {body}");
+            assert_eq!(element(&body, "IsTruncated"), Some(truncated), "{max_keys}: {body}");
+        }
+
+        // An empty page resumes where it started
+        let (_, body) = call(&router, "GET", "/bucket?max-keys=1", "").await;
+        let token = element(&body, "NextContinuationToken").unwrap().to_string();
+        let token_param = percent_encoding::utf8_percent_encode(&token, percent_encoding::NON_ALPHANUMERIC);
+        let (_, body) = call(&router, "GET", &format!("/bucket?max-keys=0&continuation-token={token_param}"), "").await;
+        assert_eq!(element(&body, "NextContinuationToken"), Some(token.as_str()), "{body}");
+
+        for (query, argument) in [
+            ("max-keys=-1", Some("max-keys")),
+            ("max-keys=abc", Some("max-keys")),
+            ("max-keys=", Some("max-keys")),
+            ("max-keys=1.5", Some("max-keys")),
+            ("max-keys=4294967296", Some("max-keys")),
+            ("continuation-token=%25%25%25", Some("continuation-token")),
+            ("continuation-token=Li4v", Some("continuation-token")),
+            ("list-type=3", Some("list-type")),
+            ("encoding-type=base64", Some("encoding-type")),
+            ("list-type=two", None),
+            ("fetch-owner=maybe", None),
+        ] {
+            let (status, body) = call(&router, "GET", &format!("/bucket?{query}"), "").await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{query}: {body}");
+            assert_eq!(element(&body, "Code"), Some("InvalidArgument"), "{query}: {body}");
+            assert_eq!(element(&body, "ArgumentName"), argument, "{query}: {body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_objects_v2_echoes_parameters() {
+        let root = tempfile::tempdir().unwrap();
+        let router = single(LocalBackend::new(root.path()).unwrap());
+        for key in ["a", "b%2Bc%20d", "dir/e", "f"] {
+            assert_eq!(send(&router, "PUT", &format!("/bucket/{key}"), "x").await, StatusCode::OK);
+        }
+
+        let (status, body) = call(&router, "GET", "/bucket?list-type=2", "").await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert!(body.contains(r#"<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">"#), "{body}");
+        assert_eq!(element(&body, "KeyCount"), Some("4"), "{body}");
+        for absent in ["StartAfter", "ContinuationToken", "NextContinuationToken", "Delimiter", "EncodingType"] {
+            assert_eq!(element(&body, absent), None, "{absent}: {body}");
+        }
+
+        // KeyCount counts common prefixes too
+        let query = "list-type=2&delimiter=/&start-after=a&encoding-type=url&max-keys=2";
+        let (status, body) = call(&router, "GET", &format!("/bucket?{query}"), "").await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(element(&body, "StartAfter"), Some("a"), "{body}");
+        assert_eq!(element(&body, "Delimiter"), Some("/"), "{body}");
+        assert_eq!(element(&body, "EncodingType"), Some("url"), "{body}");
+        assert_eq!(element(&body, "KeyCount"), Some("2"), "{body}");
+        assert_eq!(element(&body, "Key"), Some("b%2Bc+d"), "{body}");
+        assert_eq!(element(&body, "ContinuationToken"), None, "{body}");
+        let token = element(&body, "NextContinuationToken").unwrap().to_string();
+
+        // The token carries on past start-after, and is echoed back
+        let token_param = percent_encoding::utf8_percent_encode(&token, percent_encoding::NON_ALPHANUMERIC);
+        let (_, body) = call(&router, "GET", &format!("/bucket?{query}&continuation-token={token_param}"), "").await;
+        assert_eq!(element(&body, "ContinuationToken"), Some(token.as_str()), "{body}");
+        assert_eq!(element(&body, "Key"), Some("f"), "{body}");
+        assert_eq!(element(&body, "KeyCount"), Some("1"), "{body}");
+        assert_eq!(element(&body, "NextContinuationToken"), None, "{body}");
+
+        // ListObjects has no KeyCount
+        let (_, body) = call(&router, "GET", "/bucket", "").await;
+        assert_eq!(element(&body, "KeyCount"), None, "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_put_object_attributes_and_etags() {
+        let router = single(crate::storage::MemoryBackend::new(&Default::default()));
+        let request = Request::builder()
+            .method("PUT")
+            .uri("/bucket/report.csv")
+            .header("content-type", "text/csv")
+            .header("cache-control", "max-age=60")
+            .header("x-amz-meta-owner", "team-a")
+            .body(Body::from("a,b"))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let put_etag = response.headers()["etag"].to_str().unwrap().to_string();
+        assert!(put_etag.starts_with('"') && put_etag.ends_with('"'), "{put_etag}");
+
+        let request = Request::get("/bucket/report.csv").body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let headers = response.headers();
+        assert_eq!(headers["content-type"], "text/csv");
+        assert_eq!(headers["cache-control"], "max-age=60");
+        assert_eq!(headers["x-amz-meta-owner"], "team-a");
+        assert_eq!(headers["etag"], put_etag.as_str());
+
+        // HEAD and listings report the ETag the PUT returned
+        let request = Request::head("/bucket/report.csv").body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.headers()["etag"], put_etag.as_str());
+        let (_, body) = call(&router, "GET", "/bucket?list-type=2", "").await;
+        let listed = element(&body, "ETag").unwrap().replace("&quot;", "\"");
+        assert_eq!(listed, put_etag, "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_put_object_streams_large_bodies() {
+        let router = single(crate::storage::MemoryBackend::new(&Default::default()));
+        // Past one part, and of unknown length: both streamed to the backend
+        let large: Vec<u8> = (0..crate::storage::DEFAULT_PART_SIZE + 1024).map(|i| i as u8).collect();
+        let request = Request::put("/bucket/large.bin")
+            .header("content-length", large.len())
+            .body(Body::from(large.clone()))
+            .unwrap();
+        assert_eq!(router.clone().oneshot(request).await.unwrap().status(), StatusCode::OK);
+        let chunks = futures::stream::iter([Ok::<_, std::io::Error>("a,"), Ok("b")]);
+        let request = Request::put("/bucket/chunked.csv")
+            .body(Body::from_stream(chunks))
+            .unwrap();
+        assert_eq!(router.clone().oneshot(request).await.unwrap().status(), StatusCode::OK);
+
+        let request = Request::get("/bucket/large.bin").body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, large);
+        assert_eq!(call(&router, "GET", "/bucket/chunked.csv", "").await, (StatusCode::OK, "a,b".to_string()));
+    }
+
+    #[test]
+    fn test_duplicate_bucket_names_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        let backend: Arc<dyn StorageBackend> = Arc::new(LocalBackend::new(root.path()).unwrap());
+        let mut registry = BucketRegistry::new();
+        registry.insert("logs", backend.clone()).unwrap();
+        assert!(registry.insert("logs", backend).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bucket_aliases_share_one_backend() {
+        let root = tempfile::tempdir().unwrap();
+        let backend: Arc<dyn StorageBackend> = Arc::new(LocalBackend::new(root.path()).unwrap());
+        let mut registry = BucketRegistry::new();
+        registry
+            .insert("team-a", Arc::new(PrefixedBackend::new(backend.clone(), "team-a/")))
+            .unwrap();
+        registry
+            .insert("team-b", Arc::new(PrefixedBackend::new(backend, "team-b/")))
+            .unwrap();
+        let router = create_router(Arc::new(registry));
+
+        assert_eq!(send(&router, "PUT", "/team-a/report.csv", "a").await, StatusCode::OK);
+        assert_eq!(send(&router, "PUT", "/team-b/report.csv", "b").await, StatusCode::OK);
+        assert!(root.path().join("team-a/report.csv").exists());
+        assert!(root.path().join("team-b/report.csv").exists());
+
+        assert_eq!(call(&router, "GET", "/team-a/report.csv", "").await, (StatusCode::OK, "a".to_string()));
+        assert_eq!(call(&router, "GET", "/team-b/report.csv", "").await, (StatusCode::OK, "b".to_string()));
+
+        let (_, body) = call(&router, "GET", "/team-a", "").await;
+        assert!(body.contains("<Key>report.csv</Key>"), "{body}");
+        assert!(!body.contains("team-a/"), "{body}");
+
+        let (status, body) = call(&router, "GET", "/team-c/report.csv", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.contains("<Code>NoSuchBucket</Code>"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_virtual_hosted_and_path_style_are_equivalent() {
+        use axum::middleware::from_fn_with_state;
+        use tower::Layer;
+
+        let root = tempfile::tempdir().unwrap();
+        let mut registry = BucketRegistry::new();
+        registry
+            .insert("media", Arc::new(LocalBackend::new(root.path()).unwrap()))
+            .unwrap();
+        let domains = Arc::new(vec!["s3.example.com".to_string()]);
+        let app = from_fn_with_state(domains, virtual_host::rewrite).layer(create_router(Arc::new(registry)));
+
+        let call = |method: &'static str, host: &'static str, uri: &'static str, body: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header("host", host)
+                    .body(Body::from(body))
+                    .unwrap();
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, String::from_utf8_lossy(&body).into_owned())
+            }
+        };
+
+        // Write virtual-hosted-style, read back path-style and vice versa
+        let (status, _) = call("PUT", "media.s3.example.com:8080", "/img/cat.png", "meow").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(root.path().join("img/cat.png").exists());
+        assert_eq!(
+            call("GET", "s3.example.com", "/media/img/cat.png", "").await,
+            (StatusCode::OK, "meow".to_string())
+        );
+        call("PUT", "s3.example.com", "/media/img/dog.png", "woof").await;
+        assert_eq!(
+            call("GET", "media.s3.example.com", "/img/dog.png", "").await,
+            (StatusCode::OK, "woof".to_string())
+        );
+
+        // Bucket-level operations see the bucket from the host
+        let (status, vhost_list) = call("GET", "media.s3.example.com", "/?prefix=img/", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(vhost_list.contains("<Name>media</Name>"), "{vhost_list}");
+        assert!(vhost_list.contains("<Key>img/cat.png</Key>"), "{vhost_list}");
+        assert!(vhost_list.contains("<Key>img/dog.png</Key>"), "{vhost_list}");
+        let (_, path_list) = call("GET", "s3.example.com", "/media?prefix=img/", "").await;
+        assert_eq!(vhost_list.replace(|c: char| c.is_ascii_hexdigit(), ""), path_list.replace(|c: char| c.is_ascii_hexdigit(), ""));
+
+        // The bare domain keeps path-style semantics
+        let (status, body) = call("GET", "s3.example.com", "/", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("<Name>media</Name>"), "{body}");
+
+        // Unknown virtual-hosted buckets are rejected like path-style ones
+        let (status, body) = call("GET", "other.s3.example.com", "/img/cat.png", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body.contains("<Code>NoSuchBucket</Code>"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_ready_reports_shutdown() {
+        let root = tempfile::tempdir().unwrap();
+        let readiness = crate::server::Readiness::default();
+        let router = single(LocalBackend::new(root.path()).unwrap()).layer(axum::Extension(readiness.clone()));
+
+        assert_eq!(send(&router, "GET", "/ready", "").await, StatusCode::OK);
+        readiness.set_unready();
+        assert_eq!(send(&router, "GET", "/ready", "").await, StatusCode::SERVICE_UNAVAILABLE);
+        // Liveness is unaffected while draining
+        assert_eq!(send(&router, "GET", "/healthz", "").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_deep_health_reports_components() {
+        let root = tempfile::tempdir().unwrap();
+        let router = single(LocalBackend::new(root.path()).unwrap());
+
+        let (status, body) = call(&router, "GET", "/healthz/deep", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(report["status"], "ok");
+        assert_eq!(report["components"][0]["name"], "backend:*");
+        assert_eq!(report["components"][0]["status"], "ok");
+        assert!(report["components"][0]["latency_ms"].is_number());
+
+        let mut checks = crate::health::HealthChecks::new(std::time::Duration::from_secs(1));
+        checks.register(Arc::new(crate::health::BackendHealth::new(
+            "b",
+            Arc::new(LocalBackend::new(root.path()).unwrap()),
+            Some("missing".to_string()),
+        )));
+        let router = router.layer(axum::Extension(Arc::new(checks)));
+        let (status, body) = call(&router, "GET", "/healthz/deep", "").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(report["status"], "fail");
+        assert_eq!(report["components"][0]["name"], "backend:b");
+        assert!(report["components"][0]["error"].is_string());
+
+        // Plain liveness stays cheap and unaffected
+        assert_eq!(call(&router, "GET", "/healthz", "").await, (StatusCode::OK, "OK".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_log_level() {
+        use tracing_subscriber::{reload, EnvFilter, Registry};
+
+        let root = tempfile::tempdir().unwrap();
+        let (_layer, handle) = reload::Layer::<EnvFilter, Registry>::new(EnvFilter::new("info"));
+        let log_filter = Arc::new(crate::logging::LogFilter::new(handle));
+        let router = single(LocalBackend::new(root.path()).unwrap()).layer(axum::Extension(log_filter.clone()));
+
+        let (status, body) = call(&router, "PUT", "/admin/loglevel", "s3proxy=debug").await;
+        assert_eq!(status, StatusCode::OK);
+        let changed: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(changed["previous"], "info");
+        assert_eq!(changed["filter"], "s3proxy=debug");
+
+        let (status, _) = call(&router, "PUT", "/admin/loglevel", "s3proxy=chatty").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, body) = call(&router, "GET", "/admin/loglevel", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("s3proxy=debug"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_admin_config_is_redacted() {
+        let root = tempfile::tempdir().unwrap();
+        let config: crate::config::Config = toml::from_str(
+            r#"
+            [server]
+            [backend]
+            type = "aws"
+            bucket_name = "visible-bucket"
+            region = "us-east-1"
+            secret_access_key = "backend-secret-value"
+            [auth]
+            admin_token = "admin-secret-value"
+            "#,
+        )
+        .unwrap();
+        let router = single(LocalBackend::new(root.path()).unwrap()).layer(axum::Extension(Arc::new(config)));
+
+        let (status, body) = call(&router, "GET", "/admin/config", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("visible-bucket"), "{body}");
+        assert!(!body.contains("secret-value"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_admin_quotas() {
+        let root = tempfile::tempdir().unwrap();
+        let (status, _) = call(&single(LocalBackend::new(root.path()).unwrap()), "GET", "/admin/quotas", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let config = toml::from_str("[[prefixes]]\nprefix = 'team'\nmax_bytes = 10").unwrap();
+        let quotas = crate::storage::Quotas::from_config(&config).unwrap();
+        let backend = quotas.wrap(None, Arc::new(LocalBackend::new(root.path()).unwrap()));
+        let router = create_router(Arc::new(BucketRegistry::single(backend).with_quotas(quotas.clone())));
+        quotas.scan().await;
+        assert_eq!(call(&router, "PUT", "/bucket/team/a", "0123456789").await.0, StatusCode::OK);
+        let (status, body) = call(&router, "PUT", "/bucket/team/b", "x").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(body.contains("<Code>QuotaExceeded</Code>"), "{body}");
+
+        let (status, body) = call(&router, "GET", "/admin/quotas", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(report[0]["prefix"], "team");
+        assert_eq!(report[0]["used_bytes"], 10);
+        assert_eq!(report[0]["used_objects"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_version() {
+        let root = tempfile::tempdir().unwrap();
+        let router = single(LocalBackend::new(root.path()).unwrap());
+
+        let (status, body) = call(&router, "GET", "/version", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(info["commit"], crate::version::COMMIT);
+        assert!(info["build_timestamp"].is_string());
+        assert!(info["features"].is_array());
+        assert_eq!(info["read_only"], false);
+
+        let router = router.layer(axum::Extension(read_only::ReadOnly::new(true)));
+        let (_, body) = call(&router, "GET", "/version", "").await;
+        let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(info["read_only"], true);
+    }
+
+    #[tokio::test]
+    async fn test_ready_probes_backend() {
+        let root = tempfile::tempdir().unwrap();
+        let config = crate::config::ReadinessConfig {
+            sentinel_key: Some("sentinel".to_string()),
+            cache_secs: 0,
+            ..Default::default()
+        };
+        let probe = Arc::new(crate::server::BackendProbe::new(&config));
+        let router = single(LocalBackend::new(root.path()).unwrap()).layer(axum::Extension(probe));
+
+        let (status, body) = call(&router, "GET", "/ready", "").await;
+        assert_eq!((status, body.as_str()), (StatusCode::SERVICE_UNAVAILABLE, "Backend unavailable"));
+
+        assert_eq!(send(&router, "PUT", "/bucket/sentinel", "ok").await, StatusCode::OK);
+        assert_eq!(send(&router, "GET", "/ready", "").await, StatusCode::OK);
+    }
+}
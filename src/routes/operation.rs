@@ -0,0 +1,168 @@
+//! S3 operation classification
+//!
+//! Maps a path-style request to the S3 operation it invokes, from the
+//! method, the bucket/key shape of the path and the query subresources.
+//! Metrics, authorization and logging all label requests with these names,
+//! so the set is fixed: anything unrecognized is `Unknown` rather than a
+//! value derived from the raw request.
+
+use http::Method;
+
+/// Operation name for requests that match no known S3 operation
+pub const UNKNOWN_OPERATION: &str = "Unknown";
+
+/// Whether the query string contains `name`, with or without a value
+pub(crate) fn has_param(query: Option<&str>, name: &str) -> bool {
+    query.is_some_and(|query| {
+        query
+            .split('&')
+            .any(|pair| pair.split_once('=').map_or(pair, |(key, _)| key) == name)
+    })
+}
+
+/// Normalized S3 operation name for a path-style request
+pub fn operation_name(method: &Method, path: &str, query: Option<&str>) -> &'static str {
+    let mut segments = path.trim_start_matches('/').splitn(2, '/');
+    let bucket = segments.next().filter(|s| !s.is_empty());
+    let has_key = segments.next().is_some_and(|key| !key.is_empty());
+    let param = |name| has_param(query, name);
+    let bare = query.unwrap_or("").is_empty();
+
+    match (bucket, has_key, method.as_str()) {
+        (None, _, "GET") => "ListBuckets",
+        (None, _, _) => UNKNOWN_OPERATION,
+
+        (Some(_), false, "GET") if param("uploads") => "ListMultipartUploads",
+        (Some(_), false, "GET") if param("location") => "GetBucketLocation",
+        (Some(_), false, "GET") if param("versioning") => "GetBucketVersioning",
+        (Some(_), false, "GET") if param("versions") => "ListObjectVersions",
+        (Some(_), false, "GET") if param("acl") => "GetBucketAcl",
+        (Some(_), false, "GET") if param("policy") => "GetBucketPolicy",
+        (Some(_), false, "GET") if param("notification") => "GetBucketNotificationConfiguration",
+        (Some(_), false, "GET") if param("list-type") => "ListObjectsV2",
+        (Some(_), false, "GET") => "ListObjects",
+        (Some(_), false, "HEAD") => "HeadBucket",
+        (Some(_), false, "PUT") if param("notification") => "PutBucketNotificationConfiguration",
+        (Some(_), false, "PUT") if bare => "CreateBucket",
+        (Some(_), false, "DELETE") if bare => "DeleteBucket",
+        (Some(_), false, "POST") if param("delete") => "DeleteObjects",
+
+        (Some(_), true, "GET") if param("uploadId") => "ListParts",
+        (Some(_), true, "GET") if param("acl") => "GetObjectAcl",
+        (Some(_), true, "GET") if param("tagging") => "GetObjectTagging",
+        (Some(_), true, "GET") => "GetObject",
+        (Some(_), true, "HEAD") => "HeadObject",
+        (Some(_), true, "PUT") if param("uploadId") => "UploadPart",
+        (Some(_), true, "PUT") if param("tagging") => "PutObjectTagging",
+        (Some(_), true, "PUT") if param("acl") => "PutObjectAcl",
+        (Some(_), true, "PUT") => "PutObject",
+        (Some(_), true, "DELETE") if param("uploadId") => "AbortMultipartUpload",
+        (Some(_), true, "DELETE") if param("tagging") => "DeleteObjectTagging",
+        (Some(_), true, "DELETE") => "DeleteObject",
+        (Some(_), true, "POST") if param("uploads") => "CreateMultipartUpload",
+        (Some(_), true, "POST") if param("uploadId") => "CompleteMultipartUpload",
+
+        _ => UNKNOWN_OPERATION,
+    }
+}
+
+/// Whether an operation transfers object data (`data`) or only metadata
+/// (`metadata`), for coarse labels such as the in-flight gauge
+pub fn operation_class(operation: &str) -> &'static str {
+    match operation {
+        "GetObject" | "PutObject" | "UploadPart" => "data",
+        _ => "metadata",
+    }
+}
+
+/// Operation in the `REST.<VERB>.<RESOURCE>` form of S3 server access logs
+///
+/// Requests that match no known operation are logged by method as
+/// `REST.<VERB>.UNKNOWN`.
+pub fn access_log_operation(method: &Method, operation: &str) -> String {
+    let resource = match operation {
+        "ListBuckets" => "SERVICE",
+        "ListObjects" | "ListObjectsV2" | "HeadBucket" | "CreateBucket" | "DeleteBucket" => "BUCKET",
+        "ListMultipartUploads" | "CreateMultipartUpload" => "UPLOADS",
+        "GetBucketLocation" => "LOCATION",
+        "GetBucketVersioning" => "VERSIONING",
+        "ListObjectVersions" => "BUCKETVERSIONS",
+        "GetBucketAcl" | "GetObjectAcl" | "PutObjectAcl" => "ACL",
+        "GetBucketPolicy" => "BUCKETPOLICY",
+        "GetBucketNotificationConfiguration" | "PutBucketNotificationConfiguration" => "NOTIFICATION",
+        "DeleteObjects" => "MULTI_OBJECT_DELETE",
+        "ListParts" | "AbortMultipartUpload" | "CompleteMultipartUpload" => "UPLOAD",
+        "GetObjectTagging" | "PutObjectTagging" | "DeleteObjectTagging" => "OBJECT_TAGGING",
+        "UploadPart" => "PART",
+        "GetObject" | "HeadObject" | "PutObject" | "DeleteObject" => "OBJECT",
+        _ => "UNKNOWN",
+    };
+    format!("REST.{}.{}", method, resource)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operation_names() {
+        let cases = [
+            (Method::GET, "/", None, "ListBuckets"),
+            (Method::GET, "/bucket", Some("list-type=2&prefix=a"), "ListObjectsV2"),
+            (Method::GET, "/bucket/", None, "ListObjects"),
+            (Method::GET, "/bucket", Some("uploads"), "ListMultipartUploads"),
+            (Method::GET, "/bucket", Some("location="), "GetBucketLocation"),
+            (Method::HEAD, "/bucket", None, "HeadBucket"),
+            (Method::PUT, "/bucket", None, "CreateBucket"),
+            (Method::DELETE, "/bucket", None, "DeleteBucket"),
+            (Method::POST, "/bucket", Some("delete"), "DeleteObjects"),
+            (Method::GET, "/bucket/a/b", None, "GetObject"),
+            (Method::GET, "/bucket/a", Some("uploadId=1"), "ListParts"),
+            (Method::HEAD, "/bucket/a", None, "HeadObject"),
+            (Method::PUT, "/bucket/a", Some("partNumber=1&uploadId=1"), "UploadPart"),
+            (Method::PUT, "/bucket/a", None, "PutObject"),
+            (Method::DELETE, "/bucket/a", None, "DeleteObject"),
+            (Method::DELETE, "/bucket/a", Some("uploadId=1"), "AbortMultipartUpload"),
+            (Method::POST, "/bucket/a", Some("uploads"), "CreateMultipartUpload"),
+            (Method::POST, "/bucket/a", Some("uploadId=1"), "CompleteMultipartUpload"),
+        ];
+        for (method, path, query, operation) in cases {
+            assert_eq!(operation_name(&method, path, query), operation, "{method} {path}?{query:?}");
+        }
+    }
+
+    #[test]
+    fn test_access_log_operations() {
+        let cases = [
+            (Method::GET, "/bucket/a", None, "REST.GET.OBJECT"),
+            (Method::PUT, "/bucket/a", None, "REST.PUT.OBJECT"),
+            (Method::HEAD, "/bucket", None, "REST.HEAD.BUCKET"),
+            (Method::GET, "/bucket", Some("list-type=2"), "REST.GET.BUCKET"),
+            (Method::GET, "/", None, "REST.GET.SERVICE"),
+            (Method::POST, "/bucket", Some("delete"), "REST.POST.MULTI_OBJECT_DELETE"),
+            (Method::PUT, "/bucket/a", Some("partNumber=1&uploadId=1"), "REST.PUT.PART"),
+            (Method::PATCH, "/bucket/a", None, "REST.PATCH.UNKNOWN"),
+        ];
+        for (method, path, query, expected) in cases {
+            let operation = operation_name(&method, path, query);
+            assert_eq!(access_log_operation(&method, operation), expected, "{method} {path}?{query:?}");
+        }
+    }
+
+    #[test]
+    fn test_unknown_operations() {
+        let cases = [
+            (Method::POST, "/", None),
+            (Method::PATCH, "/bucket/a", None),
+            (Method::POST, "/bucket", None),
+            (Method::POST, "/bucket/a", None),
+            (Method::PUT, "/bucket", Some("lifecycle")),
+            (Method::DELETE, "/bucket", Some("cors")),
+        ];
+        for (method, path, query) in cases {
+            assert_eq!(operation_name(&method, path, query), UNKNOWN_OPERATION, "{method} {path}?{query:?}");
+        }
+        // Parameter names must match exactly, not as a prefix or a value
+        assert_eq!(operation_name(&Method::GET, "/bucket", Some("uploadsx&x=uploads")), "ListObjects");
+    }
+}
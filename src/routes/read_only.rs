@@ -0,0 +1,116 @@
+//! Read-only mode
+//!
+//! Rejects every S3 mutation, PUT, POST and DELETE on bucket and object
+//! routes, with 403 `AccessDenied`, for public mirrors and buckets frozen
+//! during a migration. Reads and listings keep working, and health,
+//! readiness, metrics and admin endpoints are unaffected. The mode is a
+//! switch shared with the server, so it can change without a restart.
+
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::errors::S3ProxyError;
+use crate::metrics::READ_ONLY;
+use crate::routes;
+
+/// Whether S3 writes are rejected
+#[derive(Debug, Clone, Default)]
+pub struct ReadOnly(Arc<AtomicBool>);
+
+impl ReadOnly {
+    pub fn new(enabled: bool) -> Self {
+        let read_only = Self::default();
+        read_only.set(enabled);
+        read_only
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Switch the mode, for every request from now on
+    pub fn set(&self, enabled: bool) {
+        self.0.store(enabled, Ordering::SeqCst);
+        READ_ONLY.set(enabled.into());
+    }
+}
+
+/// Whether a request would modify a bucket or object
+fn is_write(request: &Request) -> bool {
+    matches!(*request.method(), Method::PUT | Method::POST | Method::DELETE)
+        && !routes::is_system_path(request.uri().path())
+}
+
+/// Middleware rejecting S3 writes while the proxy is read-only
+pub async fn reject_writes(State(read_only): State<ReadOnly>, request: Request, next: Next) -> Response {
+    if read_only.is_enabled() && is_write(&request) {
+        debug!(method = %request.method(), path = request.uri().path(), "Rejected write in read-only mode");
+        return S3ProxyError::AccessDenied("The proxy is in read-only mode".to_string()).into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::middleware::from_fn_with_state;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    use crate::storage::{BucketRegistry, MockBackend, MockOperation, StorageBackend};
+
+    async fn call(router: &Router, method: &str, uri: &str) -> (StatusCode, String) {
+        let request = Request::builder().method(method).uri(uri).body(Body::from("data")).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    #[tokio::test]
+    async fn test_read_only_rejects_writes() {
+        let mock = Arc::new(MockBackend::new());
+        mock.put("a.txt", bytes::Bytes::from("data")).await.unwrap();
+        let read_only = ReadOnly::new(true);
+        let router = routes::create_router(Arc::new(BucketRegistry::single(mock.clone())))
+            .layer(from_fn_with_state(read_only.clone(), reject_writes));
+
+        for (method, uri) in [
+            ("PUT", "/bucket/b.txt"),
+            ("PUT", "/bucket"),
+            ("DELETE", "/bucket/a.txt"),
+            ("DELETE", "/bucket"),
+            ("POST", "/bucket?delete"),
+            ("POST", "/bucket/b.txt?uploads"),
+        ] {
+            let (status, body) = call(&router, method, uri).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{method} {uri}");
+            assert!(body.contains("<Code>AccessDenied</Code>"), "{body}");
+            assert!(body.contains("read-only"), "{body}");
+        }
+        assert_eq!(mock.calls_of(MockOperation::Put).len(), 1);
+        assert!(mock.calls_of(MockOperation::Delete).is_empty());
+
+        // Reads, listings and system endpoints are unaffected
+        assert_eq!(call(&router, "GET", "/bucket/a.txt").await, (StatusCode::OK, "data".to_string()));
+        assert_eq!(call(&router, "HEAD", "/bucket/a.txt").await.0, StatusCode::OK);
+        assert_eq!(call(&router, "GET", "/bucket?list-type=2").await.0, StatusCode::OK);
+        assert_eq!(call(&router, "GET", "/").await.0, StatusCode::OK);
+        assert_eq!(call(&router, "GET", "/healthz").await.0, StatusCode::OK);
+        assert_eq!(call(&router, "GET", "/metrics").await.0, StatusCode::OK);
+
+        // Switched off at runtime, writes go through again
+        read_only.set(false);
+        assert_eq!(call(&router, "PUT", "/bucket/b.txt").await.0, StatusCode::OK);
+        assert_eq!(call(&router, "DELETE", "/bucket/a.txt").await.0, StatusCode::NO_CONTENT);
+    }
+}
@@ -0,0 +1,105 @@
+//! Virtual-hosted-style addressing
+//!
+//! Clients using virtual-hosted-style URLs send `GET /key` with a Host header
+//! of `<bucket>.<base-domain>`. This middleware rewrites such requests to the
+//! path-style form `GET /<bucket>/key` before routing, so every handler sees
+//! the same request shape regardless of addressing style. Requests to the
+//! bare base domain (or any other host) are passed through untouched.
+//!
+//! Because it changes the URI, the middleware must wrap the whole `Router`
+//! rather than being added with `Router::layer`, which runs after routing.
+//! The URI as sent by the client is kept in the `OriginalUri` extension,
+//! which request signature verification relies on.
+
+use axum::{
+    extract::{OriginalUri, Request, State},
+    http::{header, Uri},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::Arc;
+use tracing::debug;
+
+/// Extract the bucket name from a Host header value
+///
+/// Returns the bucket when `host` (with any port removed) is a subdomain of
+/// one of the configured base `domains`.
+pub fn bucket_from_host<'a>(host: &'a str, domains: &[String]) -> Option<&'a str> {
+    let host = strip_port(host);
+    domains.iter().find_map(|domain| {
+        let domain = domain.trim_matches('.');
+        let split = host.len().checked_sub(domain.len() + 1)?;
+        let bucket = host.get(..split)?;
+        let suffix = host.get(split..)?.strip_prefix('.')?;
+        (!bucket.is_empty() && suffix.eq_ignore_ascii_case(domain)).then_some(bucket)
+    })
+}
+
+/// Remove the port from a Host header value, keeping IPv6 literals intact
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        return host.split_inclusive(']').next().unwrap_or(host);
+    }
+    host.rsplit_once(':').map(|(h, _)| h).unwrap_or(host)
+}
+
+/// Rewrite virtual-hosted-style requests to path-style
+pub async fn rewrite(
+    State(domains): State<Arc<Vec<String>>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if domains.is_empty() {
+        return next.run(request).await;
+    }
+
+    let host = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .or_else(|| request.uri().host())
+        .map(str::to_string);
+
+    if let Some(bucket) = host.as_deref().and_then(|h| bucket_from_host(h, &domains)) {
+        let uri = request.uri();
+        let path = match uri.path() {
+            "/" => format!("/{}", bucket),
+            path => format!("/{}{}", bucket, path),
+        };
+        let path_and_query = match uri.query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => path,
+        };
+
+        match path_and_query.parse::<Uri>() {
+            Ok(rewritten) => {
+                let original = request.uri().clone();
+                request.extensions_mut().insert(OriginalUri(original));
+                debug!(bucket = %bucket, uri = %rewritten, "Rewrote virtual-hosted-style request");
+                *request.uri_mut() = rewritten;
+            }
+            Err(e) => debug!(error = %e, "Failed to rewrite virtual-hosted-style request"),
+        }
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_from_host() {
+        let domains = vec!["s3.example.com".to_string()];
+        assert_eq!(bucket_from_host("logs.s3.example.com", &domains), Some("logs"));
+        assert_eq!(bucket_from_host("logs.s3.example.com:8080", &domains), Some("logs"));
+        assert_eq!(bucket_from_host("my.dotted.bucket.s3.example.com", &domains), Some("my.dotted.bucket"));
+        assert_eq!(bucket_from_host("LOGS.S3.Example.com", &domains), Some("LOGS"));
+        assert_eq!(bucket_from_host("s3.example.com", &domains), None);
+        assert_eq!(bucket_from_host(".s3.example.com", &domains), None);
+        assert_eq!(bucket_from_host("logs.other.com", &domains), None);
+        assert_eq!(bucket_from_host("logss3.example.com", &domains), None);
+        assert_eq!(bucket_from_host("[::1]:8080", &domains), None);
+    }
+}
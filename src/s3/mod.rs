@@ -1,32 +1,108 @@
 //! S3-compatible API response types and utilities
 //!
 //! Provides XML response generation for S3-compatible operations
-//! including ListObjectsV2, error responses, and metadata handling.
+//! including ListObjectsV2, error responses, and metadata handling, and
+//! parsing of XML request bodies.
 
+#[allow(dead_code)] // DeleteObjects, CompleteMultipartUpload and PutObjectTagging are not served yet
+mod request;
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use quick_xml::events::Event as XmlEvent;
+use quick_xml::reader::Reader;
 use quick_xml::se::to_string;
-use serde::Serialize;
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+use object_store::{Attribute, Attributes};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use crate::errors::S3ProxyError;
+use crate::storage::ObjectAttributes;
+
+pub use request::{read_xml, XmlRequest};
+
+/// Maximum object key length in bytes (S3 limit)
+pub const MAX_KEY_LENGTH: usize = 1024;
+
+/// Backend key of the notification configuration of a bucket, set by
+/// `PUT /{bucket}?notification`; reserved, so neither listed nor usable as
+/// an object key
+pub const NOTIFICATION_CONFIGURATION_KEY: &str = ".s3proxy-notification-configuration";
+
+/// Backend object name standing in for a directory marker key
+///
+/// object_store paths cannot end in a delimiter, so a client key such as
+/// `logs/2024/` (as created by Hadoop s3a, s3fs or the AWS console) is stored
+/// as `logs/2024/.s3proxy-directory-marker` and mapped back when listing.
+pub const DIRECTORY_MARKER: &str = ".s3proxy-directory-marker";
+
 /// S3 error response structure
-#[derive(Debug, Serialize)]
-#[serde(rename = "Error")]
-#[allow(dead_code)] // Used by error_xml function
+#[derive(Debug, Default, Serialize)]
+#[serde(rename = "Error", rename_all = "PascalCase")]
 pub struct S3Error {
     pub code: String,
     pub message: String,
-    pub resource: Option<String>,
-    pub request_id: Option<String>,
+    /// Method of a `MethodNotAllowed` error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    /// Resource type of a `MethodNotAllowed` error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resource_type: Option<String>,
+    /// Query parameter of an `InvalidArgument` error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub argument_name: Option<String>,
+    /// Rejected value of the query parameter
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub argument_value: Option<String>,
+    pub resource: String,
+    pub request_id: String,
+    pub host_id: String,
+}
+
+impl S3Error {
+    /// Convert to XML string
+    pub fn to_xml(&self) -> Result<String, quick_xml::DeError> {
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>{}"#,
+            to_string(self)?
+        );
+        Ok(xml)
+    }
 }
 
+/// Namespace of S3 response documents
+pub const S3_NAMESPACE: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
 /// ListObjectsV2 response structure
+///
+/// Elements are in the order S3 sends them. Optional elements echo request
+/// parameters and are omitted when the parameter was not given, as S3 does.
 #[derive(Debug, Serialize)]
 #[serde(rename = "ListBucketResult", rename_all = "PascalCase")]
 pub struct ListObjectsV2Result {
+    #[serde(rename = "@xmlns")]
+    pub xmlns: &'static str,
     pub name: String,
     pub prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_after: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub continuation_token: Option<String>,
+    /// Token listing the rest of a truncated result
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_continuation_token: Option<String>,
+    /// Keys and common prefixes in the page, reported by ListObjectsV2 only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_count: Option<u32>,
     pub max_keys: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delimiter: Option<String>,
+    /// `url` when keys, prefixes and the delimiter are URL-encoded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding_type: Option<String>,
     pub is_truncated: bool,
     pub contents: Vec<Object>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub common_prefixes: Option<Vec<CommonPrefix>>,
 }
 
@@ -36,14 +112,29 @@ pub struct ListObjectsV2Result {
 pub struct Object {
     pub key: String,
     pub last_modified: String,
-    pub etag: String,
+    /// Left out when the backend assigned none
+    #[serde(rename = "ETag", skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
     pub size: u64,
+    /// Reported by ListObjects, and by ListObjectsV2 with `fetch-owner=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Owner>,
     #[serde(rename = "StorageClass")]
     pub storage_class: String,
 }
 
+/// Owner of an object
+#[derive(Debug, Clone, Serialize)]
+pub struct Owner {
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(rename = "DisplayName")]
+    pub display_name: String,
+}
+
 /// Common prefix entry in ListObjects response
 #[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
 pub struct CommonPrefix {
     pub prefix: String,
 }
@@ -53,9 +144,16 @@ impl ListObjectsV2Result {
     #[allow(dead_code)] // Reserved for future use
     pub fn new(bucket: String, prefix: Option<String>, max_keys: u32) -> Self {
         Self {
+            xmlns: S3_NAMESPACE,
             name: bucket,
             prefix,
+            start_after: None,
+            continuation_token: None,
+            next_continuation_token: None,
+            key_count: None,
             max_keys,
+            delimiter: None,
+            encoding_type: None,
             is_truncated: false,
             contents: vec![],
             common_prefixes: None,
@@ -72,38 +170,647 @@ impl ListObjectsV2Result {
     }
 }
 
+/// ListBuckets response structure
+#[derive(Debug, Serialize)]
+#[serde(rename = "ListAllMyBucketsResult", rename_all = "PascalCase")]
+pub struct ListAllMyBucketsResult {
+    pub buckets: Buckets,
+}
+
+/// Bucket list wrapper in ListBuckets response
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Buckets {
+    pub bucket: Vec<Bucket>,
+}
+
+/// Bucket entry in ListBuckets response
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Bucket {
+    pub name: String,
+    pub creation_date: String,
+}
+
+impl ListAllMyBucketsResult {
+    /// Convert to XML string
+    pub fn to_xml(&self) -> Result<String, quick_xml::DeError> {
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>{}"#,
+            to_string(self)?
+        );
+        Ok(xml)
+    }
+}
+
+/// CopyObject response structure
+#[derive(Debug, Serialize)]
+#[serde(rename = "CopyObjectResult", rename_all = "PascalCase")]
+pub struct CopyObjectResult {
+    pub last_modified: String,
+    /// Left out when the backend assigned none
+    #[serde(rename = "ETag", skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+}
+
+impl CopyObjectResult {
+    /// Convert to XML string
+    pub fn to_xml(&self) -> Result<String, quick_xml::DeError> {
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>{}"#,
+            to_string(self)?
+        );
+        Ok(xml)
+    }
+}
+
+/// Bucket notification configuration of `GET` and `PUT /{bucket}?notification`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "NotificationConfiguration")]
+pub struct NotificationConfiguration {
+    #[serde(rename = "$value", default)]
+    pub destinations: Vec<NotificationDestination>,
+}
+
+/// Destination of a notification configuration, by destination type
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum NotificationDestination {
+    #[serde(rename = "TopicConfiguration")]
+    Topic(NotificationRule),
+    #[serde(rename = "QueueConfiguration")]
+    Queue(NotificationRule),
+    #[serde(rename = "CloudFunctionConfiguration")]
+    CloudFunction(NotificationRule),
+}
+
+/// Destination types of a notification configuration
+pub const NOTIFICATION_DESTINATION_TYPES: [&str; 3] =
+    ["TopicConfiguration", "QueueConfiguration", "CloudFunctionConfiguration"];
+
+impl NotificationDestination {
+    pub fn rule(&self) -> &NotificationRule {
+        match self {
+            NotificationDestination::Topic(rule)
+            | NotificationDestination::Queue(rule)
+            | NotificationDestination::CloudFunction(rule) => rule,
+        }
+    }
+
+    pub fn rule_mut(&mut self) -> &mut NotificationRule {
+        match self {
+            NotificationDestination::Topic(rule)
+            | NotificationDestination::Queue(rule)
+            | NotificationDestination::CloudFunction(rule) => rule,
+        }
+    }
+
+    /// ARN of the destination, from the element matching the type
+    pub fn arn(&self) -> Option<&str> {
+        let rule = self.rule();
+        match self {
+            NotificationDestination::Topic(_) => rule.topic.as_deref(),
+            NotificationDestination::Queue(_) => rule.queue.as_deref(),
+            NotificationDestination::CloudFunction(_) => rule.cloud_function.as_deref(),
+        }
+    }
+}
+
+/// Events of a notification configuration and where they are sent
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct NotificationRule {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloud_function: Option<String>,
+    #[serde(rename = "Event", default)]
+    pub events: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<NotificationFilter>,
+}
+
+/// Key filter of a notification configuration
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NotificationFilter {
+    #[serde(rename = "S3Key", default)]
+    pub key: KeyFilter,
+}
+
+/// Rules of a key filter
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeyFilter {
+    #[serde(rename = "FilterRule", default)]
+    pub rules: Vec<FilterRule>,
+}
+
+/// `prefix` or `suffix` rule of a key filter
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct FilterRule {
+    pub name: String,
+    pub value: String,
+}
+
+impl XmlRequest for NotificationConfiguration {
+    const ROOT: &'static str = "NotificationConfiguration";
+
+    /// Parse a configuration, rejecting destination types other than
+    /// topics, queues and cloud functions with `InvalidArgument`
+    fn from_xml(xml: &str) -> Result<Self, S3ProxyError> {
+        let malformed = |e: &dyn std::fmt::Display| S3ProxyError::MalformedXml(e.to_string());
+        let mut reader = Reader::from_str(xml);
+        let mut depth = 0;
+        loop {
+            let (element, nested) = match reader.read_event().map_err(|e| malformed(&e))? {
+                XmlEvent::Start(element) => (element, true),
+                XmlEvent::Empty(element) => (element, false),
+                XmlEvent::End(_) => {
+                    depth -= 1;
+                    continue;
+                }
+                XmlEvent::Eof => break,
+                _ => continue,
+            };
+            let name = String::from_utf8_lossy(element.local_name().as_ref()).into_owned();
+            if depth == 0 && name != Self::ROOT {
+                return Err(malformed(&format!("unexpected root element {}", name)));
+            }
+            if depth == 1 && !NOTIFICATION_DESTINATION_TYPES.contains(&name.as_str()) {
+                return Err(S3ProxyError::InvalidArgument(format!(
+                    "Unsupported notification destination type: {}",
+                    name
+                )));
+            }
+            if nested {
+                depth += 1;
+            }
+        }
+        quick_xml::de::from_str(xml).map_err(|e| malformed(&e))
+    }
+}
+
+impl NotificationConfiguration {
+    /// Convert to XML string
+    pub fn to_xml(&self) -> Result<String, quick_xml::DeError> {
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>{}"#,
+            to_string(self)?
+        );
+        Ok(xml)
+    }
+}
+
 /// Generate S3-compatible error XML
 #[allow(dead_code)] // Utility function for future error handling
 pub fn error_xml(code: &str, message: &str) -> String {
     let error = S3Error {
         code: code.to_string(),
         message: message.to_string(),
-        resource: None,
-        request_id: None,
+        ..S3Error::default()
     };
+    // Strings always serialize
+    error.to_xml().unwrap_or_default()
+}
+
+/// Validate an object key received from a client
+///
+/// Must run before the key is handed to a storage backend (and therefore
+/// before the backend prefix is applied) so that a crafted key can never
+/// resolve outside the configured prefix. Rejects:
+/// - keys longer than 1024 UTF-8 bytes (`KeyTooLongError`)
+/// - keys containing control characters (`InvalidRequest`)
+/// - keys containing `.` or `..` path segments (`InvalidRequest`)
+pub fn validate_key(key: &str) -> Result<(), S3ProxyError> {
+    if key.is_empty() {
+        return Err(S3ProxyError::InvalidRequest(
+            "Object key must not be empty".to_string(),
+        ));
+    }
+
+    if key.len() > MAX_KEY_LENGTH {
+        return Err(S3ProxyError::KeyTooLong { size: key.len() });
+    }
+
+    if key.chars().any(char::is_control) {
+        return Err(S3ProxyError::InvalidRequest(
+            "Object key must not contain control characters".to_string(),
+        ));
+    }
+
+    if key.split('/').any(|segment| segment == "." || segment == "..") {
+        return Err(S3ProxyError::InvalidRequest(
+            "Object key must not contain '.' or '..' path segments".to_string(),
+        ));
+    }
+
+    if key == NOTIFICATION_CONFIGURATION_KEY {
+        return Err(S3ProxyError::InvalidRequest(format!(
+            "Object key '{}' is reserved",
+            NOTIFICATION_CONFIGURATION_KEY
+        )));
+    }
+
+    if key.split('/').any(|segment| segment == DIRECTORY_MARKER) {
+        return Err(S3ProxyError::InvalidRequest(format!(
+            "Object key must not contain the reserved segment '{}'",
+            DIRECTORY_MARKER
+        )));
+    }
+
+    Ok(())
+}
 
-    format!(
-        r#"<?xml version="1.0" encoding="UTF-8"?>
-<Error>
-    <Code>{}</Code>
-    <Message>{}</Message>
-</Error>"#,
-        error.code, error.message
-    )
+/// Map a client-facing object key to the path stored in the backend
+///
+/// Keys ending in `/` are directory markers and are stored as a reserved
+/// child object, see [`DIRECTORY_MARKER`].
+pub fn to_storage_key(key: &str) -> String {
+    if key.ends_with('/') {
+        format!("{}{}", key, DIRECTORY_MARKER)
+    } else {
+        key.to_string()
+    }
+}
+
+/// Map a backend path back to the client-facing object key
+///
+/// Inverse of [`to_storage_key`]. `path` is the encoded form produced by
+/// `object_store::path::Path`, which percent-encodes characters such as `%`
+/// and `#`, so it is decoded here to return the key exactly as it was put.
+pub fn from_storage_key(path: &str) -> String {
+    let key = percent_decode_str(path).decode_utf8_lossy();
+    match key.strip_suffix(DIRECTORY_MARKER) {
+        Some(dir) if dir.ends_with('/') => dir.to_string(),
+        _ => key.into_owned(),
+    }
+}
+
+/// Characters left as they are by `encoding-type=url`: unreserved ones and
+/// the `/` separating key segments
+const LIST_KEY_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/')
+    .remove(b' ');
+
+/// Encode a key, prefix or delimiter of a listing with `encoding-type=url`
+///
+/// Spaces become `+` as in S3's responses, which SDKs decode as form data.
+pub fn url_encode_list_key(key: &str) -> String {
+    utf8_percent_encode(key, LIST_KEY_ENCODE_SET).to_string().replace(' ', "+")
 }
 
-/// Extract metadata from HTTP headers
-pub fn extract_metadata(headers: &axum::http::HeaderMap) -> HashMap<String, String> {
+/// Header naming the source object of a CopyObject request
+pub const COPY_SOURCE: &str = "x-amz-copy-source";
+
+/// Bucket and key of the object an `x-amz-copy-source` header names
+///
+/// The header is `bucket/key` with an optional leading `/`, percent-encoded,
+/// and may pick a version with `?versionId=`. The backends keep no
+/// versions, so only `versionId=null` is accepted.
+pub fn parse_copy_source(value: &str) -> Result<(String, String), S3ProxyError> {
+    let (source, query) = value.split_once('?').unwrap_or((value, ""));
+    if url::form_urlencoded::parse(query.as_bytes()).any(|(name, version)| name == "versionId" && version != "null") {
+        return Err(S3ProxyError::NotImplemented(
+            "Copying an object version is not implemented".to_string(),
+        ));
+    }
+    let invalid = || {
+        S3ProxyError::InvalidArgument(
+            "Copy Source must mention the source bucket and key: sourcebucket/sourcekey".to_string(),
+        )
+    };
+    let source = percent_decode_str(source).decode_utf8().map_err(|_| invalid())?;
+    let source = source.strip_prefix('/').unwrap_or(&source);
+    match source.split_once('/') {
+        Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => Ok((bucket.to_string(), key.to_string())),
+        _ => Err(invalid()),
+    }
+}
+
+/// Prefix of the headers carrying user metadata
+const METADATA_PREFIX: &str = "x-amz-meta-";
+
+/// Attributes a PutObject request asks to store with the object: its
+/// content headers, `x-amz-meta-*` user metadata and `x-amz-tagging` tags
+pub fn object_attributes(headers: &HeaderMap) -> ObjectAttributes {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+    // aws-chunked only describes how the body was sent, S3 doesn't store it
+    let content_encoding = header("content-encoding")
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|encoding| !encoding.eq_ignore_ascii_case("aws-chunked"))
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .filter(|value| !value.is_empty());
+
     let mut metadata = HashMap::new();
-    
-    for (key, value) in headers.iter() {
-        if let Some(key_str) = key.as_str().strip_prefix("x-amz-meta-") {
-            if let Ok(value_str) = value.to_str() {
-                metadata.insert(key_str.to_string(), value_str.to_string());
-            }
+    for (name, value) in headers {
+        if let (Some(key), Ok(value)) = (name.as_str().strip_prefix(METADATA_PREFIX), value.to_str()) {
+            metadata.insert(key.to_string(), value.to_string());
         }
     }
-    
-    metadata
+    let tags = header("x-amz-tagging")
+        .map(|tagging| url::form_urlencoded::parse(tagging.as_bytes()).into_owned().collect())
+        .unwrap_or_default();
+
+    ObjectAttributes {
+        content_type: header("content-type"),
+        cache_control: header("cache-control"),
+        content_disposition: header("content-disposition"),
+        content_encoding,
+        content_language: header("content-language"),
+        metadata,
+        tags,
+        create_only: false,
+    }
 }
 
+/// Response headers of the attributes stored with an object, leaving out
+/// any that are not valid headers
+pub fn attribute_headers(attributes: &Attributes) -> Vec<(HeaderName, HeaderValue)> {
+    attributes
+        .iter()
+        .filter_map(|(attribute, value)| {
+            let name = match attribute {
+                Attribute::ContentType => header::CONTENT_TYPE,
+                Attribute::CacheControl => header::CACHE_CONTROL,
+                Attribute::ContentDisposition => header::CONTENT_DISPOSITION,
+                Attribute::ContentEncoding => header::CONTENT_ENCODING,
+                Attribute::ContentLanguage => header::CONTENT_LANGUAGE,
+                Attribute::Metadata(key) => HeaderName::try_from(format!("{}{}", METADATA_PREFIX, key)).ok()?,
+                _ => return None,
+            };
+            Some((name, HeaderValue::from_str(value).ok()?))
+        })
+        .collect()
+}
+
+/// An ETag as S3 returns it, in double quotes
+pub fn quote_etag(etag: &str) -> String {
+    format!("\"{}\"", etag.trim_matches('"'))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Body captured from a client or from S3, in tests/fixtures/xml
+    pub(super) fn fixture(name: &str) -> Vec<u8> {
+        std::fs::read(format!("{}/tests/fixtures/xml/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+    }
+
+    /// The `<Contents>` elements of a listing
+    fn contents(xml: &str) -> &str {
+        &xml[xml.find("<Contents>").unwrap()..xml.rfind("</Contents>").unwrap() + "</Contents>".len()]
+    }
+
+    #[test]
+    fn test_object_attributes_from_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", "image/png".parse().unwrap());
+        headers.insert("content-encoding", "gzip, aws-chunked".parse().unwrap());
+        headers.insert("x-amz-meta-camera", "x100".parse().unwrap());
+        headers.insert("x-amz-tagging", "project=blue&stage=raw%20data".parse().unwrap());
+        headers.insert("x-amz-acl", "private".parse().unwrap());
+        let attributes = object_attributes(&headers);
+        assert_eq!(attributes.content_type.as_deref(), Some("image/png"));
+        assert_eq!(attributes.content_encoding.as_deref(), Some("gzip"));
+        assert_eq!(attributes.cache_control, None);
+        assert_eq!(attributes.metadata, HashMap::from([("camera".to_string(), "x100".to_string())]));
+        assert_eq!(attributes.tags["stage"], "raw data");
+        assert!(!attributes.create_only);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("content-encoding", "aws-chunked".parse().unwrap());
+        assert_eq!(object_attributes(&headers).content_encoding, None);
+
+        let stored = Attributes::from_iter([
+            (Attribute::ContentType, "image/png"),
+            (Attribute::Metadata("camera".into()), "x100"),
+        ]);
+        let mut headers = attribute_headers(&stored);
+        headers.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+        assert_eq!(
+            headers,
+            [
+                (header::CONTENT_TYPE, HeaderValue::from_static("image/png")),
+                (HeaderName::from_static("x-amz-meta-camera"), HeaderValue::from_static("x100")),
+            ]
+        );
+
+        assert_eq!(quote_etag("abc"), "\"abc\"");
+        assert_eq!(quote_etag("\"abc\""), "\"abc\"");
+    }
+
+    #[test]
+    fn test_parse_copy_source() {
+        let parsed = |value| parse_copy_source(value).unwrap();
+        let pair = |bucket: &str, key: &str| (bucket.to_string(), key.to_string());
+        assert_eq!(parsed("bucket/a/b.txt"), pair("bucket", "a/b.txt"));
+        assert_eq!(parsed("/bucket/a%20b%3F.txt"), pair("bucket", "a b?.txt"));
+        assert_eq!(parsed("/bucket/key?versionId=null"), pair("bucket", "key"));
+
+        for value in ["bucket", "/bucket/", "//key", "", "/bucket/%FF"] {
+            assert!(
+                matches!(parse_copy_source(value), Err(S3ProxyError::InvalidArgument(_))),
+                "{value}"
+            );
+        }
+        assert!(matches!(
+            parse_copy_source("bucket/key?versionId=3HL4kqtJlcpXroDTDmJ"),
+            Err(S3ProxyError::NotImplemented(_))
+        ));
+    }
+
+    #[test]
+    fn test_listing_contents_match_s3() {
+        let owner = Owner {
+            id: "75aa57f09aa0c8caeab4f8c24e99d10f8e7faeebf76c078efc7c6caea54ba06a".to_string(),
+            display_name: "mtd".to_string(),
+        };
+        for (name, owner) in [
+            ("list-objects-v2.xml", None),
+            ("list-objects-v2-fetch-owner.xml", Some(owner.clone())),
+            ("list-objects-v1.xml", Some(owner)),
+        ] {
+            let mut result = ListObjectsV2Result::new("example-bucket".to_string(), Some("photos/".to_string()), 1000);
+            result.contents.push(Object {
+                key: "photos/2024/cat.jpg".to_string(),
+                last_modified: "2024-03-01T12:00:00.000Z".to_string(),
+                etag: Some("\"fba9dede5f27731c9771645a39863328\"".to_string()),
+                size: 434234,
+                owner,
+                storage_class: "STANDARD".to_string(),
+            });
+            let expected = String::from_utf8(fixture(name)).unwrap();
+            assert_eq!(contents(&result.to_xml().unwrap()), contents(&expected), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_list_objects_v2_matches_s3() {
+        let object = |key: &str| Object {
+            key: key.to_string(),
+            last_modified: "2024-03-01T12:00:00.000Z".to_string(),
+            etag: Some("\"fba9dede5f27731c9771645a39863328\"".to_string()),
+            size: 434234,
+            owner: None,
+            storage_class: "STANDARD".to_string(),
+        };
+
+        // Parameters that were not given are left out
+        let mut result = ListObjectsV2Result::new("example-bucket".to_string(), Some("photos/".to_string()), 1000);
+        result.key_count = Some(1);
+        result.contents.push(object("photos/2024/cat.jpg"));
+        let expected = String::from_utf8(fixture("list-objects-v2.xml")).unwrap().replace('\n', "");
+        assert_eq!(result.to_xml().unwrap(), expected);
+
+        // A truncated, URL-encoded page echoes them and continues with a token
+        let mut result = ListObjectsV2Result::new("example-bucket".to_string(), Some("photos/2024/".to_string()), 2);
+        result.start_after = Some("photos/2024/a.jpg".to_string());
+        result.continuation_token = Some("cGhvdG9zLzIwMjQvYmlyZC5qcGc=".to_string());
+        result.next_continuation_token = Some("cGhvdG9zLzIwMjQvc3VtbWVyJTJCYmVhY2glMjBjYXQuanBn".to_string());
+        result.key_count = Some(2);
+        result.delimiter = Some("/".to_string());
+        result.encoding_type = Some("url".to_string());
+        result.is_truncated = true;
+        result.contents.push(object(&url_encode_list_key("photos/2024/summer+beach cat.jpg")));
+        result.common_prefixes = Some(vec![CommonPrefix {
+            prefix: url_encode_list_key("photos/2024/\u{e9}t\u{e9}/"),
+        }]);
+        let expected = String::from_utf8(fixture("list-objects-v2-truncated.xml")).unwrap().replace('\n', "");
+        assert_eq!(result.to_xml().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_validate_key_accepts_normal_keys() {
+        assert!(validate_key("file.txt").is_ok());
+        assert!(validate_key("data/2024/report.csv").is_ok());
+        assert!(validate_key("dots..in.name/..hidden/file...").is_ok());
+        assert!(validate_key(&"a".repeat(MAX_KEY_LENGTH)).is_ok());
+    }
+
+    #[test]
+    fn test_validate_key_rejects_traversal_segments() {
+        for key in ["..", "../etc/passwd", "a/../../b", "./a", "a/./b", "a/.."] {
+            assert!(
+                matches!(validate_key(key), Err(S3ProxyError::InvalidRequest(_))),
+                "expected {key:?} to be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_key_rejects_control_characters() {
+        for key in ["a\0b", "line\nbreak", "tab\there", "del\u{7f}"] {
+            assert!(
+                matches!(validate_key(key), Err(S3ProxyError::InvalidRequest(_))),
+                "expected {key:?} to be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_key_rejects_reserved_marker_segment() {
+        let key = format!("dir/{}", DIRECTORY_MARKER);
+        assert!(matches!(validate_key(&key), Err(S3ProxyError::InvalidRequest(_))));
+        assert!(matches!(
+            validate_key(NOTIFICATION_CONFIGURATION_KEY),
+            Err(S3ProxyError::InvalidRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_notification_configuration_round_trip() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <NotificationConfiguration xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+              <QueueConfiguration>
+                <Id>images</Id>
+                <Queue>arn:minio:sqs::primary:webhook</Queue>
+                <Event>s3:ObjectCreated:*</Event>
+                <Event>s3:ObjectRemoved:Delete</Event>
+                <Filter>
+                  <S3Key>
+                    <FilterRule><Name>prefix</Name><Value>images/</Value></FilterRule>
+                    <FilterRule><Name>suffix</Name><Value>.jpg</Value></FilterRule>
+                  </S3Key>
+                </Filter>
+              </QueueConfiguration>
+              <TopicConfiguration>
+                <Topic>arn:aws:sns:us-east-1:123456789012:kafka</Topic>
+                <Event>s3:ObjectRemoved:*</Event>
+              </TopicConfiguration>
+            </NotificationConfiguration>"#;
+        let configuration = NotificationConfiguration::from_xml(xml).unwrap();
+        assert_eq!(configuration.destinations.len(), 2);
+        let queue = &configuration.destinations[0];
+        assert_eq!(queue.arn(), Some("arn:minio:sqs::primary:webhook"));
+        assert_eq!(queue.rule().id.as_deref(), Some("images"));
+        assert_eq!(queue.rule().events, ["s3:ObjectCreated:*", "s3:ObjectRemoved:Delete"]);
+        let rules = &queue.rule().filter.as_ref().unwrap().key.rules;
+        assert_eq!((rules[1].name.as_str(), rules[1].value.as_str()), ("suffix", ".jpg"));
+        let topic = &configuration.destinations[1];
+        assert!(matches!(topic, NotificationDestination::Topic(_)));
+        assert_eq!(topic.arn(), Some("arn:aws:sns:us-east-1:123456789012:kafka"));
+        assert!(topic.rule().filter.is_none());
+
+        let serialized = configuration.to_xml().unwrap();
+        assert!(serialized.contains("<Queue>arn:minio:sqs::primary:webhook</Queue>"), "{serialized}");
+        assert_eq!(NotificationConfiguration::from_xml(&serialized).unwrap(), configuration);
+
+        let empty = NotificationConfiguration::from_xml("<NotificationConfiguration/>").unwrap();
+        assert!(empty.destinations.is_empty());
+        assert_eq!(NotificationConfiguration::from_xml(&empty.to_xml().unwrap()).unwrap(), empty);
+    }
+
+    #[test]
+    fn test_notification_configuration_rejects_unsupported_destinations() {
+        let xml = "<NotificationConfiguration><EventBridgeConfiguration/></NotificationConfiguration>";
+        match NotificationConfiguration::from_xml(xml) {
+            Err(S3ProxyError::InvalidArgument(message)) => assert!(message.contains("EventBridgeConfiguration")),
+            other => panic!("unexpected {other:?}"),
+        }
+        for xml in ["<Tagging/>", "<NotificationConfiguration><QueueConfiguration>", "not xml"] {
+            let result = NotificationConfiguration::from_xml(xml);
+            assert!(matches!(result, Err(S3ProxyError::MalformedXml(_))), "{xml}: {result:?}");
+        }
+    }
+
+    #[test]
+    fn test_directory_marker_key_mapping() {
+        assert_eq!(to_storage_key("a/b.txt"), "a/b.txt");
+        assert_eq!(to_storage_key("a/b/"), format!("a/b/{}", DIRECTORY_MARKER));
+        assert_eq!(from_storage_key(&to_storage_key("a/b/")), "a/b/");
+        assert_eq!(from_storage_key("a/b.txt"), "a/b.txt");
+        // Only a whole trailing segment is treated as a marker
+        let lookalike = format!("a/x{}", DIRECTORY_MARKER);
+        assert_eq!(from_storage_key(&lookalike), lookalike);
+    }
+
+    #[test]
+    fn test_from_storage_key_decodes_object_store_encoding() {
+        let path = object_store::path::Path::from("reports/100%#1.csv");
+        assert_eq!(from_storage_key(path.as_ref()), "reports/100%#1.csv");
+    }
+
+    #[test]
+    fn test_validate_key_rejects_long_keys() {
+        // Multi-byte characters count by their UTF-8 length
+        let key = "\u{e9}".repeat(MAX_KEY_LENGTH / 2 + 1);
+        assert!(matches!(
+            validate_key(&key),
+            Err(S3ProxyError::KeyTooLong { size }) if size == MAX_KEY_LENGTH + 2
+        ));
+    }
+}
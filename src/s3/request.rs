@@ -0,0 +1,322 @@
+//! S3 XML request bodies
+//!
+//! Typed documents of the operations taking an XML body, deserialized with
+//! quick-xml. Element names are matched without their namespace, so bodies
+//! with the S3 `xmlns`, as the AWS CLI and SDKs send them, and bodies
+//! without one parse alike. Bodies that are not well-formed or do not match
+//! the schema are `MalformedXML`; documents breaking one of S3's limits,
+//! such as more than 1000 keys in a Delete, are `InvalidArgument` or
+//! `InvalidRequest`.
+
+use axum::body::Body;
+use quick_xml::events::Event as XmlEvent;
+use quick_xml::reader::Reader;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashSet;
+
+use crate::errors::S3ProxyError;
+
+/// Largest XML request body read, ample for a CompleteMultipartUpload of
+/// 10000 parts
+pub const MAX_XML_BODY_SIZE: usize = 4 * 1024 * 1024;
+
+/// Most objects a Delete request may list
+pub const MAX_DELETE_OBJECTS: usize = 1000;
+
+/// Highest part number of a multipart upload
+pub const MAX_PART_NUMBER: u32 = 10000;
+
+/// Most tags an object may carry
+pub const MAX_OBJECT_TAGS: usize = 10;
+
+fn malformed(error: impl std::fmt::Display) -> S3ProxyError {
+    S3ProxyError::MalformedXml(error.to_string())
+}
+
+/// A document received as an XML request body
+pub trait XmlRequest: DeserializeOwned {
+    /// Name of the root element
+    const ROOT: &'static str;
+
+    /// Parse a document, checking its root element and S3's limits
+    fn from_xml(xml: &str) -> Result<Self, S3ProxyError> {
+        let root = root_element(xml)?;
+        if root != Self::ROOT {
+            return Err(malformed(format!("unexpected root element {}", root)));
+        }
+        let document: Self = quick_xml::de::from_str(xml).map_err(malformed)?;
+        document.validate()?;
+        Ok(document)
+    }
+
+    /// Check S3's limits on a document that matches the schema
+    fn validate(&self) -> Result<(), S3ProxyError> {
+        Ok(())
+    }
+}
+
+/// Local name of the first element of a document
+fn root_element(xml: &str) -> Result<String, S3ProxyError> {
+    let mut reader = Reader::from_str(xml);
+    loop {
+        match reader.read_event().map_err(malformed)? {
+            XmlEvent::Start(element) | XmlEvent::Empty(element) => {
+                return Ok(String::from_utf8_lossy(element.local_name().as_ref()).into_owned())
+            }
+            XmlEvent::Eof => return Err(malformed("no root element")),
+            _ => {}
+        }
+    }
+}
+
+/// Parse a buffered request body
+pub fn parse_xml<T: XmlRequest>(body: &[u8]) -> Result<T, S3ProxyError> {
+    let xml = std::str::from_utf8(body).map_err(malformed)?;
+    T::from_xml(xml)
+}
+
+/// Read a request body of up to [`MAX_XML_BODY_SIZE`] bytes and parse it
+pub async fn read_xml<T: XmlRequest>(body: Body) -> Result<T, S3ProxyError> {
+    let bytes = axum::body::to_bytes(body, MAX_XML_BODY_SIZE)
+        .await
+        .map_err(|e| S3ProxyError::InvalidRequest(format!("Failed to read body: {}", e)))?;
+    parse_xml(&bytes)
+}
+
+/// Body of DeleteObjects - POST /{bucket}?delete
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Delete {
+    #[serde(rename = "Object", default)]
+    pub objects: Vec<ObjectIdentifier>,
+    /// Report only the keys that failed to delete
+    #[serde(default)]
+    pub quiet: bool,
+}
+
+/// Object to delete in a Delete request
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ObjectIdentifier {
+    pub key: String,
+    pub version_id: Option<String>,
+}
+
+impl XmlRequest for Delete {
+    const ROOT: &'static str = "Delete";
+
+    fn validate(&self) -> Result<(), S3ProxyError> {
+        if self.objects.is_empty() {
+            return Err(malformed("no objects to delete"));
+        }
+        if self.objects.len() > MAX_DELETE_OBJECTS {
+            return Err(S3ProxyError::InvalidArgument(format!(
+                "A Delete request may list at most {} objects, not {}",
+                MAX_DELETE_OBJECTS,
+                self.objects.len()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Body of CompleteMultipartUpload - POST /{bucket}/{key}?uploadId=...
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CompleteMultipartUpload {
+    #[serde(rename = "Part", default)]
+    pub parts: Vec<CompletedPart>,
+}
+
+/// Uploaded part of a CompleteMultipartUpload request
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct CompletedPart {
+    pub part_number: u32,
+    /// ETag UploadPart returned, quoted
+    #[serde(rename = "ETag")]
+    pub etag: String,
+}
+
+impl XmlRequest for CompleteMultipartUpload {
+    const ROOT: &'static str = "CompleteMultipartUpload";
+
+    fn validate(&self) -> Result<(), S3ProxyError> {
+        if self.parts.is_empty() {
+            return Err(S3ProxyError::InvalidRequest("You must specify at least one part".to_string()));
+        }
+        if let Some(part) = self.parts.iter().find(|part| !(1..=MAX_PART_NUMBER).contains(&part.part_number)) {
+            return Err(S3ProxyError::InvalidArgument(format!(
+                "Part number must be an integer between 1 and {}, not {}",
+                MAX_PART_NUMBER, part.part_number
+            )));
+        }
+        if self.parts.windows(2).any(|pair| pair[0].part_number >= pair[1].part_number) {
+            return Err(S3ProxyError::InvalidRequest(
+                "The list of parts was not in ascending order".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Body of PutObjectTagging - PUT /{bucket}/{key}?tagging
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Tagging {
+    pub tag_set: TagSet,
+}
+
+/// Tags of a Tagging request
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct TagSet {
+    #[serde(rename = "Tag", default)]
+    pub tags: Vec<Tag>,
+}
+
+/// Key and value of a tag
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Tag {
+    pub key: String,
+    #[serde(default)]
+    pub value: String,
+}
+
+impl XmlRequest for Tagging {
+    const ROOT: &'static str = "Tagging";
+
+    fn validate(&self) -> Result<(), S3ProxyError> {
+        let tags = &self.tag_set.tags;
+        if tags.len() > MAX_OBJECT_TAGS {
+            return Err(S3ProxyError::InvalidArgument(format!(
+                "Object tags cannot be greater than {}",
+                MAX_OBJECT_TAGS
+            )));
+        }
+        let mut keys = HashSet::new();
+        for tag in tags {
+            if tag.key.is_empty() || tag.key.chars().count() > 128 {
+                return Err(S3ProxyError::InvalidArgument(format!("Invalid tag key: {:?}", tag.key)));
+            }
+            if tag.value.chars().count() > 256 {
+                return Err(S3ProxyError::InvalidArgument(format!("Tag value of {} is too long", tag.key)));
+            }
+            if !keys.insert(tag.key.as_str()) {
+                return Err(S3ProxyError::InvalidArgument(format!("Tag key {} is repeated", tag.key)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::s3::tests::fixture;
+    use crate::s3::{NotificationConfiguration, NotificationDestination};
+
+    #[test]
+    fn test_delete_fixtures() {
+        let delete: Delete = parse_xml(&fixture("delete-objects-awscli.xml")).unwrap();
+        let keys: Vec<_> = delete.objects.iter().map(|object| object.key.as_str()).collect();
+        assert_eq!(keys, ["logs/2024/app.log", "logs/2024/app & web.log"]);
+        assert!(delete.quiet);
+
+        let delete: Delete = parse_xml(&fixture("delete-objects-boto3.xml")).unwrap();
+        assert_eq!(delete.objects.len(), 2);
+        assert_eq!(delete.objects[1].version_id.as_deref(), Some("3HL4kqtJlcpXroDTDmJ+rmSpXd3dIbrHY"));
+        assert!(!delete.quiet);
+
+        // Without a namespace, Quiet first, as some other clients send it
+        let delete: Delete = parse_xml(&fixture("delete-objects-no-namespace.xml")).unwrap();
+        assert_eq!(delete.objects[0].key, "a.txt");
+        assert!(!delete.quiet);
+    }
+
+    #[test]
+    fn test_complete_multipart_upload_fixtures() {
+        for name in ["complete-multipart-upload-awscli.xml", "complete-multipart-upload-boto3.xml"] {
+            let upload: CompleteMultipartUpload = parse_xml(&fixture(name)).unwrap();
+            let parts: Vec<_> = upload.parts.iter().map(|part| (part.part_number, part.etag.as_str())).collect();
+            assert_eq!(
+                parts,
+                [(1, "\"0c78aef83f66abc1fa1e8477f296d394\""), (2, "\"acbd18db4cc2f85cedef654fccc4a4d8\"")],
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_tagging_and_notification_fixtures() {
+        let tagging: Tagging = parse_xml(&fixture("put-object-tagging-boto3.xml")).unwrap();
+        let tags: Vec<_> = tagging.tag_set.tags.iter().map(|tag| (tag.key.as_str(), tag.value.as_str())).collect();
+        assert_eq!(tags, [("project", "blue"), ("empty", "")]);
+
+        let configuration: NotificationConfiguration =
+            parse_xml(&fixture("put-bucket-notification-configuration-awscli.xml")).unwrap();
+        assert_eq!(configuration.destinations.len(), 1);
+        assert!(matches!(configuration.destinations[0], NotificationDestination::Queue(_)));
+        assert_eq!(configuration.destinations[0].rule().events, ["s3:ObjectCreated:*"]);
+    }
+
+    #[test]
+    fn test_malformed_documents() {
+        let cases: [&[u8]; 6] = [
+            b"not xml",
+            b"<Delete><Object><Key>a</Key></Delete>",
+            b"<Tagging><TagSet><Tag><Key>a</Key></Tag></TagSet></Tagging>",
+            b"<Delete/>",
+            b"<Delete><Object><VersionId>1</VersionId></Object></Delete>",
+            b"<Delete><Object><Key>\xff</Key></Object></Delete>",
+        ];
+        for body in cases {
+            let result = parse_xml::<Delete>(body);
+            assert!(matches!(result, Err(S3ProxyError::MalformedXml(_))), "{body:?}: {result:?}");
+        }
+        let result = parse_xml::<CompleteMultipartUpload>(b"<CompleteMultipartUpload><Part><PartNumber>x</PartNumber>\
+            <ETag>a</ETag></Part></CompleteMultipartUpload>");
+        assert!(matches!(result, Err(S3ProxyError::MalformedXml(_))), "{result:?}");
+    }
+
+    #[test]
+    fn test_limits() {
+        let objects = "<Object><Key>k</Key></Object>".repeat(MAX_DELETE_OBJECTS + 1);
+        let result = parse_xml::<Delete>(format!("<Delete>{}</Delete>", objects).as_bytes());
+        assert!(matches!(result, Err(S3ProxyError::InvalidArgument(_))), "{result:?}");
+
+        let part = |number: u32| format!("<Part><PartNumber>{}</PartNumber><ETag>\"e\"</ETag></Part>", number);
+        let upload = |parts: &[u32]| {
+            let parts: String = parts.iter().map(|&number| part(number)).collect();
+            let xml = format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts);
+            parse_xml::<CompleteMultipartUpload>(xml.as_bytes())
+        };
+        assert!(upload(&[1, 3]).is_ok());
+        assert!(matches!(upload(&[]), Err(S3ProxyError::InvalidRequest(_))));
+        assert!(matches!(upload(&[2, 1]), Err(S3ProxyError::InvalidRequest(_))));
+        assert!(matches!(upload(&[1, 1]), Err(S3ProxyError::InvalidRequest(_))));
+        assert!(matches!(upload(&[0]), Err(S3ProxyError::InvalidArgument(_))));
+        assert!(matches!(upload(&[MAX_PART_NUMBER + 1]), Err(S3ProxyError::InvalidArgument(_))));
+
+        let tagging = |tags: &str| {
+            let xml = format!("<Tagging><TagSet>{}</TagSet></Tagging>", tags);
+            parse_xml::<Tagging>(xml.as_bytes())
+        };
+        let tag = |key: &str| format!("<Tag><Key>{}</Key><Value>v</Value></Tag>", key);
+        assert!(tagging("").unwrap().tag_set.tags.is_empty());
+        let too_many: String = (0..=MAX_OBJECT_TAGS).map(|i| tag(&i.to_string())).collect();
+        assert!(matches!(tagging(&too_many), Err(S3ProxyError::InvalidArgument(_))));
+        assert!(matches!(tagging(&tag("a").repeat(2)), Err(S3ProxyError::InvalidArgument(_))));
+        assert!(matches!(tagging(&tag(&"k".repeat(129))), Err(S3ProxyError::InvalidArgument(_))));
+    }
+
+    #[tokio::test]
+    async fn test_read_xml_caps_body_size() {
+        let delete: Delete = read_xml(Body::from(fixture("delete-objects-awscli.xml"))).await.unwrap();
+        assert_eq!(delete.objects.len(), 2);
+
+        let oversized = format!("<Delete>{}</Delete>", " ".repeat(MAX_XML_BODY_SIZE));
+        let result = read_xml::<Delete>(Body::from(oversized)).await;
+        assert!(matches!(result, Err(S3ProxyError::InvalidRequest(_))), "{result:?}");
+    }
+}
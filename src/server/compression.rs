@@ -0,0 +1,141 @@
+//! Response compression
+//!
+//! With `server.compression = "listings"` (the default), listings and
+//! error responses are gzipped for clients sending `Accept-Encoding`.
+//! Object payloads are passed on as stored, so their length and checksums
+//! match what clients expect, and responses already carrying a
+//! `Content-Encoding` are never compressed again.
+
+use axum::extract::{Request, State};
+use axum::http::{header, Extensions, HeaderMap, StatusCode, Version};
+use axum::middleware::Next;
+use axum::response::Response;
+use tower_http::compression::predicate::{Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+
+use crate::config::CompressionMode;
+use crate::errors::ErrorCode;
+use crate::routes;
+
+/// Marks a response the compression layer may compress
+#[derive(Debug, Clone, Copy)]
+struct Compressible;
+
+/// Whether the responses of an S3 operation are listings
+fn is_listing(operation: &str) -> bool {
+    matches!(
+        operation,
+        "ListBuckets" | "ListObjects" | "ListObjectsV2" | "ListObjectVersions" | "ListMultipartUploads" | "ListParts"
+    )
+}
+
+/// Middleware marking the responses `mode` lets the compression layer
+/// compress
+pub async fn mark(State(mode): State<CompressionMode>, request: Request, next: Next) -> Response {
+    if mode == CompressionMode::Off {
+        return next.run(request).await;
+    }
+    let operation = routes::operation_name(request.method(), request.uri().path(), request.uri().query());
+    let mut response = next.run(request).await;
+    if is_listing(operation) || response.extensions().get::<ErrorCode>().is_some() {
+        response.extensions_mut().insert(Compressible);
+    }
+    response
+}
+
+fn compressible(_: StatusCode, _: Version, headers: &HeaderMap, extensions: &Extensions) -> bool {
+    extensions.get::<Compressible>().is_some() && !headers.contains_key(header::CONTENT_ENCODING)
+}
+
+/// Compression layer compressing marked responses only
+pub fn layer() -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new().compress_when(SizeAbove::default().and(compressible))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::Router;
+    use bytes::Bytes;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    use crate::config::Config;
+    use crate::server::Server;
+    use crate::storage::{BucketRegistry, MemoryBackend, StorageBackend};
+
+    fn router_with(compression: &str, backend: Arc<dyn StorageBackend>) -> Router {
+        let config: Config = toml::from_str(&format!(
+            "[server]\ncompression = \"{}\"\n[backend]\ntype = \"memory\"",
+            compression
+        ))
+        .unwrap();
+        let registry = BucketRegistry::single(backend);
+        Server::new(config, Arc::new(registry)).unwrap().build_router()
+    }
+
+    /// GET `uri` accepting gzip, returning the Content-Encoding, the
+    /// Content-Length and the body as sent
+    async fn get(router: &Router, uri: &str) -> (StatusCode, Option<String>, Option<String>, Bytes) {
+        let request = Request::builder()
+            .uri(uri)
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let header = |name| response.headers().get(name).map(|v| v.to_str().unwrap().to_string());
+        let (encoding, length) = (header(header::CONTENT_ENCODING), header(header::CONTENT_LENGTH));
+        let status = response.status();
+        (status, encoding, length, axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap())
+    }
+
+    fn text() -> Bytes {
+        Bytes::from("id,name,value\n".repeat(200))
+    }
+
+    #[tokio::test]
+    async fn test_objects_sent_as_stored() {
+        let backend: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new(&Default::default()));
+        backend.put("data.csv", text()).await.unwrap();
+        let router = router_with("listings", backend);
+
+        let (status, encoding, length, body) = get(&router, "/bucket/data.csv").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(encoding, None);
+        assert_eq!(length, Some(text().len().to_string()));
+        assert_eq!(body, text());
+    }
+
+    #[test]
+    fn test_encoded_responses_not_compressed_again() {
+        let mut extensions = Extensions::new();
+        extensions.insert(Compressible);
+        let mut headers = HeaderMap::new();
+        assert!(compressible(StatusCode::OK, Version::HTTP_11, &headers, &extensions));
+        headers.insert(header::CONTENT_ENCODING, "gzip".parse().unwrap());
+        assert!(!compressible(StatusCode::OK, Version::HTTP_11, &headers, &extensions));
+        assert!(!compressible(StatusCode::OK, Version::HTTP_11, &HeaderMap::new(), &Extensions::new()));
+    }
+
+    #[tokio::test]
+    async fn test_listings_and_errors_compressed() {
+        let backend: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new(&Default::default()));
+        for i in 0..20 {
+            backend.put(&format!("listed/object-{}", i), text()).await.unwrap();
+        }
+        let router = router_with("listings", backend.clone());
+
+        let (status, encoding, _, _) = get(&router, "/bucket?list-type=2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(encoding.as_deref(), Some("gzip"));
+        let (status, encoding, _, _) = get(&router, "/bucket/missing").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(encoding.as_deref(), Some("gzip"));
+
+        let router = router_with("off", backend);
+        let (_, encoding, _, body) = get(&router, "/bucket?list-type=2").await;
+        assert_eq!(encoding, None);
+        assert!(String::from_utf8(body.to_vec()).unwrap().contains("<Key>listed/object-0</Key>"));
+    }
+}
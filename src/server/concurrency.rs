@@ -0,0 +1,247 @@
+//! Concurrency limits and load shedding
+//!
+//! `limits.max_concurrent_requests` caps the S3 requests served at once,
+//! and data and metadata requests can have caps of their own within it, so
+//! a burst of large downloads cannot starve listings and HEADs. A request
+//! over a cap waits up to `limits.queue_timeout_ms` for a slot, then is
+//! shed with 503 `SlowDown`, which SDKs retry with backoff. The layer sits
+//! inside the request timeout, so time spent waiting counts against it.
+//!
+//! A slot is held until the response body has been sent, so streaming
+//! downloads count for as long as they stream. Health, readiness, metrics
+//! and admin endpoints are never limited.
+
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use prometheus::IntGauge;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+use tracing::debug;
+
+use super::shutdown::GuardedBody;
+use crate::config::LimitsConfig;
+use crate::errors::S3ProxyError;
+use crate::metrics::{CONCURRENCY_IN_USE, REQUESTS_SHED};
+use crate::routes;
+
+/// Slots of one concurrency limit
+struct Pool {
+    name: &'static str,
+    semaphore: Arc<Semaphore>,
+    in_use: IntGauge,
+}
+
+impl Pool {
+    fn new(name: &'static str, max: usize) -> Option<Self> {
+        (max > 0).then(|| Self {
+            name,
+            semaphore: Arc::new(Semaphore::new(max)),
+            in_use: CONCURRENCY_IN_USE.with_label_values(&[name]),
+        })
+    }
+
+    /// Take a slot, waiting until `deadline` for one to free up
+    async fn acquire(&self, deadline: Option<Instant>) -> Option<Slot> {
+        let semaphore = self.semaphore.clone();
+        let permit = match deadline {
+            None => semaphore.try_acquire_owned().ok()?,
+            Some(deadline) => tokio::time::timeout_at(deadline, semaphore.acquire_owned())
+                .await
+                .ok()?
+                // The semaphore is never closed
+                .ok()?,
+        };
+        self.in_use.inc();
+        Some(Slot {
+            _permit: permit,
+            in_use: self.in_use.clone(),
+        })
+    }
+}
+
+/// Slot held by a request until dropped
+struct Slot {
+    _permit: OwnedSemaphorePermit,
+    in_use: IntGauge,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.in_use.dec();
+    }
+}
+
+/// Concurrency limits of S3 requests
+pub struct ConcurrencyLimits {
+    global: Option<Pool>,
+    data: Option<Pool>,
+    metadata: Option<Pool>,
+    queue_timeout: Duration,
+}
+
+impl ConcurrencyLimits {
+    /// Limits of the configuration, or None when nothing is limited
+    pub fn from_config(config: &LimitsConfig) -> Option<Self> {
+        let limits = Self {
+            global: Pool::new("global", config.max_concurrent_requests),
+            data: Pool::new("data", config.max_concurrent_data_requests),
+            metadata: Pool::new("metadata", config.max_concurrent_metadata_requests),
+            queue_timeout: Duration::from_millis(config.queue_timeout_ms),
+        };
+        (limits.global.is_some() || limits.data.is_some() || limits.metadata.is_some()).then_some(limits)
+    }
+
+    /// Take the slots a request of the given operation class needs, or
+    /// name the pool that had none free in time
+    async fn acquire(&self, class: &str) -> Result<Vec<Slot>, &'static str> {
+        let class_pool = if class == "data" { &self.data } else { &self.metadata };
+        let deadline = (!self.queue_timeout.is_zero()).then(|| Instant::now() + self.queue_timeout);
+        let mut slots = Vec::with_capacity(2);
+        // The class slot first, so requests waiting on a busy class do not
+        // hold global slots the other class could use
+        for pool in [class_pool, &self.global].into_iter().flatten() {
+            slots.push(pool.acquire(deadline).await.ok_or(pool.name)?);
+        }
+        Ok(slots)
+    }
+}
+
+/// Middleware holding S3 requests to the concurrency limits
+pub async fn limit(State(limits): State<Option<Arc<ConcurrencyLimits>>>, request: Request, next: Next) -> Response {
+    let Some(limits) = limits.filter(|_| !routes::is_system_path(request.uri().path())) else {
+        return next.run(request).await;
+    };
+    let operation = routes::operation_name(request.method(), request.uri().path(), request.uri().query());
+    match limits.acquire(routes::operation_class(operation)).await {
+        Ok(slots) => next.run(request).await.map(|body| GuardedBody::wrap(body, slots)),
+        Err(pool) => {
+            REQUESTS_SHED.with_label_values(&[pool]).inc();
+            debug!(operation, pool, "Shed request over the concurrency limit");
+            S3ProxyError::SlowDown("Please reduce your request rate".to_string()).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::Router;
+    use futures::future::join_all;
+    use tower::ServiceExt;
+
+    use crate::config::Config;
+    use crate::server::Server;
+    use crate::storage::{BucketRegistry, MockBackend, StorageBackend};
+
+    /// Router over a backend answering every call after `latency`
+    fn router(limits: &str, latency: Duration) -> (Router, Arc<MockBackend>) {
+        let backend = Arc::new(MockBackend::new());
+        backend.set_latency(latency);
+        let config: Config = toml::from_str(&format!(
+            "[limits]\n{}\n[server]\n[backend]\ntype = \"gcp\"\nbucket_name = \"b\"",
+            limits
+        ))
+        .unwrap();
+        let registry = BucketRegistry::single(backend.clone());
+        (Server::new(config, Arc::new(registry)).unwrap().build_router(), backend)
+    }
+
+    async fn send(router: &Router, method: &str, uri: &str) -> (StatusCode, String) {
+        let request = Request::builder().method(method).uri(uri).body(Body::from("data")).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    #[tokio::test]
+    async fn test_queued_requests_stay_within_limit() {
+        let limits = "max_concurrent_requests = 3\nqueue_timeout_ms = 10000";
+        let (router, backend) = router(limits, Duration::from_millis(50));
+
+        let responses = join_all((0..12).map(|i| {
+            let router = router.clone();
+            async move { send(&router, "PUT", &format!("/bucket/queued-{}", i)).await }
+        }))
+        .await;
+
+        assert!(responses.iter().all(|(status, _)| *status == StatusCode::OK), "{:?}", responses);
+        assert_eq!(backend.peak_concurrency(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_requests_over_limit_shed() {
+        let (router, backend) = router("max_concurrent_requests = 2", Duration::from_millis(200));
+        let shed = || REQUESTS_SHED.with_label_values(&["global"]).get();
+        let before = shed();
+
+        let responses = join_all((0..6).map(|i| {
+            let router = router.clone();
+            async move { send(&router, "PUT", &format!("/bucket/shed-{}", i)).await }
+        }))
+        .await;
+
+        let (served, rejected): (Vec<_>, Vec<_>) = responses.iter().partition(|(status, _)| *status == StatusCode::OK);
+        assert_eq!(served.len(), 2);
+        assert_eq!(rejected.len(), 4);
+        for (status, body) in rejected {
+            assert_eq!(*status, StatusCode::SERVICE_UNAVAILABLE);
+            assert!(body.contains("<Code>SlowDown</Code>"), "{}", body);
+        }
+        assert!(backend.peak_concurrency() <= 2);
+        assert!(shed() >= before + 4);
+
+        // Slots free up once the requests complete, and probes are not limited
+        assert_eq!(send(&router, "PUT", "/bucket/after").await.0, StatusCode::OK);
+        assert_eq!(send(&router, "GET", "/healthz").await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_queue_timeout_sheds_waiting_requests() {
+        let (router, _) = router("max_concurrent_requests = 1\nqueue_timeout_ms = 50", Duration::from_millis(300));
+
+        let (first, second) = tokio::join!(send(&router, "PUT", "/bucket/slow"), async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            send(&router, "PUT", "/bucket/waiting").await
+        });
+        assert_eq!(first.0, StatusCode::OK);
+        assert_eq!(second.0, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_class_limits_keep_metadata_requests_flowing() {
+        let (router, backend) = router(
+            "max_concurrent_requests = 4\nmax_concurrent_data_requests = 2\nqueue_timeout_ms = 10000",
+            Duration::from_millis(100),
+        );
+        backend.put("listed", bytes::Bytes::from("data")).await.unwrap();
+
+        let uploads = join_all((0..6).map(|i| {
+            let router = router.clone();
+            async move { send(&router, "PUT", &format!("/bucket/upload-{}", i)).await }
+        }));
+        let listing = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let started = std::time::Instant::now();
+            let response = send(&router, "GET", "/bucket?list-type=2").await;
+            (response, started.elapsed())
+        };
+        let (uploads, ((status, _), elapsed)) = tokio::join!(uploads, listing);
+
+        assert!(uploads.iter().all(|(status, _)| *status == StatusCode::OK));
+        assert_eq!(status, StatusCode::OK);
+        // The listing got a slot while the uploads queued for theirs
+        assert!(elapsed < Duration::from_millis(250), "{:?}", elapsed);
+        assert!(backend.peak_concurrency() <= 3);
+    }
+
+    #[test]
+    fn test_nothing_limited_by_default() {
+        assert!(ConcurrencyLimits::from_config(&LimitsConfig::default()).is_none());
+    }
+}
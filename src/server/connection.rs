@@ -0,0 +1,129 @@
+//! Connection tuning
+//!
+//! Applies the `[server]` HTTP/2, keep-alive and idle timeout options to
+//! the listeners. Unset options leave hyper's defaults in place.
+//!
+//! Without `http2`, TLS stops offering HTTP/2 through ALPN, so the only
+//! HTTP/2 requests left are h2c with prior knowledge, which hyper detects
+//! from the connection preface whatever its builder says; those get 505.
+
+use axum::extract::{Request, State};
+use axum::http::{StatusCode, Version};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum_server::Server;
+use hyper_util::rt::TokioTimer;
+use socket2::{Domain, Protocol, Socket, TcpKeepalive, Type};
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+use crate::config::ServerConfig;
+
+/// Bind a listener on `address` with the socket options from `config`
+///
+/// IPv6 wildcard sockets accept IPv4 connections too on most systems, so
+/// with `v6_only` they are limited to IPv6 to leave the IPv4 address on the
+/// same port free for its own listener. Accepted connections inherit the
+/// TCP keep-alive probes set on the listener.
+pub fn bind(address: SocketAddr, v6_only: bool, config: &ServerConfig) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, Some(Protocol::TCP))?;
+    if address.is_ipv6() {
+        socket.set_only_v6(v6_only)?;
+    }
+    socket.set_reuse_address(true)?;
+    if let Some(secs) = config.tcp_keepalive_secs {
+        socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(Duration::from_secs(secs)))?;
+    }
+    socket.bind(&address.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+/// Apply the HTTP protocol options to the connections `server` serves
+pub fn configure<A>(server: &mut Server<A>, config: &ServerConfig) {
+    let builder = server.http_builder();
+    if let Some(streams) = config.http2_max_concurrent_streams {
+        builder.http2().max_concurrent_streams(streams);
+    }
+    builder.http1().keep_alive(config.http1_keep_alive);
+    if let Some(secs) = config.http1_idle_timeout_secs {
+        // Idle connections wait for the next request's headers, so the
+        // header read timeout closes them
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .header_read_timeout(Duration::from_secs(secs));
+    }
+}
+
+/// Middleware refusing HTTP/2 requests unless `http2` is enabled
+pub async fn refuse_http2(State(http2): State<bool>, request: Request, next: Next) -> Response {
+    if !http2 && request.version() == Version::HTTP_2 {
+        return StatusCode::HTTP_VERSION_NOT_SUPPORTED.into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    use crate::config::Config;
+    use crate::server::{Server, ServerHandle};
+    use crate::storage::{BucketRegistry, LocalBackend};
+
+    async fn serve(root: &std::path::Path, server: &str) -> ServerHandle {
+        let config: Config = toml::from_str(&format!(
+            "[server]\nbind_address = \"127.0.0.1:0\"\n{}\n[backend]\ntype = \"memory\"",
+            server
+        ))
+        .unwrap();
+        config.validate().unwrap();
+        let registry = BucketRegistry::single(Arc::new(LocalBackend::new(root).unwrap()));
+        Server::new(config, Arc::new(registry)).unwrap().bind().await.unwrap()
+    }
+
+    /// GET `path` over h2c with prior knowledge
+    async fn h2c_get(addr: SocketAddr, path: &str) -> Result<(StatusCode, String), hyper::Error> {
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut sender, connection) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream)).await?;
+        tokio::spawn(connection);
+        let request = Request::builder()
+            .uri(format!("http://{}{}", addr, path))
+            .body(Body::empty())
+            .unwrap();
+        let response = sender.send_request(request).await?;
+        let status = response.status();
+        let body = axum::body::to_bytes(Body::new(response.into_body()), usize::MAX).await.unwrap();
+        Ok((status, String::from_utf8(body.to_vec()).unwrap()))
+    }
+
+    #[tokio::test]
+    async fn test_h2c_prior_knowledge() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("object"), "over h2").unwrap();
+        let handle = serve(root.path(), "http2_max_concurrent_streams = 50\ntcp_keepalive_secs = 60").await;
+
+        let (status, body) = h2c_get(handle.local_addr(), "/bucket/object").await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "over h2");
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_h2c_refused_when_disabled() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("object"), "over h2").unwrap();
+        let handle = serve(root.path(), "http2 = false").await;
+
+        let (status, _) = h2c_get(handle.local_addr(), "/bucket/object").await.unwrap();
+        assert_eq!(status, StatusCode::HTTP_VERSION_NOT_SUPPORTED);
+        handle.shutdown().await.unwrap();
+    }
+}
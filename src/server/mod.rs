@@ -2,71 +2,861 @@
 //!
 //! Sets up the Axum HTTP server with:
 //! - S3 API routes
+//! - Optional TLS termination with certificate reload
+//! - Virtual-hosted-style request rewriting
+//! - Client IP filtering
+//! - Read-only mode
+//! - Concurrency limits with load shedding
+//! - Download and upload bandwidth limits
+//! - Compression of listings and errors
+//! - Request authentication and authorization
 //! - Middleware (logging, metrics, request ID, timeout)
 //! - Graceful shutdown
-//! - Health/readiness probes
+//! - HTTP/2 and keep-alive tuning
+//! - Health/readiness probes, optionally on a separate metrics listener
 
-use axum::Router;
+mod compression;
+mod concurrency;
+mod connection;
+mod probe;
+pub mod shutdown;
+mod throttle;
+mod timeout;
+mod tls;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::middleware::{from_fn, from_fn_with_state};
+use axum::{Extension, Router, ServiceExt};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use futures::future::BoxFuture;
+use hyper::body::Incoming;
+use std::net::{SocketAddr, TcpListener};
 use std::sync::Arc;
-use tower::ServiceBuilder;
-use tower_http::{
-    compression::CompressionLayer,
-    timeout::TimeoutLayer,
-    trace::TraceLayer,
-};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::{JoinError, JoinHandle};
+use tower::{Layer, ServiceBuilder};
+use tower_http::trace::TraceLayer;
 use tracing::info;
 
+use crate::access_log::{self, AccessLog};
+use crate::audit_log::{self, AuditLog};
+use crate::auth::{self, Authenticator};
 use crate::config::Config;
-use crate::routes;
-use crate::storage::StorageBackend;
+use crate::events::{self, Notifier};
+use crate::health::{BackendHealth, HealthChecks};
+use crate::logging::LogFilter;
+use crate::metrics::{self, BucketLabels};
+use crate::request_id;
+use crate::routes::{self, ip_filter::IpFilter, read_only::ReadOnly};
+use crate::storage::{self, BucketRegistry};
+use crate::telemetry;
+
+use concurrency::ConcurrencyLimits;
+pub use probe::BackendProbe;
+pub use shutdown::Readiness;
+use shutdown::InFlight;
+use throttle::Throttle;
 
 /// HTTP server for S3Proxy
 pub struct Server {
     config: Config,
-    storage: Arc<dyn StorageBackend>,
+    registry: Arc<BucketRegistry>,
+    authenticator: Arc<Authenticator>,
+    ip_filter: Arc<IpFilter>,
+    read_only: ReadOnly,
+    readiness: Readiness,
+    in_flight: InFlight,
+    bucket_labels: Arc<BucketLabels>,
+    probe: Option<Arc<BackendProbe>>,
+    health_checks: Arc<HealthChecks>,
+    log_filter: Option<Arc<LogFilter>>,
+    access_log: Option<Arc<AccessLog>>,
+    audit_log: Option<Arc<AuditLog>>,
+    notifier: Option<Arc<Notifier>>,
+    concurrency_limits: Option<Arc<ConcurrencyLimits>>,
+    throttle: Option<Arc<Throttle>>,
 }
 
 impl Server {
     /// Create a new server instance
     pub fn new(
         config: Config,
-        storage: Arc<dyn StorageBackend>,
+        registry: Arc<BucketRegistry>,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        Ok(Self { config, storage })
+        let authenticator = Authenticator::new(&config.auth, config.server.max_body_size)?;
+        let bucket_labels = BucketLabels::new(config.metrics.per_bucket_labels, registry.bucket_names());
+
+        let mut health_checks = HealthChecks::new(Duration::from_secs(config.readiness.timeout_secs));
+        for check in BackendHealth::for_registry(&registry, config.readiness.sentinel_key.as_deref()) {
+            health_checks.register(Arc::new(check));
+        }
+        if let Some(tls_config) = &config.server.tls {
+            health_checks.register(Arc::new(tls::TlsHealth(tls_config.clone())));
+        }
+        let access_log = AccessLog::from_config(&config)?;
+        let audit_log = AuditLog::from_config(&config, &registry)?;
+        let notifier = Notifier::from_config(&config, &registry)?;
+        for check in notifier.iter().flat_map(|notifier| notifier.health_checks()) {
+            health_checks.register(check.clone());
+        }
+        Ok(Self {
+            registry,
+            authenticator: Arc::new(authenticator),
+            ip_filter: Arc::new(IpFilter::new(&config.ip_filter)),
+            read_only: ReadOnly::new(config.read_only),
+            readiness: Readiness::default(),
+            in_flight: InFlight::default(),
+            bucket_labels: Arc::new(bucket_labels),
+            probe: config
+                .readiness
+                .check_backend
+                .then(|| Arc::new(BackendProbe::new(&config.readiness))),
+            health_checks: Arc::new(health_checks),
+            log_filter: None,
+            access_log: access_log.map(Arc::new),
+            audit_log: audit_log.map(Arc::new),
+            notifier: notifier.map(Arc::new),
+            concurrency_limits: ConcurrencyLimits::from_config(&config.limits).map(Arc::new),
+            throttle: Throttle::from_config(&config.limits).map(Arc::new),
+            config,
+        })
+    }
+
+    /// Allow changing the log filter through `PUT /admin/loglevel`
+    pub fn with_log_filter(mut self, log_filter: LogFilter) -> Self {
+        self.log_filter = Some(Arc::new(log_filter));
+        self
+    }
+
+    /// Switch read-only mode, in which S3 writes are rejected, for every
+    /// request from now on
+    pub fn set_read_only(&self, enabled: bool) {
+        if enabled != self.read_only.is_enabled() {
+            info!(read_only = enabled, "Read-only mode changed");
+        }
+        self.read_only.set(enabled);
     }
 
     /// Build the Axum router with all middleware
-    fn build_router(&self) -> Router {
-        routes::create_router(self.storage.clone())
+    ///
+    /// Leaves out the system endpoints when a separate metrics listener
+    /// serves them.
+    pub(crate) fn build_router(&self) -> Router {
+        let router = match self.config.server.metrics_bind_address {
+            Some(_) => routes::create_s3_router(self.registry.clone()),
+            None => routes::create_router(self.registry.clone()),
+        };
+        self.with_middleware(router)
+    }
+
+    /// Build the router of the metrics listener, serving the system
+    /// endpoints with the same middleware as the main listener
+    pub(crate) fn build_metrics_router(&self) -> Router {
+        self.with_middleware(routes::create_system_router(self.registry.clone()))
+    }
+
+    fn with_middleware(&self, router: Router) -> Router {
+        let trace_events = !self.config.access_log.to_logger();
+        let mut router = router
+            .layer(Extension(self.health_checks.clone()))
+            .layer(Extension(Arc::new(self.config.clone())))
+            .layer(Extension(self.read_only.clone()));
+        if let Some(probe) = &self.probe {
+            router = router.layer(Extension(probe.clone()));
+        }
+        if let Some(log_filter) = &self.log_filter {
+            router = router.layer(Extension(log_filter.clone()));
+        }
+        if let Some(notifier) = &self.notifier {
+            router = router.layer(Extension(notifier.clone()));
+        }
+        router
+            .layer(from_fn_with_state(self.read_only.clone(), routes::read_only::reject_writes))
+            .layer(from_fn(auth::authorize))
+            .layer(from_fn_with_state(self.authenticator.clone(), auth::authenticate))
+            .layer(from_fn_with_state(self.ip_filter.clone(), routes::ip_filter::filter))
+            .layer(Extension(self.readiness.clone()))
             .layer(
                 ServiceBuilder::new()
                     // Add request tracing (includes request ID via tracing)
-                    .layer(TraceLayer::new_for_http())
-                    // Add timeout
-                    .layer(TimeoutLayer::new(
-                        std::time::Duration::from_secs(self.config.server.timeout_secs),
+                    // (its request and response events are left out when the
+                    // access log goes to the same logger)
+                    .layer(
+                        TraceLayer::new_for_http()
+                            .make_span_with(telemetry::request_span)
+                            .on_request(telemetry::LogRequest::new(trace_events))
+                            .on_response(telemetry::RecordResponse::new(trace_events)),
+                    )
+                    // Continue or start the caller's trace
+                    .layer(from_fn(telemetry::propagate))
+                    // Assign the request ID returned in headers and errors
+                    .layer(from_fn(request_id::assign))
+                    // Write the S3 access log line once the response is sent
+                    .layer(from_fn_with_state(self.access_log.clone(), access_log::record))
+                    // Record writes and deletes, rejected ones included
+                    .layer(from_fn_with_state(self.audit_log.clone(), audit_log::record))
+                    // Queue event notifications of successful writes and deletes
+                    .layer(from_fn_with_state(self.notifier.clone(), events::publish))
+                    // Report whether the object cache answered
+                    .layer(from_fn(storage::cache::annotate))
+                    // Add timeout, not counting time uploads are throttled
+                    .layer(from_fn_with_state(
+                        Duration::from_secs(self.config.server.timeout_secs),
+                        timeout::limit,
                     ))
-                    // Add compression
-                    .layer(CompressionLayer::new())
+                    // Hold requests to the concurrency limits, inside the
+                    // timeout so time spent queued counts against it
+                    .layer(from_fn_with_state(self.concurrency_limits.clone(), concurrency::limit))
+                    // Throttle uploads, and downloads counting the
+                    // compressed bytes
+                    .layer(from_fn_with_state(self.throttle.clone(), throttle::limit))
+                    // Compress the responses marked compressible
+                    .layer(compression::layer())
+                    .layer(from_fn_with_state(self.config.server.compression, compression::mark))
                     .into_inner(),
             )
+            // Outermost, so rejections by the layers above are recorded too
+            .layer(from_fn_with_state(self.bucket_labels.clone(), metrics::record_http))
     }
 
     /// Start the server and run until shutdown signal
+    ///
+    /// Once `shutdown` completes the readiness probe reports unready and
+    /// in-flight requests are drained for up to `server.shutdown_grace_secs`.
     pub async fn start<F>(&self, shutdown: F) -> Result<(), Box<dyn std::error::Error>>
     where
         F: std::future::Future<Output = ()> + Send + 'static,
     {
-        let app = self.build_router();
+        // Poll once before binding, so signal handlers the future installs
+        // are in place before clients can connect
+        let mut shutdown = std::pin::pin!(shutdown);
+        if futures::poll!(shutdown.as_mut()).is_ready() {
+            return Ok(());
+        }
+        let mut handle = self.bind().await?;
+        tokio::select! {
+            _ = shutdown => handle.shutdown().await,
+            // Serving failed before any shutdown was requested
+            result = &mut handle.task => ServerHandle::joined(result),
+        }
+    }
+
+    /// Bind the listener, and the metrics listener if configured, and serve
+    /// in the background
+    ///
+    /// Port 0 binds an ephemeral port; the handle reports the addresses
+    /// actually bound and stops the server, draining in-flight requests as
+    /// on a shutdown signal.
+    pub async fn bind(&self) -> Result<ServerHandle, Box<dyn std::error::Error>> {
+        let make_service = |router: Router, domains: Vec<String>| {
+            // Virtual-hosted-style rewriting must happen before routing, so
+            // it wraps the whole router instead of being a route layer
+            let app = from_fn_with_state(Arc::new(domains), routes::virtual_host::rewrite).layer(router);
+            let app = from_fn_with_state(self.config.server.http2, connection::refuse_http2).layer(app);
+            // Requests are counted before anything else so draining sees them all
+            let app = from_fn_with_state(self.in_flight.clone(), shutdown::track).layer(app);
+            // axum-server hands over hyper's body type rather than axum's
+            let app = ServiceBuilder::new()
+                .map_request(|request: Request<Incoming>| request.map(Body::new))
+                .service(app);
+            // Connection info provides the peer address for IP filtering
+            app.into_make_service_with_connect_info::<SocketAddr>()
+        };
+        let app = make_service(self.build_router(), self.config.server.virtual_host_domains.clone());
+
+        let server_config = &self.config.server;
+        let bind = |address: SocketAddr, v6_only: bool| {
+            connection::bind(address, v6_only, server_config)
+                .map_err(|e| format!("Failed to bind {}: {}", address, e))
+        };
+        let mut listeners = Vec::new();
+        for address in server_config.bind_address.iter() {
+            // An IPv6 wildcard listener would also claim the IPv4 port
+            let v6_only = server_config
+                .bind_address
+                .iter()
+                .any(|other| other.is_ipv4() && other.port() == address.port());
+            listeners.push(bind(address, v6_only)?);
+        }
+        let local_addrs = listeners.iter().map(TcpListener::local_addr).collect::<Result<Vec<_>, _>>()?;
+        let metrics_listener = match server_config.metrics_bind_address {
+            Some(address) => Some(bind(address, false)?),
+            None => None,
+        };
+        let metrics_addr = metrics_listener.as_ref().map(TcpListener::local_addr).transpose()?;
 
-        let listener = tokio::net::TcpListener::bind(self.config.server.bind_address).await?;
-        info!(address = %self.config.server.bind_address, "Server listening");
+        // Both listeners share the handle, so they shut down together
+        let handle = Handle::new();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let readiness = self.readiness.clone();
+        let in_flight = self.in_flight.clone();
+        let grace = Duration::from_secs(self.config.server.shutdown_grace_secs);
+        let drain_handle = handle.clone();
+        tokio::spawn(async move {
+            // A dropped handle shuts the server down too
+            let _ = shutdown_rx.await;
+            readiness.set_unready();
+            shutdown::drain(&drain_handle, &in_flight, grace).await;
+        });
 
-        axum::serve(listener, app)
-            .with_graceful_shutdown(shutdown)
-            .await?;
+        let rustls_config = match &server_config.tls {
+            Some(tls_config) => {
+                let http2 = server_config.http2;
+                let rustls_config = RustlsConfig::from_config(Arc::new(tls::load_listener_config(tls_config, http2)?));
+                tls::spawn_reloader(tls_config.clone(), http2, rustls_config.clone());
+                Some(rustls_config)
+            }
+            None => None,
+        };
+        // Every listener serves the same router and shares the handle
+        let mut servers: Vec<BoxFuture<'static, std::io::Result<()>>> = Vec::new();
+        for (listener, address) in listeners.into_iter().zip(&local_addrs) {
+            let read_only = self.read_only.is_enabled();
+            match &rustls_config {
+                Some(rustls_config) => {
+                    info!(%address, read_only, "Server listening (TLS)");
+                    let mut server =
+                        axum_server::from_tcp_rustls(listener, rustls_config.clone()).handle(handle.clone());
+                    connection::configure(&mut server, server_config);
+                    servers.push(Box::pin(server.serve(app.clone())));
+                }
+                None => {
+                    info!(%address, read_only, "Server listening");
+                    let mut server = axum_server::from_tcp(listener).handle(handle.clone());
+                    connection::configure(&mut server, server_config);
+                    servers.push(Box::pin(server.serve(app.clone())));
+                }
+            }
+        }
+        if let (Some(listener), Some(address)) = (metrics_listener, metrics_addr) {
+            info!(%address, "Metrics listener listening");
+            let app = make_service(self.build_metrics_router(), Vec::new());
+            let mut server = axum_server::from_tcp(listener).handle(handle.clone());
+            connection::configure(&mut server, server_config);
+            servers.push(Box::pin(server.serve(app)));
+        }
+        let task = tokio::spawn(async move { futures::future::try_join_all(servers).await.map(|_| ()) });
+
+        Ok(ServerHandle {
+            local_addrs,
+            metrics_addr,
+            shutdown: shutdown_tx,
+            task,
+        })
+    }
+}
+
+/// Handle to a server serving in the background, returned by
+/// [`Server::bind`]
+///
+/// Dropping the handle shuts the server down without waiting for it.
+pub struct ServerHandle {
+    local_addrs: Vec<SocketAddr>,
+    metrics_addr: Option<SocketAddr>,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<std::io::Result<()>>,
+}
+
+impl ServerHandle {
+    /// Address the server is listening on, the first one when
+    /// `server.bind_address` lists several
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addrs[0]
+    }
+
+    /// Addresses the server is listening on, in `server.bind_address` order
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// Address the metrics listener is listening on, if one is configured
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_addr
+    }
+
+    /// Stop accepting connections, drain in-flight requests for up to
+    /// `server.shutdown_grace_secs` and wait for the server to stop
+    pub async fn shutdown(self) -> Result<(), Box<dyn std::error::Error>> {
+        let _ = self.shutdown.send(());
+        Self::joined(self.task.await)
+    }
 
-        Ok(())
+    fn joined(result: Result<std::io::Result<()>, JoinError>) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(result??)
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{Method, StatusCode};
+    use tower::ServiceExt;
+
+    use crate::metrics::{
+        BYTES_RECEIVED, BYTES_SENT, CLIENT_ABORTS, HTTP_OPERATION_DURATION, HTTP_REQUESTS, HTTP_REQUEST_DURATION,
+    };
+    use crate::storage::LocalBackend;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    fn test_config(extra: &str) -> Config {
+        toml::from_str(&format!(
+            "{}\n[server]\n[backend]\ntype = \"gcp\"\nbucket_name = \"b\"",
+            extra
+        ))
+        .unwrap()
+    }
+
+    fn test_server(root: &std::path::Path, extra: &str) -> Server {
+        let registry = BucketRegistry::single(Arc::new(LocalBackend::new(root).unwrap()));
+        Server::new(test_config(extra), Arc::new(registry)).unwrap()
+    }
+
+    async fn send(router: &Router, method: &str, uri: &str) -> StatusCode {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::from("data"))
+            .unwrap();
+        router.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_http_metrics_recorded() {
+        let root = tempfile::tempdir().unwrap();
+        let router = test_server(root.path(), "").build_router();
+        let count = |method: &str, operation: &str, status: &str| {
+            HTTP_REQUESTS.with_label_values(&[method, operation, status, ""]).get()
+        };
+        let observed = |operation: &str| HTTP_OPERATION_DURATION.with_label_values(&[operation]).get_sample_count();
+
+        let puts = count("PUT", "PutObject", "200");
+        let missing = count("GET", "GetObject", "404");
+        let lists = count("GET", "ListObjectsV2", "200");
+        let unknown = count("PATCH", "Unknown", "405");
+        let probes = HTTP_REQUESTS.with_label_values(&["HEAD", "ListObjects", "200", ""]).get();
+        let (all, get_objects) = (HTTP_REQUEST_DURATION.get_sample_count(), observed("GetObject"));
+
+        assert_eq!(send(&router, "PUT", "/bucket/metrics-key").await, StatusCode::OK);
+        assert_eq!(send(&router, "GET", "/bucket/metrics-missing").await, StatusCode::NOT_FOUND);
+        assert_eq!(send(&router, "GET", "/bucket?list-type=2").await, StatusCode::OK);
+        assert_eq!(send(&router, "PATCH", "/bucket/metrics-key").await, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(send(&router, "HEAD", "/healthz").await, StatusCode::OK);
+        assert_eq!(send(&router, "HEAD", "/metrics").await, StatusCode::OK);
+
+        // Other tests may record concurrently, so only check for growth
+        assert!(count("PUT", "PutObject", "200") > puts);
+        assert!(count("GET", "GetObject", "404") > missing);
+        assert!(count("GET", "ListObjectsV2", "200") > lists);
+        assert!(count("PATCH", "Unknown", "405") > unknown);
+        assert!(HTTP_REQUEST_DURATION.get_sample_count() >= all + 4);
+        assert!(observed("GetObject") > get_objects);
+        // Probes and scrapes are not recorded
+        assert_eq!(HTTP_REQUESTS.with_label_values(&["HEAD", "ListObjects", "200", ""]).get(), probes);
+    }
+
+    #[tokio::test]
+    async fn test_trace_id_header() {
+        let root = tempfile::tempdir().unwrap();
+        let router = test_server(root.path(), "").build_router();
+        let trace_id = |response: &axum::response::Response| {
+            response.headers()[&telemetry::TRACE_ID_HEADER].to_str().unwrap().to_string()
+        };
+
+        let request = Request::builder()
+            .uri("/bucket/trace-missing")
+            .header("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(trace_id(&response), "4bf92f3577b34da6a3ce929d0e0e4736");
+
+        // Without a traceparent each request starts a new trace
+        let request = || Request::builder().uri("/healthz").body(Body::empty()).unwrap();
+        let first = trace_id(&router.clone().oneshot(request()).await.unwrap());
+        let second = trace_id(&router.clone().oneshot(request()).await.unwrap());
+        assert_eq!(first.len(), 32);
+        assert_ne!(first, "0".repeat(32));
+        assert_ne!(first, second);
+    }
+
+    #[tokio::test]
+    async fn test_request_id_in_header_and_error() {
+        let root = tempfile::tempdir().unwrap();
+        let router = test_server(root.path(), "").build_router();
+
+        let request = Request::builder().uri("/bucket/request-id-missing").body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let id = response.headers()[&request_id::REQUEST_ID_HEADER].to_str().unwrap().to_string();
+        let host_id = response.headers()[&request_id::HOST_ID_HEADER].to_str().unwrap().to_string();
+        assert_eq!(id.len(), 16);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(&format!("<RequestId>{}</RequestId>", id)), "{}", body);
+        assert!(body.contains(&format!("<HostId>{}</HostId>", host_id)), "{}", body);
+
+        // So do the router's fallbacks
+        let request = Request::builder().method("PATCH").uri("/bucket/key").body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let fallback_id = response.headers()[&request_id::REQUEST_ID_HEADER].to_str().unwrap().to_string();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(&format!("<RequestId>{}</RequestId>", fallback_id)), "{}", body);
+
+        // Successful responses carry one too, unique per request
+        let request = Request::builder().uri("/healthz").body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_ne!(response.headers()[&request_id::REQUEST_ID_HEADER], id.as_str());
+    }
+
+    #[tokio::test]
+    async fn test_client_retries_counted() {
+        use crate::metrics::CLIENT_RETRIES;
+
+        let root = tempfile::tempdir().unwrap();
+        let router = test_server(root.path(), "").build_router();
+        let retries = || CLIENT_RETRIES.with_label_values(&["HeadObject"]).get();
+        let before = retries();
+
+        for attempt in ["attempt=1; max=3", "attempt=2; max=3"] {
+            let request = Request::builder()
+                .method("HEAD")
+                .uri("/bucket/retried-key")
+                .header("amz-sdk-invocation-id", "4f4d5a4e-1b7c-4b7a-9c8e-0d1e2f3a4b5c")
+                .header("amz-sdk-request", attempt)
+                .body(Body::empty())
+                .unwrap();
+            router.clone().oneshot(request).await.unwrap();
+        }
+        assert_eq!(retries(), before + 1);
+    }
+
+    #[tokio::test]
+    async fn test_byte_counters() {
+        let root = tempfile::tempdir().unwrap();
+        let router = test_server(root.path(), "").build_router();
+        let payload = "x".repeat(64 * 1024);
+        let received = BYTES_RECEIVED.with_label_values(&["PutObject"]).get();
+        let sent = BYTES_SENT.with_label_values(&["GetObject"]).get();
+
+        let request = Request::builder()
+            .method("PUT")
+            .uri("/bucket/bytes-key")
+            .body(Body::from(payload.clone()))
+            .unwrap();
+        assert_eq!(router.clone().oneshot(request).await.unwrap().status(), StatusCode::OK);
+
+        let request = Request::builder().uri("/bucket/bytes-key").body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.len(), payload.len());
+
+        assert!(BYTES_RECEIVED.with_label_values(&["PutObject"]).get() >= received + payload.len() as u64);
+        assert!(BYTES_SENT.with_label_values(&["GetObject"]).get() >= sent + payload.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn test_per_bucket_labels_limited_to_known_buckets() {
+        use prometheus::core::Collector;
+
+        let root = tempfile::tempdir().unwrap();
+        let mut registry = BucketRegistry::new();
+        registry
+            .insert("labeled-bucket", Arc::new(LocalBackend::new(root.path()).unwrap()))
+            .unwrap();
+        let router = Server::new(test_config("[metrics]\nper_bucket_labels = true"), Arc::new(registry))
+            .unwrap()
+            .build_router();
+        let count = |bucket: &str, status: &str| {
+            HTTP_REQUESTS.with_label_values(&["PUT", "PutObject", status, bucket]).get()
+        };
+        let (known, unknown) = (count("labeled-bucket", "200"), count("unknown", "404"));
+
+        assert_eq!(send(&router, "PUT", "/labeled-bucket/key").await, StatusCode::OK);
+        assert_eq!(send(&router, "PUT", "/probe-7f3a9c/key").await, StatusCode::NOT_FOUND);
+
+        assert!(count("labeled-bucket", "200") > known);
+        assert!(count("unknown", "404") > unknown);
+        let probed = HTTP_REQUESTS
+            .collect()
+            .iter()
+            .flat_map(|family| family.get_metric())
+            .flat_map(|metric| metric.get_label())
+            .any(|label| label.get_value() == "probe-7f3a9c");
+        assert!(!probed, "unknown bucket name became a label value");
+    }
+
+    #[tokio::test]
+    async fn test_http_metrics_include_auth_rejections() {
+        let root = tempfile::tempdir().unwrap();
+        let router = test_server(root.path(), "[auth]\nmode = \"bearer\"\ntokens = [\"t\"]").build_router();
+        let denied = HTTP_REQUESTS.with_label_values(&["DELETE", "DeleteObject", "403", ""]).get();
+
+        assert_eq!(send(&router, "DELETE", "/bucket/key").await, StatusCode::FORBIDDEN);
+        assert!(HTTP_REQUESTS.with_label_values(&["DELETE", "DeleteObject", "403", ""]).get() > denied);
+    }
+
+    #[tokio::test]
+    async fn test_bind_ephemeral_port_and_shutdown() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let root = tempfile::tempdir().unwrap();
+        let registry = BucketRegistry::single(Arc::new(LocalBackend::new(root.path()).unwrap()));
+        let mut config = test_config("");
+        config.server.bind_address = "127.0.0.1:0".parse().unwrap();
+        let server = Server::new(config, Arc::new(registry)).unwrap();
+
+        let handle = server.bind().await.unwrap();
+        let addr = handle.local_addr();
+        assert_ne!(addr.port(), 0);
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        handle.shutdown().await.unwrap();
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_metrics_listener_serves_system_endpoints() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        async fn status(addr: SocketAddr, path: &str) -> String {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path);
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response.lines().next().unwrap_or_default().to_string()
+        }
+
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("object"), "data").unwrap();
+        let registry = BucketRegistry::single(Arc::new(LocalBackend::new(root.path()).unwrap()));
+        let mut config = test_config("");
+        config.server.bind_address = "127.0.0.1:0".parse().unwrap();
+        config.server.metrics_bind_address = Some("127.0.0.1:0".parse().unwrap());
+        let server = Server::new(config, Arc::new(registry)).unwrap();
+
+        let handle = server.bind().await.unwrap();
+        let (main, metrics) = (handle.local_addr(), handle.metrics_addr().unwrap());
+        assert_ne!(main, metrics);
+
+        assert_eq!(status(main, "/bucket/object").await, "HTTP/1.1 200 OK");
+        assert_eq!(status(metrics, "/bucket/object").await, "HTTP/1.1 404 Not Found");
+        for path in ["/metrics", "/healthz", "/ready"] {
+            assert_eq!(status(metrics, path).await, "HTTP/1.1 200 OK", "{}", path);
+            // Not taken for a bucket on the main listener either
+            assert_eq!(status(main, path).await, "HTTP/1.1 404 Not Found", "{}", path);
+        }
+
+        handle.shutdown().await.unwrap();
+        assert!(tokio::net::TcpStream::connect(main).await.is_err());
+        assert!(tokio::net::TcpStream::connect(metrics).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bind_multiple_addresses() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("object"), "data").unwrap();
+        let registry = BucketRegistry::single(Arc::new(LocalBackend::new(root.path()).unwrap()));
+        let mut config = test_config("");
+        config.server.bind_address = "127.0.0.1:0,127.0.0.1:0".parse().unwrap();
+        let server = Server::new(config, Arc::new(registry)).unwrap();
+
+        let handle = server.bind().await.unwrap();
+        let addrs = handle.local_addrs().to_vec();
+        assert_eq!(addrs.len(), 2);
+        assert_ne!(addrs[0], addrs[1]);
+        assert_eq!(handle.local_addr(), addrs[0]);
+
+        for addr in &addrs {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(b"GET /bucket/object HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            assert!(response.starts_with("HTTP/1.1 200"), "{}: {}", addr, response);
+            assert!(response.ends_with("data"), "{}: {}", addr, response);
+        }
+
+        handle.shutdown().await.unwrap();
+        for addr in &addrs {
+            assert!(tokio::net::TcpStream::connect(addr).await.is_err(), "{}", addr);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bind_failure_names_address() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = taken.local_addr().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let registry = BucketRegistry::single(Arc::new(LocalBackend::new(root.path()).unwrap()));
+        let mut config = test_config("");
+        config.server.bind_address = vec!["127.0.0.1:0".parse().unwrap(), address].into();
+        let server = Server::new(config, Arc::new(registry)).unwrap();
+
+        let error = server.bind().await.err().unwrap().to_string();
+        assert!(error.starts_with(&format!("Failed to bind {}: ", address)), "{}", error);
+    }
+
+    /// Wait up to five seconds for `condition` to hold
+    async fn eventually(condition: impl Fn() -> bool) {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(std::time::Instant::now() < deadline, "condition not met in time");
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    /// Storage endpoint sending an endless object, with the number of
+    /// chunks sent so far and whether it stopped sending
+    async fn endless_backend() -> (SocketAddr, Arc<AtomicUsize>, Arc<AtomicBool>) {
+        struct Stopped(Arc<AtomicBool>);
+
+        impl Drop for Stopped {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let sent = Arc::new(AtomicUsize::new(0));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let app = Router::new().fallback({
+            let (sent, stopped) = (sent.clone(), stopped.clone());
+            move || async move {
+                let body = futures::stream::unfold(Stopped(stopped), move |guard| {
+                    let sent = sent.clone();
+                    async move {
+                        tokio::time::sleep(Duration::from_millis(2)).await;
+                        sent.fetch_add(1, Ordering::SeqCst);
+                        Some((Ok::<_, std::io::Error>(bytes::Bytes::from(vec![0; 1024])), guard))
+                    }
+                });
+                axum::response::Response::builder()
+                    .header("content-length", 64 << 20)
+                    .header("etag", "\"1\"")
+                    .header("last-modified", "Wed, 21 Oct 2015 07:28:00 GMT")
+                    .body(Body::from_stream(body))
+                    .unwrap()
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (address, sent, stopped)
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_stops_backend_reads() {
+        use tokio::io::AsyncWriteExt;
+
+        let (backend, sent, stopped) = endless_backend().await;
+        let gcp = crate::config::GcpConfig {
+            bucket_name: "bucket".to_string(),
+            use_managed_identity: false,
+            service_account_path: None,
+            service_account_key: None,
+            endpoint: Some(format!("http://{backend}")),
+            allow_http: true,
+            retry: crate::config::BackendRetryConfig::default(),
+        };
+        let registry = BucketRegistry::single(Arc::new(crate::storage::GcpBackend::new(&gcp).await.unwrap()));
+        let mut config = test_config("");
+        config.server.bind_address = "127.0.0.1:0".parse().unwrap();
+        let handle = Server::new(config, Arc::new(registry)).unwrap().bind().await.unwrap();
+        let aborted = || CLIENT_ABORTS.with_label_values(&["GetObject"]).get();
+        let before = aborted();
+
+        let mut client = tokio::net::TcpStream::connect(handle.local_addr()).await.unwrap();
+        client
+            .write_all(b"GET /bucket/huge HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        eventually(|| sent.load(Ordering::SeqCst) >= 10).await;
+        drop(client);
+
+        // The proxy stops reading, and the backend stops sending
+        eventually(|| stopped.load(Ordering::SeqCst)).await;
+        let total = sent.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(sent.load(Ordering::SeqCst), total);
+        eventually(|| aborted() > before).await;
+    }
+
+    /// Storage endpoint taking multipart uploads, with the requests it got
+    /// as method and query
+    async fn multipart_backend() -> (SocketAddr, Arc<std::sync::Mutex<Vec<String>>>) {
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let app = Router::new().fallback({
+            let requests = requests.clone();
+            move |method: Method, uri: axum::http::Uri| async move {
+                let query = uri.query().unwrap_or_default().to_string();
+                requests.lock().unwrap().push(format!("{method} ?{query}"));
+                let response = axum::response::Response::builder().header("etag", "\"1\"");
+                match method {
+                    Method::POST if query.starts_with("uploads") => response.body(Body::from(
+                        "<InitiateMultipartUploadResult><UploadId>u1</UploadId></InitiateMultipartUploadResult>",
+                    )),
+                    Method::DELETE => response.status(StatusCode::NO_CONTENT).body(Body::empty()),
+                    _ => response.body(Body::empty()),
+                }
+                .unwrap()
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (address, requests)
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_aborts_backend_upload() {
+        use tokio::io::AsyncWriteExt;
+
+        let (backend, requests) = multipart_backend().await;
+        let gcp = crate::config::GcpConfig {
+            bucket_name: "bucket".to_string(),
+            use_managed_identity: false,
+            service_account_path: None,
+            service_account_key: None,
+            endpoint: Some(format!("http://{backend}")),
+            allow_http: true,
+            retry: crate::config::BackendRetryConfig::default(),
+        };
+        let registry = BucketRegistry::single(Arc::new(crate::storage::GcpBackend::new(&gcp).await.unwrap()));
+        let mut config = test_config("");
+        config.server.bind_address = "127.0.0.1:0".parse().unwrap();
+        let handle = Server::new(config, Arc::new(registry)).unwrap().bind().await.unwrap();
+        let sent = |request: &str| requests.lock().unwrap().iter().any(|r| r.starts_with(request));
+
+        // Too large to buffer, so the proxy starts a multipart upload
+        let mut client = tokio::net::TcpStream::connect(handle.local_addr()).await.unwrap();
+        client
+            .write_all(b"PUT /bucket/huge HTTP/1.1\r\nHost: localhost\r\nContent-Length: 67108864\r\n\r\n")
+            .await
+            .unwrap();
+        client.write_all(&[0; 1 << 20]).await.unwrap();
+        eventually(|| sent("POST ?uploads")).await;
+        drop(client);
+
+        // The upload is aborted rather than completed or left behind
+        eventually(|| sent("DELETE ?uploadId=u1")).await;
+        assert!(!sent("POST ?uploadId"), "{:?}", requests.lock().unwrap());
+    }
+}
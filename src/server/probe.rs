@@ -0,0 +1,123 @@
+//! Backend connectivity probe for the readiness endpoint
+//!
+//! A pod whose backend credentials are broken fails every real request, so
+//! `/ready` checks each backend with a cheap call: a HEAD of the configured
+//! sentinel key, or a listing of at most one object. Kubelet polls the
+//! endpoint every few seconds, so results are reused for
+//! `readiness.cache_secs` and concurrent checks share a single probe.
+
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::config::ReadinessConfig;
+use crate::health::{BackendHealth, HealthCheck};
+use crate::metrics::{READINESS_PROBES, READINESS_PROBE_DURATION};
+use crate::storage::BucketRegistry;
+
+/// Cached backend connectivity check
+pub struct BackendProbe {
+    sentinel_key: Option<String>,
+    cache: Duration,
+    timeout: Duration,
+    last: Mutex<Option<(Instant, Result<(), String>)>>,
+}
+
+impl BackendProbe {
+    pub fn new(config: &ReadinessConfig) -> Self {
+        Self {
+            sentinel_key: config.sentinel_key.clone(),
+            cache: Duration::from_secs(config.cache_secs),
+            timeout: Duration::from_secs(config.timeout_secs),
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Check every backend in `registry`, reusing a recent result
+    pub async fn check(&self, registry: &BucketRegistry) -> Result<(), String> {
+        let mut last = self.last.lock().await;
+        if let Some((checked, result)) = last.as_ref() {
+            if checked.elapsed() < self.cache {
+                return result.clone();
+            }
+        }
+
+        let start = Instant::now();
+        let (outcome, result) = match tokio::time::timeout(self.timeout, self.probe_all(registry)).await {
+            Ok(Ok(())) => ("ok", Ok(())),
+            Ok(Err(e)) => ("error", Err(e)),
+            Err(_) => ("timeout", Err(format!("Backend probe timed out after {:?}", self.timeout))),
+        };
+        READINESS_PROBE_DURATION.observe(start.elapsed().as_secs_f64());
+        READINESS_PROBES.with_label_values(&[outcome]).inc();
+        if let Err(e) = &result {
+            warn!(error = %e, "Backend readiness probe failed");
+        }
+
+        *last = Some((Instant::now(), result.clone()));
+        result
+    }
+
+    async fn probe_all(&self, registry: &BucketRegistry) -> Result<(), String> {
+        let checks = BackendHealth::for_registry(registry, self.sentinel_key.as_deref());
+        let probes = checks.iter().map(|check| async move {
+            check
+                .check()
+                .await
+                .map_err(|e| format!("{}: {}", check.name(), e))
+        });
+        futures::future::try_join_all(probes).await.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use std::sync::Arc;
+
+    use crate::storage::LocalBackend;
+
+    fn probe(sentinel_key: Option<&str>, cache_secs: u64) -> BackendProbe {
+        BackendProbe::new(&ReadinessConfig {
+            check_backend: true,
+            sentinel_key: sentinel_key.map(str::to_string),
+            cache_secs,
+            timeout_secs: 2,
+        })
+    }
+
+    fn registry(root: &std::path::Path) -> BucketRegistry {
+        BucketRegistry::single(Arc::new(LocalBackend::new(root).unwrap()))
+    }
+
+    #[tokio::test]
+    async fn test_list_probe() {
+        let root = tempfile::tempdir().unwrap();
+        let registry = registry(root.path());
+        let before = READINESS_PROBES.with_label_values(&["ok"]).get();
+
+        probe(None, 0).check(&registry).await.unwrap();
+        assert!(READINESS_PROBES.with_label_values(&["ok"]).get() > before);
+
+        // A registry without backends has nothing to probe
+        probe(None, 0).check(&BucketRegistry::new()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sentinel_key_probe_and_cache() {
+        let root = tempfile::tempdir().unwrap();
+        let registry = registry(root.path());
+
+        let uncached = probe(Some("health/sentinel"), 0);
+        assert!(uncached.check(&registry).await.unwrap_err().starts_with("backend:*: "));
+
+        let cached = probe(Some("health/sentinel"), 60);
+        assert!(cached.check(&registry).await.is_err());
+        let backend = registry.resolve("bucket").unwrap();
+        backend.put("health/sentinel", Bytes::from("ok")).await.unwrap();
+        // The failure is reused until the cache expires
+        assert!(cached.check(&registry).await.is_err());
+        uncached.check(&registry).await.unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+//! Shutdown signals, readiness state and request draining
+//!
+//! Kubernetes stops pods with SIGTERM, interactive runs use Ctrl+C. Either
+//! starts a graceful shutdown: the listener stops accepting connections,
+//! the readiness probe reports unready so load balancers stop sending new
+//! traffic, and in-flight requests get `server.shutdown_grace_secs` to
+//! finish before the remaining connections are force-closed.
+
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum_server::Handle;
+use bytes::Bytes;
+use http_body::{Frame, SizeHint};
+use prometheus::IntGauge;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::metrics::INFLIGHT_REQUESTS;
+use crate::routes;
+
+/// Whether the proxy should receive new traffic
+#[derive(Debug, Clone)]
+pub struct Readiness(Arc<AtomicBool>);
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Self(Arc::new(AtomicBool::new(true)))
+    }
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Report unready from now on
+    pub fn set_unready(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Number of requests currently being served
+#[derive(Clone)]
+pub struct InFlight(Arc<watch::Sender<usize>>);
+
+impl Default for InFlight {
+    fn default() -> Self {
+        Self(Arc::new(watch::Sender::new(0)))
+    }
+}
+
+impl InFlight {
+    pub fn count(&self) -> usize {
+        *self.0.borrow()
+    }
+
+    /// Count a request of the given operation class until the returned
+    /// guard is dropped
+    fn start(&self, class: &str) -> InFlightGuard {
+        let gauge = INFLIGHT_REQUESTS.with_label_values(&[class]);
+        self.0.send_modify(|count| *count += 1);
+        gauge.inc();
+        InFlightGuard {
+            in_flight: self.clone(),
+            gauge,
+        }
+    }
+
+    /// Wait until no requests are in flight
+    async fn wait_idle(&self) {
+        let mut count = self.0.subscribe();
+        // The sender lives in self, so the channel cannot close while waiting
+        let _ = count.wait_for(|count| *count == 0).await;
+    }
+}
+
+struct InFlightGuard {
+    in_flight: InFlight,
+    gauge: IntGauge,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.in_flight.0.send_modify(|count| *count -= 1);
+        self.gauge.dec();
+    }
+}
+
+/// Middleware counting requests in flight
+///
+/// A request stays counted until its response body has been sent, so
+/// streaming downloads are included. Requests dropped by a force-close or
+/// a client disconnect are counted out as well.
+pub async fn track(State(in_flight): State<InFlight>, request: Request, next: Next) -> Response {
+    let operation = routes::operation_name(request.method(), request.uri().path(), request.uri().query());
+    let guard = in_flight.start(routes::operation_class(operation));
+    let response = next.run(request).await;
+    response.map(|body| GuardedBody::wrap(body, guard))
+}
+
+/// Response body releasing a guard once fully sent or dropped
+pub(super) struct GuardedBody<G> {
+    inner: Body,
+    guard: Option<G>,
+}
+
+impl<G: Send + Unpin + 'static> GuardedBody<G> {
+    pub(super) fn wrap(inner: Body, guard: G) -> Body {
+        Body::new(Self {
+            inner,
+            guard: Some(guard),
+        })
+    }
+}
+
+impl<G: Unpin> http_body::Body for GuardedBody<G> {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let frame = Pin::new(&mut self.inner).poll_frame(cx);
+        if matches!(frame, Poll::Ready(None | Some(Err(_)))) || self.inner.is_end_stream() {
+            self.guard = None;
+        }
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Stop accepting connections and wait up to `grace` for in-flight
+/// requests, then force-close the remaining connections
+///
+/// Returns the number of requests abandoned by the force-close.
+pub async fn drain(handle: &Handle, in_flight: &InFlight, grace: Duration) -> usize {
+    info!(in_flight = in_flight.count(), grace_secs = grace.as_secs_f64(), "Draining in-flight requests");
+    handle.graceful_shutdown(None);
+
+    if tokio::time::timeout(grace, in_flight.wait_idle()).await.is_ok() {
+        info!("All in-flight requests completed");
+        return 0;
+    }
+
+    let abandoned = in_flight.count();
+    warn!(abandoned, "Shutdown grace period elapsed, closing remaining connections");
+    handle.shutdown();
+    abandoned
+}
+
+/// Wait for SIGTERM or SIGINT (Ctrl+C) and return the signal's name
+pub async fn signal() -> &'static str {
+    let name = wait_for_signal().await;
+    info!(signal = name, "Received shutdown signal");
+    name
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to install SIGTERM signal handler");
+    let mut interrupt = signal(SignalKind::interrupt()).expect("Failed to install SIGINT signal handler");
+    tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = interrupt.recv() => "SIGINT",
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> &'static str {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to install CTRL+C signal handler");
+    "CTRL+C"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::{middleware::from_fn_with_state, routing::get, Router};
+    use futures::channel::mpsc;
+    use futures::StreamExt;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_streaming_response_counted_until_sent() {
+        let in_flight = InFlight::default();
+        let (sender, receiver) = mpsc::unbounded::<Result<Bytes, std::io::Error>>();
+        let body = Arc::new(std::sync::Mutex::new(Some(receiver)));
+        let router = Router::new()
+            .route(
+                "/bucket/*key",
+                get(move || {
+                    let receiver = body.lock().unwrap().take().unwrap();
+                    async move { Body::from_stream(receiver) }
+                }),
+            )
+            .layer(from_fn_with_state(in_flight.clone(), track));
+        let gauge = INFLIGHT_REQUESTS.with_label_values(&["data"]);
+
+        let request = Request::builder().uri("/bucket/key").body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        // The handler has returned but the body is still streaming
+        assert_eq!(in_flight.count(), 1);
+        assert!(gauge.get() >= 1);
+
+        let mut stream = response.into_body().into_data_stream();
+        sender.unbounded_send(Ok(Bytes::from("chunk"))).unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap(), Bytes::from("chunk"));
+        assert_eq!(in_flight.count(), 1);
+
+        drop(sender);
+        assert!(stream.next().await.is_none());
+        assert_eq!(in_flight.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_response_counted_out() {
+        let in_flight = InFlight::default();
+        let router = Router::new()
+            .route("/bucket", get(|| async { "listing" }))
+            .layer(from_fn_with_state(in_flight.clone(), track));
+
+        let request = Request::builder().uri("/bucket").body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(in_flight.count(), 1);
+        drop(response);
+        assert_eq!(in_flight.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_requests() {
+        let in_flight = InFlight::default();
+        let guard = in_flight.start("data");
+        assert_eq!(in_flight.count(), 1);
+
+        let finish = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(guard);
+        });
+        let abandoned = drain(&Handle::new(), &in_flight, Duration::from_secs(10)).await;
+        assert_eq!(abandoned, 0);
+        assert_eq!(in_flight.count(), 0);
+        finish.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_drain_abandons_requests_outliving_grace() {
+        let in_flight = InFlight::default();
+        let _guard = in_flight.start("data");
+        let _other = in_flight.start("metadata");
+
+        let started = std::time::Instant::now();
+        assert_eq!(drain(&Handle::new(), &in_flight, Duration::from_millis(100)).await, 2);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_drain_with_zero_grace() {
+        let in_flight = InFlight::default();
+        assert_eq!(drain(&Handle::new(), &in_flight, Duration::ZERO).await, 0);
+
+        let _guard = in_flight.start("metadata");
+        assert_eq!(drain(&Handle::new(), &in_flight, Duration::ZERO).await, 1);
+    }
+}
@@ -0,0 +1,543 @@
+//! Bandwidth limits
+//!
+//! `limits.max_download_bytes_per_sec` caps the object download bytes sent
+//! per second across all clients, so a few large pulls cannot saturate the
+//! node's network, and `limits.max_download_bytes_per_sec_per_connection`
+//! caps each client connection. The upload limits do the same for the
+//! bodies of PutObject and UploadPart, and `limits.upload_schedule` gives
+//! time windows limits of their own, so backups can run at full speed
+//! off-hours. Each limit is a token bucket holding a second's worth of
+//! bytes: a body passes on its data in small parts, each once every bucket
+//! it draws from has the tokens for it.
+//!
+//! Time an upload spends waiting for tokens does not count against the
+//! request timeout, so throttled uploads are not cut off. Download bytes
+//! are counted on the wire, after compression, so the throttled throughput
+//! shows in `s3proxy_bytes_sent_total`.
+
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use bytes::Bytes;
+use chrono::Timelike;
+use http_body::{Frame, SizeHint};
+use prometheus::{Counter, IntGauge};
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::time::{Instant, Sleep};
+
+use crate::config::{BandwidthWindow, LimitsConfig};
+use crate::metrics::{BANDWIDTH_LIMIT, THROTTLED_SECONDS};
+use crate::routes;
+
+/// Largest part of a body passed on at once, so throttled bodies flow
+/// evenly instead of in bursts
+const PART_SIZE: usize = 16 * 1024;
+
+/// Token bucket holding up to a second's worth of tokens at the rate it
+/// is refilled
+struct TokenBucket {
+    /// Tokens available at the instant, negative while in debt
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new((f64::INFINITY, Instant::now())),
+        }
+    }
+
+    /// Take the tokens for `bytes` at `rate` bytes per second, 0 being
+    /// unlimited, and return how long to wait until they are there
+    ///
+    /// Tokens not yet there are taken as debt, so concurrent callers wait
+    /// their turn instead of racing for refills.
+    fn reserve(&self, bytes: usize, rate: u64) -> Duration {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let (tokens, updated) = &mut *state;
+        let now = Instant::now();
+        if rate == 0 {
+            // Full once a limit applies again
+            *state = (f64::INFINITY, now);
+            return Duration::ZERO;
+        }
+        let rate = rate as f64;
+        *tokens = (*tokens + now.duration_since(*updated).as_secs_f64() * rate).min(rate);
+        *updated = now;
+        *tokens -= bytes as f64;
+        if *tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-*tokens / rate)
+        }
+    }
+}
+
+/// Bytes per second across all clients and per connection, 0 being
+/// unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Rates {
+    global: u64,
+    per_connection: u64,
+}
+
+impl Rates {
+    fn is_unlimited(&self) -> bool {
+        self.global == 0 && self.per_connection == 0
+    }
+}
+
+/// Daily window with rates of its own, in minutes after midnight UTC
+struct Window {
+    start: u32,
+    end: u32,
+    rates: Rates,
+}
+
+impl Window {
+    fn from_config(window: &BandwidthWindow) -> Option<Self> {
+        let (start, end) = window.minutes()?;
+        Some(Self {
+            start,
+            end,
+            rates: Rates {
+                global: window.max_bytes_per_sec,
+                per_connection: window.max_bytes_per_sec_per_connection,
+            },
+        })
+    }
+
+    fn contains(&self, minute: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+/// Bandwidth limit of one direction of traffic
+struct BandwidthLimit {
+    rates: Rates,
+    schedule: Vec<Window>,
+    global: TokenBucket,
+    /// Buckets of the connections with bodies in flight
+    connections: Mutex<HashMap<SocketAddr, Weak<TokenBucket>>>,
+    limits: [IntGauge; 2],
+    throttled: Counter,
+}
+
+impl BandwidthLimit {
+    fn new(direction: &'static str, rates: Rates, schedule: Vec<Window>) -> Option<Self> {
+        let limit = Self {
+            rates,
+            schedule,
+            global: TokenBucket::new(),
+            connections: Mutex::default(),
+            limits: ["global", "per_connection"].map(|scope| BANDWIDTH_LIMIT.with_label_values(&[direction, scope])),
+            throttled: THROTTLED_SECONDS.with_label_values(&[direction]),
+        };
+        if limit.rates.is_unlimited() && limit.schedule.iter().all(|window| window.rates.is_unlimited()) {
+            return None;
+        }
+        limit.current_rates();
+        Some(limit)
+    }
+
+    /// Rates at `minute` after midnight UTC: those of the first window
+    /// containing it, or else the default ones
+    fn rates_at(&self, minute: u32) -> Rates {
+        let window = self.schedule.iter().find(|window| window.contains(minute));
+        window.map_or(self.rates, |window| window.rates)
+    }
+
+    fn current_rates(&self) -> Rates {
+        let now = chrono::Utc::now();
+        let rates = self.rates_at(now.hour() * 60 + now.minute());
+        self.limits[0].set(rates.global as i64);
+        self.limits[1].set(rates.per_connection as i64);
+        rates
+    }
+
+    /// Bucket of the connection from `peer`, when connections are limited
+    ///
+    /// Bodies without a known peer get a connection bucket of their own.
+    fn connection(&self, peer: Option<SocketAddr>) -> Option<Arc<TokenBucket>> {
+        let limited = self.rates.per_connection > 0 || self.schedule.iter().any(|w| w.rates.per_connection > 0);
+        if !limited {
+            return None;
+        }
+        let Some(peer) = peer else {
+            return Some(Arc::new(TokenBucket::new()));
+        };
+        let mut connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(bucket) = connections.get(&peer).and_then(Weak::upgrade) {
+            return Some(bucket);
+        }
+        connections.retain(|_, bucket| bucket.strong_count() > 0);
+        let bucket = Arc::new(TokenBucket::new());
+        connections.insert(peer, Arc::downgrade(&bucket));
+        Some(bucket)
+    }
+
+    /// Take the tokens for `bytes` sent over `connection`, and return how
+    /// long to wait until they are there
+    fn reserve(&self, connection: Option<&TokenBucket>, bytes: usize) -> Duration {
+        let rates = self.current_rates();
+        let wait = self.global.reserve(bytes, rates.global);
+        let wait = connection.map_or(wait, |bucket| wait.max(bucket.reserve(bytes, rates.per_connection)));
+        if !wait.is_zero() {
+            self.throttled.inc_by(wait.as_secs_f64());
+        }
+        wait
+    }
+}
+
+/// Time the body of a request waited for bandwidth limits, which the
+/// request timeout does not count
+#[derive(Debug, Clone, Default)]
+pub(super) struct ThrottledTime(Arc<AtomicU64>);
+
+impl ThrottledTime {
+    pub(super) fn get(&self) -> Duration {
+        Duration::from_nanos(self.0.load(Ordering::SeqCst))
+    }
+
+    fn add(&self, wait: Duration) {
+        self.0.fetch_add(wait.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+/// Bandwidth limits of S3 traffic
+pub struct Throttle {
+    download: Option<Arc<BandwidthLimit>>,
+    upload: Option<Arc<BandwidthLimit>>,
+}
+
+impl Throttle {
+    /// Limits of the configuration, or None when nothing is limited
+    pub fn from_config(config: &LimitsConfig) -> Option<Self> {
+        let download = Rates {
+            global: config.max_download_bytes_per_sec,
+            per_connection: config.max_download_bytes_per_sec_per_connection,
+        };
+        let upload = Rates {
+            global: config.max_upload_bytes_per_sec,
+            per_connection: config.max_upload_bytes_per_sec_per_connection,
+        };
+        // Windows are validated with the configuration
+        let schedule = config.upload_schedule.iter().filter_map(Window::from_config).collect();
+        let throttle = Self {
+            download: BandwidthLimit::new("download", download, Vec::new()).map(Arc::new),
+            upload: BandwidthLimit::new("upload", upload, schedule).map(Arc::new),
+        };
+        (throttle.download.is_some() || throttle.upload.is_some()).then_some(throttle)
+    }
+}
+
+/// Middleware throttling object downloads and uploads to the bandwidth
+/// limits
+pub async fn limit(State(throttle): State<Option<Arc<Throttle>>>, request: Request, next: Next) -> Response {
+    let Some(throttle) = throttle else {
+        return next.run(request).await;
+    };
+    let path = request.uri().path();
+    if routes::is_system_path(path) {
+        return next.run(request).await;
+    }
+    let operation = routes::operation_name(request.method(), path, request.uri().query());
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| *addr);
+    match (operation, &throttle.download, &throttle.upload) {
+        ("GetObject", Some(download), _) => {
+            let body = |body| ThrottledBody::wrap(body, download.clone(), download.connection(peer), None);
+            next.run(request).await.map(body)
+        }
+        ("PutObject" | "UploadPart", _, Some(upload)) => {
+            let throttled = request.extensions().get::<ThrottledTime>().cloned();
+            let connection = upload.connection(peer);
+            let request = request.map(|body| ThrottledBody::wrap(body, upload.clone(), connection, throttled));
+            next.run(request).await
+        }
+        _ => next.run(request).await,
+    }
+}
+
+/// Body passing on its data as a bandwidth limit allows
+struct ThrottledBody {
+    inner: Body,
+    limit: Arc<BandwidthLimit>,
+    connection: Option<Arc<TokenBucket>>,
+    /// Where to add the time waited, for request bodies
+    throttled: Option<ThrottledTime>,
+    /// Data of the current frame not yet passed on
+    pending: Bytes,
+    /// Part waiting for its tokens
+    held: Option<(Bytes, Pin<Box<Sleep>>)>,
+}
+
+impl ThrottledBody {
+    fn wrap(
+        inner: Body,
+        limit: Arc<BandwidthLimit>,
+        connection: Option<Arc<TokenBucket>>,
+        throttled: Option<ThrottledTime>,
+    ) -> Body {
+        Body::new(Self {
+            inner,
+            limit,
+            connection,
+            throttled,
+            pending: Bytes::new(),
+            held: None,
+        })
+    }
+
+    fn buffered(&self) -> u64 {
+        (self.pending.len() + self.held.as_ref().map_or(0, |(part, _)| part.len())) as u64
+    }
+}
+
+impl http_body::Body for ThrottledBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = &mut *self;
+        loop {
+            if let Some((_, delay)) = &mut this.held {
+                ready!(delay.as_mut().poll(cx));
+                let (part, _) = this.held.take().expect("held part");
+                return Poll::Ready(Some(Ok(Frame::data(part))));
+            }
+            if this.pending.is_empty() {
+                match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+                    Some(Ok(frame)) => match frame.into_data() {
+                        Ok(data) => this.pending = data,
+                        Err(frame) => return Poll::Ready(Some(Ok(frame))),
+                    },
+                    end => return Poll::Ready(end),
+                }
+                continue;
+            }
+            let part = this.pending.split_to(this.pending.len().min(PART_SIZE));
+            let wait = this.limit.reserve(this.connection.as_deref(), part.len());
+            if wait.is_zero() {
+                return Poll::Ready(Some(Ok(Frame::data(part))));
+            }
+            if let Some(throttled) = &this.throttled {
+                throttled.add(wait);
+            }
+            this.held = Some((part, Box::pin(tokio::time::sleep(wait))));
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.buffered() == 0 && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let inner = self.inner.size_hint();
+        let mut hint = SizeHint::new();
+        hint.set_lower(inner.lower() + self.buffered());
+        if let Some(upper) = inner.upper() {
+            hint.set_upper(upper + self.buffered());
+        }
+        hint
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    use crate::config::Config;
+    use crate::server::Server;
+    use crate::storage::{BucketRegistry, MockBackend, StorageBackend};
+
+    const MB: usize = 1_000_000;
+
+    /// Router with the given request timeout over `backend`
+    fn router_over(backend: Arc<MockBackend>, limits: &str, timeout_secs: u64) -> Router {
+        let config: Config = toml::from_str(&format!(
+            "[limits]\n{}\n[server]\ntimeout_secs = {}\n[backend]\ntype = \"gcp\"\nbucket_name = \"b\"",
+            limits, timeout_secs
+        ))
+        .unwrap();
+        let registry = BucketRegistry::single(backend);
+        Server::new(config, Arc::new(registry)).unwrap().build_router()
+    }
+
+    /// Router over a backend holding `objects` of the given sizes
+    async fn router(limits: &str, objects: &[(&str, usize)]) -> Router {
+        let backend = Arc::new(MockBackend::new());
+        for (key, size) in objects {
+            backend.put(key, Bytes::from(vec![b'x'; *size])).await.unwrap();
+        }
+        router_over(backend, limits, 300)
+    }
+
+    /// Upload `size` bytes, returning the status and how long it took
+    async fn upload(router: &Router, key: &str, size: usize) -> (StatusCode, Duration) {
+        let started = Instant::now();
+        let request = Request::builder()
+            .method("PUT")
+            .uri(format!("/bucket/{}", key))
+            .body(Body::from(vec![b'x'; size]))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        (response.status(), started.elapsed())
+    }
+
+    /// Download `key` over the connection from `peer`, returning its size
+    /// and how long it took
+    async fn download(router: &Router, key: &str, peer: Option<&str>) -> (usize, Duration) {
+        let started = Instant::now();
+        let mut request = Request::builder().uri(format!("/bucket/{}", key)).body(Body::empty()).unwrap();
+        if let Some(peer) = peer {
+            request.extensions_mut().insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+        }
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (body.len(), started.elapsed())
+    }
+
+    #[test]
+    fn test_token_bucket_takes_debt() {
+        let bucket = TokenBucket::new();
+        assert_eq!(bucket.reserve(1000, 1000), Duration::ZERO);
+        let wait = bucket.reserve(500, 1000);
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500), "{:?}", wait);
+        let wait = bucket.reserve(500, 1000);
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_millis(1000), "{:?}", wait);
+
+        // Unlimited clears the debt
+        assert_eq!(bucket.reserve(1_000_000, 0), Duration::ZERO);
+        assert_eq!(bucket.reserve(1000, 1000), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_schedule_windows() {
+        let config: LimitsConfig = toml::from_str(
+            r#"
+            max_upload_bytes_per_sec = 1000
+            [[upload_schedule]]
+            start = "22:00"
+            end = "06:00"
+            max_bytes_per_sec = 0
+            [[upload_schedule]]
+            start = "09:00"
+            end = "17:30"
+            max_bytes_per_sec = 500
+            max_bytes_per_sec_per_connection = 100
+            "#,
+        )
+        .unwrap();
+        let throttle = Throttle::from_config(&config).unwrap();
+        let upload = throttle.upload.unwrap();
+        assert!(throttle.download.is_none());
+
+        let rates = |hour: u32, minute: u32| upload.rates_at(hour * 60 + minute);
+        let unlimited = Rates::default();
+        assert_eq!(rates(23, 0), unlimited);
+        assert_eq!(rates(0, 0), unlimited);
+        assert_eq!(rates(5, 59), unlimited);
+        assert_eq!(rates(6, 0), Rates { global: 1000, per_connection: 0 });
+        assert_eq!(rates(9, 0), Rates { global: 500, per_connection: 100 });
+        assert_eq!(rates(17, 29), Rates { global: 500, per_connection: 100 });
+        assert_eq!(rates(17, 30), Rates { global: 1000, per_connection: 0 });
+        // A per-connection limit in any window gives connections buckets
+        assert!(upload.connection(None).is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_download_throttled_to_global_limit() {
+        let router = router("max_download_bytes_per_sec = 1000000", &[("large", 10 * MB)]).await;
+
+        let (size, elapsed) = download(&router, "large", None).await;
+        assert_eq!(size, 10 * MB);
+        // The first second's worth is sent at once, the rest at the limit
+        assert!(elapsed >= Duration::from_secs(8) && elapsed <= Duration::from_secs(11), "{:?}", elapsed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_downloads_share_global_limit() {
+        let router = router("max_download_bytes_per_sec = 1000000", &[("a", 3 * MB), ("b", 3 * MB)]).await;
+
+        let (a, b) = tokio::join!(download(&router, "a", None), download(&router, "b", None));
+        let slowest = a.1.max(b.1);
+        assert!(slowest >= Duration::from_secs(4) && slowest <= Duration::from_secs(7), "{:?}", slowest);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_per_connection_limit() {
+        let limits = "max_download_bytes_per_sec_per_connection = 1000000";
+        let router = router(limits, &[("a", 3 * MB), ("b", 3 * MB)]).await;
+
+        // Two connections each get the full rate
+        let (a, b) = tokio::join!(
+            download(&router, "a", Some("10.0.0.1:1000")),
+            download(&router, "b", Some("10.0.0.2:1000")),
+        );
+        let slowest = a.1.max(b.1);
+        assert!(slowest >= Duration::from_secs(1) && slowest <= Duration::from_millis(2500), "{:?}", slowest);
+
+        // Bodies of one connection share it
+        let (a, b) = tokio::join!(
+            download(&router, "a", Some("10.0.0.1:1000")),
+            download(&router, "b", Some("10.0.0.1:1000")),
+        );
+        let slowest = a.1.max(b.1);
+        assert!(slowest >= Duration::from_secs(4) && slowest <= Duration::from_secs(7), "{:?}", slowest);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throttled_upload_outlives_timeout() {
+        let router = router_over(Arc::new(MockBackend::new()), "max_upload_bytes_per_sec = 500000", 1);
+        let throttled = THROTTLED_SECONDS.with_label_values(&["upload"]);
+        let before = throttled.get();
+
+        let (status, elapsed) = upload(&router, "backup", 2 * MB).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(elapsed >= Duration::from_secs(2) && elapsed <= Duration::from_secs(5), "{:?}", elapsed);
+        assert!(throttled.get() >= before + 2.0);
+
+        // Small uploads pass at once
+        let (status, elapsed) = upload(&router, "small", 1000).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout_still_applies_to_throttled_uploads() {
+        let backend = Arc::new(MockBackend::new());
+        backend.set_latency(Duration::from_secs(5));
+        let router = router_over(backend, "max_upload_bytes_per_sec = 1000000", 1);
+
+        // Waiting for the backend is not throttling
+        let (status, _) = upload(&router, "stuck", 2 * MB).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn test_nothing_throttled_by_default() {
+        assert!(Throttle::from_config(&LimitsConfig::default()).is_none());
+    }
+}
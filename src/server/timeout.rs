@@ -0,0 +1,100 @@
+//! Request timeout
+//!
+//! Requests not answered within `server.timeout_secs` get the S3
+//! `RequestTimeout` error, with a 503 status so SDKs retry them. Time an
+//! upload body spends waiting for the bandwidth limits is added to the
+//! deadline, so deliberately throttled uploads are not cut off; the
+//! timeout still applies to everything else the request waits for.
+
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::debug;
+
+use super::throttle::ThrottledTime;
+use crate::errors::S3ProxyError;
+
+/// Middleware answering requests that outlive `timeout` with
+/// `RequestTimeout`
+pub async fn limit(State(timeout): State<Duration>, mut request: Request, next: Next) -> Response {
+    let throttled = ThrottledTime::default();
+    request.extensions_mut().insert(throttled.clone());
+    let started = Instant::now();
+    let response = next.run(request);
+    tokio::pin!(response);
+    loop {
+        let deadline = started + timeout + throttled.get();
+        tokio::select! {
+            response = &mut response => return response,
+            _ = tokio::time::sleep_until(deadline) => {
+                // The body may have waited for the bandwidth limits meanwhile
+                if started + timeout + throttled.get() <= Instant::now() {
+                    debug!(timeout_secs = timeout.as_secs_f64(), "Request timed out");
+                    return S3ProxyError::RequestTimeout.into_response();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{header, Request, StatusCode};
+    use axum::Router;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    use crate::config::Config;
+    use crate::server::Server;
+    use crate::storage::{BucketRegistry, MockBackend, MockOperation, MockResponse};
+
+    /// Router over `backend` timing requests out after a second
+    fn router(backend: Arc<MockBackend>) -> Router {
+        let config: Config =
+            toml::from_str("[server]\ntimeout_secs = 1\n[backend]\ntype = \"gcp\"\nbucket_name = \"b\"").unwrap();
+        let registry = BucketRegistry::single(backend);
+        Server::new(config, Arc::new(registry)).unwrap().build_router()
+    }
+
+    async fn get(router: &Router, uri: &str) -> (StatusCode, String, Option<String>, String) {
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let header = |name: &str| response.headers().get(name).map(|v| v.to_str().unwrap().to_string());
+        let (content_type, request_id) = (header(header::CONTENT_TYPE.as_str()), header("x-amz-request-id"));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        (status, body, content_type, request_id.unwrap())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_expired_request_gets_request_timeout() {
+        let backend = Arc::new(MockBackend::new());
+        backend.set_latency(Duration::from_secs(5));
+
+        let (status, body, content_type, request_id) = get(&router(backend), "/bucket/slow").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(content_type.as_deref(), Some("application/xml"));
+        assert!(body.starts_with("<?xml"), "{}", body);
+        assert!(body.contains("<Code>RequestTimeout</Code>"), "{}", body);
+        assert!(body.contains(&format!("<RequestId>{}</RequestId>", request_id)), "{}", body);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_backend_timeout_gets_slow_down() {
+        let backend = Arc::new(MockBackend::new());
+        let timed_out = object_store::Error::Generic {
+            store: "timeout",
+            source: Box::new(std::io::Error::new(std::io::ErrorKind::TimedOut, "Storage get timed out")),
+        };
+        backend.push(MockOperation::Get, MockResponse::Error(timed_out));
+
+        let (status, body, _, _) = get(&router(backend), "/bucket/stuck").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body.contains("<Code>SlowDown</Code>"), "{}", body);
+    }
+}
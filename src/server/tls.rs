@@ -0,0 +1,285 @@
+//! TLS termination for the listener
+//!
+//! Certificates and keys are read from PEM files at startup, where any
+//! problem is a startup error. While running, the files are reloaded on
+//! SIGHUP and whenever their modification time changes, so rotated
+//! certificates (e.g. cert-manager secrets) are picked up without a
+//! restart. A failed reload keeps serving the previous certificate.
+
+use async_trait::async_trait;
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+use crate::config::TlsConfig;
+use crate::health::HealthCheck;
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// Build the rustls server configuration from the configured PEM files
+pub fn load_server_config(tls: &TlsConfig) -> Result<ServerConfig, String> {
+    let certs = read_certs(&tls.cert_path)?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}", tls.cert_path));
+    }
+    let key = read_key(&tls.key_path)?;
+
+    let builder = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("Invalid TLS protocol configuration: {}", e))?;
+    let builder = match &tls.client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(path)? {
+                roots
+                    .add(cert)
+                    .map_err(|e| format!("Invalid client CA certificate in {}: {}", path, e))?;
+            }
+            if roots.is_empty() {
+                return Err(format!("No client CA certificates found in {}", path));
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider())
+                .build()
+                .map_err(|e| format!("Invalid client CA bundle {}: {}", path, e))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder.with_single_cert(certs, key).map_err(|e| {
+        format!(
+            "Certificate {} does not match key {}: {}",
+            tls.cert_path, tls.key_path, e
+        )
+    })?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+/// Build the rustls configuration of the listener, offering HTTP/2
+/// through ALPN only when it is enabled
+pub fn load_listener_config(tls: &TlsConfig, http2: bool) -> Result<ServerConfig, String> {
+    let mut config = load_server_config(tls)?;
+    if !http2 {
+        config.alpn_protocols.retain(|protocol| protocol.as_slice() != b"h2");
+    }
+    Ok(config)
+}
+
+fn read_pem(path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))
+}
+
+fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    rustls_pemfile::certs(&mut read_pem(path)?.as_slice())
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("Invalid certificate PEM in {}: {}", path, e))
+}
+
+fn read_key(path: &str) -> Result<PrivateKeyDer<'static>, String> {
+    rustls_pemfile::private_key(&mut read_pem(path)?.as_slice())
+        .map_err(|e| format!("Invalid private key PEM in {}: {}", path, e))?
+        .ok_or_else(|| format!("No private key found in {}", path))
+}
+
+/// Modification times of the configured files, used to detect rotation
+fn modified_times(tls: &TlsConfig) -> Vec<Option<SystemTime>> {
+    [Some(&tls.cert_path), Some(&tls.key_path), tls.client_ca_path.as_ref()]
+        .into_iter()
+        .flatten()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Reload `config` from the files on SIGHUP or when they change
+pub fn spawn_reloader(tls: TlsConfig, http2: bool, config: RustlsConfig) {
+    tokio::spawn(async move {
+        let mut hangup = Hangup::new();
+        let interval = Duration::from_secs(tls.reload_interval_secs);
+        let mut last_modified = modified_times(&tls);
+
+        loop {
+            let reason = tokio::select! {
+                _ = hangup.recv() => "SIGHUP",
+                _ = tokio::time::sleep(interval), if !interval.is_zero() => {
+                    let modified = modified_times(&tls);
+                    if modified == last_modified {
+                        continue;
+                    }
+                    last_modified = modified;
+                    "file change"
+                }
+            };
+
+            match load_listener_config(&tls, http2) {
+                Ok(server_config) => {
+                    config.reload_from_config(Arc::new(server_config));
+                    info!(reason, cert_path = %tls.cert_path, "TLS certificate reloaded");
+                }
+                Err(e) => warn!(reason, error = %e, "TLS certificate reload failed, keeping previous certificate"),
+            }
+        }
+    });
+}
+
+/// Whether the configured files would load on the next reload
+///
+/// Not critical: a broken rotation keeps serving the previous certificate.
+pub struct TlsHealth(pub TlsConfig);
+
+#[async_trait]
+impl HealthCheck for TlsHealth {
+    fn name(&self) -> String {
+        "tls".to_string()
+    }
+
+    fn critical(&self) -> bool {
+        false
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        load_server_config(&self.0).map(|_| ())
+    }
+}
+
+/// SIGHUP listener; never fires where SIGHUP doesn't exist
+struct Hangup {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangup {
+    fn new() -> Self {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let signal = signal(SignalKind::hangup())
+                .map_err(|e| warn!(error = %e, "Failed to install SIGHUP handler"))
+                .ok();
+            Self { signal }
+        }
+        #[cfg(not(unix))]
+        Self {}
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            signal.recv().await;
+            return;
+        }
+        std::future::pending::<()>().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+
+    struct Files {
+        dir: tempfile::TempDir,
+    }
+
+    impl Files {
+        fn new() -> Self {
+            Self {
+                dir: tempfile::tempdir().unwrap(),
+            }
+        }
+
+        fn write(&self, name: &str, contents: &str) -> String {
+            let path = self.dir.path().join(name);
+            std::fs::write(&path, contents).unwrap();
+            path.to_str().unwrap().to_string()
+        }
+    }
+
+    fn self_signed(name: &str) -> (String, String) {
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec![name.to_string()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+
+    fn tls_config(cert_path: String, key_path: String) -> TlsConfig {
+        TlsConfig {
+            cert_path,
+            key_path,
+            client_ca_path: None,
+            reload_interval_secs: 0,
+        }
+    }
+
+    #[test]
+    fn test_load_server_config() {
+        let files = Files::new();
+        let (cert, key) = self_signed("localhost");
+        let tls = tls_config(files.write("cert.pem", &cert), files.write("key.pem", &key));
+
+        let config = load_server_config(&tls).unwrap();
+        assert_eq!(config.alpn_protocols, vec![b"h2".to_vec(), b"http/1.1".to_vec()]);
+        let config = load_listener_config(&tls, false).unwrap();
+        assert_eq!(config.alpn_protocols, vec![b"http/1.1".to_vec()]);
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let files = Files::new();
+        let (cert, key) = self_signed("localhost");
+        let (_, other_key) = self_signed("other");
+        let cert_path = files.write("cert.pem", &cert);
+        let key_path = files.write("key.pem", &key);
+
+        let missing = tls_config(cert_path.clone(), files.dir.path().join("missing.pem").to_str().unwrap().to_string());
+        assert!(load_server_config(&missing).unwrap_err().starts_with("Failed to read"));
+
+        let no_key = tls_config(cert_path.clone(), cert_path.clone());
+        assert!(load_server_config(&no_key).unwrap_err().starts_with("No private key found"));
+
+        let no_cert = tls_config(key_path.clone(), key_path.clone());
+        assert!(load_server_config(&no_cert).unwrap_err().starts_with("No certificates found"));
+
+        let mismatched = tls_config(cert_path, files.write("other.pem", &other_key));
+        assert!(load_server_config(&mismatched).unwrap_err().contains("does not match"));
+    }
+
+    #[test]
+    fn test_client_ca() {
+        let files = Files::new();
+        let (cert, key) = self_signed("localhost");
+        let mut tls = tls_config(files.write("cert.pem", &cert), files.write("key.pem", &key));
+
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = params.self_signed(&ca_key).unwrap();
+        tls.client_ca_path = Some(files.write("ca.pem", &ca.pem()));
+        load_server_config(&tls).unwrap();
+
+        tls.client_ca_path = Some(files.write("empty.pem", ""));
+        assert!(load_server_config(&tls).unwrap_err().starts_with("No client CA certificates"));
+    }
+
+    #[test]
+    fn test_modified_times_detect_rotation() {
+        let files = Files::new();
+        let (cert, key) = self_signed("localhost");
+        let tls = tls_config(files.write("cert.pem", &cert), files.write("key.pem", &key));
+        let before = modified_times(&tls);
+        assert_eq!(before.len(), 2);
+
+        let file = std::fs::File::options().write(true).open(&tls.cert_path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+        assert_ne!(modified_times(&tls), before);
+    }
+}
@@ -3,6 +3,8 @@
 //! Uses object_store::aws::AmazonS3 with support for:
 //! - Managed identity via IRSA (IAM Role for Service Account) in Kubernetes
 //! - Explicit credentials (access key ID and secret access key)
+//! - An IAM role assumed with either, e.g. one in another account
+//! - A named profile of the shared config files (e.g. for local development)
 //!
 //! When using managed identity, relies on the default AWS credential chain:
 //! - IRSA role annotations in Kubernetes
@@ -12,52 +14,137 @@
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use futures::stream::StreamExt;
-use object_store::aws::{AmazonS3, AmazonS3Builder};
+use futures::stream::{self, BoxStream, StreamExt};
+use object_store::aws::{AmazonS3, AmazonS3Builder, AmazonS3ConfigKey, S3ConditionalPut};
 use object_store::path::Path;
-use object_store::{ObjectMeta, ObjectStore};
+use object_store::{ClientOptions, GetOptions, GetResult, ListResult, ObjectMeta, ObjectStore, PutResult};
+use std::io;
 use std::sync::Arc;
+use tracing::warn;
 
-use crate::config::AwsConfig;
-use crate::storage::StorageBackend;
+use crate::config::{AddressingStyle, AwsConfig};
+use crate::errors::StorageError;
+use crate::storage::credentials::SdkCredentials;
+use crate::storage::{
+    context, list_ordered, put_multipart, retry_config, strip_list_prefix, strip_prefix, ObjectAttributes,
+    StorageBackend, DEFAULT_DELETE_CONCURRENCY, DEFAULT_PART_SIZE,
+};
+
+/// Backend label of errors
+const BACKEND: &str = "aws";
+
+/// Store name of the error S3 denied a request with while the backend does
+/// not pay for requests, as requester-pays buckets do
+pub(crate) const REQUESTER_PAYS_STORE: &str = "S3RequesterPays";
+
+/// Keys deleted at most by one DeleteObjects request
+const MAX_DELETE_BATCH: usize = 1000;
 
 /// AWS S3 storage backend
 pub struct AwsBackend {
     store: Arc<AmazonS3>,
     prefix: Option<String>,
+    delete_concurrency: usize,
+    part_size: usize,
+    /// Requests are unsigned, so writes can never succeed
+    anonymous: bool,
+    /// Requests carry `x-amz-request-payer: requester`
+    request_payer: bool,
 }
 
 impl AwsBackend {
     /// Create a new AWS S3 backend
     ///
-    /// Supports two authentication modes:
+    /// Supports three authentication modes:
     /// 1. Managed identity (default): Uses default AWS credential provider chain
-    /// 2. Explicit credentials: Sets AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY env vars
+    /// 2. Explicit credentials: Passes the configured keys to this backend's
+    ///    client only, so backends with different keys can coexist
+    /// 3. Named profile: Loads the profile's credentials from the shared
+    ///    config files, failing if they cannot be obtained
     pub async fn new(config: &AwsConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::new_with_client_options(config, ClientOptions::default()).await
+    }
+
+    /// Create a new AWS S3 backend whose HTTP client has `client_options`
+    pub async fn new_with_client_options(
+        config: &AwsConfig,
+        client_options: ClientOptions,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut builder = AmazonS3Builder::new()
+            .with_client_options(client_options)
+            .with_retry(retry_config(&config.retry))
+            .with_bucket_name(&config.bucket_name)
+            .with_region(&config.region)
+            // Create-only puts, for write-once mode, send If-None-Match: *
+            .with_conditional_put(S3ConditionalPut::ETagMatch);
+        builder = with_request_payer(builder, config);
+
         // Configure authentication
-        if !config.use_managed_identity {
-            // Use explicit credentials via environment variables
-            // object_store uses the AWS SDK which reads from environment variables
-            if let (Some(access_key_id), Some(secret_access_key)) =
-                (&config.access_key_id, &config.secret_access_key)
-            {
-                std::env::set_var("AWS_ACCESS_KEY_ID", access_key_id);
-                std::env::set_var("AWS_SECRET_ACCESS_KEY", secret_access_key);
-            } else {
+        if config.anonymous {
+            let credentials = [
+                ("access_key_id", config.access_key_id.is_some()),
+                ("secret_access_key", config.secret_access_key.is_some()),
+                ("session_token", config.session_token.is_some()),
+                ("role_arn", config.role_arn.is_some()),
+                ("web_identity_token_file", config.web_identity_token_file.is_some()),
+                ("profile", config.profile.is_some()),
+                // S3 only honors x-amz-request-payer when it is signed
+                ("request_payer", config.request_payer),
+            ];
+            let set: Vec<_> = credentials.iter().filter(|(_, set)| *set).map(|(name, _)| *name).collect();
+            if !set.is_empty() {
+                return Err(format!("AWS anonymous access cannot be combined with {}", set.join(", ")).into());
+            }
+            // Public buckets: no credentials are looked up at all
+            builder = builder.with_config(AmazonS3ConfigKey::SkipSignature, "true");
+        } else if config.web_identity_token_file.is_some() && config.role_arn.is_none() {
+            return Err("AWS web_identity_token_file requires role_arn".into());
+        }
+        if config.profile.is_some() {
+            if config.access_key_id.is_some() || config.secret_access_key.is_some() {
+                return Err("AWS profile and explicit credentials (access_key_id, secret_access_key) are mutually exclusive".into());
+            }
+            if config.role_arn.is_some() {
+                return Err("AWS profile and role_arn are mutually exclusive; set role_arn in the profile instead".into());
+            }
+        }
+        if !config.use_managed_identity
+            && !config.anonymous
+            && config.web_identity_token_file.is_none()
+            && config.profile.is_none()
+        {
+            let (Some(access_key_id), Some(secret_access_key)) = (&config.access_key_id, &config.secret_access_key)
+            else {
                 return Err("AWS credentials (access_key_id and secret_access_key) are required when use_managed_identity is false".into());
+            };
+            if config.role_arn.is_none() {
+                builder = builder
+                    .with_access_key_id(access_key_id)
+                    .with_secret_access_key(secret_access_key.expose());
+                if let Some(token) = &config.session_token {
+                    builder = builder.with_token(token.expose());
+                }
             }
         }
         // If use_managed_identity is true, builder will use default credential chain
         // (IRSA, environment variables, EC2 metadata, etc.)
 
-        let mut builder = AmazonS3Builder::new()
-            .with_bucket_name(&config.bucket_name)
-            .with_region(&config.region);
+        // Assume a role with the credentials above or a web identity token,
+        // refreshing its temporary credentials before they expire
+        if let Some(role_arn) = &config.role_arn {
+            let credentials = SdkCredentials::assume_role(role_arn, config).await?;
+            builder = builder.with_credentials(Arc::new(credentials));
+        } else if let Some(profile) = &config.profile {
+            // A profile of ~/.aws/config and ~/.aws/credentials
+            let credentials = SdkCredentials::profile(profile, config, None).await?;
+            builder = builder.with_credentials(Arc::new(credentials));
+        }
 
         // Configure endpoint (for S3-compatible services like MinIO)
         if let Some(endpoint) = &config.endpoint {
             builder = builder.with_endpoint(endpoint);
         }
+        builder = with_addressing_style(builder, config);
 
         // Configure HTTP/HTTPS
         if config.allow_http {
@@ -70,6 +157,10 @@ impl AwsBackend {
         Ok(Self {
             store,
             prefix: None, // Prefix is applied at Config level
+            delete_concurrency: DEFAULT_DELETE_CONCURRENCY,
+            part_size: DEFAULT_PART_SIZE,
+            anonymous: config.anonymous,
+            request_payer: config.request_payer,
         })
     }
 
@@ -83,49 +174,230 @@ impl AwsBackend {
         Path::from(full_path)
     }
 
+    /// Delete a batch of keys with one DeleteObjects request, returning the
+    /// outcome for each key in order
+    async fn delete_batch(&self, batch: Vec<String>) -> Vec<Result<(), object_store::Error>> {
+        let locations = batch.iter().map(|path| Ok(self.apply_prefix(path)));
+        let results: Vec<_> = self.store.delete_stream(stream::iter(locations).boxed()).collect().await;
+        if results.len() == batch.len() {
+            return results.into_iter().map(|result| result.map(|_| ())).collect();
+        }
+        // The request itself failed, for every key
+        let error = results
+            .into_iter()
+            .find_map(Result::err)
+            .map_or_else(|| "Incomplete DeleteObjects response".to_string(), |e| e.to_string());
+        batch
+            .iter()
+            .map(|_| {
+                Err(object_store::Error::Generic {
+                    store: "S3",
+                    source: error.clone().into(),
+                })
+            })
+            .collect()
+    }
+
     /// Set the prefix for this backend
     pub fn with_prefix(mut self, prefix: Option<String>) -> Self {
         self.prefix = prefix;
         self
     }
+
+    /// Set the requests in flight at once in `delete_many`
+    pub fn with_delete_concurrency(mut self, concurrency: usize) -> Self {
+        self.delete_concurrency = concurrency;
+        self
+    }
+
+    /// Set the size of the parts `put_stream` uploads
+    pub fn with_part_size(mut self, part_size: usize) -> Self {
+        self.part_size = part_size;
+        self
+    }
+
+    /// Attach the operation and path to an S3 error
+    ///
+    /// S3 denies requests to a requester-pays bucket that do not pay for
+    /// them like any other, so denials are marked with
+    /// [`REQUESTER_PAYS_STORE`] while `request_payer` is off, to point at
+    /// the setting.
+    fn context<'a>(
+        &self,
+        operation: &'static str,
+        path: &'a str,
+    ) -> impl Fn(object_store::Error) -> StorageError + Copy + 'a {
+        let request_payer = self.request_payer;
+        move |error| {
+            let error = match error {
+                error @ object_store::Error::PermissionDenied { .. } if !request_payer => {
+                    object_store::Error::Generic {
+                        store: REQUESTER_PAYS_STORE,
+                        source: Box::new(error),
+                    }
+                }
+                error => error,
+            };
+            context(BACKEND, operation, path)(error)
+        }
+    }
+
+    /// Refuse `operation` on `path` up front when anonymous, as S3 would
+    fn check_writable(&self, operation: &'static str, path: &str) -> Result<(), StorageError> {
+        if !self.anonymous {
+            return Ok(());
+        }
+        let source = io::Error::new(io::ErrorKind::PermissionDenied, "anonymous access is read-only");
+        Err(context(BACKEND, operation, path)(object_store::Error::Generic {
+            store: "S3",
+            source: Box::new(source),
+        }))
+    }
+}
+
+/// Pay for requests when `config` asks, so requester-pays buckets accept
+/// them
+fn with_request_payer(builder: AmazonS3Builder, config: &AwsConfig) -> AmazonS3Builder {
+    builder.with_request_payer(config.request_payer)
+}
+
+/// Address the bucket as `config` asks, path style unless told otherwise
+fn with_addressing_style(builder: AmazonS3Builder, config: &AwsConfig) -> AmazonS3Builder {
+    let style = match config.addressing_style {
+        Some(style) => style,
+        None => {
+            if config.endpoint.is_some() {
+                warn!(
+                    bucket = %config.bucket_name,
+                    "Custom AWS endpoint without an addressing_style, using path style"
+                );
+            }
+            AddressingStyle::Path
+        }
+    };
+    if style == AddressingStyle::VirtualHosted && config.bucket_name.contains('.') && !config.allow_http {
+        // `a.b.s3.amazonaws.com` is not covered by the `*.s3.amazonaws.com` certificate
+        warn!(
+            bucket = %config.bucket_name,
+            "Virtual-hosted addressing of a bucket name with dots fails TLS verification on AWS, use path style"
+        );
+    }
+    builder.with_virtual_hosted_style_request(style == AddressingStyle::VirtualHosted)
 }
 
 #[async_trait]
 impl StorageBackend for AwsBackend {
-    async fn get(&self, path: &str) -> Result<Bytes, object_store::Error> {
-        let path = self.apply_prefix(path);
-        let data = self.store.get(&path).await?;
-        let bytes = data.bytes().await?;
-        Ok(bytes)
+    async fn get_opts(&self, path: &str, options: GetOptions) -> Result<GetResult, StorageError> {
+        let mut result = self
+            .store
+            .get_opts(&self.apply_prefix(path), options)
+            .await
+            .map_err(self.context("get", path))?;
+        result.meta = strip_prefix(self.prefix.as_deref(), result.meta);
+        Ok(result)
     }
 
-    async fn put(&self, path: &str, data: Bytes) -> Result<(), object_store::Error> {
-        let path = self.apply_prefix(path);
-        self.store.put(&path, data.into()).await?;
-        Ok(())
+    async fn put_with_attributes(
+        &self,
+        path: &str,
+        data: Bytes,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        self.check_writable("put", path)?;
+        self.store
+            .put_opts(&self.apply_prefix(path), data.into(), attributes.put_options())
+            .await
+            .map_err(self.context("put", path))
     }
 
-    async fn delete(&self, path: &str) -> Result<(), object_store::Error> {
-        let path = self.apply_prefix(path);
-        self.store.delete(&path).await?;
-        Ok(())
+    async fn put_stream(
+        &self,
+        path: &str,
+        stream: BoxStream<'static, Result<Bytes, io::Error>>,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        self.check_writable("put_stream", path)?;
+        put_multipart(self.store.as_ref(), &self.apply_prefix(path), stream, attributes, self.part_size)
+            .await
+            .map_err(self.context("put_stream", path))
     }
 
-    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>, object_store::Error> {
-        let prefix = self.apply_prefix(prefix);
-        let mut results = vec![];
-        let mut stream = self.store.list(Some(&prefix));
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        self.check_writable("delete", path)?;
+        self.store
+            .delete(&self.apply_prefix(path))
+            .await
+            .map_err(self.context("delete", path))
+    }
 
-        while let Some(meta) = stream.next().await {
-            results.push(meta?);
-        }
+    async fn list(
+        &self,
+        prefix: &str,
+        offset: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<ObjectMeta>, StorageError> {
+        let location = self.apply_prefix(prefix);
+        list_ordered(self.store.as_ref(), self.prefix.as_deref(), &location, offset, limit)
+            .await
+            .map_err(self.context("list", prefix))
+    }
+
+    async fn list_with_delimiter(&self, prefix: &str) -> Result<ListResult, StorageError> {
+        let result = self
+            .store
+            .list_with_delimiter(Some(&self.apply_prefix(prefix)))
+            .await
+            .map_err(self.context("list_with_delimiter", prefix))?;
+        Ok(strip_list_prefix(self.prefix.as_deref(), result))
+    }
+
+    async fn head(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        self.store
+            .head(&self.apply_prefix(path))
+            .await
+            .map_err(self.context("head", path))
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.check_writable("copy", to)?;
+        self.store
+            .copy(&self.apply_prefix(from), &self.apply_prefix(to))
+            .await
+            .map_err(self.context("copy", to))
+    }
 
-        Ok(results)
+    async fn copy_if_not_exists(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.check_writable("copy_if_not_exists", to)?;
+        self.store
+            .copy_if_not_exists(&self.apply_prefix(from), &self.apply_prefix(to))
+            .await
+            .map_err(self.context("copy_if_not_exists", to))
     }
 
-    async fn head(&self, path: &str) -> Result<ObjectMeta, object_store::Error> {
-        let path = self.apply_prefix(path);
-        self.store.head(&path).await
+    async fn delete_many(&self, paths: Vec<String>) -> Vec<(String, Result<(), StorageError>)> {
+        if self.anonymous {
+            return paths
+                .into_iter()
+                .map(|path| {
+                    let result = self.check_writable("delete", &path);
+                    (path, result)
+                })
+                .collect();
+        }
+        let batches: Vec<Vec<String>> = paths.chunks(MAX_DELETE_BATCH).map(<[String]>::to_vec).collect();
+        let results: Vec<_> = stream::iter(batches)
+            .map(|batch| self.delete_batch(batch))
+            .buffered(self.delete_concurrency.max(1))
+            .collect()
+            .await;
+        paths
+            .into_iter()
+            .zip(results.into_iter().flatten())
+            .map(|(path, result)| {
+                let result = result.map_err(self.context("delete", &path));
+                (path, result)
+            })
+            .collect()
     }
 
     #[allow(dead_code)] // Part of trait interface for extensibility
@@ -133,3 +405,192 @@ impl StorageBackend for AwsBackend {
         self.store.as_ref()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BackendRetryConfig;
+
+    fn config(access_key_id: &str, secret_access_key: &str) -> AwsConfig {
+        AwsConfig {
+            bucket_name: "bucket".to_string(),
+            region: "us-east-1".to_string(),
+            endpoint: None,
+            use_managed_identity: false,
+            access_key_id: Some(access_key_id.to_string()),
+            secret_access_key: Some(secret_access_key.into()),
+            session_token: Some("session-token".into()),
+            role_arn: None,
+            external_id: None,
+            web_identity_token_file: None,
+            profile: None,
+            request_payer: false,
+            anonymous: false,
+            addressing_style: None,
+            role_session_name: "s3proxy".to_string(),
+            allow_http: false,
+            retry: BackendRetryConfig::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_explicit_credentials_stay_out_of_process_env() {
+        AwsBackend::new(&config("AKIDFIRST", "first-secret")).await.unwrap();
+        AwsBackend::new(&config("AKIDSECOND", "second-secret")).await.unwrap();
+
+        for (name, value) in std::env::vars() {
+            for key in ["AKIDFIRST", "first-secret", "AKIDSECOND", "second-secret", "session-token"] {
+                assert!(!value.contains(key), "{key} leaked into {name}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_explicit_credentials_required_without_managed_identity() {
+        let config = AwsConfig {
+            secret_access_key: None,
+            ..config("AKIDFIRST", "first-secret")
+        };
+        assert!(AwsBackend::new(&config).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_role_assumed_with_explicit_credentials() {
+        let config = AwsConfig {
+            role_arn: Some("arn:aws:iam::123456789012:role/s3proxy".to_string()),
+            external_id: Some("external".to_string()),
+            ..config("AKIDFIRST", "first-secret")
+        };
+        // STS is only called once the first request needs credentials
+        AwsBackend::new(&config).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_web_identity_token_file_read_at_startup() {
+        let dir = tempfile::tempdir().unwrap();
+        let token_file = dir.path().join("token");
+        let config = AwsConfig {
+            use_managed_identity: false,
+            access_key_id: None,
+            secret_access_key: None,
+            role_arn: Some("arn:aws:iam::123456789012:role/s3proxy".to_string()),
+            web_identity_token_file: Some(token_file.to_string_lossy().into_owned()),
+            ..config("AKIDFIRST", "first-secret")
+        };
+
+        let err = AwsBackend::new(&config).await.err().unwrap();
+        assert!(err.to_string().contains(&*token_file.to_string_lossy()), "{err}");
+
+        std::fs::write(&token_file, "eyJhbGciOiJSUzI1NiJ9.token").unwrap();
+        AwsBackend::new(&config).await.unwrap();
+
+        let without_role = AwsConfig { role_arn: None, ..config };
+        assert!(AwsBackend::new(&without_role).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_profile_exclusive_with_explicit_credentials() {
+        let config = AwsConfig {
+            profile: Some("staging".to_string()),
+            ..config("AKIDFIRST", "first-secret")
+        };
+        let err = AwsBackend::new(&config).await.err().unwrap();
+        assert!(err.to_string().contains("mutually exclusive"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_request_payer() {
+        let payer = |request_payer| {
+            let config = AwsConfig {
+                request_payer,
+                ..config("AKIDFIRST", "first-secret")
+            };
+            let builder = with_request_payer(AmazonS3Builder::new(), &config);
+            builder.get_config_value(&AmazonS3ConfigKey::RequestPayer).unwrap()
+        };
+        assert_eq!(payer(false), "false");
+        assert_eq!(payer(true), "true");
+
+        let config = AwsConfig {
+            request_payer: true,
+            ..config("AKIDFIRST", "first-secret")
+        };
+        AwsBackend::new(&config).await.unwrap();
+        let anonymous = AwsConfig {
+            anonymous: true,
+            access_key_id: None,
+            secret_access_key: None,
+            session_token: None,
+            ..config
+        };
+        let err = AwsBackend::new(&anonymous).await.err().unwrap();
+        assert!(err.to_string().contains("request_payer"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_denials_point_at_request_payer() {
+        let denied = || object_store::Error::PermissionDenied {
+            path: "key".to_string(),
+            source: "Client error with status 403 Forbidden: AccessDenied".into(),
+        };
+        let backend = AwsBackend::new(&config("AKIDFIRST", "first-secret")).await.unwrap();
+        let err = backend.context("get", "key")(denied());
+        assert!(matches!(
+            err.object_store_error(),
+            object_store::Error::Generic { store: REQUESTER_PAYS_STORE, .. }
+        ));
+        assert_eq!(err.class(), crate::errors::StorageErrorClass::Permission);
+        assert_eq!(err.path(), Some("key"));
+
+        let config = AwsConfig {
+            request_payer: true,
+            ..config("AKIDFIRST", "first-secret")
+        };
+        let backend = AwsBackend::new(&config).await.unwrap();
+        let err = backend.context("get", "key")(denied());
+        assert!(matches!(err.object_store_error(), object_store::Error::PermissionDenied { .. }));
+        assert_eq!(err.class(), crate::errors::StorageErrorClass::Permission);
+    }
+
+    #[tokio::test]
+    async fn test_anonymous_refuses_credentials_and_writes() {
+        let config = AwsConfig {
+            anonymous: true,
+            ..config("AKIDFIRST", "first-secret")
+        };
+        let err = AwsBackend::new(&config).await.err().unwrap();
+        assert!(err.to_string().contains("access_key_id, secret_access_key, session_token"), "{err}");
+
+        let config = AwsConfig {
+            access_key_id: None,
+            secret_access_key: None,
+            session_token: None,
+            ..config
+        };
+        let backend = AwsBackend::new(&config).await.unwrap();
+        let err = backend.put("key", Bytes::from("data")).await.unwrap_err();
+        assert_eq!(err.class(), crate::errors::StorageErrorClass::Permission);
+        let results = backend.delete_many(vec!["a".to_string(), "b".to_string()]).await;
+        assert!(results.iter().all(|(_, result)| result.is_err()));
+    }
+
+    #[test]
+    fn test_addressing_style() {
+        let style = |addressing_style, bucket_name: &str| {
+            let config = AwsConfig {
+                bucket_name: bucket_name.to_string(),
+                endpoint: Some("http://minio.internal:9000".to_string()),
+                addressing_style,
+                ..config("AKIDFIRST", "first-secret")
+            };
+            let builder = with_addressing_style(AmazonS3Builder::new(), &config);
+            builder.get_config_value(&AmazonS3ConfigKey::VirtualHostedStyleRequest).unwrap()
+        };
+
+        assert_eq!(style(None, "bucket"), "false");
+        assert_eq!(style(Some(AddressingStyle::Path), "bucket"), "false");
+        assert_eq!(style(Some(AddressingStyle::VirtualHosted), "bucket"), "true");
+        assert_eq!(style(Some(AddressingStyle::Path), "data.example.com"), "false");
+        assert_eq!(style(Some(AddressingStyle::VirtualHosted), "data.example.com"), "true");
+    }
+}
@@ -3,7 +3,7 @@
 //! Uses object_store::azure::MicrosoftAzure with support for:
 //! - Managed identity (system or user-assigned)
 //! - Workload identity federation in AKS
-//! - Explicit credentials (storage account access key)
+//! - Explicit credentials (storage account access key or SAS token)
 //!
 //! When using managed identity, authentication is handled via
 //! azure_identity::DefaultAzureCredential which automatically discovers:
@@ -14,58 +14,120 @@
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use futures::stream::StreamExt;
-use object_store::azure::{MicrosoftAzure, MicrosoftAzureBuilder};
+use futures::stream::BoxStream;
+use object_store::azure::{AzureConfigKey, MicrosoftAzure, MicrosoftAzureBuilder};
 use object_store::path::Path;
-use object_store::{ObjectMeta, ObjectStore};
+use object_store::{ClientOptions, GetOptions, GetResult, ListResult, ObjectMeta, ObjectStore, PutResult};
+use std::io;
 use std::sync::Arc;
+use tracing::warn;
 
 use crate::config::AzureConfig;
-use crate::storage::StorageBackend;
+use crate::errors::{StorageError, StorageErrorClass};
+use crate::storage::{
+    context, delete_each, list_ordered, put_multipart, retry_config, strip_list_prefix, strip_prefix,
+    ObjectAttributes, StorageBackend, DEFAULT_DELETE_CONCURRENCY, DEFAULT_PART_SIZE,
+};
+
+/// Backend label of errors
+const BACKEND: &str = "azure";
 
 /// Azure Blob Storage backend
 pub struct AzureBackend {
     store: Arc<MicrosoftAzure>,
     prefix: Option<String>,
+    delete_concurrency: usize,
+    part_size: usize,
+    /// Whether requests are authorized by a SAS token
+    sas: bool,
 }
 
 impl AzureBackend {
     /// Create a new Azure Blob Storage backend
     ///
     /// Supports two authentication modes:
-    /// 1. Managed identity (default): Uses DefaultAzureCredential
-    /// 2. Explicit credentials: Uses provided access_key
+    /// 1. Managed identity (default): Uses DefaultAzureCredential, or the
+    ///    user-assigned identity of `client_id` when set
+    /// 2. Explicit credentials: Uses provided access_key or sas_token
     pub async fn new(config: &AzureConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::new_with_client_options(config, ClientOptions::default()).await
+    }
+
+    /// Create a new Azure Blob Storage backend whose HTTP client has
+    /// `client_options`
+    pub async fn new_with_client_options(
+        config: &AzureConfig,
+        client_options: ClientOptions,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let builder = Self::builder(config, client_options)?;
+
+        // Build the store
+        let store = Arc::new(builder.build()?);
+
+        Ok(Self {
+            store,
+            prefix: None, // Prefix is applied at Config level
+            delete_concurrency: DEFAULT_DELETE_CONCURRENCY,
+            part_size: DEFAULT_PART_SIZE,
+            sas: config.sas_token.is_some(),
+        })
+    }
+
+    /// Builder of the store `config` describes
+    fn builder(
+        config: &AzureConfig,
+        client_options: ClientOptions,
+    ) -> Result<MicrosoftAzureBuilder, Box<dyn std::error::Error>> {
         let mut builder = MicrosoftAzureBuilder::new()
+            .with_client_options(client_options)
+            .with_retry(retry_config(&config.retry))
             .with_account(&config.account_name)
             .with_container_name(&config.container_name);
 
         // Configure authentication
-        if !config.use_managed_identity {
-            // Use explicit credentials
-            // object_store's Azure builder supports with_access_key method
-            if let Some(access_key) = &config.access_key {
-                // Try to use with_access_key if available, otherwise set env var
-                // Note: object_store may use different method names
-                builder = builder.with_access_key(access_key);
-            } else {
-                return Err("Azure access_key is required when use_managed_identity is false".into());
+        if config.use_managed_identity {
+            if config.sas_token.is_some() {
+                return Err("Azure sas_token requires use_managed_identity = false".into());
+            }
+            // Pick one of several identities attached to the node
+            if let Some(client_id) = &config.client_id {
+                builder = builder.with_client_id(client_id);
+            }
+            if let Some(tenant_id) = &config.tenant_id {
+                builder = builder.with_tenant_id(tenant_id);
+            }
+            if let Some(authority_host) = &config.authority_host {
+                builder = builder.with_authority_host(authority_host);
+            }
+        } else {
+            match (&config.access_key, &config.sas_token) {
+                (Some(access_key), None) => builder = builder.with_access_key(access_key.expose()),
+                (None, Some(sas_token)) => builder = builder.with_config(AzureConfigKey::SasKey, sas_token.expose()),
+                (Some(_), Some(_)) => return Err("Azure access_key and sas_token are mutually exclusive".into()),
+                (None, None) => {
+                    return Err("Azure access_key or sas_token is required when use_managed_identity is false".into())
+                }
+            }
+        }
+
+        // Configure endpoint (private endpoints, or Azurite on another host)
+        if let Some(endpoint) = &config.endpoint {
+            if config.use_emulator {
+                return Err("Azure endpoint and use_emulator are mutually exclusive".into());
             }
+            builder = builder.with_endpoint(endpoint.clone());
         }
-        // If use_managed_identity is true, builder will use DefaultAzureCredential
 
         // Configure emulator (for local development)
         if config.use_emulator {
             builder = builder.with_use_emulator(true);
         }
 
-        // Build the store
-        let store = Arc::new(builder.build()?);
-
-        Ok(Self {
-            store,
-            prefix: None, // Prefix is applied at Config level
-        })
+        // Allow HTTP (for emulators and plain HTTP endpoints)
+        if config.allow_http {
+            builder = builder.with_allow_http(true);
+        }
+        Ok(builder)
     }
 
     /// Apply prefix to path if configured
@@ -83,44 +145,123 @@ impl AzureBackend {
         self.prefix = prefix;
         self
     }
+
+    /// Set the requests in flight at once in `delete_many`
+    pub fn with_delete_concurrency(mut self, concurrency: usize) -> Self {
+        self.delete_concurrency = concurrency;
+        self
+    }
+
+    /// Set the size of the parts `put_stream` uploads
+    pub fn with_part_size(mut self, part_size: usize) -> Self {
+        self.part_size = part_size;
+        self
+    }
+
+    /// Context of errors of `operation` on `path`, logging a hint when a
+    /// SAS token was refused: SAS tokens expire, unlike account keys
+    fn context<'a>(
+        &self,
+        operation: &'static str,
+        path: &'a str,
+    ) -> impl Fn(object_store::Error) -> StorageError + Copy + 'a {
+        let sas = self.sas;
+        move |error| {
+            let error = context(BACKEND, operation, path)(error);
+            if sas && error.class() == StorageErrorClass::Permission {
+                warn!(operation, path, "Azure denied access to the SAS token, which may have expired");
+            }
+            error
+        }
+    }
 }
 
 #[async_trait]
 impl StorageBackend for AzureBackend {
-    async fn get(&self, path: &str) -> Result<Bytes, object_store::Error> {
-        let path = self.apply_prefix(path);
-        let data = self.store.get(&path).await?;
-        let bytes = data.bytes().await?;
-        Ok(bytes)
+    async fn get_opts(&self, path: &str, options: GetOptions) -> Result<GetResult, StorageError> {
+        let mut result = self
+            .store
+            .get_opts(&self.apply_prefix(path), options)
+            .await
+            .map_err(self.context("get", path))?;
+        result.meta = strip_prefix(self.prefix.as_deref(), result.meta);
+        Ok(result)
     }
 
-    async fn put(&self, path: &str, data: Bytes) -> Result<(), object_store::Error> {
-        let path = self.apply_prefix(path);
-        self.store.put(&path, data.into()).await?;
-        Ok(())
+    async fn put_with_attributes(
+        &self,
+        path: &str,
+        data: Bytes,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        self.store
+            .put_opts(&self.apply_prefix(path), data.into(), attributes.put_options())
+            .await
+            .map_err(self.context("put", path))
     }
 
-    async fn delete(&self, path: &str) -> Result<(), object_store::Error> {
-        let path = self.apply_prefix(path);
-        self.store.delete(&path).await?;
-        Ok(())
+    async fn put_stream(
+        &self,
+        path: &str,
+        stream: BoxStream<'static, Result<Bytes, io::Error>>,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        put_multipart(self.store.as_ref(), &self.apply_prefix(path), stream, attributes, self.part_size)
+            .await
+            .map_err(self.context("put_stream", path))
     }
 
-    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>, object_store::Error> {
-        let prefix = self.apply_prefix(prefix);
-        let mut results = vec![];
-        let mut stream = self.store.list(Some(&prefix));
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        self.store
+            .delete(&self.apply_prefix(path))
+            .await
+            .map_err(self.context("delete", path))
+    }
 
-        while let Some(meta) = stream.next().await {
-            results.push(meta?);
-        }
+    async fn list(
+        &self,
+        prefix: &str,
+        offset: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<ObjectMeta>, StorageError> {
+        let location = self.apply_prefix(prefix);
+        list_ordered(self.store.as_ref(), self.prefix.as_deref(), &location, offset, limit)
+            .await
+            .map_err(self.context("list", prefix))
+    }
+
+    async fn list_with_delimiter(&self, prefix: &str) -> Result<ListResult, StorageError> {
+        let result = self
+            .store
+            .list_with_delimiter(Some(&self.apply_prefix(prefix)))
+            .await
+            .map_err(self.context("list_with_delimiter", prefix))?;
+        Ok(strip_list_prefix(self.prefix.as_deref(), result))
+    }
+
+    async fn head(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        self.store
+            .head(&self.apply_prefix(path))
+            .await
+            .map_err(self.context("head", path))
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.store
+            .copy(&self.apply_prefix(from), &self.apply_prefix(to))
+            .await
+            .map_err(self.context("copy", to))
+    }
 
-        Ok(results)
+    async fn copy_if_not_exists(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.store
+            .copy_if_not_exists(&self.apply_prefix(from), &self.apply_prefix(to))
+            .await
+            .map_err(self.context("copy_if_not_exists", to))
     }
 
-    async fn head(&self, path: &str) -> Result<ObjectMeta, object_store::Error> {
-        let path = self.apply_prefix(path);
-        self.store.head(&path).await
+    async fn delete_many(&self, paths: Vec<String>) -> Vec<(String, Result<(), StorageError>)> {
+        delete_each(paths, self.delete_concurrency, |path| async move { self.delete(&path).await }).await
     }
 
     #[allow(dead_code)] // Part of trait interface for extensibility
@@ -128,3 +269,117 @@ impl StorageBackend for AzureBackend {
         self.store.as_ref()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BackendRetryConfig;
+
+    fn config(use_managed_identity: bool) -> AzureConfig {
+        AzureConfig {
+            account_name: "account".to_string(),
+            container_name: "container".to_string(),
+            use_managed_identity,
+            access_key: None,
+            sas_token: None,
+            client_id: Some("client".to_string()),
+            tenant_id: Some("tenant".to_string()),
+            authority_host: None,
+            use_emulator: false,
+            endpoint: None,
+            allow_http: false,
+            retry: BackendRetryConfig::default(),
+        }
+    }
+
+    #[test]
+    fn test_managed_identity_targets_client_id() {
+        let builder = AzureBackend::builder(&config(true), ClientOptions::default()).unwrap();
+        assert_eq!(builder.get_config_value(&AzureConfigKey::ClientId).as_deref(), Some("client"));
+        assert_eq!(builder.get_config_value(&AzureConfigKey::AuthorityId).as_deref(), Some("tenant"));
+        builder.build().unwrap();
+    }
+
+    #[test]
+    fn test_client_id_ignored_with_access_key() {
+        let config = AzureConfig {
+            access_key: Some("a2V5".into()),
+            ..config(false)
+        };
+        let builder = AzureBackend::builder(&config, ClientOptions::default()).unwrap();
+        assert_eq!(builder.get_config_value(&AzureConfigKey::ClientId), None);
+    }
+
+    #[test]
+    fn test_sas_token_authorizes_requests() {
+        let config = AzureConfig {
+            sas_token: Some("?sv=2022-11-02&sr=c&sig=abc%3D".into()),
+            ..config(false)
+        };
+        let builder = AzureBackend::builder(&config, ClientOptions::default()).unwrap();
+        assert_eq!(
+            builder.get_config_value(&AzureConfigKey::SasKey).as_deref(),
+            Some("?sv=2022-11-02&sr=c&sig=abc%3D")
+        );
+        builder.build().unwrap();
+    }
+
+    #[test]
+    fn test_sas_token_exclusive_with_other_credentials() {
+        let with_key = AzureConfig {
+            access_key: Some("a2V5".into()),
+            sas_token: Some("sv=2022-11-02&sig=abc".into()),
+            ..config(false)
+        };
+        let err = AzureBackend::builder(&with_key, ClientOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("mutually exclusive"), "{err}");
+
+        let with_identity = AzureConfig {
+            sas_token: Some("sv=2022-11-02&sig=abc".into()),
+            ..config(true)
+        };
+        let err = AzureBackend::builder(&with_identity, ClientOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("use_managed_identity"), "{err}");
+
+        assert!(AzureBackend::builder(&config(false), ClientOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_endpoint_exclusive_with_emulator() {
+        let config = AzureConfig {
+            endpoint: Some("http://azurite:10000/devstoreaccount1".to_string()),
+            use_emulator: true,
+            ..config(true)
+        };
+        let err = AzureBackend::builder(&config, ClientOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("mutually exclusive"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_requests_sent_to_custom_endpoint() {
+        // Stand-in for an Azurite container on a non-default host and port
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let app = axum::Router::new().fallback({
+            let seen = seen.clone();
+            move |uri: axum::http::Uri| async move {
+                seen.lock().unwrap().push(uri.path().to_string());
+                axum::http::StatusCode::NOT_FOUND
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let config = AzureConfig {
+            access_key: Some("a2V5".into()),
+            endpoint: Some(format!("http://{address}/devstoreaccount1")),
+            allow_http: true,
+            ..config(false)
+        };
+        let backend = AzureBackend::new(&config).await.unwrap();
+        let err = backend.head("key").await.unwrap_err();
+
+        assert_eq!(err.class(), StorageErrorClass::NotFound);
+        assert_eq!(*seen.lock().unwrap(), ["/devstoreaccount1/container/key"]);
+    }
+}
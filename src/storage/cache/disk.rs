@@ -0,0 +1,311 @@
+//! Disk cache tier
+//!
+//! Hits are served as a file payload, streamed from disk a chunk at a time.
+//! On a miss the backend stream is teed into a temporary file as the reader
+//! consumes it, and renamed into place once the whole object has passed
+//! through; readers only ever see whole files, and a read that fails or is
+//! abandoned midway leaves nothing behind. Each key has a lock held from a
+//! miss until its file is complete, so concurrent readers of a key wait for
+//! the one fetching it and are then served from disk, rather than all
+//! reading it from the backend. A file evicted or invalidated while being
+//! read stays readable through the open handle.
+//!
+//! The index lives in memory, so files left by a previous run, complete or
+//! partial, are removed when the directory is first opened. Each cache
+//! keeps its files in its own subdirectory, removed when the cache is
+//! dropped.
+
+use bytes::Bytes;
+use futures::stream::{self, BoxStream, StreamExt};
+use object_store::{Attributes, GetResult, GetResultPayload, ObjectMeta};
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::OwnedMutexGuard;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::lru::Lru;
+
+/// Tier label of the disk cache metrics
+pub(super) const TIER: &str = "disk";
+
+/// Cached object: its file, and what a read returns besides the body
+#[derive(Clone)]
+struct Entry {
+    path: PathBuf,
+    meta: ObjectMeta,
+    attributes: Attributes,
+}
+
+/// Object cache keeping files in a directory
+pub(super) struct DiskCache {
+    directory: PathBuf,
+    max_bytes: u64,
+    lru: Mutex<Lru<String, Entry>>,
+    /// Locks of the keys being read on a miss
+    locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    ttl: Duration,
+}
+
+impl DiskCache {
+    /// Open a cache in a new subdirectory of `root`
+    pub(super) fn open(root: &Path, max_bytes: u64, ttl: Duration) -> io::Result<Self> {
+        remove_leftovers(root)?;
+        let directory = root.join(Uuid::new_v4().simple().to_string());
+        std::fs::create_dir_all(&directory)?;
+        Ok(Self {
+            directory,
+            max_bytes,
+            lru: Mutex::new(Lru::new(TIER, max_bytes)),
+            locks: Mutex::new(HashMap::new()),
+            ttl,
+        })
+    }
+
+    fn lru(&self) -> MutexGuard<'_, Lru<String, Entry>> {
+        self.lru.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn locks(&self) -> MutexGuard<'_, HashMap<String, Arc<tokio::sync::Mutex<()>>>> {
+        self.locks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Cached object `key`, streamed from its file
+    pub(super) async fn get(&self, key: &str) -> Option<GetResult> {
+        let entry = self.lru().get(&key.to_string())?;
+        let file = match tokio::fs::File::open(&entry.path).await {
+            Ok(file) => file.into_std().await,
+            Err(e) => {
+                debug!(key, error = %e, "Cached file no longer readable");
+                return None;
+            }
+        };
+        Some(GetResult {
+            range: 0..entry.meta.size,
+            payload: GetResultPayload::File(file, entry.path),
+            meta: entry.meta,
+            attributes: entry.attributes,
+        })
+    }
+
+    /// Wait for the lock of `key`, to be held while it is fetched and filled
+    pub(super) async fn lock(self: &Arc<Self>, key: &str) -> KeyLock {
+        let lock = self.locks().entry(key.to_string()).or_default().clone();
+        KeyLock {
+            cache: self.clone(),
+            key: key.to_string(),
+            _guard: lock.lock_owned().await,
+        }
+    }
+
+    /// Tee the body of `result`, read from the backend, into the cache as
+    /// the caller consumes it, holding `lock` until done
+    ///
+    /// The file is dropped rather than cached if `generation` has moved on
+    /// from `at` by the time it is complete: the data may then predate a
+    /// write through the proxy.
+    pub(super) async fn fill(
+        self: &Arc<Self>,
+        lock: KeyLock,
+        result: GetResult,
+        generation: Arc<AtomicU64>,
+        at: u64,
+    ) -> GetResult {
+        if result.meta.size as u64 > self.max_bytes {
+            return result;
+        }
+        let path = self.directory.join(Uuid::new_v4().simple().to_string());
+        let temp = path.with_extension("tmp");
+        let file = match tokio::fs::File::create(&temp).await {
+            Ok(file) => file,
+            Err(e) => {
+                warn!(key = %lock.key, error = %e, "Failed to create cache file");
+                return result;
+            }
+        };
+        let fill = Fill {
+            cache: self.clone(),
+            lock,
+            file,
+            temp: Some(temp),
+            path,
+            written: 0,
+            meta: result.meta.clone(),
+            attributes: result.attributes.clone(),
+            generation,
+            at,
+        };
+        let (meta, range, attributes) = (result.meta.clone(), result.range.clone(), result.attributes.clone());
+        GetResult {
+            payload: GetResultPayload::Stream(fill.tee(result.into_stream())),
+            meta,
+            range,
+            attributes,
+        }
+    }
+
+    pub(super) fn invalidate(&self, key: &str) {
+        let removed = self.lru().remove(&key.to_string());
+        remove_files(removed.map(|entry| entry.path));
+    }
+
+    #[cfg(test)]
+    pub(super) fn bytes(&self) -> u64 {
+        self.lru().bytes()
+    }
+}
+
+impl Drop for DiskCache {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.directory) {
+            warn!(directory = %self.directory.display(), error = %e, "Failed to remove cache directory");
+        }
+    }
+}
+
+/// Lock of a key, taken by [`DiskCache::lock`]
+pub(super) struct KeyLock {
+    cache: Arc<DiskCache>,
+    key: String,
+    _guard: OwnedMutexGuard<()>,
+}
+
+impl Drop for KeyLock {
+    fn drop(&mut self) {
+        // Forget the lock unless others wait for it: only the map and this
+        // guard hold it then
+        let mut locks = self.cache.locks();
+        if locks.get(&self.key).is_some_and(|lock| Arc::strong_count(lock) == 2) {
+            locks.remove(&self.key);
+        }
+    }
+}
+
+/// File being written from a backend stream
+struct Fill {
+    cache: Arc<DiskCache>,
+    lock: KeyLock,
+    file: tokio::fs::File,
+    /// Temporary file, until renamed to `path`
+    temp: Option<PathBuf>,
+    path: PathBuf,
+    written: u64,
+    meta: ObjectMeta,
+    attributes: Attributes,
+    generation: Arc<AtomicU64>,
+    at: u64,
+}
+
+impl Fill {
+    /// Pass `stream` through, writing it to the file; the fill is given up
+    /// on the first failure, without failing the read
+    fn tee(
+        self,
+        stream: BoxStream<'static, object_store::Result<Bytes>>,
+    ) -> BoxStream<'static, object_store::Result<Bytes>> {
+        stream::unfold((stream, Some(self)), |(mut stream, mut fill)| async move {
+            match stream.next().await {
+                Some(Ok(chunk)) => {
+                    if let Some(writing) = &mut fill {
+                        if let Err(e) = writing.file.write_all(&chunk).await {
+                            warn!(key = %writing.lock.key, error = %e, "Failed to write cache file");
+                            fill = None;
+                        } else {
+                            writing.written += chunk.len() as u64;
+                        }
+                    }
+                    Some((Ok(chunk), (stream, fill)))
+                }
+                Some(Err(e)) => Some((Err(e), (stream, None))),
+                None => {
+                    if let Some(fill) = fill {
+                        fill.finish().await;
+                    }
+                    None
+                }
+            }
+        })
+        .boxed()
+    }
+
+    /// Move the complete file into place and index it
+    async fn finish(mut self) {
+        if self.written != self.meta.size as u64 {
+            warn!(key = %self.lock.key, written = self.written, size = self.meta.size, "Cache file incomplete");
+            return;
+        }
+        let Some(temp) = self.temp.take() else {
+            return;
+        };
+        let renamed = async {
+            self.file.flush().await?;
+            tokio::fs::rename(&temp, &self.path).await
+        }
+        .await;
+        if let Err(e) = renamed {
+            warn!(key = %self.lock.key, error = %e, "Failed to write cache file");
+            self.temp = Some(temp);
+            return;
+        }
+        let entry = Entry {
+            path: self.path.clone(),
+            meta: self.meta.clone(),
+            attributes: self.attributes.clone(),
+        };
+        let removed = {
+            let mut lru = self.cache.lru();
+            if self.generation.load(Ordering::SeqCst) != self.at {
+                vec![entry]
+            } else {
+                let expires = Instant::now() + self.cache.ttl;
+                lru.insert(self.lock.key.clone(), entry, self.written, expires)
+            }
+        };
+        remove_files(removed.into_iter().map(|entry| entry.path));
+    }
+}
+
+impl Drop for Fill {
+    fn drop(&mut self) {
+        // Not renamed into place: the read failed or was abandoned
+        if let Some(temp) = self.temp.take() {
+            remove_files([temp]);
+        }
+    }
+}
+
+fn remove_files(paths: impl IntoIterator<Item = PathBuf>) {
+    for path in paths {
+        if let Err(e) = std::fs::remove_file(&path) {
+            debug!(path = %path.display(), error = %e, "Failed to remove cache file");
+        }
+    }
+}
+
+/// Remove cache subdirectories of `root` left by earlier runs, the first
+/// time this process opens it; later caches share it with earlier ones
+fn remove_leftovers(root: &Path) -> io::Result<()> {
+    static OPENED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+    std::fs::create_dir_all(root)?;
+    let root = root.canonicalize()?;
+    if !OPENED.lock().unwrap_or_else(|e| e.into_inner()).insert(root.clone()) {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(&root)? {
+        let entry = entry?;
+        // Only names this cache creates, in case the directory is shared
+        let ours = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| Uuid::try_parse(name).is_ok());
+        if ours && entry.file_type()?.is_dir() {
+            std::fs::remove_dir_all(entry.path())?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,129 @@
+//! Size-bounded least recently used map shared by the cache tiers
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::time::Instant;
+
+use prometheus::IntGauge;
+
+use crate::metrics::{CACHE_BYTES, CACHE_EVICTIONS};
+
+struct Entry<V> {
+    value: V,
+    size: u64,
+    expires: Instant,
+    /// Position in the use order
+    used: u64,
+}
+
+/// Map evicting its least recently used entries beyond a total size
+pub(super) struct Lru<K, V> {
+    /// Cache tier label of the eviction metric
+    tier: &'static str,
+    /// Cache size metric of the tier, when sizes are in bytes
+    gauge: Option<IntGauge>,
+    max_bytes: u64,
+    entries: HashMap<K, Entry<V>>,
+    /// Keys by last use, oldest first
+    by_use: BTreeMap<u64, K>,
+    bytes: u64,
+    clock: u64,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> Lru<K, V> {
+    /// Map bounded by the total size in bytes of its entries
+    pub(super) fn new(tier: &'static str, max_bytes: u64) -> Self {
+        Self::with_gauge(tier, Some(CACHE_BYTES.with_label_values(&[tier])), max_bytes)
+    }
+
+    /// Map bounded by its number of entries, each to be inserted with a
+    /// size of 1
+    pub(super) fn counted(tier: &'static str, max_entries: u64) -> Self {
+        Self::with_gauge(tier, None, max_entries)
+    }
+
+    fn with_gauge(tier: &'static str, gauge: Option<IntGauge>, max_bytes: u64) -> Self {
+        Self {
+            tier,
+            gauge,
+            max_bytes,
+            entries: HashMap::new(),
+            by_use: BTreeMap::new(),
+            bytes: 0,
+            clock: 0,
+        }
+    }
+
+    /// Total size of the entries
+    #[cfg(test)]
+    pub(super) fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Value of an unexpired entry, which becomes the most recently used
+    pub(super) fn get(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.get_mut(key)?;
+        if entry.expires <= Instant::now() {
+            self.remove(key);
+            return None;
+        }
+        self.clock += 1;
+        self.by_use.remove(&entry.used);
+        entry.used = self.clock;
+        self.by_use.insert(self.clock, key.clone());
+        Some(entry.value.clone())
+    }
+
+    /// Insert an entry, evicting others to make room, and return the
+    /// values no longer in the map: the replaced and evicted ones, or
+    /// `value` itself when it can never fit
+    pub(super) fn insert(&mut self, key: K, value: V, size: u64, expires: Instant) -> Vec<V> {
+        let mut removed: Vec<V> = self.remove(&key).into_iter().collect();
+        if size > self.max_bytes {
+            removed.push(value);
+            return removed;
+        }
+        while self.bytes + size > self.max_bytes {
+            let Some((_, oldest)) = self.by_use.first_key_value() else {
+                break;
+            };
+            let oldest = oldest.clone();
+            removed.extend(self.remove(&oldest));
+            CACHE_EVICTIONS.with_label_values(&[self.tier]).inc();
+        }
+        self.clock += 1;
+        self.by_use.insert(self.clock, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                size,
+                expires,
+                used: self.clock,
+            },
+        );
+        self.bytes += size;
+        if let Some(gauge) = &self.gauge {
+            gauge.add(size as i64);
+        }
+        removed
+    }
+
+    pub(super) fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.by_use.remove(&entry.used);
+        self.bytes -= entry.size;
+        if let Some(gauge) = &self.gauge {
+            gauge.sub(entry.size as i64);
+        }
+        Some(entry.value)
+    }
+}
+
+impl<K, V> Drop for Lru<K, V> {
+    fn drop(&mut self) {
+        if let Some(gauge) = &self.gauge {
+            gauge.sub(self.bytes as i64);
+        }
+    }
+}
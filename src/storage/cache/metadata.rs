@@ -0,0 +1,261 @@
+//! Metadata cache
+//!
+//! Answers HEADs from recently seen object metadata, for clients such as
+//! Hadoop's s3a that check the same key's existence many times in quick
+//! succession. Puts and deletes through the proxy invalidate the key; with
+//! `seed_from_list`, listings also fill the cache with the metadata of
+//! every object they return. Entries expire after `ttl_ms`, which bounds
+//! how stale metadata of objects changed directly in the bucket can be.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use object_store::{GetOptions, GetResult, ListResult, ObjectMeta, ObjectStore, PutResult};
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::lru::Lru;
+use super::{record, report};
+use crate::config::MetadataCacheConfig;
+use crate::errors::StorageError;
+use crate::storage::{ObjectAttributes, StorageBackend};
+
+/// Tier label of the metadata cache metrics
+const TIER: &str = "metadata";
+
+struct Entries {
+    lru: Lru<String, ObjectMeta>,
+    /// Bumped by every invalidation
+    generation: u64,
+}
+
+/// Storage backend caching object metadata of an inner backend
+pub struct MetadataCacheBackend {
+    inner: Arc<dyn StorageBackend>,
+    entries: Mutex<Entries>,
+    ttl: Duration,
+    seed_from_list: bool,
+}
+
+impl MetadataCacheBackend {
+    pub fn new(inner: Arc<dyn StorageBackend>, config: &MetadataCacheConfig) -> Self {
+        Self {
+            inner,
+            entries: Mutex::new(Entries {
+                lru: Lru::counted(TIER, config.max_entries),
+                generation: 0,
+            }),
+            ttl: Duration::from_millis(config.ttl_ms),
+            seed_from_list: config.seed_from_list,
+        }
+    }
+
+    fn entries(&self) -> MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn generation(&self) -> u64 {
+        self.entries().generation
+    }
+
+    /// Cache metadata read from the backend, unless a key was invalidated
+    /// since `generation` was taken: it may predate a write through the
+    /// proxy
+    fn insert(&self, generation: u64, metas: impl IntoIterator<Item = (String, ObjectMeta)>) {
+        let mut entries = self.entries();
+        if entries.generation != generation {
+            return;
+        }
+        let expires = Instant::now() + self.ttl;
+        for (path, meta) in metas {
+            entries.lru.insert(path, meta, 1, expires);
+        }
+    }
+
+    fn invalidate(&self, path: &str) {
+        let mut entries = self.entries();
+        entries.generation += 1;
+        entries.lru.remove(&path.to_string());
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MetadataCacheBackend {
+    async fn get_opts(&self, path: &str, options: GetOptions) -> Result<GetResult, StorageError> {
+        self.inner.get_opts(path, options).await
+    }
+
+    async fn put_with_attributes(
+        &self,
+        path: &str,
+        data: Bytes,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        let result = self.inner.put_with_attributes(path, data, attributes).await;
+        // Even a failed write may have replaced the object
+        self.invalidate(path);
+        result
+    }
+
+    async fn put_stream(
+        &self,
+        path: &str,
+        stream: BoxStream<'static, Result<Bytes, io::Error>>,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        let result = self.inner.put_stream(path, stream, attributes).await;
+        self.invalidate(path);
+        result
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        let result = self.inner.delete(path).await;
+        self.invalidate(path);
+        result
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        offset: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<ObjectMeta>, StorageError> {
+        if !self.seed_from_list {
+            return self.inner.list(prefix, offset, limit).await;
+        }
+        let generation = self.generation();
+        let metas = self.inner.list(prefix, offset, limit).await?;
+        // Listed locations are the keys `head` is called with
+        self.insert(generation, metas.iter().map(|meta| (meta.location.to_string(), meta.clone())));
+        Ok(metas)
+    }
+
+    async fn list_with_delimiter(&self, prefix: &str) -> Result<ListResult, StorageError> {
+        if !self.seed_from_list {
+            return self.inner.list_with_delimiter(prefix).await;
+        }
+        let generation = self.generation();
+        let result = self.inner.list_with_delimiter(prefix).await?;
+        self.insert(generation, result.objects.iter().map(|meta| (meta.location.to_string(), meta.clone())));
+        Ok(result)
+    }
+
+    async fn head(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        let cached = self.entries().lru.get(&path.to_string());
+        record(TIER, "head", cached.is_some());
+        report(cached.is_some());
+        if let Some(meta) = cached {
+            return Ok(meta);
+        }
+        let generation = self.generation();
+        let meta = self.inner.head(path).await?;
+        self.insert(generation, [(path.to_string(), meta.clone())]);
+        Ok(meta)
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let result = self.inner.copy(from, to).await;
+        // Even a failed copy may have replaced the object
+        self.invalidate(to);
+        result
+    }
+
+    async fn copy_if_not_exists(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let result = self.inner.copy_if_not_exists(from, to).await;
+        self.invalidate(to);
+        result
+    }
+
+    async fn delete_many(&self, paths: Vec<String>) -> Vec<(String, Result<(), StorageError>)> {
+        let results = self.inner.delete_many(paths).await;
+        for (path, _) in &results {
+            self.invalidate(path);
+        }
+        results
+    }
+
+    fn object_store(&self) -> &dyn ObjectStore {
+        self.inner.object_store()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::StorageErrorClass;
+    use crate::metrics::CACHE_REQUESTS;
+    use crate::storage::{MockBackend, MockOperation};
+
+    fn backend(ttl_ms: u64, max_entries: u64, seed_from_list: bool) -> (Arc<MockBackend>, MetadataCacheBackend) {
+        let mock = Arc::new(MockBackend::new());
+        let config = MetadataCacheConfig {
+            enabled: true,
+            ttl_ms,
+            max_entries,
+            seed_from_list,
+        };
+        (mock.clone(), MetadataCacheBackend::new(mock, &config))
+    }
+
+    #[tokio::test]
+    async fn test_heads_served_from_cache() {
+        let (mock, backend) = backend(60_000, 100, false);
+        mock.put("a", Bytes::from("data")).await.unwrap();
+        let hits = || CACHE_REQUESTS.with_label_values(&[TIER, "head", "hit"]).get();
+        let before = hits();
+
+        assert_eq!(backend.head("a").await.unwrap().size, 4);
+        assert_eq!(backend.head("a").await.unwrap().size, 4);
+        assert_eq!(mock.calls_of(MockOperation::Head).len(), 1);
+        assert!(hits() > before);
+    }
+
+    #[tokio::test]
+    async fn test_put_then_head_sees_new_object() {
+        let (_, backend) = backend(60_000, 100, false);
+        backend.put("a", Bytes::from("data")).await.unwrap();
+        assert_eq!(backend.head("a").await.unwrap().size, 4);
+
+        backend.put("a", Bytes::from("longer data")).await.unwrap();
+        assert_eq!(backend.head("a").await.unwrap().size, 11);
+
+        backend.delete("a").await.unwrap();
+        assert!(matches!(backend.head("a").await, Err(e) if e.class() == StorageErrorClass::NotFound));
+    }
+
+    #[tokio::test]
+    async fn test_listing_seeds_cache() {
+        let (mock, backend) = backend(60_000, 100, true);
+        mock.put("dir/a", Bytes::from("aa")).await.unwrap();
+        mock.put("dir/b", Bytes::from("bbb")).await.unwrap();
+
+        backend.list("dir/", None, None).await.unwrap();
+        assert_eq!(backend.head("dir/a").await.unwrap().size, 2);
+        assert_eq!(backend.head("dir/b").await.unwrap().size, 3);
+        assert!(mock.calls_of(MockOperation::Head).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_entries_expire() {
+        let (mock, backend) = backend(0, 100, false);
+        mock.put("a", Bytes::from("data")).await.unwrap();
+        backend.head("a").await.unwrap();
+        backend.head("a").await.unwrap();
+        assert_eq!(mock.calls_of(MockOperation::Head).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_entries_bounded() {
+        let (mock, backend) = backend(60_000, 1, true);
+        mock.put("a", Bytes::from("a")).await.unwrap();
+        mock.put("b", Bytes::from("b")).await.unwrap();
+        backend.list("", None, None).await.unwrap();
+        // Only the last listed key is kept
+        backend.head("b").await.unwrap();
+        backend.head("a").await.unwrap();
+        let heads = mock.calls_of(MockOperation::Head);
+        assert_eq!(heads.len(), 1);
+        assert_eq!(heads[0].path, "a");
+    }
+}
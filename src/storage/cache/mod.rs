@@ -0,0 +1,642 @@
+//! Read-through object cache
+//!
+//! Keeps frequently read objects in memory or on local disk, so repeated
+//! GETs and HEADs are answered without calling the backend. Objects up to
+//! `max_object_bytes` and their metadata are kept in memory; larger objects
+//! go to the disk tier when a directory is configured, and are otherwise
+//! never cached. Each tier is bounded by the total size of its objects and
+//! evicts the least recently used ones beyond it. Only whole-object reads
+//! are cached: small objects are read into memory on a miss, while large
+//! ones are streamed from the disk tier, and teed into it on a miss, so
+//! they are never held in memory whole. Puts and deletes
+//! through the proxy invalidate the key, and entries expire after `ttl_ms`
+//! to pick up changes made to the bucket by other clients.
+//!
+//! [`MetadataCacheBackend`] separately caches object metadata only, and
+//! [`NegativeCacheBackend`] keys found missing.
+//!
+//! Responses to cached operations carry an `x-s3proxy-cache` header of
+//! `HIT` or `MISS`, added by the [`annotate`] middleware.
+
+mod disk;
+mod lru;
+mod metadata;
+mod negative;
+
+use async_trait::async_trait;
+use axum::extract::Request;
+use axum::http::{HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use bytes::Bytes;
+use futures::stream::{self, BoxStream, StreamExt};
+use object_store::{
+    Attributes, GetOptions, GetResult, GetResultPayload, ListResult, ObjectMeta, ObjectStore, PutResult,
+};
+use std::cell::Cell;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::config::CacheConfig;
+use crate::errors::StorageError;
+use crate::metrics::CACHE_REQUESTS;
+use crate::storage::{ObjectAttributes, StorageBackend};
+use disk::{DiskCache, KeyLock};
+use lru::Lru;
+
+pub use metadata::MetadataCacheBackend;
+pub use negative::NegativeCacheBackend;
+
+/// Response header reporting whether the cache answered the request
+pub static CACHE_HEADER: HeaderName = HeaderName::from_static("x-s3proxy-cache");
+
+/// Tier label of the memory cache metrics
+const MEMORY_TIER: &str = "memory";
+
+/// Accounted size of cached metadata, on top of its key
+const META_SIZE: u64 = std::mem::size_of::<ObjectMeta>() as u64;
+
+tokio::task_local! {
+    static STATUS: Cell<Option<&'static str>>;
+}
+
+/// What a memory cache entry holds for a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    Object,
+    Meta,
+}
+
+#[derive(Clone)]
+enum Value {
+    Object(CachedObject),
+    Meta(ObjectMeta),
+}
+
+impl Value {
+    fn kind(&self) -> Kind {
+        match self {
+            Value::Object(_) => Kind::Object,
+            Value::Meta(_) => Kind::Meta,
+        }
+    }
+}
+
+/// Object cached in memory, and what a read returns besides the body
+#[derive(Clone)]
+struct CachedObject {
+    data: Bytes,
+    meta: ObjectMeta,
+    attributes: Attributes,
+}
+
+impl CachedObject {
+    /// Read `result` whole
+    async fn read(result: GetResult) -> Result<Self, StorageError> {
+        let (meta, attributes) = (result.meta.clone(), result.attributes.clone());
+        let data = result.bytes().await?;
+        Ok(Self { data, meta, attributes })
+    }
+
+    /// Answer a read with the cached object
+    fn into_result(self) -> GetResult {
+        let data = self.data;
+        GetResult {
+            range: 0..data.len(),
+            payload: GetResultPayload::Stream(stream::once(async move { Ok(data) }).boxed()),
+            meta: self.meta,
+            attributes: self.attributes,
+        }
+    }
+}
+
+/// Storage backend caching objects read from an inner backend
+pub struct CachingBackend {
+    inner: Arc<dyn StorageBackend>,
+    memory: Mutex<Lru<(String, Kind), Value>>,
+    disk: Option<Arc<DiskCache>>,
+    max_object_bytes: u64,
+    ttl: Duration,
+    /// Bumped by every invalidation
+    generation: Arc<AtomicU64>,
+}
+
+impl CachingBackend {
+    /// Cache reads of `inner`; fails when the disk cache directory cannot
+    /// be prepared
+    pub fn new(inner: Arc<dyn StorageBackend>, config: &CacheConfig) -> std::io::Result<Self> {
+        let ttl = Duration::from_millis(config.ttl_ms);
+        let disk = match &config.disk_directory {
+            Some(directory) => Some(Arc::new(DiskCache::open(Path::new(directory), config.disk_max_bytes, ttl)?)),
+            None => None,
+        };
+        Ok(Self {
+            inner,
+            memory: Mutex::new(Lru::new(MEMORY_TIER, config.max_bytes)),
+            disk,
+            max_object_bytes: config.max_object_bytes,
+            ttl,
+            generation: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    fn memory(&self) -> MutexGuard<'_, Lru<(String, Kind), Value>> {
+        self.memory.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lookup(&self, path: &str, kind: Kind) -> Option<Value> {
+        self.memory().get(&(path.to_string(), kind))
+    }
+
+    /// Cache a value fetched from the backend in memory, unless the key
+    /// was invalidated since `generation` was taken: the value may predate
+    /// a write through the proxy
+    fn insert(&self, generation: u64, path: &str, value: Value, size: u64) {
+        let mut memory = self.memory();
+        if self.generation.load(Ordering::SeqCst) != generation {
+            return;
+        }
+        let expires = Instant::now() + self.ttl;
+        memory.insert((path.to_string(), value.kind()), value, path.len() as u64 + size, expires);
+    }
+
+    /// Cached object at `path` in memory
+    fn lookup_object(&self, path: &str) -> Option<CachedObject> {
+        match self.lookup(path, Kind::Object) {
+            Some(Value::Object(object)) => Some(object),
+            _ => None,
+        }
+    }
+
+    /// Read a whole object from the backend, caching it in memory if small
+    /// enough, or else on disk while holding `disk`'s lock of the key
+    async fn fetch(
+        &self,
+        path: &str,
+        options: GetOptions,
+        disk: Option<(&Arc<DiskCache>, KeyLock)>,
+    ) -> Result<GetResult, StorageError> {
+        let generation = self.generation();
+        let result = self.inner.get_opts(path, options).await?;
+        if result.meta.size as u64 <= self.max_object_bytes {
+            let object = CachedObject::read(result).await?;
+            let size = object.data.len() as u64;
+            self.insert(generation, path, Value::Object(object.clone()), size);
+            return Ok(object.into_result());
+        }
+        match disk {
+            Some((disk, lock)) => Ok(disk.fill(lock, result, self.generation.clone(), generation).await),
+            None => Ok(result),
+        }
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    fn invalidate(&self, path: &str) {
+        // Before removing, so a fill racing with the write is dropped
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        let mut memory = self.memory();
+        memory.remove(&(path.to_string(), Kind::Object));
+        memory.remove(&(path.to_string(), Kind::Meta));
+        drop(memory);
+        if let Some(disk) = &self.disk {
+            disk.invalidate(path);
+        }
+    }
+}
+
+/// Whether a read is of a whole object, as cached
+fn is_whole_object(options: &GetOptions) -> bool {
+    options.if_match.is_none()
+        && options.if_none_match.is_none()
+        && options.if_modified_since.is_none()
+        && options.if_unmodified_since.is_none()
+        && options.range.is_none()
+        && options.version.is_none()
+        && !options.head
+}
+
+/// Record a lookup in `tier`
+fn record(tier: &str, operation: &str, hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    CACHE_REQUESTS.with_label_values(&[tier, operation, result]).inc();
+}
+
+/// Report the request as answered by the cache or not
+fn report(hit: bool) {
+    let _ = STATUS.try_with(|status| status.set(Some(if hit { "HIT" } else { "MISS" })));
+}
+
+#[async_trait]
+impl StorageBackend for CachingBackend {
+    async fn get_opts(&self, path: &str, options: GetOptions) -> Result<GetResult, StorageError> {
+        // Ranged, conditional and versioned reads are not cached
+        if !is_whole_object(&options) {
+            return self.inner.get_opts(path, options).await;
+        }
+        let cached = self.lookup_object(path);
+        record(MEMORY_TIER, "get", cached.is_some());
+        if let Some(object) = cached {
+            report(true);
+            return Ok(object.into_result());
+        }
+        let Some(disk) = &self.disk else {
+            report(false);
+            return self.fetch(path, options, None).await;
+        };
+
+        if let Some(result) = disk.get(path).await {
+            record(disk::TIER, "get", true);
+            report(true);
+            return Ok(result);
+        }
+        // Readers of a key missing at the same time wait for the first to
+        // fill it, then read what it cached
+        let lock = disk.lock(path).await;
+        if let Some(object) = self.lookup_object(path) {
+            report(true);
+            return Ok(object.into_result());
+        }
+        let cached = disk.get(path).await;
+        record(disk::TIER, "get", cached.is_some());
+        report(cached.is_some());
+        if let Some(result) = cached {
+            return Ok(result);
+        }
+        self.fetch(path, options, Some((disk, lock))).await
+    }
+
+    async fn put_with_attributes(
+        &self,
+        path: &str,
+        data: Bytes,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        let result = self.inner.put_with_attributes(path, data, attributes).await;
+        // Even a failed write may have replaced the object
+        self.invalidate(path);
+        result
+    }
+
+    async fn put_stream(
+        &self,
+        path: &str,
+        stream: BoxStream<'static, Result<Bytes, io::Error>>,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        let result = self.inner.put_stream(path, stream, attributes).await;
+        self.invalidate(path);
+        result
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        let result = self.inner.delete(path).await;
+        self.invalidate(path);
+        result
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        offset: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<ObjectMeta>, StorageError> {
+        self.inner.list(prefix, offset, limit).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: &str) -> Result<ListResult, StorageError> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn head(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        let cached = self.lookup(path, Kind::Meta);
+        record(MEMORY_TIER, "head", cached.is_some());
+        report(cached.is_some());
+        if let Some(Value::Meta(meta)) = cached {
+            return Ok(meta);
+        }
+        let generation = self.generation();
+        let meta = self.inner.head(path).await?;
+        if meta.size as u64 <= self.max_object_bytes {
+            let size = META_SIZE + meta.location.as_ref().len() as u64;
+            self.insert(generation, path, Value::Meta(meta.clone()), size);
+        }
+        Ok(meta)
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let result = self.inner.copy(from, to).await;
+        // Even a failed copy may have replaced the object
+        self.invalidate(to);
+        result
+    }
+
+    async fn copy_if_not_exists(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let result = self.inner.copy_if_not_exists(from, to).await;
+        self.invalidate(to);
+        result
+    }
+
+    async fn delete_many(&self, paths: Vec<String>) -> Vec<(String, Result<(), StorageError>)> {
+        let results = self.inner.delete_many(paths).await;
+        for (path, _) in &results {
+            self.invalidate(path);
+        }
+        results
+    }
+
+    fn object_store(&self) -> &dyn ObjectStore {
+        self.inner.object_store()
+    }
+}
+
+/// Middleware adding the `x-s3proxy-cache` header to responses of requests
+/// that looked an object up in the cache
+pub async fn annotate(request: Request, next: Next) -> Response {
+    let (mut response, status) = STATUS
+        .scope(Cell::new(None), async {
+            let response = next.run(request).await;
+            (response, STATUS.with(Cell::get))
+        })
+        .await;
+    if let Some(status) = status {
+        response
+            .headers_mut()
+            .insert(CACHE_HEADER.clone(), HeaderValue::from_static(status));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::CACHE_EVICTIONS;
+    use crate::storage::{MockBackend, MockOperation};
+    use object_store::GetResultPayload;
+    use axum::body::Body;
+    use axum::extract::State;
+    use axum::middleware::from_fn;
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    fn config(max_bytes: u64, max_object_bytes: u64, ttl_ms: u64) -> CacheConfig {
+        CacheConfig {
+            enabled: true,
+            max_bytes,
+            max_object_bytes,
+            ttl_ms,
+            ..CacheConfig::default()
+        }
+    }
+
+    async fn backend(config: &CacheConfig) -> (Arc<MockBackend>, CachingBackend) {
+        let mock = Arc::new(MockBackend::new());
+        mock.put("a", Bytes::from("aaaa")).await.unwrap();
+        mock.put("b", Bytes::from("bbbb")).await.unwrap();
+        (mock.clone(), CachingBackend::new(mock, config).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_reads_served_from_cache() {
+        let (mock, backend) = backend(&config(1024, 1024, 60_000)).await;
+        let hits = || CACHE_REQUESTS.with_label_values(&[MEMORY_TIER, "get", "hit"]).get();
+        let before = hits();
+
+        assert_eq!(backend.get("a").await.unwrap(), Bytes::from("aaaa"));
+        assert_eq!(backend.get("a").await.unwrap(), Bytes::from("aaaa"));
+        assert_eq!(backend.head("a").await.unwrap().size, 4);
+        assert_eq!(backend.head("a").await.unwrap().size, 4);
+
+        assert_eq!(mock.calls_of(MockOperation::Get).len(), 1);
+        assert_eq!(mock.calls_of(MockOperation::Head).len(), 1);
+        assert!(hits() > before);
+    }
+
+    #[tokio::test]
+    async fn test_writes_invalidate() {
+        let (mock, backend) = backend(&config(1024, 1024, 60_000)).await;
+        backend.get("a").await.unwrap();
+        backend.put("a", Bytes::from("new")).await.unwrap();
+        assert_eq!(backend.get("a").await.unwrap(), Bytes::from("new"));
+
+        backend.delete("a").await.unwrap();
+        assert!(backend.get("a").await.is_err());
+        assert_eq!(mock.calls_of(MockOperation::Get).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_large_objects_bypass() {
+        let (mock, backend) = backend(&config(1024, 3, 60_000)).await;
+        backend.get("a").await.unwrap();
+        backend.get("a").await.unwrap();
+        backend.head("a").await.unwrap();
+        backend.head("a").await.unwrap();
+        assert_eq!(mock.calls().len(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_entries_expire() {
+        let (mock, backend) = backend(&config(1024, 1024, 0)).await;
+        backend.get("a").await.unwrap();
+        backend.get("a").await.unwrap();
+        assert_eq!(mock.calls_of(MockOperation::Get).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_used_evicted() {
+        // Room for two of the objects, each 4 bytes plus a 1 byte key
+        let (mock, backend) = backend(&config(10, 1024, 60_000)).await;
+        mock.put("c", Bytes::from("cccc")).await.unwrap();
+        let evictions = || CACHE_EVICTIONS.with_label_values(&[MEMORY_TIER]).get();
+        let before = evictions();
+
+        backend.get("a").await.unwrap();
+        backend.get("b").await.unwrap();
+        backend.get("a").await.unwrap();
+        backend.get("c").await.unwrap();
+        assert_eq!(backend.memory().bytes(), 10);
+        assert!(evictions() > before);
+
+        let gets = mock.calls_of(MockOperation::Get).len();
+        backend.get("a").await.unwrap();
+        assert_eq!(mock.calls_of(MockOperation::Get).len(), gets);
+        backend.get("b").await.unwrap();
+        assert_eq!(mock.calls_of(MockOperation::Get).len(), gets + 1);
+    }
+
+    fn disk_config(root: &tempfile::TempDir) -> CacheConfig {
+        CacheConfig {
+            disk_directory: Some(root.path().to_str().unwrap().to_string()),
+            ..config(1024, 3, 60_000)
+        }
+    }
+
+    /// Files in the disk cache directory, complete or not
+    fn cache_files(root: &tempfile::TempDir) -> usize {
+        std::fs::read_dir(root.path())
+            .unwrap()
+            .map(|directory| std::fs::read_dir(directory.unwrap().path()).unwrap().count())
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn test_large_objects_cached_on_disk() {
+        let root = tempfile::tempdir().unwrap();
+        let (mock, backend) = backend(&disk_config(&root)).await;
+        let hits = || CACHE_REQUESTS.with_label_values(&[disk::TIER, "get", "hit"]).get();
+        let before = hits();
+
+        backend.get("a").await.unwrap();
+        assert_eq!(backend.disk.as_ref().unwrap().bytes(), 4);
+        assert_eq!(backend.get("a").await.unwrap(), Bytes::from("aaaa"));
+        assert_eq!(mock.calls_of(MockOperation::Get).len(), 1);
+        assert!(hits() > before);
+
+        backend.put("a", Bytes::from("changed")).await.unwrap();
+        assert_eq!(backend.disk.as_ref().unwrap().bytes(), 0);
+        assert_eq!(cache_files(&root), 0);
+        assert_eq!(backend.get("a").await.unwrap(), Bytes::from("changed"));
+        assert_eq!(mock.calls_of(MockOperation::Get).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_disk_hits_streamed_from_file() {
+        let root = tempfile::tempdir().unwrap();
+        let (mock, backend) = backend(&disk_config(&root)).await;
+
+        let miss = backend.get_opts("a", GetOptions::default()).await.unwrap();
+        assert!(matches!(miss.payload, GetResultPayload::Stream(_)));
+        // Only cached once the whole body has been read through
+        assert_eq!(backend.disk.as_ref().unwrap().bytes(), 0);
+        assert_eq!(miss.bytes().await.unwrap(), Bytes::from("aaaa"));
+        assert_eq!(backend.disk.as_ref().unwrap().bytes(), 4);
+
+        let hit = backend.get_opts("a", GetOptions::default()).await.unwrap();
+        assert!(matches!(hit.payload, GetResultPayload::File(..)));
+        assert_eq!((hit.meta.size, hit.range.clone()), (4, 0..4));
+        assert_eq!(hit.bytes().await.unwrap(), Bytes::from("aaaa"));
+        assert_eq!(mock.calls_of(MockOperation::Get).len(), 1);
+
+        // Ranged reads go to the backend
+        let options = GetOptions {
+            range: Some((0..2).into()),
+            ..GetOptions::default()
+        };
+        assert_eq!(backend.get_opts("a", options).await.unwrap().bytes().await.unwrap(), Bytes::from("aa"));
+        assert_eq!(mock.calls_of(MockOperation::Get).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_reads_not_cached() {
+        let root = tempfile::tempdir().unwrap();
+        let (mock, backend) = backend(&disk_config(&root)).await;
+
+        let miss = backend.get_opts("a", GetOptions::default()).await.unwrap();
+        assert_eq!(cache_files(&root), 1);
+        drop(miss);
+        assert_eq!(cache_files(&root), 0);
+        assert_eq!(backend.disk.as_ref().unwrap().bytes(), 0);
+
+        // The key's lock went with it
+        assert_eq!(backend.get("a").await.unwrap(), Bytes::from("aaaa"));
+        assert_eq!(mock.calls_of(MockOperation::Get).len(), 2);
+        assert_eq!(cache_files(&root), 1);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_misses_fetch_once() {
+        let root = tempfile::tempdir().unwrap();
+        let (mock, backend) = backend(&disk_config(&root)).await;
+        mock.set_operation_latency(MockOperation::Get, Duration::from_millis(50));
+
+        let (first, second) = tokio::join!(backend.get("a"), backend.get("a"));
+        assert_eq!(first.unwrap(), Bytes::from("aaaa"));
+        assert_eq!(second.unwrap(), Bytes::from("aaaa"));
+        assert_eq!(mock.calls_of(MockOperation::Get).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache_removes_leftovers() {
+        let root = tempfile::tempdir().unwrap();
+        let leftover = root.path().join(uuid::Uuid::new_v4().simple().to_string());
+        std::fs::create_dir(&leftover).unwrap();
+        std::fs::write(leftover.join("partial.tmp"), "part").unwrap();
+        std::fs::write(root.path().join("unrelated"), "keep").unwrap();
+
+        let (_, backend) = backend(&disk_config(&root)).await;
+        assert!(!leftover.exists());
+        assert!(root.path().join("unrelated").exists());
+
+        drop(backend);
+        assert_eq!(std::fs::read_dir(root.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_object_served_from_memory() {
+        let (mock, backend) = backend(&config(1024, 1024, 60_000)).await;
+        let registry = crate::storage::BucketRegistry::single(Arc::new(backend));
+        let router = crate::routes::create_router(Arc::new(registry)).layer(from_fn(annotate));
+        let get_object = || async {
+            let request = Request::get("/bucket/a").body(Body::empty()).unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            let status = response.headers()[&CACHE_HEADER].to_str().unwrap().to_string();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, body)
+        };
+
+        assert_eq!(get_object().await, ("MISS".to_string(), Bytes::from("aaaa")));
+        assert_eq!(get_object().await, ("HIT".to_string(), Bytes::from("aaaa")));
+        assert_eq!(mock.calls_of(MockOperation::Get).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_small_objects_kept_in_memory_beside_disk() {
+        let root = tempfile::tempdir().unwrap();
+        let config = CacheConfig {
+            max_object_bytes: 4,
+            ..disk_config(&root)
+        };
+        let (mock, backend) = backend(&config).await;
+        mock.put("large", Bytes::from("large")).await.unwrap();
+
+        for _ in 0..2 {
+            let small = backend.get_opts("a", GetOptions::default()).await.unwrap();
+            assert_eq!(small.bytes().await.unwrap(), Bytes::from("aaaa"));
+            let large = backend.get_opts("large", GetOptions::default()).await.unwrap();
+            assert_eq!(large.bytes().await.unwrap(), Bytes::from("large"));
+        }
+        assert_eq!(mock.calls_of(MockOperation::Get).len(), 2);
+        assert_eq!(backend.disk.as_ref().unwrap().bytes(), 5);
+        assert_eq!(cache_files(&root), 1);
+    }
+
+    #[tokio::test]
+    async fn test_responses_report_cache_status() {
+        let (_, backend) = backend(&config(1024, 1024, 60_000)).await;
+        let app = Router::new()
+            .route(
+                "/",
+                get(|State(backend): State<Arc<CachingBackend>>| async move { backend.get("a").await.unwrap() }),
+            )
+            .route("/uncached", get(|| async { "ok" }))
+            .with_state(Arc::new(backend))
+            .layer(from_fn(annotate));
+        let status = |path: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(Request::get(path).body(Body::empty()).unwrap()).await.unwrap();
+                response.headers().get(&CACHE_HEADER).cloned()
+            }
+        };
+
+        assert_eq!(status("/").await.unwrap(), "MISS");
+        assert_eq!(status("/").await.unwrap(), "HIT");
+        assert!(status("/uncached").await.is_none());
+    }
+}
@@ -0,0 +1,270 @@
+//! Negative cache
+//!
+//! Remembers keys a GET or HEAD found missing, and answers further reads
+//! of them with NotFound without calling the backend. Puts through the
+//! proxy purge the key at once, but an object created directly in the
+//! bucket stays invisible until the entry expires: `ttl_ms` bounds how
+//! stale a NotFound can be. Once `max_entries` keys are remembered, the
+//! oldest are forgotten first.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use object_store::{GetOptions, GetResult, ListResult, ObjectMeta, ObjectStore, PutResult};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::{record, report};
+use crate::config::NegativeCacheConfig;
+use crate::errors::{StorageError, StorageErrorClass};
+use crate::storage::{ObjectAttributes, StorageBackend};
+
+/// Tier label of the negative cache metrics
+const TIER: &str = "negative";
+
+#[derive(Default)]
+struct Entries {
+    /// Expiry of each missing key
+    expires: HashMap<String, Instant>,
+    /// Keys in insertion order, possibly already purged
+    order: VecDeque<String>,
+    /// Bumped by every purge
+    generation: u64,
+}
+
+/// Storage backend remembering keys missing from an inner backend
+pub struct NegativeCacheBackend {
+    inner: Arc<dyn StorageBackend>,
+    entries: Mutex<Entries>,
+    ttl: Duration,
+    max_entries: usize,
+}
+
+impl NegativeCacheBackend {
+    pub fn new(inner: Arc<dyn StorageBackend>, config: &NegativeCacheConfig) -> Self {
+        Self {
+            inner,
+            entries: Mutex::new(Entries::default()),
+            ttl: Duration::from_millis(config.ttl_ms),
+            max_entries: config.max_entries,
+        }
+    }
+
+    fn entries(&self) -> MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Answer from the cache when `path` is known to be missing, otherwise
+    /// run `call` and remember a NotFound it returns
+    async fn read<T, Fut>(&self, operation: &'static str, path: &str, call: Fut) -> Result<T, StorageError>
+    where
+        Fut: Future<Output = Result<T, StorageError>>,
+    {
+        let generation = {
+            let mut entries = self.entries();
+            let missing = match entries.expires.get(path) {
+                Some(expires) if *expires > Instant::now() => true,
+                Some(_) => {
+                    entries.expires.remove(path);
+                    false
+                }
+                None => false,
+            };
+            record(TIER, operation, missing);
+            if missing {
+                report(true);
+                return Err(not_found(path).into());
+            }
+            entries.generation
+        };
+
+        let result = call.await;
+        if matches!(&result, Err(e) if e.class() == StorageErrorClass::NotFound) {
+            self.remember(path, generation);
+        }
+        result
+    }
+
+    /// Remember `path` as missing, unless it was purged since `generation`
+    /// was taken: a put through the proxy may have created it since
+    fn remember(&self, path: &str, generation: u64) {
+        let mut entries = self.entries();
+        if entries.generation != generation || self.max_entries == 0 {
+            return;
+        }
+        let expires = Instant::now() + self.ttl;
+        if entries.expires.insert(path.to_string(), expires).is_none() {
+            entries.order.push_back(path.to_string());
+        }
+        while entries.expires.len() > self.max_entries {
+            let Some(oldest) = entries.order.pop_front() else {
+                break;
+            };
+            entries.expires.remove(&oldest);
+        }
+        // Drop purged keys once they make up most of the queue
+        if entries.order.len() > 2 * self.max_entries {
+            let Entries { expires, order, .. } = &mut *entries;
+            order.retain(|key| expires.contains_key(key));
+        }
+    }
+
+    fn purge(&self, path: &str) {
+        let mut entries = self.entries();
+        entries.generation += 1;
+        entries.expires.remove(path);
+    }
+}
+
+fn not_found(path: &str) -> object_store::Error {
+    object_store::Error::NotFound {
+        path: path.to_string(),
+        source: "Object recently found missing".into(),
+    }
+}
+
+#[async_trait]
+impl StorageBackend for NegativeCacheBackend {
+    async fn get_opts(&self, path: &str, options: GetOptions) -> Result<GetResult, StorageError> {
+        self.read("get", path, self.inner.get_opts(path, options)).await
+    }
+
+    async fn put_with_attributes(
+        &self,
+        path: &str,
+        data: Bytes,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        let result = self.inner.put_with_attributes(path, data, attributes).await;
+        // Even a failed write may have created the object
+        self.purge(path);
+        result
+    }
+
+    async fn put_stream(
+        &self,
+        path: &str,
+        stream: BoxStream<'static, Result<Bytes, io::Error>>,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        let result = self.inner.put_stream(path, stream, attributes).await;
+        self.purge(path);
+        result
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        self.inner.delete(path).await
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        offset: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<ObjectMeta>, StorageError> {
+        self.inner.list(prefix, offset, limit).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: &str) -> Result<ListResult, StorageError> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn head(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        self.read("head", path, self.inner.head(path)).await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let result = self.inner.copy(from, to).await;
+        // Even a failed copy may have created the object
+        self.purge(to);
+        result
+    }
+
+    async fn copy_if_not_exists(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let result = self.inner.copy_if_not_exists(from, to).await;
+        self.purge(to);
+        result
+    }
+
+    async fn delete_many(&self, paths: Vec<String>) -> Vec<(String, Result<(), StorageError>)> {
+        self.inner.delete_many(paths).await
+    }
+
+    fn object_store(&self) -> &dyn ObjectStore {
+        self.inner.object_store()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::CACHE_REQUESTS;
+    use crate::storage::{MockBackend, MockOperation};
+
+    fn backend(ttl_ms: u64, max_entries: usize) -> (Arc<MockBackend>, NegativeCacheBackend) {
+        let mock = Arc::new(MockBackend::new());
+        let config = NegativeCacheConfig {
+            enabled: true,
+            ttl_ms,
+            max_entries,
+        };
+        (mock.clone(), NegativeCacheBackend::new(mock, &config))
+    }
+
+    fn is_not_found<T>(result: Result<T, StorageError>) -> bool {
+        matches!(result, Err(e) if e.class() == StorageErrorClass::NotFound)
+    }
+
+    #[tokio::test]
+    async fn test_missing_keys_served_from_cache() {
+        let (mock, backend) = backend(60_000, 100);
+        let hits = || CACHE_REQUESTS.with_label_values(&[TIER, "head", "hit"]).get();
+        let before = hits();
+
+        assert!(is_not_found(backend.get("missing").await));
+        assert!(is_not_found(backend.get("missing").await));
+        assert!(is_not_found(backend.head("missing").await));
+
+        assert_eq!(mock.calls().len(), 1);
+        assert!(hits() > before);
+    }
+
+    #[tokio::test]
+    async fn test_put_through_proxy_purges() {
+        let (_, backend) = backend(60_000, 100);
+        assert!(is_not_found(backend.get("a").await));
+
+        backend.put("a", Bytes::from("data")).await.unwrap();
+        assert_eq!(backend.get("a").await.unwrap(), Bytes::from("data"));
+    }
+
+    #[tokio::test]
+    async fn test_out_of_band_creation_visible_after_ttl() {
+        let (mock, backend) = backend(50, 100);
+        assert!(is_not_found(backend.get("a").await));
+
+        // Created in the bucket directly, not through the proxy
+        mock.put("a", Bytes::from("data")).await.unwrap();
+        assert!(is_not_found(backend.get("a").await));
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(backend.get("a").await.unwrap(), Bytes::from("data"));
+    }
+
+    #[tokio::test]
+    async fn test_entries_bounded() {
+        let (mock, backend) = backend(60_000, 2);
+        for key in ["a", "b", "c"] {
+            assert!(is_not_found(backend.head(key).await));
+        }
+        assert_eq!(backend.entries().expires.len(), 2);
+
+        // The oldest key was forgotten
+        assert!(is_not_found(backend.head("a").await));
+        assert!(is_not_found(backend.head("c").await));
+        assert_eq!(mock.calls_of(MockOperation::Head).len(), 4);
+    }
+}
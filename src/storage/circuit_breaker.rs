@@ -0,0 +1,462 @@
+//! Circuit-breaking storage backend decorator
+//!
+//! Wraps another backend and stops calling it while it is failing, so
+//! requests fail fast with `503 SlowDown` and a `Retry-After` hint instead
+//! of each waiting out the backend timeout. Reads (get, head, list) and
+//! writes (put, delete) have separate breakers, since an outage often hits
+//! only one of them.
+//!
+//! A closed breaker passes every operation through and opens after
+//! `consecutive_failures` transient failures in a row, or when at least
+//! `failure_rate` of the operations in a `window_ms` window fail (once the
+//! window has seen `min_requests`). Missing objects and permission or
+//! precondition failures are answers from a healthy backend and count as
+//! successes. An open breaker fails operations for `open_ms`, then turns
+//! half-open: one operation every `probe_interval_ms` is let through as a
+//! probe, and the first successful probe closes the breaker again.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use object_store::{GetOptions, GetResult, ListResult, ObjectMeta, ObjectStore, PutResult};
+use std::future::Future;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::config::CircuitBreakerConfig;
+use crate::errors::StorageError;
+use crate::metrics::CIRCUIT_BREAKER_TRANSITIONS;
+use crate::storage::retry::transient;
+use crate::storage::{ObjectAttributes, StorageBackend};
+
+/// Error returned without calling the backend while a breaker is open
+///
+/// Carried as the source of an `object_store::Error::Generic` in a
+/// `StorageError`, and mapped to `503 SlowDown` with a `Retry-After` header.
+#[derive(Debug, Clone)]
+pub struct CircuitOpen {
+    class: &'static str,
+    retry_after: Duration,
+}
+
+impl CircuitOpen {
+    /// Find the circuit breaker rejection behind a storage error, if any
+    pub fn find(error: &StorageError) -> Option<&CircuitOpen> {
+        let mut source: Option<&(dyn std::error::Error + 'static)> = std::error::Error::source(error);
+        while let Some(e) = source {
+            if let Some(open) = e.downcast_ref::<CircuitOpen>() {
+                return Some(open);
+            }
+            source = e.source();
+        }
+        None
+    }
+
+    /// When the backend may be tried again
+    pub fn retry_after(&self) -> Duration {
+        self.retry_after
+    }
+}
+
+impl std::fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Circuit breaker open for {} operations, retry after {}ms",
+            self.class,
+            self.retry_after.as_millis()
+        )
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Closed,
+    Open { until: Instant },
+    HalfOpen { next_probe: Instant },
+}
+
+impl State {
+    fn name(&self) -> &'static str {
+        match self {
+            State::Closed => "closed",
+            State::Open { .. } => "open",
+            State::HalfOpen { .. } => "half_open",
+        }
+    }
+}
+
+/// How an operation was let through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Admission {
+    Normal,
+    Probe,
+}
+
+struct Counters {
+    state: State,
+    consecutive_failures: u32,
+    window_start: Instant,
+    requests: u32,
+    failures: u32,
+}
+
+/// Breaker for one operation class of one backend
+struct Breaker {
+    backend: &'static str,
+    class: &'static str,
+    consecutive_failures: u32,
+    failure_rate: f64,
+    min_requests: u32,
+    window: Duration,
+    open: Duration,
+    probe_interval: Duration,
+    counters: Mutex<Counters>,
+}
+
+impl Breaker {
+    fn new(config: &CircuitBreakerConfig, backend: &'static str, class: &'static str) -> Self {
+        Self {
+            backend,
+            class,
+            consecutive_failures: config.consecutive_failures.max(1),
+            failure_rate: config.failure_rate,
+            min_requests: config.min_requests.max(1),
+            window: Duration::from_millis(config.window_ms),
+            open: Duration::from_millis(config.open_ms),
+            probe_interval: Duration::from_millis(config.probe_interval_ms),
+            counters: Mutex::new(Counters {
+                state: State::Closed,
+                consecutive_failures: 0,
+                window_start: Instant::now(),
+                requests: 0,
+                failures: 0,
+            }),
+        }
+    }
+
+    fn counters(&self) -> std::sync::MutexGuard<'_, Counters> {
+        self.counters.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Let an operation through, or reject it with the time until the
+    /// backend may be tried again
+    fn admit(&self, now: Instant) -> Result<Admission, CircuitOpen> {
+        let mut counters = self.counters();
+        if let State::Open { until } = counters.state {
+            if now < until {
+                return Err(self.rejection(until - now));
+            }
+            self.transition(&mut counters, State::HalfOpen { next_probe: now });
+        }
+        match counters.state {
+            State::Closed => Ok(Admission::Normal),
+            State::HalfOpen { next_probe } if now >= next_probe => {
+                // Another probe is due if this one never reports back
+                counters.state = State::HalfOpen {
+                    next_probe: now + self.probe_interval,
+                };
+                Ok(Admission::Probe)
+            }
+            State::HalfOpen { next_probe } => Err(self.rejection(next_probe - now)),
+            State::Open { .. } => unreachable!("open breaker turned half-open above"),
+        }
+    }
+
+    /// Record the outcome of an admitted operation
+    fn record(&self, admission: Admission, failed: bool, now: Instant) {
+        let mut counters = self.counters();
+        match (admission, counters.state) {
+            (Admission::Probe, State::HalfOpen { .. }) => {
+                if failed {
+                    counters.state = State::HalfOpen {
+                        next_probe: now + self.probe_interval,
+                    };
+                } else {
+                    self.transition(&mut counters, State::Closed);
+                }
+            }
+            (Admission::Normal, State::Closed) => {
+                if now.duration_since(counters.window_start) >= self.window {
+                    counters.window_start = now;
+                    counters.requests = 0;
+                    counters.failures = 0;
+                }
+                counters.requests += 1;
+                if failed {
+                    counters.failures += 1;
+                    counters.consecutive_failures += 1;
+                } else {
+                    counters.consecutive_failures = 0;
+                }
+                let rate_exceeded = counters.requests >= self.min_requests
+                    && counters.failures as f64 / counters.requests as f64 >= self.failure_rate;
+                if counters.consecutive_failures >= self.consecutive_failures || rate_exceeded {
+                    self.transition(&mut counters, State::Open { until: now + self.open });
+                }
+            }
+            // The state changed while the operation ran
+            _ => {}
+        }
+    }
+
+    fn transition(&self, counters: &mut Counters, state: State) {
+        let from = counters.state.name();
+        counters.state = state;
+        counters.consecutive_failures = 0;
+        counters.window_start = Instant::now();
+        counters.requests = 0;
+        counters.failures = 0;
+
+        CIRCUIT_BREAKER_TRANSITIONS
+            .with_label_values(&[self.backend, self.class, state.name()])
+            .inc();
+        match state {
+            State::Open { .. } => warn!(
+                backend = self.backend,
+                class = self.class,
+                from,
+                open_ms = self.open.as_millis() as u64,
+                "Storage circuit breaker opened"
+            ),
+            _ => info!(
+                backend = self.backend,
+                class = self.class,
+                from,
+                to = state.name(),
+                "Storage circuit breaker state changed"
+            ),
+        }
+    }
+
+    fn rejection(&self, retry_after: Duration) -> CircuitOpen {
+        CircuitOpen {
+            class: self.class,
+            retry_after,
+        }
+    }
+
+    async fn call<T>(
+        &self,
+        future: impl Future<Output = Result<T, StorageError>>,
+    ) -> Result<T, StorageError> {
+        let admission = self.admit(Instant::now()).map_err(|open| object_store::Error::Generic {
+            store: "CircuitBreaker",
+            source: Box::new(open),
+        })?;
+        let result = future.await;
+        let failed = matches!(&result, Err(e) if transient(e).is_some());
+        self.record(admission, failed, Instant::now());
+        result
+    }
+}
+
+/// Storage backend failing fast while an inner backend is failing
+pub struct CircuitBreakerBackend {
+    inner: Arc<dyn StorageBackend>,
+    reads: Breaker,
+    writes: Breaker,
+}
+
+impl CircuitBreakerBackend {
+    /// Wrap `inner` of backend type `backend`, which labels the breaker's
+    /// metrics and logs
+    pub fn new(inner: Arc<dyn StorageBackend>, config: &CircuitBreakerConfig, backend: &'static str) -> Self {
+        Self {
+            inner,
+            reads: Breaker::new(config, backend, "read"),
+            writes: Breaker::new(config, backend, "write"),
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for CircuitBreakerBackend {
+    async fn get_opts(&self, path: &str, options: GetOptions) -> Result<GetResult, StorageError> {
+        self.reads.call(self.inner.get_opts(path, options)).await
+    }
+
+    async fn put_with_attributes(
+        &self,
+        path: &str,
+        data: Bytes,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        self.writes
+            .call(self.inner.put_with_attributes(path, data, attributes))
+            .await
+    }
+
+    async fn put_stream(
+        &self,
+        path: &str,
+        stream: BoxStream<'static, Result<Bytes, io::Error>>,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        self.writes
+            .call(self.inner.put_stream(path, stream, attributes))
+            .await
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        self.writes.call(self.inner.delete(path)).await
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        offset: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<ObjectMeta>, StorageError> {
+        self.reads.call(self.inner.list(prefix, offset, limit)).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: &str) -> Result<ListResult, StorageError> {
+        self.reads.call(self.inner.list_with_delimiter(prefix)).await
+    }
+
+    async fn head(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        self.reads.call(self.inner.head(path)).await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.writes.call(self.inner.copy(from, to)).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.writes.call(self.inner.copy_if_not_exists(from, to)).await
+    }
+
+    async fn delete_many(&self, paths: Vec<String>) -> Vec<(String, Result<(), StorageError>)> {
+        let admission = match self.writes.admit(Instant::now()) {
+            Ok(admission) => admission,
+            Err(open) => {
+                return paths
+                    .into_iter()
+                    .map(|path| {
+                        let error = object_store::Error::Generic {
+                            store: "CircuitBreaker",
+                            source: Box::new(open.clone()),
+                        };
+                        (path, Err(error.into()))
+                    })
+                    .collect()
+            }
+        };
+        let results = self.inner.delete_many(paths).await;
+        let failed = results.iter().any(|(_, result)| matches!(result, Err(e) if transient(e).is_some()));
+        self.writes.record(admission, failed, Instant::now());
+        results
+    }
+
+    fn object_store(&self) -> &dyn ObjectStore {
+        self.inner.object_store()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{S3ProxyError, StorageErrorClass};
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use crate::storage::{MockBackend, MockOperation, MockResponse};
+
+    fn config() -> CircuitBreakerConfig {
+        CircuitBreakerConfig {
+            enabled: true,
+            consecutive_failures: 2,
+            min_requests: 100,
+            open_ms: 50,
+            probe_interval_ms: 50,
+            ..CircuitBreakerConfig::default()
+        }
+    }
+
+    fn unavailable() -> MockResponse {
+        MockResponse::Error(object_store::Error::Generic {
+            store: "mock",
+            source: "Server returned non-2xx status code: 500 Internal Server Error".into(),
+        })
+    }
+
+    fn state(backend: &CircuitBreakerBackend) -> &'static str {
+        backend.reads.counters().state.name()
+    }
+
+    #[tokio::test]
+    async fn test_closed_open_half_open_closed() {
+        let mock = Arc::new(MockBackend::new());
+        let backend = CircuitBreakerBackend::new(mock.clone(), &config(), "mock");
+        let opened = || CIRCUIT_BREAKER_TRANSITIONS.with_label_values(&["mock", "read", "open"]).get();
+        let opened_before = opened();
+
+        // Missing objects are healthy answers and keep the breaker closed
+        for _ in 0..3 {
+            assert!(backend.head("missing").await.is_err());
+        }
+        assert_eq!(state(&backend), "closed");
+
+        // Two failures in a row open it
+        mock.push(MockOperation::Get, unavailable());
+        mock.push(MockOperation::Get, unavailable());
+        assert!(backend.get("a").await.is_err());
+        assert!(backend.get("a").await.is_err());
+        assert_eq!(state(&backend), "open");
+        assert!(opened() > opened_before);
+
+        // Open: rejected without calling the backend, as SlowDown
+        let calls = mock.calls().len();
+        let error = backend.list("", None, None).await.unwrap_err();
+        assert_eq!(mock.calls().len(), calls);
+        assert_eq!(error.class(), StorageErrorClass::Throttled);
+        let retry_after = CircuitOpen::find(&error).unwrap().retry_after();
+        assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_millis(50));
+        let response = S3ProxyError::Storage(error).into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["retry-after"], "1");
+
+        // Writes have their own breaker
+        backend.put("a", Bytes::from("data")).await.unwrap();
+
+        // Half-open: a failed probe keeps it half-open and calls are
+        // rejected until the next probe is due
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        mock.push(MockOperation::Get, unavailable());
+        assert!(backend.get("a").await.is_err());
+        assert_eq!(state(&backend), "half_open");
+        let calls = mock.calls().len();
+        assert!(CircuitOpen::find(&backend.get("a").await.unwrap_err()).is_some());
+        assert_eq!(mock.calls().len(), calls);
+
+        // A successful probe closes it
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(backend.get("a").await.unwrap(), Bytes::from("data"));
+        assert_eq!(state(&backend), "closed");
+        assert_eq!(backend.get("a").await.unwrap(), Bytes::from("data"));
+    }
+
+    #[tokio::test]
+    async fn test_failure_rate_opens() {
+        let mock = Arc::new(MockBackend::new());
+        let config = CircuitBreakerConfig {
+            consecutive_failures: 100,
+            min_requests: 4,
+            failure_rate: 0.5,
+            ..config()
+        };
+        let backend = CircuitBreakerBackend::new(mock.clone(), &config, "mock");
+        mock.put("a", Bytes::from("data")).await.unwrap();
+
+        for _ in 0..2 {
+            mock.push(MockOperation::Get, unavailable());
+            assert!(backend.get("a").await.is_err());
+            assert!(backend.get("a").await.is_ok());
+        }
+        assert_eq!(state(&backend), "open");
+    }
+}
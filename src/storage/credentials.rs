@@ -0,0 +1,360 @@
+//! Refreshing credentials from the AWS SDK credential providers
+//!
+//! Lets the AWS backend use credentials object_store cannot obtain itself:
+//! those of a role assumed with its own identity, e.g. one in another
+//! account; of a role assumed through AssumeRoleWithWebIdentity with an
+//! OIDC token projected to a file, read again on every refresh as the
+//! token rotates; or of a named profile of the shared config files,
+//! including SSO and assume-role profiles. Such credentials expire,
+//! typically after an hour, so they are refreshed a few minutes before
+//! they do. A failed refresh is counted in
+//! `s3proxy_credential_refreshes_total` and the current credentials stay in
+//! use while they are valid; only once they have expired do requests, and
+//! with them the readiness probe, fail.
+
+use async_trait::async_trait;
+use aws_config::profile::ProfileFileCredentialsProvider;
+use aws_config::provider_config::ProviderConfig;
+use aws_config::sts::AssumeRoleProvider;
+use aws_config::web_identity_token::{StaticConfiguration, WebIdentityTokenCredentialsProvider};
+use aws_config::Region;
+use aws_credential_types::provider::ProvideCredentials;
+use aws_credential_types::Credentials;
+use aws_runtime::env_config::file::EnvConfigFiles;
+use object_store::aws::AwsCredential;
+use object_store::CredentialProvider;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::config::AwsConfig;
+use crate::metrics::CREDENTIAL_REFRESHES;
+
+/// Refresh credentials this long before they expire
+const REFRESH_AHEAD: Duration = Duration::from_secs(5 * 60);
+
+/// Delay after a failed refresh before the next attempt, while the current
+/// credentials are still valid
+const RETRY_AFTER: Duration = Duration::from_secs(10);
+
+struct Cached {
+    credential: Arc<AwsCredential>,
+    expires: Option<SystemTime>,
+}
+
+impl Cached {
+    fn valid_at(&self, time: SystemTime) -> bool {
+        self.expires.is_none_or(|expires| time < expires)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    cached: Option<Cached>,
+    /// No refresh before this time, after a failed one
+    retry_at: Option<SystemTime>,
+}
+
+/// object_store credential provider of AWS SDK provided credentials
+pub(crate) struct SdkCredentials {
+    /// Where credentials come from, for logs and errors
+    name: String,
+    /// Advice added to errors
+    hint: Option<String>,
+    source: Arc<dyn ProvideCredentials>,
+    state: Mutex<State>,
+}
+
+impl SdkCredentials {
+    /// Credentials of `role_arn`, assumed with the web identity token file
+    /// of `config`, its explicit credentials, or else the default
+    /// credential chain
+    ///
+    /// Fails when the web identity token file cannot be read.
+    pub(crate) async fn assume_role(role_arn: &str, config: &AwsConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let region = Region::new(config.region.clone());
+        if let Some(token_file) = &config.web_identity_token_file {
+            match std::fs::read_to_string(token_file) {
+                Ok(token) if !token.trim().is_empty() => {}
+                Ok(_) => return Err(format!("AWS web identity token file {token_file} is empty").into()),
+                Err(e) => return Err(format!("Cannot read AWS web identity token file {token_file}: {e}").into()),
+            }
+            let provider = WebIdentityTokenCredentialsProvider::builder()
+                .configure(&ProviderConfig::default().with_region(Some(region)))
+                .static_configuration(StaticConfiguration {
+                    web_identity_token_file: token_file.into(),
+                    role_arn: role_arn.to_string(),
+                    session_name: config.role_session_name.clone(),
+                })
+                .build();
+            return Ok(Self::with_source(format!("role {role_arn}"), Arc::new(provider)));
+        }
+
+        let mut builder = AssumeRoleProvider::builder(role_arn)
+            .region(region)
+            .session_name(&config.role_session_name);
+        if let Some(external_id) = &config.external_id {
+            builder = builder.external_id(external_id);
+        }
+        let provider = match (&config.access_key_id, &config.secret_access_key) {
+            (Some(access_key_id), Some(secret_access_key)) if !config.use_managed_identity => {
+                let token = config.session_token.as_ref().map(|token| token.expose().to_string());
+                let base = Credentials::new(access_key_id, secret_access_key.expose(), token, None, "s3proxy");
+                builder.build_from_provider(base).await
+            }
+            _ => builder.build().await,
+        };
+        Ok(Self::with_source(format!("role {role_arn}"), Arc::new(provider)))
+    }
+
+    /// Credentials of the named profile of `files`, or of the default
+    /// shared config and credentials files
+    ///
+    /// Fails when the profile yields no credentials, e.g. when its SSO
+    /// session has expired.
+    pub(crate) async fn profile(
+        profile: &str,
+        config: &AwsConfig,
+        files: Option<EnvConfigFiles>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut builder = ProfileFileCredentialsProvider::builder()
+            .configure(&ProviderConfig::default().with_region(Some(Region::new(config.region.clone()))))
+            .profile_name(profile);
+        if let Some(files) = files {
+            builder = builder.profile_files(files);
+        }
+        let credentials = Self {
+            hint: Some(format!(
+                "if the profile uses SSO, run `aws sso login --profile {profile}` to renew its session"
+            )),
+            ..Self::with_source(format!("profile {profile}"), Arc::new(builder.build()))
+        };
+        credentials.get_credential().await?;
+        Ok(credentials)
+    }
+
+    fn with_source(name: String, source: Arc<dyn ProvideCredentials>) -> Self {
+        Self {
+            name,
+            hint: None,
+            source,
+            state: Mutex::new(State::default()),
+        }
+    }
+}
+
+impl std::fmt::Debug for SdkCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SdkCredentials")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl CredentialProvider for SdkCredentials {
+    type Credential = AwsCredential;
+
+    async fn get_credential(&self) -> object_store::Result<Arc<AwsCredential>> {
+        // Held across the refresh, so concurrent requests share one STS call
+        let mut state = self.state.lock().await;
+        let now = SystemTime::now();
+        if let Some(cached) = &state.cached {
+            let fresh = cached.valid_at(now + REFRESH_AHEAD);
+            let backing_off = state.retry_at.is_some_and(|retry_at| now < retry_at);
+            if fresh || (backing_off && cached.valid_at(now)) {
+                return Ok(cached.credential.clone());
+            }
+        }
+
+        match self.source.provide_credentials().await {
+            Ok(credentials) => {
+                CREDENTIAL_REFRESHES.with_label_values(&["ok"]).inc();
+                debug!(credentials = %self.name, expires = ?credentials.expiry(), "Refreshed credentials");
+                let credential = Arc::new(AwsCredential {
+                    key_id: credentials.access_key_id().to_string(),
+                    secret_key: credentials.secret_access_key().to_string(),
+                    token: credentials.session_token().map(str::to_string),
+                });
+                state.cached = Some(Cached {
+                    credential: credential.clone(),
+                    expires: credentials.expiry(),
+                });
+                state.retry_at = None;
+                Ok(credential)
+            }
+            Err(e) => {
+                CREDENTIAL_REFRESHES.with_label_values(&["error"]).inc();
+                state.retry_at = Some(now + RETRY_AFTER);
+                match &state.cached {
+                    Some(cached) if cached.valid_at(now) => {
+                        warn!(
+                            credentials = %self.name,
+                            error = %e,
+                            "Failed to refresh credentials, keeping the current ones until they expire"
+                        );
+                        Ok(cached.credential.clone())
+                    }
+                    _ => {
+                        warn!(credentials = %self.name, error = %e, "Failed to obtain credentials");
+                        let message = match &self.hint {
+                            Some(hint) => format!("No credentials from {}: {}; {}", self.name, e, hint),
+                            None => format!("No credentials from {}: {}", self.name, e),
+                        };
+                        Err(object_store::Error::Generic {
+                            store: "S3",
+                            source: message.into(),
+                        })
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_credential_types::provider::error::CredentialsError;
+    use aws_credential_types::provider::future;
+    use aws_runtime::env_config::file::EnvConfigFileKind;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    /// Stand-in for STS, issuing numbered credentials
+    #[derive(Debug)]
+    struct Sts {
+        calls: AtomicUsize,
+        lifetime: Duration,
+        /// Issue credentials that have already expired
+        expired: bool,
+        fail: AtomicBool,
+    }
+
+    impl Sts {
+        fn new(lifetime: Duration, expired: bool) -> Arc<Self> {
+            Arc::new(Self {
+                calls: AtomicUsize::new(0),
+                lifetime,
+                expired,
+                fail: AtomicBool::new(false),
+            })
+        }
+    }
+
+    impl ProvideCredentials for Sts {
+        fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+        where
+            Self: 'a,
+        {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.fail.load(Ordering::SeqCst) {
+                return future::ProvideCredentials::ready(Err(CredentialsError::provider_error("AccessDenied")));
+            }
+            let expires = if self.expired {
+                SystemTime::now() - self.lifetime
+            } else {
+                SystemTime::now() + self.lifetime
+            };
+            let token = Some("token".to_string());
+            let credentials = Credentials::new(format!("ASIA{call}"), "secret", token, Some(expires), "sts");
+            future::ProvideCredentials::ready(Ok(credentials))
+        }
+    }
+
+    fn provider(sts: &Arc<Sts>) -> SdkCredentials {
+        SdkCredentials::with_source("role arn:aws:iam::123456789012:role/test".to_string(), sts.clone())
+    }
+
+    #[tokio::test]
+    async fn test_credentials_reused_until_refresh_due() {
+        let sts = Sts::new(Duration::from_secs(3600), false);
+        let provider = provider(&sts);
+
+        let first = provider.get_credential().await.unwrap();
+        let second = provider.get_credential().await.unwrap();
+        assert_eq!(first.key_id, "ASIA0");
+        assert_eq!(second.key_id, "ASIA0");
+        assert_eq!(second.token.as_deref(), Some("token"));
+        assert_eq!(sts.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_credentials_rotate_once_expired() {
+        let sts = Sts::new(Duration::from_secs(1), true);
+        let provider = provider(&sts);
+
+        assert_eq!(provider.get_credential().await.unwrap().key_id, "ASIA0");
+        assert_eq!(provider.get_credential().await.unwrap().key_id, "ASIA1");
+        assert_eq!(sts.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_refresh_keeps_valid_credentials() {
+        // Valid for a minute: within the refresh window, but not expired
+        let sts = Sts::new(Duration::from_secs(60), false);
+        let provider = provider(&sts);
+        let errors = || CREDENTIAL_REFRESHES.with_label_values(&["error"]).get();
+        provider.get_credential().await.unwrap();
+
+        sts.fail.store(true, Ordering::SeqCst);
+        let before = errors();
+        assert_eq!(provider.get_credential().await.unwrap().key_id, "ASIA0");
+        assert!(errors() > before);
+
+        // Backing off: no STS call until the retry delay passes
+        assert_eq!(provider.get_credential().await.unwrap().key_id, "ASIA0");
+        assert_eq!(sts.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_refresh_without_valid_credentials_errors() {
+        let sts = Sts::new(Duration::from_secs(3600), false);
+        sts.fail.store(true, Ordering::SeqCst);
+        assert!(provider(&sts).get_credential().await.is_err());
+    }
+
+    fn profile_files(dir: &std::path::Path, config: &str, credentials: &str) -> EnvConfigFiles {
+        std::fs::write(dir.join("config"), config).unwrap();
+        std::fs::write(dir.join("credentials"), credentials).unwrap();
+        EnvConfigFiles::builder()
+            .with_file(EnvConfigFileKind::Config, dir.join("config"))
+            .with_file(EnvConfigFileKind::Credentials, dir.join("credentials"))
+            .build()
+    }
+
+    fn aws_config() -> AwsConfig {
+        toml::from_str("bucket_name = \"bucket\"\nregion = \"us-east-1\"").unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_profile_credentials_from_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = profile_files(
+            dir.path(),
+            "[profile staging]\nregion = us-east-1\n",
+            "[default]\naws_access_key_id = AKIDDEFAULT\naws_secret_access_key = default\n\
+             [staging]\naws_access_key_id = AKIDSTAGING\naws_secret_access_key = staging\n",
+        );
+
+        let credentials = SdkCredentials::profile("staging", &aws_config(), Some(files)).await.unwrap();
+        let credential = credentials.get_credential().await.unwrap();
+        assert_eq!(credential.key_id, "AKIDSTAGING");
+        assert_eq!(credential.secret_key, "staging");
+    }
+
+    #[tokio::test]
+    async fn test_expired_sso_session_asks_to_log_in() {
+        let dir = tempfile::tempdir().unwrap();
+        // No token for the session in the SSO cache, as after it expired
+        let files = profile_files(
+            dir.path(),
+            "[profile dev]\nsso_session = corp\nsso_account_id = 123456789012\nsso_role_name = Dev\n\
+             [sso-session corp]\nsso_region = us-east-1\nsso_start_url = https://corp.awsapps.com/start\n",
+            "",
+        );
+
+        let err = SdkCredentials::profile("dev", &aws_config(), Some(files)).await.err().unwrap();
+        assert!(err.to_string().contains("aws sso login --profile dev"), "{err}");
+    }
+}
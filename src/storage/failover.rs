@@ -0,0 +1,240 @@
+//! Failover storage backend
+//!
+//! Combines a primary backend with a secondary, such as a cross-region
+//! replica of the same bucket. Reads go to the primary and fall back to the
+//! secondary when it fails with a transient error or its circuit breaker
+//! is open. A missing object is an answer, not a failure, so it stays a
+//! 404 rather than being looked up in the secondary. Writes go to the
+//! primary only, unless write failover is enabled; the secondary must then
+//! be writable and replicated back by other means.
+//!
+//! The member answering each operation is counted in
+//! `s3proxy_failover_operations_total` and recorded in the access log.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use object_store::{GetOptions, GetResult, ListResult, ObjectMeta, ObjectStore, PutResult};
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::access_log;
+use crate::errors::StorageError;
+use crate::metrics::FAILOVER_OPERATIONS;
+use crate::storage::retry::transient;
+use crate::storage::{clone_get_options, CircuitOpen, ObjectAttributes, StorageBackend};
+
+/// Storage backend falling back from a primary to a secondary backend
+pub struct FailoverBackend {
+    primary: Arc<dyn StorageBackend>,
+    secondary: Arc<dyn StorageBackend>,
+    failover_writes: bool,
+}
+
+impl FailoverBackend {
+    pub fn new(primary: Arc<dyn StorageBackend>, secondary: Arc<dyn StorageBackend>, failover_writes: bool) -> Self {
+        Self {
+            primary,
+            secondary,
+            failover_writes,
+        }
+    }
+
+    /// Run `call` on the primary, and on the secondary if the primary
+    /// failed and `failover` allows it
+    async fn call<'a, T, F, Fut>(
+        &'a self,
+        operation: &'static str,
+        key: &str,
+        failover: bool,
+        call: F,
+    ) -> Result<T, StorageError>
+    where
+        F: Fn(&'a dyn StorageBackend) -> Fut,
+        Fut: Future<Output = Result<T, StorageError>>,
+    {
+        let result = call(self.primary.as_ref()).await;
+        let failed_over = match &result {
+            Err(e) if failover && unavailable(e) => {
+                warn!(operation, key, error = %e, "Primary backend failed, falling back to secondary");
+                true
+            }
+            _ => false,
+        };
+        let (served_by, result) = if failed_over {
+            ("secondary", call(self.secondary.as_ref()).await)
+        } else {
+            ("primary", result)
+        };
+        FAILOVER_OPERATIONS.with_label_values(&[operation, served_by]).inc();
+        access_log::record_served_by(served_by);
+        result
+    }
+}
+
+/// Whether an error means the backend could not answer
+fn unavailable(error: &StorageError) -> bool {
+    transient(error).is_some() || CircuitOpen::find(error).is_some()
+}
+
+#[async_trait]
+impl StorageBackend for FailoverBackend {
+    async fn get_opts(&self, path: &str, options: GetOptions) -> Result<GetResult, StorageError> {
+        self.call("get", path, true, |backend| backend.get_opts(path, clone_get_options(&options)))
+            .await
+    }
+
+    async fn put_with_attributes(
+        &self,
+        path: &str,
+        data: Bytes,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        self.call("put", path, self.failover_writes, |backend| {
+            backend.put_with_attributes(path, data.clone(), attributes.clone())
+        })
+        .await
+    }
+
+    async fn put_stream(
+        &self,
+        path: &str,
+        stream: BoxStream<'static, Result<Bytes, io::Error>>,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        // A stream cannot be replayed on the secondary
+        let result = self.primary.put_stream(path, stream, attributes).await;
+        FAILOVER_OPERATIONS.with_label_values(&["put_stream", "primary"]).inc();
+        access_log::record_served_by("primary");
+        result
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        self.call("delete", path, self.failover_writes, |backend| backend.delete(path))
+            .await
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        offset: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<ObjectMeta>, StorageError> {
+        self.call("list", prefix, true, |backend| backend.list(prefix, offset, limit))
+            .await
+    }
+
+    async fn list_with_delimiter(&self, prefix: &str) -> Result<ListResult, StorageError> {
+        self.call("list_with_delimiter", prefix, true, |backend| backend.list_with_delimiter(prefix))
+            .await
+    }
+
+    async fn head(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        self.call("head", path, true, |backend| backend.head(path)).await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.call("copy", to, self.failover_writes, |backend| backend.copy(from, to))
+            .await
+    }
+
+    async fn copy_if_not_exists(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.call("copy_if_not_exists", to, self.failover_writes, |backend| {
+            backend.copy_if_not_exists(from, to)
+        })
+        .await
+    }
+
+    async fn delete_many(&self, paths: Vec<String>) -> Vec<(String, Result<(), StorageError>)> {
+        let mut results = self.primary.delete_many(paths).await;
+        let failed: Vec<usize> = (0..results.len())
+            .filter(|&i| self.failover_writes && matches!(&results[i].1, Err(e) if unavailable(e)))
+            .collect();
+        if failed.is_empty() {
+            FAILOVER_OPERATIONS.with_label_values(&["delete_many", "primary"]).inc();
+            access_log::record_served_by("primary");
+            return results;
+        }
+
+        warn!(keys = failed.len(), "Primary backend failed, falling back to secondary");
+        let retried = self
+            .secondary
+            .delete_many(failed.iter().map(|&i| results[i].0.clone()).collect())
+            .await;
+        for (i, (_, result)) in failed.into_iter().zip(retried) {
+            results[i].1 = result;
+        }
+        FAILOVER_OPERATIONS.with_label_values(&["delete_many", "secondary"]).inc();
+        access_log::record_served_by("secondary");
+        results
+    }
+
+    fn object_store(&self) -> &dyn ObjectStore {
+        self.primary.object_store()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::StorageErrorClass;
+    use crate::storage::{MockBackend, MockOperation, MockResponse};
+
+    fn unavailable() -> MockResponse {
+        MockResponse::Error(object_store::Error::Generic {
+            store: "mock",
+            source: "error sending request: connection reset by peer".into(),
+        })
+    }
+
+    async fn backends(failover_writes: bool) -> (Arc<MockBackend>, Arc<MockBackend>, FailoverBackend) {
+        let primary = Arc::new(MockBackend::new());
+        let secondary = Arc::new(MockBackend::new());
+        primary.put("a", Bytes::from("primary")).await.unwrap();
+        secondary.put("a", Bytes::from("secondary")).await.unwrap();
+        let backend = FailoverBackend::new(primary.clone(), secondary.clone(), failover_writes);
+        (primary, secondary, backend)
+    }
+
+    #[tokio::test]
+    async fn test_reads_fall_back_on_transient_errors() {
+        let (primary, _, backend) = backends(false).await;
+        let served = |by| FAILOVER_OPERATIONS.with_label_values(&["get", by]).get();
+        let (by_primary, by_secondary) = (served("primary"), served("secondary"));
+
+        assert_eq!(backend.get("a").await.unwrap(), Bytes::from("primary"));
+        primary.push(MockOperation::Get, unavailable());
+        assert_eq!(backend.get("a").await.unwrap(), Bytes::from("secondary"));
+
+        assert!(served("primary") > by_primary);
+        assert!(served("secondary") > by_secondary);
+    }
+
+    #[tokio::test]
+    async fn test_not_found_is_not_failed_over() {
+        let (_, secondary, backend) = backends(false).await;
+        secondary.put("only-secondary", Bytes::from("data")).await.unwrap();
+
+        assert!(matches!(
+            backend.get("only-secondary").await,
+            Err(e) if e.class() == StorageErrorClass::NotFound
+        ));
+        assert!(secondary.calls_of(MockOperation::Get).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_writes_fail_over_only_when_enabled() {
+        let (primary, secondary, backend) = backends(false).await;
+        primary.push(MockOperation::Put, unavailable());
+        assert!(backend.put("b", Bytes::from("data")).await.is_err());
+        // Only the write setting up the test
+        assert_eq!(secondary.calls_of(MockOperation::Put).len(), 1);
+
+        let (primary, secondary, backend) = backends(true).await;
+        primary.push(MockOperation::Put, unavailable());
+        backend.put("b", Bytes::from("data")).await.unwrap();
+        assert_eq!(secondary.get("b").await.unwrap(), Bytes::from("data"));
+    }
+}
@@ -10,23 +10,42 @@
 //! - GOOGLE_APPLICATION_CREDENTIALS environment variable
 //! - GCE metadata server
 //! - User credentials
+//!
+//! A custom endpoint, such as fake-gcs-server, is reached with the service
+//! account credentials if any, and unauthenticated otherwise.
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use futures::stream::StreamExt;
-use object_store::gcp::{GoogleCloudStorage, GoogleCloudStorageBuilder};
+use futures::stream::BoxStream;
+use object_store::gcp::{GoogleCloudStorage, GoogleCloudStorageBuilder, GoogleConfigKey};
 use object_store::path::Path;
-use object_store::{ObjectMeta, ObjectStore};
+use object_store::{
+    ClientConfigKey, ClientOptions, GetOptions, GetResult, ListResult, ObjectMeta, ObjectStore, PutResult,
+};
+use std::io;
 use std::sync::Arc;
+use tracing::warn;
 
 use crate::config::GcpConfig;
-use crate::storage::StorageBackend;
-use uuid::Uuid;
+use crate::errors::StorageError;
+use crate::storage::{
+    context, delete_each, list_ordered, put_multipart, retry_config, strip_list_prefix, strip_prefix,
+    ObjectAttributes, StorageBackend, DEFAULT_DELETE_CONCURRENCY, DEFAULT_PART_SIZE,
+};
+
+/// Backend label of errors
+const BACKEND: &str = "gcp";
+
+/// Key object_store accepts to send requests without credentials
+const UNAUTHENTICATED_KEY: &str =
+    r#"{"private_key": "", "private_key_id": "", "client_email": "", "disable_oauth": true}"#;
 
 /// Google Cloud Storage backend
 pub struct GcpBackend {
     store: Arc<GoogleCloudStorage>,
     prefix: Option<String>,
+    delete_concurrency: usize,
+    part_size: usize,
 }
 
 impl GcpBackend {
@@ -34,26 +53,35 @@ impl GcpBackend {
     ///
     /// Supports multiple authentication modes:
     /// 1. Managed identity (default): Uses Application Default Credentials (ADC)
-    /// 2. Service account file: Uses service_account_path or GOOGLE_APPLICATION_CREDENTIALS env var
-    /// 3. Service account key: Uses service_account_key (JSON string) via env var
+    /// 2. Service account file: Uses service_account_path
+    /// 3. Service account key: Uses service_account_key (JSON string)
+    ///
+    /// Explicit credentials are handed to the builder, so neither the process
+    /// environment nor the filesystem is touched and several backends with
+    /// different service accounts can coexist.
     pub async fn new(config: &GcpConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::new_with_client_options(config, ClientOptions::default()).await
+    }
+
+    /// Create a new GCP Cloud Storage backend whose HTTP client has
+    /// `client_options`
+    pub async fn new_with_client_options(
+        config: &GcpConfig,
+        client_options: ClientOptions,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut builder = GoogleCloudStorageBuilder::new()
+            .with_client_options(client_options)
+            .with_retry(retry_config(&config.retry))
+            .with_bucket_name(&config.bucket_name);
+
         // Configure authentication
-        if !config.use_managed_identity {
-            // Use explicit service account credentials
+        if let Some(endpoint) = &config.endpoint {
+            builder = builder.with_service_account_key(endpoint_key(config, endpoint)?);
+        } else if !config.use_managed_identity {
             if let Some(service_account_path) = &config.service_account_path {
-                // Set GOOGLE_APPLICATION_CREDENTIALS environment variable
-                // object_store's GCP builder reads from this env var
-                std::env::set_var("GOOGLE_APPLICATION_CREDENTIALS", service_account_path);
+                builder = builder.with_service_account_path(service_account_path);
             } else if let Some(service_account_key) = &config.service_account_key {
-                // For JSON key as string, write it to a temporary file
-                // and set GOOGLE_APPLICATION_CREDENTIALS to point to it
-                use std::io::Write;
-                let temp_dir = std::env::temp_dir();
-                let temp_file = temp_dir.join(format!("gcp-sa-key-{}.json", Uuid::new_v4()));
-                let mut file = std::fs::File::create(&temp_file)?;
-                file.write_all(service_account_key.as_bytes())?;
-                file.sync_all()?;
-                std::env::set_var("GOOGLE_APPLICATION_CREDENTIALS", temp_file.to_str().unwrap());
+                builder = builder.with_service_account_key(service_account_key.expose());
             } else {
                 return Err("GCP service account credentials (service_account_path or service_account_key) are required when use_managed_identity is false".into());
             }
@@ -61,15 +89,18 @@ impl GcpBackend {
         // If use_managed_identity is true, builder will use Application Default Credentials
         // (Workload Identity, GOOGLE_APPLICATION_CREDENTIALS, GCE metadata, etc.)
 
-        // Build the store
-        // The builder will use GOOGLE_APPLICATION_CREDENTIALS if set, or ADC if not
-        let builder = GoogleCloudStorageBuilder::new()
-            .with_bucket_name(&config.bucket_name);
+        // Allow HTTP (for emulators)
+        if config.allow_http {
+            builder = builder.with_config(GoogleConfigKey::Client(ClientConfigKey::AllowHttp), "true");
+        }
+
         let store = Arc::new(builder.build()?);
 
         Ok(Self {
             store,
             prefix: None, // Prefix is applied at Config level
+            delete_concurrency: DEFAULT_DELETE_CONCURRENCY,
+            part_size: DEFAULT_PART_SIZE,
         })
     }
 
@@ -88,44 +119,133 @@ impl GcpBackend {
         self.prefix = prefix;
         self
     }
+
+    /// Set the requests in flight at once in `delete_many`
+    pub fn with_delete_concurrency(mut self, concurrency: usize) -> Self {
+        self.delete_concurrency = concurrency;
+        self
+    }
+
+    /// Set the size of the parts `put_stream` uploads
+    pub fn with_part_size(mut self, part_size: usize) -> Self {
+        self.part_size = part_size;
+        self
+    }
+}
+
+/// Service account key directing requests to `endpoint`, the only way
+/// object_store takes a custom GCS URL: the configured service account's
+/// key, or without one a key sending no credentials, as emulators expect
+fn endpoint_key(config: &GcpConfig, endpoint: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let key = match (&config.service_account_path, &config.service_account_key) {
+        _ if config.use_managed_identity => {
+            warn!(
+                endpoint,
+                "Managed identity is not used with a custom GCP endpoint, requests are sent unauthenticated"
+            );
+            None
+        }
+        (Some(path), _) => Some(
+            std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read GCP service account file {}: {}", path, e))?,
+        ),
+        (None, Some(key)) => Some(key.expose().to_string()),
+        (None, None) => None,
+    };
+    let mut key: serde_json::Map<String, serde_json::Value> = match key {
+        Some(key) => serde_json::from_str(&key).map_err(|e| format!("Invalid GCP service account key: {}", e))?,
+        None => serde_json::from_str(UNAUTHENTICATED_KEY)?,
+    };
+    key.insert("gcs_base_url".to_string(), endpoint.trim_end_matches('/').into());
+    Ok(serde_json::Value::Object(key).to_string())
 }
 
 #[async_trait]
 impl StorageBackend for GcpBackend {
-    async fn get(&self, path: &str) -> Result<Bytes, object_store::Error> {
-        let path = self.apply_prefix(path);
-        let data = self.store.get(&path).await?;
-        let bytes = data.bytes().await?;
-        Ok(bytes)
+    async fn get_opts(&self, path: &str, options: GetOptions) -> Result<GetResult, StorageError> {
+        let mut result = self
+            .store
+            .get_opts(&self.apply_prefix(path), options)
+            .await
+            .map_err(context(BACKEND, "get", path))?;
+        result.meta = strip_prefix(self.prefix.as_deref(), result.meta);
+        Ok(result)
     }
 
-    async fn put(&self, path: &str, data: Bytes) -> Result<(), object_store::Error> {
-        let path = self.apply_prefix(path);
-        self.store.put(&path, data.into()).await?;
-        Ok(())
+    async fn put_with_attributes(
+        &self,
+        path: &str,
+        data: Bytes,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        self.store
+            .put_opts(&self.apply_prefix(path), data.into(), attributes.put_options())
+            .await
+            .map_err(context(BACKEND, "put", path))
     }
 
-    async fn delete(&self, path: &str) -> Result<(), object_store::Error> {
-        let path = self.apply_prefix(path);
-        self.store.delete(&path).await?;
-        Ok(())
+    async fn put_stream(
+        &self,
+        path: &str,
+        stream: BoxStream<'static, Result<Bytes, io::Error>>,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        put_multipart(self.store.as_ref(), &self.apply_prefix(path), stream, attributes, self.part_size)
+            .await
+            .map_err(context(BACKEND, "put_stream", path))
     }
 
-    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>, object_store::Error> {
-        let prefix = self.apply_prefix(prefix);
-        let mut results = vec![];
-        let mut stream = self.store.list(Some(&prefix));
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        self.store
+            .delete(&self.apply_prefix(path))
+            .await
+            .map_err(context(BACKEND, "delete", path))
+    }
 
-        while let Some(meta) = stream.next().await {
-            results.push(meta?);
-        }
+    async fn list(
+        &self,
+        prefix: &str,
+        offset: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<ObjectMeta>, StorageError> {
+        let location = self.apply_prefix(prefix);
+        list_ordered(self.store.as_ref(), self.prefix.as_deref(), &location, offset, limit)
+            .await
+            .map_err(context(BACKEND, "list", prefix))
+    }
 
-        Ok(results)
+    async fn list_with_delimiter(&self, prefix: &str) -> Result<ListResult, StorageError> {
+        let result = self
+            .store
+            .list_with_delimiter(Some(&self.apply_prefix(prefix)))
+            .await
+            .map_err(context(BACKEND, "list_with_delimiter", prefix))?;
+        Ok(strip_list_prefix(self.prefix.as_deref(), result))
     }
 
-    async fn head(&self, path: &str) -> Result<ObjectMeta, object_store::Error> {
-        let path = self.apply_prefix(path);
-        self.store.head(&path).await
+    async fn head(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        self.store
+            .head(&self.apply_prefix(path))
+            .await
+            .map_err(context(BACKEND, "head", path))
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.store
+            .copy(&self.apply_prefix(from), &self.apply_prefix(to))
+            .await
+            .map_err(context(BACKEND, "copy", to))
+    }
+
+    async fn copy_if_not_exists(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.store
+            .copy_if_not_exists(&self.apply_prefix(from), &self.apply_prefix(to))
+            .await
+            .map_err(context(BACKEND, "copy_if_not_exists", to))
+    }
+
+    async fn delete_many(&self, paths: Vec<String>) -> Vec<(String, Result<(), StorageError>)> {
+        delete_each(paths, self.delete_concurrency, |path| async move { self.delete(&path).await }).await
     }
 
     #[allow(dead_code)] // Part of trait interface for extensibility
@@ -133,3 +253,103 @@ impl StorageBackend for GcpBackend {
         self.store.as_ref()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BackendRetryConfig;
+
+    /// Key object_store accepts without a private key to sign with
+    const KEY: &str = r#"{"private_key": "", "private_key_id": "", "client_email": "", "disable_oauth": true}"#;
+
+    fn key_files() -> Vec<std::path::PathBuf> {
+        std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.file_name().unwrap().to_string_lossy().starts_with("gcp-sa-key-"))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_service_account_key_stays_out_of_env_and_filesystem() {
+        let env_before = std::env::var_os("GOOGLE_APPLICATION_CREDENTIALS");
+        let files_before = key_files();
+
+        let config = GcpConfig {
+            bucket_name: "bucket".to_string(),
+            use_managed_identity: false,
+            service_account_path: None,
+            service_account_key: Some(KEY.into()),
+            endpoint: None,
+            allow_http: false,
+            retry: BackendRetryConfig::default(),
+        };
+        drop(GcpBackend::new(&config).await.unwrap());
+
+        assert_eq!(std::env::var_os("GOOGLE_APPLICATION_CREDENTIALS"), env_before);
+        assert_eq!(key_files(), files_before);
+    }
+
+    #[tokio::test]
+    async fn test_service_account_required_without_managed_identity() {
+        let config = GcpConfig {
+            bucket_name: "bucket".to_string(),
+            use_managed_identity: false,
+            service_account_path: None,
+            service_account_key: None,
+            endpoint: None,
+            allow_http: false,
+            retry: BackendRetryConfig::default(),
+        };
+        assert!(GcpBackend::new(&config).await.is_err());
+    }
+
+    #[test]
+    fn test_endpoint_added_to_service_account_key() {
+        let config = GcpConfig {
+            bucket_name: "bucket".to_string(),
+            use_managed_identity: false,
+            service_account_path: None,
+            service_account_key: Some(r#"{"client_email": "proxy@project.iam.gserviceaccount.com"}"#.into()),
+            endpoint: None,
+            allow_http: false,
+            retry: BackendRetryConfig::default(),
+        };
+        let key = endpoint_key(&config, "https://gcs.internal/").unwrap();
+        let key: serde_json::Value = serde_json::from_str(&key).unwrap();
+        assert_eq!(key["client_email"], "proxy@project.iam.gserviceaccount.com");
+        assert_eq!(key["gcs_base_url"], "https://gcs.internal");
+        assert!(key.get("disable_oauth").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_emulator_endpoint_needs_no_credentials() {
+        // Stand-in for a fake-gcs-server container
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let app = axum::Router::new().fallback({
+            let seen = seen.clone();
+            move |uri: axum::http::Uri| async move {
+                seen.lock().unwrap().push(uri.path().to_string());
+                axum::http::StatusCode::NOT_FOUND
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let config = GcpConfig {
+            bucket_name: "bucket".to_string(),
+            use_managed_identity: false,
+            service_account_path: None,
+            service_account_key: None,
+            endpoint: Some(format!("http://{address}")),
+            allow_http: true,
+            retry: BackendRetryConfig::default(),
+        };
+        let backend = GcpBackend::new(&config).await.unwrap();
+        let err = backend.head("key").await.unwrap_err();
+
+        assert_eq!(err.class(), crate::errors::StorageErrorClass::NotFound);
+        assert_eq!(*seen.lock().unwrap(), ["/bucket/key"]);
+    }
+}
@@ -0,0 +1,275 @@
+//! Local filesystem storage backend implementation
+//!
+//! Uses object_store::local::LocalFileSystem rooted at a directory. Only
+//! compiled for tests, where it provides a hermetic backend that still
+//! exercises real path handling (prefixes, traversal, directory layout).
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::{BoxStream, StreamExt};
+use object_store::local::LocalFileSystem;
+use object_store::path::Path;
+use object_store::{GetOptions, GetResult, ListResult, ObjectMeta, ObjectStore, PutResult};
+use std::io;
+use std::sync::Arc;
+
+use crate::errors::StorageError;
+use crate::storage::{
+    context, delete_each, offset_path, put_multipart, strip_list_prefix, strip_prefix, ObjectAttributes, StorageBackend,
+    DEFAULT_DELETE_CONCURRENCY, DEFAULT_PART_SIZE,
+};
+
+/// Backend label of errors
+const BACKEND: &str = "local";
+
+/// Local filesystem storage backend
+pub struct LocalBackend {
+    store: Arc<LocalFileSystem>,
+    prefix: Option<String>,
+    delete_concurrency: usize,
+    part_size: usize,
+}
+
+impl LocalBackend {
+    /// Create a new local filesystem backend rooted at `root`
+    pub fn new(root: &std::path::Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let store = Arc::new(LocalFileSystem::new_with_prefix(root)?);
+
+        Ok(Self {
+            store,
+            prefix: None,
+            delete_concurrency: DEFAULT_DELETE_CONCURRENCY,
+            part_size: DEFAULT_PART_SIZE,
+        })
+    }
+
+    /// Apply prefix to path if configured
+    fn apply_prefix(&self, path: &str) -> Path {
+        let full_path = if let Some(prefix) = &self.prefix {
+            format!("{}/{}", prefix.trim_end_matches('/'), path)
+        } else {
+            path.to_string()
+        };
+        Path::from(full_path)
+    }
+
+    /// Set the prefix for this backend
+    pub fn with_prefix(mut self, prefix: Option<String>) -> Self {
+        self.prefix = prefix;
+        self
+    }
+
+    /// Set the requests in flight at once in `delete_many`
+    pub fn with_delete_concurrency(mut self, concurrency: usize) -> Self {
+        self.delete_concurrency = concurrency;
+        self
+    }
+
+    /// Set the size of the parts `put_stream` uploads
+    pub fn with_part_size(mut self, part_size: usize) -> Self {
+        self.part_size = part_size;
+        self
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalBackend {
+    async fn get_opts(&self, path: &str, options: GetOptions) -> Result<GetResult, StorageError> {
+        let mut result = self
+            .store
+            .get_opts(&self.apply_prefix(path), options)
+            .await
+            .map_err(context(BACKEND, "get", path))?;
+        result.meta = strip_prefix(self.prefix.as_deref(), result.meta);
+        Ok(result)
+    }
+
+    async fn put_with_attributes(
+        &self,
+        path: &str,
+        data: Bytes,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        self.store
+            .put_opts(&self.apply_prefix(path), data.into(), attributes.put_options())
+            .await
+            .map_err(context(BACKEND, "put", path))
+    }
+
+    async fn put_stream(
+        &self,
+        path: &str,
+        stream: BoxStream<'static, Result<Bytes, io::Error>>,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        put_multipart(self.store.as_ref(), &self.apply_prefix(path), stream, attributes, self.part_size)
+            .await
+            .map_err(context(BACKEND, "put_stream", path))
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        self.store
+            .delete(&self.apply_prefix(path))
+            .await
+            .map_err(context(BACKEND, "delete", path))
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        offset: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<ObjectMeta>, StorageError> {
+        // The file system lists in directory order, so the whole prefix is
+        // listed and sorted before the page is taken
+        let error = context(BACKEND, "list", prefix);
+        let location = self.apply_prefix(prefix);
+        let offset = offset
+            .map(|offset| offset_path(self.prefix.as_deref(), offset))
+            .transpose()
+            .map_err(error)?;
+        let mut results = vec![];
+        let mut stream = self.store.list(Some(&location));
+
+        while let Some(meta) = stream.next().await {
+            let meta = meta.map_err(error)?;
+            if offset.as_ref().is_none_or(|offset| &meta.location > offset) {
+                results.push(meta);
+            }
+        }
+        results.sort_by(|a, b| a.location.cmp(&b.location));
+        results.truncate(limit.unwrap_or(usize::MAX));
+
+        Ok(results
+            .into_iter()
+            .map(|meta| strip_prefix(self.prefix.as_deref(), meta))
+            .collect())
+    }
+
+    async fn list_with_delimiter(&self, prefix: &str) -> Result<ListResult, StorageError> {
+        let result = self
+            .store
+            .list_with_delimiter(Some(&self.apply_prefix(prefix)))
+            .await
+            .map_err(context(BACKEND, "list_with_delimiter", prefix))?;
+        Ok(strip_list_prefix(self.prefix.as_deref(), result))
+    }
+
+    async fn head(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        self.store
+            .head(&self.apply_prefix(path))
+            .await
+            .map_err(context(BACKEND, "head", path))
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.store
+            .copy(&self.apply_prefix(from), &self.apply_prefix(to))
+            .await
+            .map_err(context(BACKEND, "copy", to))
+    }
+
+    async fn copy_if_not_exists(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.store
+            .copy_if_not_exists(&self.apply_prefix(from), &self.apply_prefix(to))
+            .await
+            .map_err(context(BACKEND, "copy_if_not_exists", to))
+    }
+
+    async fn delete_many(&self, paths: Vec<String>) -> Vec<(String, Result<(), StorageError>)> {
+        delete_each(paths, self.delete_concurrency, |path| async move { self.delete(&path).await }).await
+    }
+
+    fn object_store(&self) -> &dyn ObjectStore {
+        self.store.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::StorageErrorClass;
+
+    #[tokio::test]
+    async fn test_copy() {
+        let root = tempfile::tempdir().unwrap();
+        let backend = LocalBackend::new(root.path()).unwrap().with_prefix(Some("tenant".to_string()));
+        backend.put("dir/a", Bytes::from("data")).await.unwrap();
+
+        backend.copy("dir/a", "other/b").await.unwrap();
+        assert_eq!(backend.get("other/b").await.unwrap(), Bytes::from("data"));
+        assert_eq!(backend.head("other/b").await.unwrap().size, 4);
+        assert!(root.path().join("tenant/other/b").exists());
+
+        backend.put("c", Bytes::from("other")).await.unwrap();
+        assert!(matches!(
+            backend.copy_if_not_exists("dir/a", "c").await,
+            Err(e) if matches!(e.object_store_error(), object_store::Error::AlreadyExists { .. })
+        ));
+        assert_eq!(backend.get("c").await.unwrap(), Bytes::from("other"));
+        backend.copy_if_not_exists("dir/a", "d").await.unwrap();
+        assert_eq!(backend.get("d").await.unwrap(), Bytes::from("data"));
+    }
+
+    #[tokio::test]
+    async fn test_delete_many() {
+        let root = tempfile::tempdir().unwrap();
+        let backend = LocalBackend::new(root.path()).unwrap().with_delete_concurrency(2);
+        let paths: Vec<String> = (0..5).map(|i| format!("dir/{i}")).collect();
+        for path in &paths[..3] {
+            backend.put(path, Bytes::from("data")).await.unwrap();
+        }
+
+        let results = backend.delete_many(paths.clone()).await;
+        assert_eq!(results.iter().map(|(path, _)| path.clone()).collect::<Vec<_>>(), paths);
+        assert!(results[..3].iter().all(|(_, result)| result.is_ok()));
+        assert!(results[3..]
+            .iter()
+            .all(|(_, result)| matches!(result, Err(e) if e.class() == StorageErrorClass::NotFound)));
+        assert!(backend.list("dir", None, None).await.unwrap().is_empty());
+    }
+
+    /// Stream of `data` in chunks of `chunk` bytes
+    fn chunked(data: &[u8], chunk: usize) -> Vec<Result<Bytes, io::Error>> {
+        data.chunks(chunk).map(|c| Ok(Bytes::copy_from_slice(c))).collect()
+    }
+
+    #[tokio::test]
+    async fn test_put_stream() {
+        let root = tempfile::tempdir().unwrap();
+        let backend = LocalBackend::new(root.path())
+            .unwrap()
+            .with_prefix(Some("tenant".to_string()))
+            .with_part_size(1024);
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+
+        let stream = futures::stream::iter(chunked(&data, 700)).boxed();
+        backend
+            .put_stream("dir/a", stream, ObjectAttributes::default())
+            .await
+            .unwrap();
+        assert_eq!(backend.get("dir/a").await.unwrap(), Bytes::from(data));
+        assert!(root.path().join("tenant/dir/a").exists());
+    }
+
+    #[tokio::test]
+    async fn test_put_stream_aborted_on_stream_error() {
+        let root = tempfile::tempdir().unwrap();
+        let backend = LocalBackend::new(root.path()).unwrap().with_part_size(1024);
+        let mut chunks = chunked(&[7; 3000], 1000);
+        chunks.push(Err(io::Error::new(io::ErrorKind::ConnectionReset, "client went away")));
+
+        let stream = futures::stream::iter(chunks).boxed();
+        assert!(backend
+            .put_stream("dir/a", stream, ObjectAttributes::default())
+            .await
+            .is_err());
+        assert!(matches!(
+            backend.head("dir/a").await,
+            Err(e) if e.class() == StorageErrorClass::NotFound
+        ));
+        // No staged parts are left behind
+        let left: Vec<_> = std::fs::read_dir(root.path().join("dir")).unwrap().collect();
+        assert!(left.is_empty());
+    }
+}
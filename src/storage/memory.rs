@@ -0,0 +1,360 @@
+//! In-memory storage backend implementation
+//!
+//! Uses object_store::memory::InMemory, so the proxy can run hermetically
+//! without cloud credentials: in tests, demos and ephemeral deployments.
+//! Contents live in the process and are lost on restart. An optional size
+//! cap rejects writes that would grow the stored data beyond it, so an
+//! ephemeral deployment cannot exhaust the pod's memory.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::{BoxStream, TryStreamExt};
+use object_store::memory::InMemory;
+use object_store::path::Path;
+use object_store::{GetOptions, GetResult, ListResult, ObjectMeta, ObjectStore, PutResult};
+use std::io;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::config::MemoryConfig;
+use crate::errors::StorageError;
+use crate::storage::{
+    context, delete_each, list_ordered, strip_list_prefix, strip_prefix, ObjectAttributes, StorageBackend,
+    DEFAULT_DELETE_CONCURRENCY,
+};
+
+/// Backend label of errors
+const BACKEND: &str = "memory";
+
+/// In-memory storage backend
+pub struct MemoryBackend {
+    store: Arc<InMemory>,
+    prefix: Option<String>,
+    delete_concurrency: usize,
+    max_size_bytes: Option<u64>,
+    /// Total size of stored objects; writes and deletes hold the lock so
+    /// the accounting matches the store
+    used_bytes: Mutex<u64>,
+}
+
+impl MemoryBackend {
+    /// Create a new, empty in-memory backend
+    pub fn new(config: &MemoryConfig) -> Self {
+        Self {
+            store: Arc::new(InMemory::new()),
+            prefix: None,
+            delete_concurrency: DEFAULT_DELETE_CONCURRENCY,
+            max_size_bytes: config.max_size_bytes,
+            used_bytes: Mutex::new(0),
+        }
+    }
+
+    /// Apply prefix to path if configured
+    fn apply_prefix(&self, path: &str) -> Path {
+        let full_path = if let Some(prefix) = &self.prefix {
+            format!("{}/{}", prefix.trim_end_matches('/'), path)
+        } else {
+            path.to_string()
+        };
+        Path::from(full_path)
+    }
+
+    /// Set the prefix for this backend
+    pub fn with_prefix(mut self, prefix: Option<String>) -> Self {
+        self.prefix = prefix;
+        self
+    }
+
+    /// Set the requests in flight at once in `delete_many`
+    pub fn with_delete_concurrency(mut self, concurrency: usize) -> Self {
+        self.delete_concurrency = concurrency;
+        self
+    }
+
+    /// Fail a write of `len` bytes at `path` that would grow the stored
+    /// data to `size` bytes, beyond the limit
+    fn check_limit(&self, path: &Path, len: u64, size: u64) -> Result<(), object_store::Error> {
+        match self.max_size_bytes {
+            Some(max) if size > max => Err(object_store::Error::Generic {
+                store: "Memory",
+                source: format!(
+                    "Storing {} bytes at {} would exceed the in-memory backend limit of {} bytes",
+                    len, path, max
+                )
+                .into(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Size of an existing object, or 0 when there is none
+    async fn existing_size(&self, path: &Path) -> Result<u64, object_store::Error> {
+        match self.store.head(path).await {
+            Ok(meta) => Ok(meta.size as u64),
+            Err(object_store::Error::NotFound { .. }) => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MemoryBackend {
+    async fn get_opts(&self, path: &str, options: GetOptions) -> Result<GetResult, StorageError> {
+        let mut result = self
+            .store
+            .get_opts(&self.apply_prefix(path), options)
+            .await
+            .map_err(context(BACKEND, "get", path))?;
+        result.meta = strip_prefix(self.prefix.as_deref(), result.meta);
+        Ok(result)
+    }
+
+    async fn put_with_attributes(
+        &self,
+        path: &str,
+        data: Bytes,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        let error = context(BACKEND, "put", path);
+        let path = self.apply_prefix(path);
+        let mut used = self.used_bytes.lock().await;
+        let replaced = self.existing_size(&path).await.map_err(error)?;
+        let size = *used - replaced + data.len() as u64;
+        self.check_limit(&path, data.len() as u64, size).map_err(error)?;
+        let result = self
+            .store
+            .put_opts(&path, data.into(), attributes.put_options())
+            .await
+            .map_err(error)?;
+        *used = size;
+        Ok(result)
+    }
+
+    async fn put_stream(
+        &self,
+        path: &str,
+        stream: BoxStream<'static, Result<Bytes, io::Error>>,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        // Objects are held in memory anyway, and the size cap needs the length
+        let chunks: Vec<Bytes> = stream
+            .try_collect()
+            .await
+            .map_err(|e| object_store::Error::Generic {
+                store: "Memory",
+                source: Box::new(e),
+            })
+            .map_err(context(BACKEND, "put_stream", path))?;
+        self.put_with_attributes(path, chunks.concat().into(), attributes).await
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        let error = context(BACKEND, "delete", path);
+        let path = self.apply_prefix(path);
+        let mut used = self.used_bytes.lock().await;
+        let removed = self.existing_size(&path).await.map_err(error)?;
+        self.store.delete(&path).await.map_err(error)?;
+        *used -= removed;
+        Ok(())
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        offset: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<ObjectMeta>, StorageError> {
+        let location = self.apply_prefix(prefix);
+        list_ordered(self.store.as_ref(), self.prefix.as_deref(), &location, offset, limit)
+            .await
+            .map_err(context(BACKEND, "list", prefix))
+    }
+
+    async fn list_with_delimiter(&self, prefix: &str) -> Result<ListResult, StorageError> {
+        let result = self
+            .store
+            .list_with_delimiter(Some(&self.apply_prefix(prefix)))
+            .await
+            .map_err(context(BACKEND, "list_with_delimiter", prefix))?;
+        Ok(strip_list_prefix(self.prefix.as_deref(), result))
+    }
+
+    async fn head(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        self.store
+            .head(&self.apply_prefix(path))
+            .await
+            .map_err(context(BACKEND, "head", path))
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let error = context(BACKEND, "copy", to);
+        let (from, to) = (self.apply_prefix(from), self.apply_prefix(to));
+        let mut used = self.used_bytes.lock().await;
+        let len = self.store.head(&from).await.map_err(error)?.size as u64;
+        let size = *used - self.existing_size(&to).await.map_err(error)? + len;
+        self.check_limit(&to, len, size).map_err(error)?;
+        self.store.copy(&from, &to).await.map_err(error)?;
+        *used = size;
+        Ok(())
+    }
+
+    async fn copy_if_not_exists(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let error = context(BACKEND, "copy_if_not_exists", to);
+        let (from, to) = (self.apply_prefix(from), self.apply_prefix(to));
+        let mut used = self.used_bytes.lock().await;
+        let len = self.store.head(&from).await.map_err(error)?.size as u64;
+        let size = *used + len;
+        self.check_limit(&to, len, size).map_err(error)?;
+        self.store.copy_if_not_exists(&from, &to).await.map_err(error)?;
+        *used = size;
+        Ok(())
+    }
+
+    async fn delete_many(&self, paths: Vec<String>) -> Vec<(String, Result<(), StorageError>)> {
+        delete_each(paths, self.delete_concurrency, |path| async move { self.delete(&path).await }).await
+    }
+
+    fn object_store(&self) -> &dyn ObjectStore {
+        self.store.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::StorageErrorClass;
+    use object_store::{Attribute, GetRange};
+
+    #[tokio::test]
+    async fn test_round_trip_with_prefix() {
+        let backend = MemoryBackend::new(&MemoryConfig::default()).with_prefix(Some("tenant/".to_string()));
+
+        backend.put("a/b.txt", Bytes::from("data")).await.unwrap();
+        assert_eq!(backend.get("a/b.txt").await.unwrap(), Bytes::from("data"));
+        assert_eq!(backend.head("a/b.txt").await.unwrap().size, 4);
+
+        let listed = backend.list("a", None, None).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].location.as_ref(), "a/b.txt");
+
+        backend.put("a/c/d.txt", Bytes::from("data")).await.unwrap();
+        let listed = backend.list_with_delimiter("a").await.unwrap();
+        assert_eq!(listed.objects.len(), 1);
+        assert_eq!(listed.objects[0].location.as_ref(), "a/b.txt");
+        assert_eq!(listed.common_prefixes, vec![Path::from("a/c")]);
+
+        backend.delete("a/b.txt").await.unwrap();
+        assert!(matches!(
+            backend.get("a/b.txt").await,
+            Err(e) if e.class() == StorageErrorClass::NotFound
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_size_cap() {
+        let backend = MemoryBackend::new(&MemoryConfig {
+            max_size_bytes: Some(10),
+        });
+
+        backend.put("a", Bytes::from("123456")).await.unwrap();
+        assert!(backend.put("b", Bytes::from("12345")).await.is_err());
+        assert!(backend.head("b").await.is_err());
+
+        // Replacing an object only counts the difference
+        backend.put("a", Bytes::from("1234567890")).await.unwrap();
+
+        // Deleting frees its space
+        backend.delete("a").await.unwrap();
+        backend.put("b", Bytes::from("12345")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_copy() {
+        let backend = MemoryBackend::new(&MemoryConfig::default()).with_prefix(Some("tenant/".to_string()));
+        backend.put("a", Bytes::from("data")).await.unwrap();
+
+        backend.copy("a", "b").await.unwrap();
+        assert_eq!(backend.get("b").await.unwrap(), Bytes::from("data"));
+        assert_eq!(backend.head("b").await.unwrap().size, 4);
+        assert_eq!(backend.get("a").await.unwrap(), Bytes::from("data"));
+
+        backend.put("c", Bytes::from("other")).await.unwrap();
+        assert!(matches!(
+            backend.copy_if_not_exists("a", "c").await,
+            Err(e) if matches!(e.object_store_error(), object_store::Error::AlreadyExists { .. })
+        ));
+        assert_eq!(backend.get("c").await.unwrap(), Bytes::from("other"));
+        backend.copy_if_not_exists("a", "d").await.unwrap();
+        assert_eq!(backend.get("d").await.unwrap(), Bytes::from("data"));
+    }
+
+    #[tokio::test]
+    async fn test_copies_count_towards_size_cap() {
+        let backend = MemoryBackend::new(&MemoryConfig {
+            max_size_bytes: Some(10),
+        });
+        backend.put("a", Bytes::from("123456")).await.unwrap();
+
+        assert!(backend.copy("a", "b").await.is_err());
+        assert!(backend.copy_if_not_exists("a", "b").await.is_err());
+        assert!(backend.head("b").await.is_err());
+
+        // Copying over an object only counts the difference
+        backend.put("b", Bytes::from("1234")).await.unwrap();
+        backend.copy("b", "a").await.unwrap();
+        backend.put("c", Bytes::from("12")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_put_with_attributes() {
+        let backend = MemoryBackend::new(&MemoryConfig::default()).with_prefix(Some("tenant".to_string()));
+        let attributes = ObjectAttributes {
+            content_type: Some("text/csv".to_string()),
+            cache_control: Some("max-age=60".to_string()),
+            metadata: [("owner".to_string(), "etl".to_string())].into(),
+            ..ObjectAttributes::default()
+        };
+
+        let put = backend
+            .put_with_attributes("a.csv", Bytes::from("a,b"), attributes)
+            .await
+            .unwrap();
+        assert!(put.e_tag.is_some());
+
+        let stored = backend.object_store().get(&Path::from("tenant/a.csv")).await.unwrap();
+        assert_eq!(stored.meta.e_tag, put.e_tag);
+        let attributes = stored.attributes;
+        assert_eq!(attributes.get(&Attribute::ContentType).map(|v| v.as_ref()), Some("text/csv"));
+        assert_eq!(attributes.get(&Attribute::CacheControl).map(|v| v.as_ref()), Some("max-age=60"));
+        assert_eq!(
+            attributes.get(&Attribute::Metadata("owner".into())).map(|v| v.as_ref()),
+            Some("etl")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_opts() {
+        let backend = MemoryBackend::new(&MemoryConfig::default()).with_prefix(Some("tenant/".to_string()));
+        backend.put("a/b.txt", Bytes::from("0123456789")).await.unwrap();
+
+        let options = GetOptions {
+            range: Some(GetRange::Bounded(2..5)),
+            ..GetOptions::default()
+        };
+        let result = backend.get_opts("a/b.txt", options).await.unwrap();
+        assert_eq!(result.meta.location.as_ref(), "a/b.txt");
+        assert_eq!(result.meta.size, 10);
+        assert_eq!(result.range, 2..5);
+        let e_tag = result.meta.e_tag.clone();
+        assert_eq!(result.bytes().await.unwrap(), Bytes::from("234"));
+
+        let options = GetOptions {
+            if_none_match: e_tag,
+            ..GetOptions::default()
+        };
+        assert!(matches!(
+            backend.get_opts("a/b.txt", options).await,
+            Err(e) if matches!(e.object_store_error(), object_store::Error::NotModified { .. })
+        ));
+    }
+}
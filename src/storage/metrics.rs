@@ -0,0 +1,275 @@
+//! Metrics-recording storage backend decorator
+//!
+//! Wraps another backend and records `STORAGE_OPERATIONS` and
+//! `STORAGE_OPERATION_DURATION` for every call, separating backend latency
+//! from proxy overhead. Failed calls are also counted in `STORAGE_ERRORS`
+//! by error class. Each bucket in the registry gets its own wrapper so
+//! operations can be labeled with the bucket they serve.
+//!
+//! Every call also runs in a `storage` span carrying the bucket, key,
+//! operation, outcome and bytes transferred, which is exported as a child
+//! of the request span when OTLP export is enabled.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use object_store::{GetOptions, GetResult, ListResult, ObjectMeta, ObjectStore, PutResult};
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::field::Empty;
+use tracing::Instrument;
+
+use crate::errors::{StorageError, StorageErrorClass};
+use crate::metrics::{STORAGE_ERRORS, STORAGE_OPERATIONS, STORAGE_OPERATION_DURATION};
+use crate::storage::{ObjectAttributes, StorageBackend};
+
+/// Storage backend recording operation metrics for an inner backend
+pub struct MetricsBackend {
+    inner: Arc<dyn StorageBackend>,
+    backend: &'static str,
+    bucket: String,
+}
+
+impl MetricsBackend {
+    /// Wrap `inner` of backend type `backend`, labeling its operations with
+    /// `bucket` (may be empty)
+    pub fn new(inner: Arc<dyn StorageBackend>, backend: &'static str, bucket: impl Into<String>) -> Self {
+        Self {
+            inner,
+            backend,
+            bucket: bucket.into(),
+        }
+    }
+
+    /// Run `future` on `key` and record its outcome, duration and the bytes
+    /// `bytes` reports for a successful result
+    async fn record<T>(
+        &self,
+        operation: &str,
+        key: &str,
+        bytes: impl FnOnce(&T) -> Option<u64>,
+        future: impl Future<Output = Result<T, StorageError>>,
+    ) -> Result<T, StorageError> {
+        let span = tracing::debug_span!(
+            "storage",
+            otel.name = %format_args!("storage.{}", operation),
+            otel.kind = "client",
+            otel.status_code = Empty,
+            s3.operation = operation,
+            s3.bucket = %self.bucket,
+            s3.key = key,
+            storage.backend = self.backend,
+            storage.status = Empty,
+            storage.bytes = Empty,
+        );
+        let start = Instant::now();
+        let result = future.instrument(span.clone()).await;
+        crate::access_log::record_backend_time(start.elapsed());
+        span.record("storage.status", outcome(&result));
+        match &result {
+            Ok(value) => {
+                if let Some(bytes) = bytes(value) {
+                    span.record("storage.bytes", bytes);
+                }
+            }
+            Err(_) => {
+                span.record("otel.status_code", "ERROR");
+            }
+        }
+        STORAGE_OPERATION_DURATION
+            .with_label_values(&[operation])
+            .observe(start.elapsed().as_secs_f64());
+        STORAGE_OPERATIONS
+            .with_label_values(&[operation, outcome(&result), &self.bucket])
+            .inc();
+        if let Err(e) = &result {
+            STORAGE_ERRORS
+                .with_label_values(&[self.backend, e.class().as_str()])
+                .inc();
+        }
+        result
+    }
+}
+
+/// Outcome label for an operation result
+fn outcome<T>(result: &Result<T, StorageError>) -> &'static str {
+    match result {
+        Ok(_) => "ok",
+        Err(e) if e.class() == StorageErrorClass::NotFound => "not_found",
+        Err(_) => "error",
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MetricsBackend {
+    async fn get_opts(&self, path: &str, options: GetOptions) -> Result<GetResult, StorageError> {
+        let bytes = |result: &GetResult| Some((result.range.end - result.range.start) as u64);
+        self.record("get", path, bytes, self.inner.get_opts(path, options)).await
+    }
+
+    async fn put_with_attributes(
+        &self,
+        path: &str,
+        data: Bytes,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        let len = data.len() as u64;
+        let put = self.inner.put_with_attributes(path, data, attributes);
+        self.record("put", path, |_| Some(len), put).await
+    }
+
+    async fn put_stream(
+        &self,
+        path: &str,
+        stream: BoxStream<'static, Result<Bytes, io::Error>>,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        let len = Arc::new(AtomicU64::new(0));
+        let counted = len.clone();
+        let stream = stream
+            .inspect_ok(move |chunk| {
+                counted.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            })
+            .boxed();
+        let put = self.inner.put_stream(path, stream, attributes);
+        self.record("put_stream", path, |_| Some(len.load(Ordering::Relaxed)), put)
+            .await
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        self.record("delete", path, |_| None, self.inner.delete(path)).await
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        offset: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<ObjectMeta>, StorageError> {
+        self.record("list", prefix, |_| None, self.inner.list(prefix, offset, limit))
+            .await
+    }
+
+    async fn list_with_delimiter(&self, prefix: &str) -> Result<ListResult, StorageError> {
+        self.record("list_with_delimiter", prefix, |_| None, self.inner.list_with_delimiter(prefix))
+            .await
+    }
+
+    async fn head(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        self.record("head", path, |meta| Some(meta.size as u64), self.inner.head(path)).await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.record("copy", to, |_| None, self.inner.copy(from, to)).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.record("copy_if_not_exists", to, |_| None, self.inner.copy_if_not_exists(from, to))
+            .await
+    }
+
+    async fn delete_many(&self, paths: Vec<String>) -> Vec<(String, Result<(), StorageError>)> {
+        // One operation for the batch; each key failing counts as an error
+        let results = self
+            .record("delete_many", "", |_| None, async {
+                Ok(self.inner.delete_many(paths).await)
+            })
+            .await
+            .unwrap_or_default();
+        for (_, result) in &results {
+            if let Err(e) = result {
+                STORAGE_ERRORS
+                    .with_label_values(&[self.backend, e.class().as_str()])
+                    .inc();
+            }
+        }
+        results
+    }
+
+    fn object_store(&self) -> &dyn ObjectStore {
+        self.inner.object_store()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::storage::{LocalBackend, MockBackend, MockCall, MockOperation, MockResponse};
+
+    fn failure() -> MockResponse {
+        MockResponse::Error(object_store::Error::Generic {
+            store: "failing",
+            source: "backend unavailable".into(),
+        })
+    }
+
+    fn count(operation: &str, outcome: &str) -> u64 {
+        STORAGE_OPERATIONS.with_label_values(&[operation, outcome, ""]).get()
+    }
+
+    fn observed(operation: &str) -> u64 {
+        STORAGE_OPERATION_DURATION
+            .with_label_values(&[operation])
+            .get_sample_count()
+    }
+
+    // Metrics are process-wide and other tests record concurrently, so the
+    // checks only look for growth
+    #[tokio::test]
+    async fn test_success_and_not_found_recorded() {
+        let root = tempfile::tempdir().unwrap();
+        let backend = MetricsBackend::new(Arc::new(LocalBackend::new(root.path()).unwrap()), "local", "");
+        let (puts, gets, missing, heads) = (count("put", "ok"), count("get", "ok"), count("head", "not_found"), observed("head"));
+
+        backend.put("a.txt", Bytes::from("data")).await.unwrap();
+        assert_eq!(backend.get("a.txt").await.unwrap(), Bytes::from("data"));
+        assert!(backend.head("missing.txt").await.is_err());
+
+        assert!(count("put", "ok") > puts);
+        assert!(count("get", "ok") > gets);
+        assert!(count("head", "not_found") > missing);
+        assert!(observed("head") > heads);
+    }
+
+    #[tokio::test]
+    async fn test_errors_recorded() {
+        let mock = Arc::new(MockBackend::new());
+        mock.push(MockOperation::List, failure());
+        mock.push(MockOperation::Delete, failure());
+        let backend = MetricsBackend::new(mock.clone(), "failing", "");
+        let (lists, deletes) = (count("list", "error"), count("delete", "error"));
+        let errors = || STORAGE_ERRORS.with_label_values(&["failing", "other"]).get();
+        let before = errors();
+
+        assert!(backend.list("", None, None).await.is_err());
+        assert!(backend.delete("a.txt").await.is_err());
+
+        assert!(count("list", "error") > lists);
+        assert!(count("delete", "error") > deletes);
+        assert_eq!(errors(), before + 2);
+        assert_eq!(
+            mock.calls_of(MockOperation::Delete),
+            vec![MockCall {
+                operation: MockOperation::Delete,
+                path: "a.txt".to_string(),
+                size: None,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bucket_label() {
+        let mock = Arc::new(MockBackend::new());
+        mock.push(MockOperation::Get, failure());
+        let backend = MetricsBackend::new(mock, "failing", "photos");
+        let labeled = || STORAGE_OPERATIONS.with_label_values(&["get", "error", "photos"]).get();
+        let before = labeled();
+
+        assert!(backend.get("a.txt").await.is_err());
+        assert_eq!(labeled(), before + 1);
+    }
+}
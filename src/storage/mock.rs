@@ -0,0 +1,416 @@
+//! Scriptable storage backend for tests
+//!
+//! Available to this crate's tests and, with the `test-util` feature, to
+//! code embedding the proxy. Unscripted calls behave like an in-memory
+//! backend, so a test only scripts the calls it cares about:
+//!
+//! - queue the outcome of the next call of an operation with
+//!   [`MockBackend::push`], either a value or an `object_store::Error`
+//! - delay every call, or every call of one operation, with
+//!   [`MockBackend::set_latency`] and [`MockBackend::set_operation_latency`]
+//! - inspect the calls made, in order, with [`MockBackend::calls`]
+//!
+//! ```
+//! use bytes::Bytes;
+//! use s3proxy_rs::storage::{MockBackend, MockOperation, MockResponse, StorageBackend};
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let mock = MockBackend::new();
+//! mock.push(
+//!     MockOperation::Get,
+//!     MockResponse::Error(object_store::Error::Generic {
+//!         store: "mock",
+//!         source: "Server returned non-2xx status code: 503 Slow Down".into(),
+//!     }),
+//! );
+//!
+//! // The first get fails as scripted, the next one reads what was stored
+//! mock.put("a.txt", Bytes::from("data")).await.unwrap();
+//! assert!(mock.get("a.txt").await.is_err());
+//! assert_eq!(mock.get("a.txt").await.unwrap(), Bytes::from("data"));
+//!
+//! let calls = mock.calls();
+//! assert_eq!(calls.len(), 3);
+//! assert_eq!(calls[0].size, Some(4));
+//! # }
+//! ```
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use object_store::memory::InMemory;
+use object_store::path::Path;
+use object_store::{
+    Attributes, GetOptions, GetResult, GetResultPayload, ListResult, ObjectMeta, ObjectStore, PutResult,
+};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::errors::StorageError;
+use crate::storage::{ObjectAttributes, StorageBackend};
+
+/// Storage operation a response is scripted for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MockOperation {
+    Get,
+    Put,
+    PutStream,
+    Delete,
+    List,
+    ListWithDelimiter,
+    Head,
+    Copy,
+    CopyIfNotExists,
+}
+
+/// Scripted outcome of one call
+///
+/// The value must suit the operation: `Bytes` for get, `Meta` for head,
+/// `List` for list, `ListResult` for list_with_delimiter and `Unit` for
+/// the puts, delete and the copies. Errors suit any operation.
+#[derive(Debug)]
+pub enum MockResponse {
+    Bytes(Bytes),
+    Meta(ObjectMeta),
+    List(Vec<ObjectMeta>),
+    ListResult(ListResult),
+    Unit,
+    Error(object_store::Error),
+}
+
+/// A call made to the mock
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockCall {
+    pub operation: MockOperation,
+    /// Object path, the destination of a copy, or the prefix for list
+    pub path: String,
+    /// Payload size for put, or the limit of a list
+    pub size: Option<usize>,
+}
+
+#[derive(Default)]
+struct State {
+    responses: HashMap<MockOperation, VecDeque<MockResponse>>,
+    latency: Duration,
+    operation_latency: HashMap<MockOperation, Duration>,
+    calls: Vec<MockCall>,
+    /// Calls currently waiting out their latency, and the most seen at once
+    active: usize,
+    peak: usize,
+}
+
+/// Storage backend with scriptable responses, latency and call recording
+#[derive(Default)]
+pub struct MockBackend {
+    store: InMemory,
+    state: Mutex<State>,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue the outcome of the next unanswered call of `operation`
+    pub fn push(&self, operation: MockOperation, response: MockResponse) {
+        self.state()
+            .responses
+            .entry(operation)
+            .or_default()
+            .push_back(response);
+    }
+
+    /// Delay every call by `latency`
+    pub fn set_latency(&self, latency: Duration) {
+        self.state().latency = latency;
+    }
+
+    /// Delay calls of `operation` by `latency`, instead of the common latency
+    pub fn set_operation_latency(&self, operation: MockOperation, latency: Duration) {
+        self.state().operation_latency.insert(operation, latency);
+    }
+
+    /// Calls made so far, in order
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.state().calls.clone()
+    }
+
+    /// Calls of `operation` made so far, in order
+    pub fn calls_of(&self, operation: MockOperation) -> Vec<MockCall> {
+        self.calls()
+            .into_iter()
+            .filter(|call| call.operation == operation)
+            .collect()
+    }
+
+    /// Most calls in progress at once so far
+    pub fn peak_concurrency(&self) -> usize {
+        self.state().peak
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record the call, wait out its latency and take its scripted
+    /// response, if any
+    async fn call(&self, operation: MockOperation, path: &str, size: Option<usize>) -> Option<MockResponse> {
+        let (latency, response) = {
+            let mut state = self.state();
+            state.calls.push(MockCall {
+                operation,
+                path: path.to_string(),
+                size,
+            });
+            let latency = state
+                .operation_latency
+                .get(&operation)
+                .copied()
+                .unwrap_or(state.latency);
+            let response = state
+                .responses
+                .get_mut(&operation)
+                .and_then(VecDeque::pop_front);
+            state.active += 1;
+            state.peak = state.peak.max(state.active);
+            (latency, response)
+        };
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+        self.state().active -= 1;
+        response
+    }
+}
+
+fn mismatch(operation: MockOperation, response: MockResponse) -> ! {
+    panic!("Scripted response {:?} does not suit {:?}", response, operation)
+}
+
+#[async_trait]
+impl StorageBackend for MockBackend {
+    async fn get_opts(&self, path: &str, options: GetOptions) -> Result<GetResult, StorageError> {
+        match self.call(MockOperation::Get, path, None).await {
+            None => self.store.get_opts(&Path::from(path), options).await.map_err(Into::into),
+            Some(MockResponse::Bytes(data)) => Ok(GetResult {
+                range: 0..data.len(),
+                meta: ObjectMeta {
+                    location: Path::from(path),
+                    last_modified: chrono::Utc::now(),
+                    size: data.len(),
+                    e_tag: None,
+                    version: None,
+                },
+                attributes: Attributes::new(),
+                payload: GetResultPayload::Stream(stream::once(async move { Ok(data) }).boxed()),
+            }),
+            Some(MockResponse::Error(e)) => Err(e.into()),
+            Some(other) => mismatch(MockOperation::Get, other),
+        }
+    }
+
+    async fn put_with_attributes(
+        &self,
+        path: &str,
+        data: Bytes,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        match self.call(MockOperation::Put, path, Some(data.len())).await {
+            None => {
+                self.store
+                    .put_opts(&Path::from(path), data.into(), attributes.put_options())
+                    .await
+                    .map_err(Into::into)
+            }
+            Some(MockResponse::Unit) => Ok(PutResult {
+                e_tag: None,
+                version: None,
+            }),
+            Some(MockResponse::Error(e)) => Err(e.into()),
+            Some(other) => mismatch(MockOperation::Put, other),
+        }
+    }
+
+    async fn put_stream(
+        &self,
+        path: &str,
+        stream: BoxStream<'static, Result<Bytes, io::Error>>,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        let chunks: Vec<Bytes> = stream.try_collect().await.map_err(|e| object_store::Error::Generic {
+            store: "Mock",
+            source: Box::new(e),
+        })?;
+        let data = Bytes::from(chunks.concat());
+        match self.call(MockOperation::PutStream, path, Some(data.len())).await {
+            None => {
+                self.store
+                    .put_opts(&Path::from(path), data.into(), attributes.put_options())
+                    .await
+                    .map_err(Into::into)
+            }
+            Some(MockResponse::Unit) => Ok(PutResult {
+                e_tag: None,
+                version: None,
+            }),
+            Some(MockResponse::Error(e)) => Err(e.into()),
+            Some(other) => mismatch(MockOperation::PutStream, other),
+        }
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        match self.call(MockOperation::Delete, path, None).await {
+            None => self.store.delete(&Path::from(path)).await.map_err(Into::into),
+            Some(MockResponse::Unit) => Ok(()),
+            Some(MockResponse::Error(e)) => Err(e.into()),
+            Some(other) => mismatch(MockOperation::Delete, other),
+        }
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        offset: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<ObjectMeta>, StorageError> {
+        match self.call(MockOperation::List, prefix, limit).await {
+            None => {
+                let prefix = Path::from(prefix);
+                let stream = match offset {
+                    Some(offset) => {
+                        let offset = Path::parse(offset).map_err(object_store::Error::from)?;
+                        self.store.list_with_offset(Some(&prefix), &offset)
+                    }
+                    None => self.store.list(Some(&prefix)),
+                };
+                Ok(stream.take(limit.unwrap_or(usize::MAX)).try_collect().await?)
+            }
+            Some(MockResponse::List(objects)) => Ok(objects),
+            Some(MockResponse::Error(e)) => Err(e.into()),
+            Some(other) => mismatch(MockOperation::List, other),
+        }
+    }
+
+    async fn list_with_delimiter(&self, prefix: &str) -> Result<ListResult, StorageError> {
+        match self.call(MockOperation::ListWithDelimiter, prefix, None).await {
+            None => self.store.list_with_delimiter(Some(&Path::from(prefix))).await.map_err(Into::into),
+            Some(MockResponse::ListResult(result)) => Ok(result),
+            Some(MockResponse::Error(e)) => Err(e.into()),
+            Some(other) => mismatch(MockOperation::ListWithDelimiter, other),
+        }
+    }
+
+    async fn head(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        match self.call(MockOperation::Head, path, None).await {
+            None => self.store.head(&Path::from(path)).await.map_err(Into::into),
+            Some(MockResponse::Meta(meta)) => Ok(meta),
+            Some(MockResponse::Error(e)) => Err(e.into()),
+            Some(other) => mismatch(MockOperation::Head, other),
+        }
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        match self.call(MockOperation::Copy, to, None).await {
+            None => self.store.copy(&Path::from(from), &Path::from(to)).await.map_err(Into::into),
+            Some(MockResponse::Unit) => Ok(()),
+            Some(MockResponse::Error(e)) => Err(e.into()),
+            Some(other) => mismatch(MockOperation::Copy, other),
+        }
+    }
+
+    async fn copy_if_not_exists(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        match self.call(MockOperation::CopyIfNotExists, to, None).await {
+            None => self.store.copy_if_not_exists(&Path::from(from), &Path::from(to)).await.map_err(Into::into),
+            Some(MockResponse::Unit) => Ok(()),
+            Some(MockResponse::Error(e)) => Err(e.into()),
+            Some(other) => mismatch(MockOperation::CopyIfNotExists, other),
+        }
+    }
+
+    async fn delete_many(&self, paths: Vec<String>) -> Vec<(String, Result<(), StorageError>)> {
+        // One scriptable delete per key
+        let mut results = vec![];
+        for path in paths {
+            let result = self.delete(&path).await;
+            results.push((path, result));
+        }
+        results
+    }
+
+    fn object_store(&self) -> &dyn ObjectStore {
+        &self.store
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn test_scripted_responses_then_store() {
+        let mock = MockBackend::new();
+        mock.push(MockOperation::Get, MockResponse::Bytes(Bytes::from("scripted")));
+        mock.push(
+            MockOperation::Get,
+            MockResponse::Error(object_store::Error::NotImplemented),
+        );
+
+        mock.put("a", Bytes::from("stored")).await.unwrap();
+        assert_eq!(mock.get("a").await.unwrap(), Bytes::from("scripted"));
+        assert!(matches!(
+            mock.get("a").await,
+            Err(e) if matches!(e.object_store_error(), object_store::Error::NotImplemented)
+        ));
+        assert_eq!(mock.get("a").await.unwrap(), Bytes::from("stored"));
+
+        assert_eq!(
+            mock.calls(),
+            vec![
+                MockCall { operation: MockOperation::Put, path: "a".to_string(), size: Some(6) },
+                MockCall { operation: MockOperation::Get, path: "a".to_string(), size: None },
+                MockCall { operation: MockOperation::Get, path: "a".to_string(), size: None },
+                MockCall { operation: MockOperation::Get, path: "a".to_string(), size: None },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scripted_values() {
+        let mock = MockBackend::new();
+        let meta = ObjectMeta {
+            location: Path::from("a"),
+            last_modified: chrono::Utc::now(),
+            size: 3,
+            e_tag: None,
+            version: None,
+        };
+        mock.push(MockOperation::Head, MockResponse::Meta(meta.clone()));
+        mock.push(MockOperation::List, MockResponse::List(vec![meta.clone()]));
+        mock.push(MockOperation::Delete, MockResponse::Unit);
+
+        assert_eq!(mock.head("a").await.unwrap(), meta);
+        assert_eq!(mock.list("", None, None).await.unwrap(), vec![meta]);
+        mock.delete("a").await.unwrap();
+        assert!(mock.head("a").await.is_err());
+        assert_eq!(mock.calls_of(MockOperation::Head).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_latency() {
+        let mock = MockBackend::new();
+        mock.set_latency(Duration::from_millis(50));
+        mock.set_operation_latency(MockOperation::List, Duration::ZERO);
+
+        let start = Instant::now();
+        assert!(mock.head("missing").await.is_err());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let start = Instant::now();
+        assert!(mock.list("", None, None).await.unwrap().is_empty());
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,126 @@
+//! Prefix-scoping storage backend decorator
+//!
+//! Wraps another backend and confines every operation to a key prefix within
+//! it. Used to serve bucket aliases, where several logical buckets share one
+//! backend container under different prefixes.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use object_store::path::Path;
+use object_store::{GetOptions, GetResult, ListResult, ObjectMeta, ObjectStore, PutResult};
+use std::io;
+use std::sync::Arc;
+
+use crate::errors::StorageError;
+use crate::storage::{strip_list_prefix, strip_prefix, ObjectAttributes, StorageBackend};
+
+/// Storage backend scoped to a key prefix of an inner backend
+pub struct PrefixedBackend {
+    inner: Arc<dyn StorageBackend>,
+    prefix: String,
+}
+
+impl PrefixedBackend {
+    /// Scope `inner` to `prefix` (leading and trailing slashes are ignored)
+    pub fn new(inner: Arc<dyn StorageBackend>, prefix: &str) -> Self {
+        Self {
+            inner,
+            prefix: prefix.trim_matches('/').to_string(),
+        }
+    }
+
+    /// Apply prefix to path
+    fn apply_prefix(&self, path: &str) -> String {
+        if self.prefix.is_empty() {
+            path.to_string()
+        } else {
+            format!("{}/{}", self.prefix, path)
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for PrefixedBackend {
+    async fn get_opts(&self, path: &str, options: GetOptions) -> Result<GetResult, StorageError> {
+        let mut result = self.inner.get_opts(&self.apply_prefix(path), options).await?;
+        result.meta = strip_prefix(Some(&self.prefix), result.meta);
+        Ok(result)
+    }
+
+    async fn put_with_attributes(
+        &self,
+        path: &str,
+        data: Bytes,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        self.inner
+            .put_with_attributes(&self.apply_prefix(path), data, attributes)
+            .await
+    }
+
+    async fn put_stream(
+        &self,
+        path: &str,
+        stream: BoxStream<'static, Result<Bytes, io::Error>>,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        self.inner
+            .put_stream(&self.apply_prefix(path), stream, attributes)
+            .await
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        self.inner.delete(&self.apply_prefix(path)).await
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        offset: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<ObjectMeta>, StorageError> {
+        // Offsets are encoded locations, so the prefix is encoded too
+        let offset = offset.map(|offset| {
+            if self.prefix.is_empty() {
+                offset.to_string()
+            } else {
+                format!("{}/{}", Path::from(self.prefix.as_str()), offset)
+            }
+        });
+        let results = self.inner.list(&self.apply_prefix(prefix), offset.as_deref(), limit).await?;
+        Ok(results
+            .into_iter()
+            .map(|meta| strip_prefix(Some(&self.prefix), meta))
+            .collect())
+    }
+
+    async fn list_with_delimiter(&self, prefix: &str) -> Result<ListResult, StorageError> {
+        let result = self.inner.list_with_delimiter(&self.apply_prefix(prefix)).await?;
+        Ok(strip_list_prefix(Some(&self.prefix), result))
+    }
+
+    async fn head(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        self.inner.head(&self.apply_prefix(path)).await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.inner.copy(&self.apply_prefix(from), &self.apply_prefix(to)).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.inner
+            .copy_if_not_exists(&self.apply_prefix(from), &self.apply_prefix(to))
+            .await
+    }
+
+    async fn delete_many(&self, paths: Vec<String>) -> Vec<(String, Result<(), StorageError>)> {
+        let prefixed = paths.iter().map(|path| self.apply_prefix(path)).collect();
+        let results = self.inner.delete_many(prefixed).await;
+        paths.into_iter().zip(results).map(|(path, (_, result))| (path, result)).collect()
+    }
+
+    fn object_store(&self) -> &dyn ObjectStore {
+        self.inner.object_store()
+    }
+}
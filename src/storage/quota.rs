@@ -0,0 +1,611 @@
+//! Storage quotas of key prefixes
+//!
+//! Each quota caps the total size, and optionally the number, of the
+//! objects below a key prefix of a bucket, so one team cannot fill a
+//! shared bucket. Usage is counted by listing the prefix at startup and
+//! every `quotas.rescan_interval_secs`, and kept up to date by the writes,
+//! copies and deletes passing through the proxy. A write that would take a
+//! prefix over its quota fails with 403 `QuotaExceeded`; writes that
+//! shrink usage always pass. Quotas are enforced once their prefix has
+//! been counted.
+//!
+//! Replacing an object counts the difference in size, so writes and
+//! deletes below a quota prefix HEAD the object first. Streamed uploads
+//! are counted as they arrive and aborted once over the quota. Writes that
+//! bypass the proxy are only counted by the next rescan.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::stream::{BoxStream, StreamExt};
+use object_store::{GetOptions, GetResult, ListResult, ObjectMeta, ObjectStore, PutResult};
+use prometheus::IntGauge;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::config::QuotasConfig;
+use crate::errors::{StorageError, StorageErrorClass};
+use crate::metrics::{QUOTA_LIMIT, QUOTA_USAGE};
+use crate::storage::{ObjectAttributes, StorageBackend};
+
+/// Store name of the error rejecting writes over a quota
+pub(crate) const QUOTA_STORE: &str = "Quota";
+
+/// Objects listed per request while counting usage
+const SCAN_PAGE_SIZE: usize = 1000;
+
+/// Quota of the objects below a key prefix of a bucket
+struct PrefixQuota {
+    /// None for the backend serving every bucket name
+    bucket: Option<String>,
+    /// Prefix without surrounding slashes, empty for the whole bucket
+    prefix: String,
+    max_bytes: u64,
+    max_objects: u64,
+    /// Usage gauges of bytes and objects
+    gauges: [IntGauge; 2],
+}
+
+impl PrefixQuota {
+    /// Whether the object at `key` counts towards the quota
+    fn covers(&self, key: &str) -> bool {
+        self.prefix.is_empty()
+            || key
+                .strip_prefix(self.prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
+    fn name(&self) -> String {
+        match &self.bucket {
+            Some(bucket) => format!("{}/{}", bucket, self.prefix),
+            None => self.prefix.clone(),
+        }
+    }
+}
+
+/// Usage of a quota, which may drift below zero until first counted
+#[derive(Debug, Clone, Copy, Default)]
+struct Usage {
+    bytes: i64,
+    objects: i64,
+    counted: Option<DateTime<Utc>>,
+}
+
+/// Quota with its usage, as reported by `GET /admin/quotas`
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaReport {
+    pub bucket: Option<String>,
+    pub prefix: String,
+    pub max_bytes: u64,
+    pub max_objects: u64,
+    pub used_bytes: u64,
+    pub used_objects: u64,
+    /// When usage was last counted by listing; quotas not yet counted are
+    /// not enforced
+    pub counted_at: Option<DateTime<Utc>>,
+}
+
+/// Storage quotas of key prefixes and their usage
+pub struct Quotas {
+    quotas: Vec<PrefixQuota>,
+    usage: Mutex<Vec<Usage>>,
+    /// Backends of the buckets with quotas, listed to count usage
+    backends: Mutex<HashMap<Option<String>, Arc<dyn StorageBackend>>>,
+}
+
+impl Quotas {
+    /// Quotas of the configuration, or None when there are none
+    pub fn from_config(config: &QuotasConfig) -> Option<Arc<Self>> {
+        if config.prefixes.is_empty() {
+            return None;
+        }
+        let quotas: Vec<_> = config
+            .prefixes
+            .iter()
+            .map(|quota| {
+                let bucket = quota.bucket.clone().unwrap_or_default();
+                let prefix = quota.prefix.trim_matches('/').to_string();
+                for (unit, max) in [("bytes", quota.max_bytes), ("objects", quota.max_objects)] {
+                    QUOTA_LIMIT.with_label_values(&[&bucket, &prefix, unit]).set(max as i64);
+                }
+                PrefixQuota {
+                    gauges: ["bytes", "objects"].map(|unit| QUOTA_USAGE.with_label_values(&[&bucket, &prefix, unit])),
+                    bucket: quota.bucket.clone(),
+                    prefix,
+                    max_bytes: quota.max_bytes,
+                    max_objects: quota.max_objects,
+                }
+            })
+            .collect();
+        Some(Arc::new(Self {
+            usage: Mutex::new(vec![Usage::default(); quotas.len()]),
+            quotas,
+            backends: Mutex::default(),
+        }))
+    }
+
+    /// Enforce the quotas of `bucket`, None being the backend serving every
+    /// bucket name, on its backend
+    pub fn wrap(self: &Arc<Self>, bucket: Option<&str>, backend: Arc<dyn StorageBackend>) -> Arc<dyn StorageBackend> {
+        let indices: Vec<usize> = (0..self.quotas.len())
+            .filter(|&i| self.quotas[i].bucket.as_deref() == bucket)
+            .collect();
+        if indices.is_empty() {
+            return backend;
+        }
+        let mut backends = self.backends.lock().unwrap_or_else(|e| e.into_inner());
+        backends.insert(bucket.map(str::to_string), backend.clone());
+        Arc::new(QuotaBackend {
+            inner: backend,
+            quotas: self.clone(),
+            indices,
+        })
+    }
+
+    /// Count the usage of every quota by listing its prefix, returning how
+    /// many could not be counted
+    pub async fn scan(&self) -> usize {
+        let backends = self.backends.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let mut failed = 0;
+        for (i, quota) in self.quotas.iter().enumerate() {
+            let Some(backend) = backends.get(&quota.bucket) else {
+                continue;
+            };
+            match count(backend.as_ref(), &quota.prefix).await {
+                Ok((bytes, objects)) => {
+                    let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+                    usage[i] = Usage {
+                        bytes,
+                        objects,
+                        counted: Some(Utc::now()),
+                    };
+                    self.update_gauges(i, &usage[i]);
+                }
+                Err(e) => {
+                    warn!(quota = %quota.name(), error = %e, "Failed to count quota usage");
+                    failed += 1;
+                }
+            }
+        }
+        failed
+    }
+
+    /// Count usage again every `interval`, for as long as the quotas are in
+    /// use; a zero interval never counts again
+    pub fn spawn_rescan(self: &Arc<Self>, interval: Duration) {
+        if interval.is_zero() {
+            return;
+        }
+        let quotas = Arc::downgrade(self);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let Some(quotas) = quotas.upgrade() else {
+                    return;
+                };
+                let failed = quotas.scan().await;
+                info!(quotas = quotas.quotas.len(), failed, "Counted quota usage");
+            }
+        });
+    }
+
+    /// Every quota with its usage
+    pub fn report(&self) -> Vec<QuotaReport> {
+        let usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        self.quotas
+            .iter()
+            .zip(usage.iter())
+            .map(|(quota, usage)| QuotaReport {
+                bucket: quota.bucket.clone(),
+                prefix: quota.prefix.clone(),
+                max_bytes: quota.max_bytes,
+                max_objects: quota.max_objects,
+                used_bytes: usage.bytes.max(0) as u64,
+                used_objects: usage.objects.max(0) as u64,
+                counted_at: usage.counted,
+            })
+            .collect()
+    }
+
+    /// Count `bytes` and `objects` more below the quotas covering `key`,
+    /// or describe the first quota they would exceed
+    fn reserve(&self, indices: &[usize], key: &str, bytes: i64, objects: i64) -> Result<(), String> {
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        let covering: Vec<usize> = indices.iter().copied().filter(|&i| self.quotas[i].covers(key)).collect();
+        for &i in &covering {
+            let (quota, used) = (&self.quotas[i], &usage[i]);
+            if used.counted.is_none() {
+                continue;
+            }
+            if bytes > 0 && quota.max_bytes > 0 && used.bytes + bytes > quota.max_bytes as i64 {
+                return Err(format!(
+                    "The quota of {} allows {} bytes, of which {} are used",
+                    quota.name(),
+                    quota.max_bytes,
+                    used.bytes.max(0)
+                ));
+            }
+            if objects > 0 && quota.max_objects > 0 && used.objects + objects > quota.max_objects as i64 {
+                return Err(format!(
+                    "The quota of {} allows {} objects, of which {} are used",
+                    quota.name(),
+                    quota.max_objects,
+                    used.objects.max(0)
+                ));
+            }
+        }
+        for i in covering {
+            usage[i].bytes += bytes;
+            usage[i].objects += objects;
+            self.update_gauges(i, &usage[i]);
+        }
+        Ok(())
+    }
+
+    /// Count `bytes` and `objects` more below the quotas covering `key`,
+    /// whatever their limits
+    fn adjust(&self, indices: &[usize], key: &str, bytes: i64, objects: i64) {
+        if bytes == 0 && objects == 0 {
+            return;
+        }
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        for &i in indices.iter().filter(|&&i| self.quotas[i].covers(key)) {
+            usage[i].bytes += bytes;
+            usage[i].objects += objects;
+            self.update_gauges(i, &usage[i]);
+        }
+    }
+
+    fn update_gauges(&self, i: usize, usage: &Usage) {
+        let [bytes, objects] = &self.quotas[i].gauges;
+        bytes.set(usage.bytes.max(0));
+        objects.set(usage.objects.max(0));
+    }
+}
+
+/// Total size and number of the objects below `prefix`
+async fn count(backend: &dyn StorageBackend, prefix: &str) -> Result<(i64, i64), StorageError> {
+    let (mut bytes, mut objects) = (0, 0);
+    let mut offset: Option<String> = None;
+    loop {
+        let page = backend.list(prefix, offset.as_deref(), Some(SCAN_PAGE_SIZE)).await?;
+        bytes += page.iter().map(|meta| meta.size as i64).sum::<i64>();
+        objects += page.len() as i64;
+        match page.last() {
+            Some(last) if page.len() == SCAN_PAGE_SIZE => offset = Some(last.location.to_string()),
+            _ => return Ok((bytes, objects)),
+        }
+    }
+}
+
+/// Error rejecting a write over a quota
+fn exceeded(operation: &'static str, path: &str, message: String) -> StorageError {
+    let source = io::Error::new(io::ErrorKind::PermissionDenied, message);
+    let error = object_store::Error::Generic {
+        store: QUOTA_STORE,
+        source: Box::new(source),
+    };
+    StorageError::from(error).with_context(QUOTA_STORE, operation, path)
+}
+
+/// Storage backend enforcing the quotas of a bucket on an inner backend
+pub struct QuotaBackend {
+    inner: Arc<dyn StorageBackend>,
+    quotas: Arc<Quotas>,
+    /// Quotas of the bucket
+    indices: Vec<usize>,
+}
+
+impl QuotaBackend {
+    fn covered(&self, path: &str) -> bool {
+        self.indices.iter().any(|&i| self.quotas.quotas[i].covers(path))
+    }
+
+    /// Size of the object at `path`, or None when there is none
+    async fn size(&self, path: &str) -> Result<Option<i64>, StorageError> {
+        match self.inner.head(path).await {
+            Ok(meta) => Ok(Some(meta.size as i64)),
+            Err(e) if e.class() == StorageErrorClass::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reserve what writing `size` bytes to `path` adds, returning the
+    /// bytes and objects reserved
+    async fn reserve_write(&self, operation: &'static str, path: &str, size: i64) -> Result<(i64, i64), StorageError> {
+        let replaced = self.size(path).await?;
+        let (bytes, objects) = (size - replaced.unwrap_or(0), i64::from(replaced.is_none()));
+        self.quotas
+            .reserve(&self.indices, path, bytes, objects)
+            .map_err(|message| exceeded(operation, path, message))?;
+        Ok((bytes, objects))
+    }
+
+    /// Write `to` as a copy of `from`, counting it
+    async fn counted_copy(&self, from: &str, to: &str, if_not_exists: bool) -> Result<(), StorageError> {
+        if !self.covered(to) {
+            return match if_not_exists {
+                true => self.inner.copy_if_not_exists(from, to).await,
+                false => self.inner.copy(from, to).await,
+            };
+        }
+        let size = self.inner.head(from).await?.size as i64;
+        let (bytes, objects) = self.reserve_write("copy", to, size).await?;
+        let result = match if_not_exists {
+            true => self.inner.copy_if_not_exists(from, to).await,
+            false => self.inner.copy(from, to).await,
+        };
+        if result.is_err() {
+            self.quotas.adjust(&self.indices, to, -bytes, -objects);
+        }
+        result
+    }
+}
+
+/// Bytes of a streamed upload counted so far
+#[derive(Default)]
+struct StreamTally {
+    /// Size of the object replaced, not yet offset by streamed bytes
+    credit: i64,
+    /// Bytes beyond the credit, reserved as they arrived
+    reserved: i64,
+    /// Why the upload was aborted, if it went over a quota
+    exceeded: Option<String>,
+}
+
+#[async_trait]
+impl StorageBackend for QuotaBackend {
+    async fn get_opts(&self, path: &str, options: GetOptions) -> Result<GetResult, StorageError> {
+        self.inner.get_opts(path, options).await
+    }
+
+    async fn put_with_attributes(
+        &self,
+        path: &str,
+        data: Bytes,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        if !self.covered(path) {
+            return self.inner.put_with_attributes(path, data, attributes).await;
+        }
+        let (bytes, objects) = self.reserve_write("put", path, data.len() as i64).await?;
+        let result = self.inner.put_with_attributes(path, data, attributes).await;
+        if result.is_err() {
+            self.quotas.adjust(&self.indices, path, -bytes, -objects);
+        }
+        result
+    }
+
+    async fn put_stream(
+        &self,
+        path: &str,
+        stream: BoxStream<'static, Result<Bytes, io::Error>>,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        if !self.covered(path) {
+            return self.inner.put_stream(path, stream, attributes).await;
+        }
+        let replaced = self.size(path).await?;
+        let objects = i64::from(replaced.is_none());
+        self.quotas
+            .reserve(&self.indices, path, 0, objects)
+            .map_err(|message| exceeded("put", path, message))?;
+        let tally = Arc::new(Mutex::new(StreamTally {
+            credit: replaced.unwrap_or(0),
+            ..Default::default()
+        }));
+        let counted = {
+            let (tally, quotas) = (tally.clone(), self.quotas.clone());
+            let (indices, key) = (self.indices.clone(), path.to_string());
+            stream.map(move |chunk| {
+                let chunk = chunk?;
+                let mut tally = tally.lock().unwrap_or_else(|e| e.into_inner());
+                let from_credit = (chunk.len() as i64).min(tally.credit);
+                tally.credit -= from_credit;
+                let extra = chunk.len() as i64 - from_credit;
+                if let Err(message) = quotas.reserve(&indices, &key, extra, 0) {
+                    tally.exceeded = Some(message.clone());
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, message));
+                }
+                tally.reserved += extra;
+                Ok(chunk)
+            })
+        };
+
+        let result = self.inner.put_stream(path, counted.boxed(), attributes).await;
+        let tally = std::mem::take(&mut *tally.lock().unwrap_or_else(|e| e.into_inner()));
+        match result {
+            // The bytes of the replaced object not offset are gone now
+            Ok(put) => {
+                self.quotas.adjust(&self.indices, path, -tally.credit, 0);
+                Ok(put)
+            }
+            Err(e) => {
+                self.quotas.adjust(&self.indices, path, -tally.reserved, -objects);
+                match tally.exceeded {
+                    Some(message) => Err(exceeded("put", path, message)),
+                    None => Err(e),
+                }
+            }
+        }
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        if !self.covered(path) {
+            return self.inner.delete(path).await;
+        }
+        let size = self.size(path).await?;
+        self.inner.delete(path).await?;
+        if let Some(size) = size {
+            self.quotas.adjust(&self.indices, path, -size, -1);
+        }
+        Ok(())
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        offset: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<ObjectMeta>, StorageError> {
+        self.inner.list(prefix, offset, limit).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: &str) -> Result<ListResult, StorageError> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn head(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        self.inner.head(path).await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.counted_copy(from, to, false).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.counted_copy(from, to, true).await
+    }
+
+    async fn delete_many(&self, paths: Vec<String>) -> Vec<(String, Result<(), StorageError>)> {
+        let mut sizes = HashMap::new();
+        for path in paths.iter().filter(|path| self.covered(path)) {
+            // Objects that cannot be sized are deleted uncounted, and
+            // counted again by the next rescan
+            if let Ok(Some(size)) = self.size(path).await {
+                sizes.insert(path.clone(), size);
+            }
+        }
+        let results = self.inner.delete_many(paths).await;
+        for (path, result) in &results {
+            if let (Ok(()), Some(size)) = (result, sizes.get(path)) {
+                self.quotas.adjust(&self.indices, path, -size, -1);
+            }
+        }
+        results
+    }
+
+    fn object_store(&self) -> &dyn ObjectStore {
+        self.inner.object_store()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::S3ProxyError;
+    use crate::storage::MemoryBackend;
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use futures::stream;
+
+    fn quotas(toml: &str) -> Arc<Quotas> {
+        Quotas::from_config(&toml::from_str(toml).unwrap()).unwrap()
+    }
+
+    fn usage(quotas: &Quotas, prefix: &str) -> (u64, u64) {
+        let report = quotas.report();
+        let quota = report.iter().find(|quota| quota.prefix == prefix).unwrap();
+        (quota.used_bytes, quota.used_objects)
+    }
+
+    async fn rejected(result: Result<(), StorageError>) -> String {
+        let response = S3ProxyError::Storage(result.unwrap_err()).into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_usage_counted_and_enforced() {
+        let inner: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new(&Default::default()));
+        inner.put("team-a/existing", Bytes::from(vec![0; 40])).await.unwrap();
+        inner.put("team-ab/other", Bytes::from(vec![0; 500])).await.unwrap();
+        let quotas = quotas("[[prefixes]]\nprefix = 'team-a/'\nmax_bytes = 100\nmax_objects = 3");
+        let backend = quotas.wrap(None, inner.clone());
+        assert_eq!(quotas.scan().await, 0);
+        // Keys sharing the prefix's leading characters are not below it
+        assert_eq!(usage(&quotas, "team-a"), (40, 1));
+
+        backend.put("team-a/new", Bytes::from(vec![0; 50])).await.unwrap();
+        assert_eq!(usage(&quotas, "team-a"), (90, 2));
+        let body = rejected(backend.put("team-a/big", Bytes::from(vec![0; 20])).await).await;
+        assert!(body.contains("<Code>QuotaExceeded</Code>"), "{}", body);
+        assert!(body.contains("allows 100 bytes, of which 90 are used"), "{}", body);
+        assert_eq!(usage(&quotas, "team-a"), (90, 2));
+
+        // Replacing counts the difference, and shrinking always passes
+        backend.put("team-a/new", Bytes::from(vec![0; 60])).await.unwrap();
+        assert_eq!(usage(&quotas, "team-a"), (100, 2));
+        backend.put("team-a/new", Bytes::from(vec![0; 10])).await.unwrap();
+        assert_eq!(usage(&quotas, "team-a"), (50, 2));
+
+        // Objects are counted too
+        backend.put("team-a/empty", Bytes::new()).await.unwrap();
+        let body = rejected(backend.put("team-a/one-more", Bytes::new()).await).await;
+        assert!(body.contains("allows 3 objects"), "{}", body);
+
+        // Deletes and copies update usage; other prefixes are not limited
+        backend.delete("team-a/existing").await.unwrap();
+        assert_eq!(usage(&quotas, "team-a"), (10, 2));
+        backend.copy("team-ab/other", "team-a/copied").await.unwrap_err();
+        backend.copy("team-a/new", "team-a/copied").await.unwrap();
+        assert_eq!(usage(&quotas, "team-a"), (20, 3));
+        backend.put("elsewhere", Bytes::from(vec![0; 1000])).await.unwrap();
+        let deleted = backend.delete_many(vec!["team-a/new".to_string(), "team-a/copied".to_string()]).await;
+        assert!(deleted.iter().all(|(_, result)| result.is_ok()));
+        assert_eq!(usage(&quotas, "team-a"), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn test_streamed_upload_aborted_over_quota() {
+        let inner: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new(&Default::default()));
+        let quotas = quotas("[[prefixes]]\nprefix = 'team'\nmax_bytes = 100");
+        let backend = quotas.wrap(None, inner.clone());
+        quotas.scan().await;
+        let body = |chunks: usize| -> BoxStream<'static, Result<Bytes, io::Error>> {
+            Box::pin(stream::iter((0..chunks).map(|_| Ok(Bytes::from(vec![0; 30])))))
+        };
+
+        backend.put_stream("team/a", body(2), ObjectAttributes::default()).await.unwrap();
+        assert_eq!(usage(&quotas, "team"), (60, 1));
+        let error = backend.put_stream("team/b", body(3), ObjectAttributes::default()).await;
+        let body_xml = rejected(error.map(|_| ())).await;
+        assert!(body_xml.contains("QuotaExceeded"), "{}", body_xml);
+        assert!(inner.head("team/b").await.is_err());
+        assert_eq!(usage(&quotas, "team"), (60, 1));
+
+        // Replacing a streamed object offsets its size
+        backend.put_stream("team/a", body(1), ObjectAttributes::default()).await.unwrap();
+        assert_eq!(usage(&quotas, "team"), (30, 1));
+    }
+
+    #[tokio::test]
+    async fn test_not_enforced_until_counted() {
+        let inner: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new(&Default::default()));
+        let quotas = quotas("[[prefixes]]\nprefix = ''\nmax_objects = 1");
+        let backend = quotas.wrap(None, inner.clone());
+
+        backend.put("a", Bytes::from("a")).await.unwrap();
+        backend.put("b", Bytes::from("b")).await.unwrap();
+        assert!(quotas.report()[0].counted_at.is_none());
+
+        // Counting catches up with writes that bypassed the proxy
+        inner.put("c", Bytes::from("c")).await.unwrap();
+        quotas.scan().await;
+        assert_eq!(usage(&quotas, ""), (3, 3));
+        assert!(backend.put("d", Bytes::from("d")).await.is_err());
+    }
+
+    #[test]
+    fn test_other_buckets_not_wrapped() {
+        let quotas = quotas("[[prefixes]]\nbucket = 'team'\nprefix = ''\nmax_bytes = 1");
+        let inner: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new(&Default::default()));
+        assert!(Arc::ptr_eq(&quotas.wrap(Some("other"), inner.clone()), &inner));
+        assert!(!Arc::ptr_eq(&quotas.wrap(Some("team"), inner.clone()), &inner));
+    }
+}
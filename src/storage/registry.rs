@@ -0,0 +1,114 @@
+//! Bucket registry
+//!
+//! Maps the bucket names S3 clients use to the storage backends serving
+//! them. In single-backend mode (no named buckets configured) every bucket
+//! name resolves to the default backend, matching the original behavior.
+
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::errors::S3ProxyError;
+use crate::storage::{Quotas, StorageBackend};
+
+/// Lookup structure resolving bucket names to storage backends
+pub struct BucketRegistry {
+    default: Option<Arc<dyn StorageBackend>>,
+    buckets: BTreeMap<String, Arc<dyn StorageBackend>>,
+    created: DateTime<Utc>,
+    quotas: Option<Arc<Quotas>>,
+}
+
+impl BucketRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self {
+            default: None,
+            buckets: BTreeMap::new(),
+            created: Utc::now(),
+            quotas: None,
+        }
+    }
+
+    /// Create a registry serving every bucket name from one backend
+    pub fn single(backend: Arc<dyn StorageBackend>) -> Self {
+        Self::new().with_default(backend)
+    }
+
+    /// Set the backend serving every bucket name
+    ///
+    /// Only consulted while no named buckets are registered.
+    pub fn with_default(mut self, backend: Arc<dyn StorageBackend>) -> Self {
+        self.default = Some(backend);
+        self
+    }
+
+    /// Set the storage quotas enforced by the backends
+    pub fn with_quotas(mut self, quotas: Arc<Quotas>) -> Self {
+        self.quotas = Some(quotas);
+        self
+    }
+
+    /// Register a named bucket, failing if the name is already taken
+    pub fn insert(
+        &mut self,
+        name: impl Into<String>,
+        backend: Arc<dyn StorageBackend>,
+    ) -> Result<(), String> {
+        let name = name.into();
+        if self.buckets.contains_key(&name) {
+            return Err(format!("Bucket '{}' is configured more than once", name));
+        }
+        self.buckets.insert(name, backend);
+        Ok(())
+    }
+
+    /// Resolve a bucket name to its backend
+    ///
+    /// Returns `NoSuchBucket` for names that are not served by this proxy.
+    pub fn resolve(&self, bucket: &str) -> Result<Arc<dyn StorageBackend>, S3ProxyError> {
+        let backend = if self.buckets.is_empty() {
+            self.default.as_ref()
+        } else {
+            self.buckets.get(bucket)
+        };
+
+        backend.cloned().ok_or_else(|| S3ProxyError::NoSuchBucket {
+            bucket: bucket.to_string(),
+        })
+    }
+
+    /// Names of the explicitly configured buckets, in sorted order
+    pub fn bucket_names(&self) -> impl Iterator<Item = &str> {
+        self.buckets.keys().map(String::as_str)
+    }
+
+    /// Every backend with the bucket name it serves
+    ///
+    /// In single-backend mode the default backend is listed as `*`.
+    pub fn backends(&self) -> Vec<(&str, Arc<dyn StorageBackend>)> {
+        if self.buckets.is_empty() {
+            return self.default.iter().map(|backend| ("*", backend.clone())).collect();
+        }
+        self.buckets
+            .iter()
+            .map(|(name, backend)| (name.as_str(), backend.clone()))
+            .collect()
+    }
+
+    /// Storage quotas enforced by the backends, if any are configured
+    pub fn quotas(&self) -> Option<&Arc<Quotas>> {
+        self.quotas.as_ref()
+    }
+
+    /// Time the registry was built, reported as the bucket creation date
+    pub fn created(&self) -> DateTime<Utc> {
+        self.created
+    }
+}
+
+impl Default for BucketRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
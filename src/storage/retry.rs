@@ -0,0 +1,347 @@
+//! Retrying storage backend decorator
+//!
+//! Wraps another backend and retries operations failing with a transient
+//! error: throttling, timeouts, and other failures reported by the backend
+//! client such as connection resets and 5xx responses. Missing objects,
+//! permission and precondition failures are returned at once. Every
+//! operation is safe to repeat; put bodies are buffered, so they can be
+//! sent again.
+//!
+//! Backoff doubles from `initial_backoff_ms` up to `max_backoff_ms`, with
+//! full jitter so clients failing together don't retry together. An
+//! operation stops retrying after `max_attempts`, or when the next attempt
+//! would start after `budget_ms` has elapsed, so retries end well before
+//! the request times out.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use object_store::{GetOptions, GetResult, ListResult, ObjectMeta, ObjectStore, PutResult};
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::config::RetryConfig;
+use crate::errors::{StorageError, StorageErrorClass};
+use crate::metrics::STORAGE_RETRIES;
+use crate::storage::{clone_get_options, CircuitOpen, ObjectAttributes, StorageBackend};
+
+/// Storage backend retrying transient failures of an inner backend
+pub struct RetryBackend {
+    inner: Arc<dyn StorageBackend>,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    budget: Duration,
+}
+
+impl RetryBackend {
+    pub fn new(inner: Arc<dyn StorageBackend>, config: &RetryConfig) -> Self {
+        Self {
+            inner,
+            max_attempts: config.max_attempts.max(1),
+            initial_backoff: Duration::from_millis(config.initial_backoff_ms),
+            max_backoff: Duration::from_millis(config.max_backoff_ms),
+            budget: Duration::from_millis(config.budget_ms),
+        }
+    }
+
+    /// Run the operation `attempt` creates until it succeeds, fails with a
+    /// permanent error or runs out of attempts or time
+    async fn retry<T, F, Fut>(&self, operation: &str, key: &str, mut attempt: F) -> Result<T, StorageError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, StorageError>>,
+    {
+        let start = Instant::now();
+        let mut attempts = 1;
+        loop {
+            let error = match attempt().await {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+            let Some(class) = transient(&error) else {
+                return Err(error);
+            };
+            if attempts >= self.max_attempts {
+                return Err(error);
+            }
+            let backoff = jitter(self.backoff(attempts));
+            if start.elapsed() + backoff > self.budget {
+                return Err(error);
+            }
+
+            attempts += 1;
+            debug!(
+                operation,
+                key,
+                attempt = attempts,
+                class = class.as_str(),
+                backoff_ms = backoff.as_millis() as u64,
+                error = %error,
+                "Retrying storage operation"
+            );
+            STORAGE_RETRIES.with_label_values(&[operation, class.as_str()]).inc();
+            tokio::time::sleep(backoff).await;
+        }
+    }
+
+    /// Backoff ceiling after `attempts` failed attempts
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts - 1);
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+/// Class of a transient error, worth retrying, or `None` for permanent ones
+pub(crate) fn transient(error: &StorageError) -> Option<StorageErrorClass> {
+    // Only backend client failures can be transient; other variants report
+    // invalid paths, configuration or unsupported operations
+    if !matches!(error.object_store_error(), object_store::Error::Generic { .. }) {
+        return None;
+    }
+    // The backend was not called, and won't be until the breaker lets it
+    if CircuitOpen::find(error).is_some() {
+        return None;
+    }
+    match error.class() {
+        class @ (StorageErrorClass::Throttled
+        | StorageErrorClass::Timeout
+        | StorageErrorClass::Proxy
+        | StorageErrorClass::Other) => Some(class),
+        StorageErrorClass::NotFound | StorageErrorClass::Permission | StorageErrorClass::Precondition => None,
+    }
+}
+
+/// Random duration between zero and `backoff`
+pub(crate) fn jitter(backoff: Duration) -> Duration {
+    // Top 53 random bits as a fraction in [0, 1)
+    let random = (Uuid::new_v4().as_u64_pair().0 >> 11) as f64 / (1u64 << 53) as f64;
+    backoff.mul_f64(random)
+}
+
+#[async_trait]
+impl StorageBackend for RetryBackend {
+    async fn get_opts(&self, path: &str, options: GetOptions) -> Result<GetResult, StorageError> {
+        self.retry("get", path, || self.inner.get_opts(path, clone_get_options(&options))).await
+    }
+
+    async fn put_with_attributes(
+        &self,
+        path: &str,
+        data: Bytes,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        self.retry("put", path, || {
+            self.inner.put_with_attributes(path, data.clone(), attributes.clone())
+        })
+        .await
+    }
+
+    async fn put_stream(
+        &self,
+        path: &str,
+        stream: BoxStream<'static, Result<Bytes, io::Error>>,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        // The stream is consumed by the first attempt
+        self.inner.put_stream(path, stream, attributes).await
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        self.retry("delete", path, || self.inner.delete(path)).await
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        offset: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<ObjectMeta>, StorageError> {
+        self.retry("list", prefix, || self.inner.list(prefix, offset, limit)).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: &str) -> Result<ListResult, StorageError> {
+        self.retry("list_with_delimiter", prefix, || self.inner.list_with_delimiter(prefix))
+            .await
+    }
+
+    async fn head(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        self.retry("head", path, || self.inner.head(path)).await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.retry("copy", to, || self.inner.copy(from, to)).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        // Not retried: a copy that succeeded before its response was lost
+        // would make the retry fail with AlreadyExists
+        self.inner.copy_if_not_exists(from, to).await
+    }
+
+    async fn delete_many(&self, paths: Vec<String>) -> Vec<(String, Result<(), StorageError>)> {
+        // Only the keys failing transiently are deleted again
+        let start = Instant::now();
+        let mut results = self.inner.delete_many(paths).await;
+        for attempts in 1..self.max_attempts {
+            let failed: Vec<usize> = (0..results.len())
+                .filter(|&i| matches!(&results[i].1, Err(e) if transient(e).is_some()))
+                .collect();
+            if failed.is_empty() {
+                break;
+            }
+            let backoff = jitter(self.backoff(attempts));
+            if start.elapsed() + backoff > self.budget {
+                break;
+            }
+
+            for &i in &failed {
+                if let (path, Err(error)) = &results[i] {
+                    let class = transient(error).map_or("other", |class| class.as_str());
+                    debug!(
+                        operation = "delete_many",
+                        key = %path,
+                        attempt = attempts + 1,
+                        class,
+                        backoff_ms = backoff.as_millis() as u64,
+                        error = %error,
+                        "Retrying storage operation"
+                    );
+                    STORAGE_RETRIES.with_label_values(&["delete_many", class]).inc();
+                }
+            }
+            tokio::time::sleep(backoff).await;
+            let retried = self
+                .inner
+                .delete_many(failed.iter().map(|&i| results[i].0.clone()).collect())
+                .await;
+            for (i, (_, result)) in failed.into_iter().zip(retried) {
+                results[i].1 = result;
+            }
+        }
+        results
+    }
+
+    fn object_store(&self) -> &dyn ObjectStore {
+        self.inner.object_store()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MockBackend, MockOperation, MockResponse};
+
+    fn config(max_attempts: u32, budget_ms: u64) -> RetryConfig {
+        RetryConfig {
+            max_attempts,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 2,
+            budget_ms,
+        }
+    }
+
+    fn unavailable() -> MockResponse {
+        MockResponse::Error(object_store::Error::Generic {
+            store: "mock",
+            source: "Server returned non-2xx status code: 503 Service Unavailable".into(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_transient_failures_retried() {
+        let mock = Arc::new(MockBackend::new());
+        mock.push(MockOperation::Put, unavailable());
+        mock.push(MockOperation::Put, unavailable());
+        let backend = RetryBackend::new(mock.clone(), &config(3, 1000));
+        let retries = || STORAGE_RETRIES.with_label_values(&["put", "other"]).get();
+        let before = retries();
+
+        backend.put("a.txt", Bytes::from("data")).await.unwrap();
+
+        let puts = mock.calls_of(MockOperation::Put);
+        assert_eq!(puts.len(), 3);
+        assert!(puts.iter().all(|call| call.size == Some(4)));
+        assert!(retries() >= before + 2);
+        assert_eq!(mock.get("a.txt").await.unwrap(), Bytes::from("data"));
+    }
+
+    #[tokio::test]
+    async fn test_attempts_capped() {
+        let mock = Arc::new(MockBackend::new());
+        for _ in 0..3 {
+            mock.push(MockOperation::Get, unavailable());
+        }
+        let backend = RetryBackend::new(mock.clone(), &config(2, 1000));
+
+        assert!(backend.get("a.txt").await.is_err());
+        assert_eq!(mock.calls_of(MockOperation::Get).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_permanent_failures_not_retried() {
+        let mock = Arc::new(MockBackend::new());
+        mock.push(
+            MockOperation::Delete,
+            MockResponse::Error(object_store::Error::Generic {
+                store: "mock",
+                source: "403 Forbidden: AccessDenied".into(),
+            }),
+        );
+        let backend = RetryBackend::new(mock.clone(), &config(3, 1000));
+
+        assert!(backend.head("missing").await.is_err());
+        assert!(backend.delete("a.txt").await.is_err());
+        assert_eq!(mock.calls().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_delete_retries_failed_keys() {
+        let mock = Arc::new(MockBackend::new());
+        mock.put("a", Bytes::from("a")).await.unwrap();
+        mock.put("b", Bytes::from("b")).await.unwrap();
+        mock.push(MockOperation::Delete, unavailable());
+        let backend = RetryBackend::new(mock.clone(), &config(3, 1000));
+
+        let results = backend.delete_many(vec!["a".to_string(), "b".to_string()]).await;
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+        assert_eq!(results[0].0, "a");
+
+        // Only the key that failed was deleted again
+        let deletes: Vec<String> = mock.calls_of(MockOperation::Delete).into_iter().map(|call| call.path).collect();
+        assert_eq!(deletes, ["a", "b", "a"]);
+        assert!(mock.head("a").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_budget_ends_retries() {
+        let mock = Arc::new(MockBackend::new());
+        mock.set_operation_latency(MockOperation::List, Duration::from_millis(20));
+        mock.push(MockOperation::List, unavailable());
+        mock.push(MockOperation::List, unavailable());
+        let backend = RetryBackend::new(mock.clone(), &config(5, 10));
+
+        assert!(backend.list("", None, None).await.is_err());
+        assert_eq!(mock.calls_of(MockOperation::List).len(), 1);
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let backend = RetryBackend::new(
+            Arc::new(MockBackend::new()),
+            &RetryConfig {
+                initial_backoff_ms: 100,
+                max_backoff_ms: 300,
+                ..RetryConfig::default()
+            },
+        );
+        assert_eq!(backend.backoff(1), Duration::from_millis(100));
+        assert_eq!(backend.backoff(2), Duration::from_millis(200));
+        assert_eq!(backend.backoff(3), Duration::from_millis(300));
+        assert!(jitter(Duration::from_millis(100)) <= Duration::from_millis(100));
+    }
+}
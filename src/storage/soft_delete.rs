@@ -0,0 +1,410 @@
+//! Soft-delete storage backend decorator
+//!
+//! Turns deletes into moves to a trash prefix, so objects deleted by
+//! mistake, say by a recursive delete of the wrong prefix, can be copied
+//! back. A deleted object is copied to `<trash>/<timestamp>/<key>` with the
+//! backend's copy and then deleted; objects already in the trash are
+//! deleted for real. Trash keys are hidden from listings outside the trash
+//! prefix, and entries older than the retention period are purged in the
+//! background.
+//!
+//! An object that cannot be kept in the trash is not deleted at all: one
+//! whose trash key would pass S3's key length limit, or one too large for
+//! the backend to copy in one request. The delete fails with `AccessDenied`
+//! saying why, rather than losing the object for good.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use futures::stream::BoxStream;
+use object_store::path::Path;
+use object_store::{GetOptions, GetResult, ListResult, ObjectMeta, ObjectStore, PutResult};
+use percent_encoding::percent_decode_str;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::config::SoftDeleteConfig;
+use crate::errors::{StorageError, StorageErrorClass};
+use crate::metrics::SOFT_DELETES;
+use crate::s3;
+use crate::storage::{delete_each, ObjectAttributes, StorageBackend};
+
+/// Format of the trash directory named after the time of the delete,
+/// sorting in time order
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+/// Largest object S3 copies in one request
+const MAX_COPY_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+/// Store name of the error refusing a delete that cannot keep the object
+pub(crate) const SOFT_DELETE_STORE: &str = "SoftDelete";
+
+/// Storage backend moving deleted objects of an inner backend to a trash
+/// prefix
+pub struct SoftDeleteBackend {
+    inner: Arc<dyn StorageBackend>,
+    /// Trash prefix as a key, without surrounding slashes
+    trash: String,
+    /// Trash prefix in the encoded form of listed locations
+    trash_location: String,
+    retention: Duration,
+    delete_concurrency: usize,
+}
+
+impl SoftDeleteBackend {
+    pub fn new(inner: Arc<dyn StorageBackend>, config: &SoftDeleteConfig, delete_concurrency: usize) -> Self {
+        let trash = config.trash_prefix.trim_matches('/').to_string();
+        Self {
+            inner,
+            trash_location: Path::from(trash.as_str()).to_string(),
+            trash,
+            retention: Duration::from_secs(config.retention_secs),
+            delete_concurrency,
+        }
+    }
+
+    /// Purge expired trash entries every `interval`, for as long as the
+    /// backend is in use; a zero interval never purges
+    pub fn spawn_purger(self: &Arc<Self>, interval: Duration) {
+        if interval.is_zero() {
+            return;
+        }
+        let backend = Arc::downgrade(self);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let Some(backend) = backend.upgrade() else {
+                    return;
+                };
+                match backend.purge(Utc::now()).await {
+                    Ok(0) => {}
+                    Ok(purged) => info!(trash = %backend.trash, purged, "Purged expired objects from the trash"),
+                    Err(e) => warn!(trash = %backend.trash, error = %e, "Failed to purge the trash"),
+                }
+            }
+        });
+    }
+
+    /// Delete every object moved to the trash longer than the retention
+    /// period before `now`, returning how many were deleted
+    pub async fn purge(&self, now: DateTime<Utc>) -> Result<usize, StorageError> {
+        let deletes = self.inner.list_with_delimiter(&self.trash).await?;
+        let mut purged = 0;
+        for directory in deletes.common_prefixes {
+            let deleted_at = directory
+                .filename()
+                .and_then(|name| NaiveDateTime::parse_from_str(name, TIMESTAMP_FORMAT).ok());
+            let Some(deleted_at) = deleted_at else {
+                continue;
+            };
+            if (now - deleted_at.and_utc()).to_std().unwrap_or_default() < self.retention {
+                continue;
+            }
+            let objects = self.inner.list(&decoded(&directory), None, None).await?;
+            let paths = objects.iter().map(|meta| decoded(&meta.location)).collect();
+            for (path, result) in self.inner.delete_many(paths).await {
+                match result {
+                    Ok(()) => purged += 1,
+                    Err(e) => warn!(path, error = %e, "Failed to purge object from the trash"),
+                }
+            }
+        }
+        SOFT_DELETES.with_label_values(&["purged"]).inc_by(purged as u64);
+        Ok(purged)
+    }
+
+    /// Whether `path`, a key or a listed location, is in the trash
+    fn in_trash(&self, path: &str, trash: &str) -> bool {
+        path.strip_prefix(trash)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
+    fn is_trash_key(&self, path: &str) -> bool {
+        self.in_trash(path, &self.trash)
+    }
+
+    fn is_trash_location(&self, location: &Path) -> bool {
+        self.in_trash(location.as_ref(), &self.trash_location)
+    }
+
+    /// Whether a listing of `prefix` is of the trash itself, which shows
+    /// trash keys
+    fn lists_trash(&self, prefix: &str) -> bool {
+        self.is_trash_key(prefix.trim_end_matches('/'))
+    }
+
+    fn trash_key(&self, path: &str) -> String {
+        format!("{}/{}/{}", self.trash, Utc::now().format(TIMESTAMP_FORMAT), path)
+    }
+
+    /// The error to report when copying `path` to the trash failed with
+    /// `error`: a refusal if the object is too large to copy
+    async fn copy_failure(&self, path: &str, error: StorageError) -> StorageError {
+        match self.inner.head(path).await {
+            Ok(meta) if meta.size as u64 > MAX_COPY_SIZE => refused(
+                path,
+                format!(
+                    "{} is {} bytes, too large to copy to the trash (at most {} bytes); it was not deleted",
+                    path, meta.size, MAX_COPY_SIZE
+                ),
+            ),
+            _ => error,
+        }
+    }
+}
+
+/// Error refusing the delete of `path`, saying why
+fn refused(path: &str, message: String) -> StorageError {
+    let source = io::Error::new(io::ErrorKind::PermissionDenied, message);
+    let error = object_store::Error::Generic {
+        store: SOFT_DELETE_STORE,
+        source: Box::new(source),
+    };
+    StorageError::from(error).with_context(SOFT_DELETE_STORE, "delete", path)
+}
+
+/// Key of a listed location
+fn decoded(location: &Path) -> String {
+    percent_decode_str(location.as_ref()).decode_utf8_lossy().into_owned()
+}
+
+#[async_trait]
+impl StorageBackend for SoftDeleteBackend {
+    async fn get_opts(&self, path: &str, options: GetOptions) -> Result<GetResult, StorageError> {
+        self.inner.get_opts(path, options).await
+    }
+
+    async fn put_with_attributes(
+        &self,
+        path: &str,
+        data: Bytes,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        self.inner.put_with_attributes(path, data, attributes).await
+    }
+
+    async fn put_stream(
+        &self,
+        path: &str,
+        stream: BoxStream<'static, Result<Bytes, io::Error>>,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        self.inner.put_stream(path, stream, attributes).await
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        if self.is_trash_key(path) {
+            self.inner.delete(path).await?;
+            SOFT_DELETES.with_label_values(&["purged"]).inc();
+            return Ok(());
+        }
+        let trash_key = self.trash_key(path);
+        if trash_key.len() > s3::MAX_KEY_LENGTH {
+            return Err(refused(
+                path,
+                format!(
+                    "{} is too long to be kept in the trash as {} bytes (at most {}); it was not deleted",
+                    path,
+                    trash_key.len(),
+                    s3::MAX_KEY_LENGTH
+                ),
+            ));
+        }
+        match self.inner.copy(path, &trash_key).await {
+            Ok(()) => {}
+            // Nothing to keep; the delete reports the missing key as usual
+            Err(e) if e.class() == StorageErrorClass::NotFound => return self.inner.delete(path).await,
+            Err(e) => return Err(self.copy_failure(path, e).await),
+        }
+        self.inner.delete(path).await?;
+        SOFT_DELETES.with_label_values(&["trashed"]).inc();
+        Ok(())
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        offset: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<ObjectMeta>, StorageError> {
+        if self.lists_trash(prefix) {
+            return self.inner.list(prefix, offset, limit).await;
+        }
+        // Pages may be all trash, so keep listing until enough other keys
+        // are found or the listing ends
+        let mut listed = Vec::new();
+        let mut offset = offset.map(str::to_string);
+        loop {
+            let wanted = limit.map(|limit| limit - listed.len());
+            let page = self.inner.list(prefix, offset.as_deref(), wanted).await?;
+            let ended = wanted.is_none_or(|wanted| page.len() < wanted);
+            offset = page.last().map(|meta| meta.location.to_string());
+            listed.extend(page.into_iter().filter(|meta| !self.is_trash_location(&meta.location)));
+            if ended || limit.is_some_and(|limit| listed.len() >= limit) {
+                return Ok(listed);
+            }
+        }
+    }
+
+    async fn list_with_delimiter(&self, prefix: &str) -> Result<ListResult, StorageError> {
+        let mut result = self.inner.list_with_delimiter(prefix).await?;
+        if !self.lists_trash(prefix) {
+            result.objects.retain(|meta| !self.is_trash_location(&meta.location));
+            result.common_prefixes.retain(|prefix| !self.is_trash_location(prefix));
+        }
+        Ok(result)
+    }
+
+    async fn head(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        self.inner.head(path).await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.inner.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+
+    async fn delete_many(&self, paths: Vec<String>) -> Vec<(String, Result<(), StorageError>)> {
+        delete_each(paths, self.delete_concurrency, |path| async move { self.delete(&path).await }).await
+    }
+
+    fn object_store(&self) -> &dyn ObjectStore {
+        self.inner.object_store()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::S3ProxyError;
+    use crate::storage::{MemoryBackend, MockBackend, MockOperation, MockResponse};
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+
+    async fn response(error: StorageError) -> (StatusCode, String) {
+        let response = S3ProxyError::Storage(error).into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    fn soft_delete(inner: &Arc<dyn StorageBackend>) -> SoftDeleteBackend {
+        SoftDeleteBackend::new(inner.clone(), &SoftDeleteConfig::default(), 4)
+    }
+
+    async fn keys(backend: &dyn StorageBackend, prefix: &str) -> Vec<String> {
+        let listed = backend.list(prefix, None, None).await.unwrap();
+        listed.iter().map(decoded).collect()
+    }
+
+    fn decoded(meta: &ObjectMeta) -> String {
+        super::decoded(&meta.location)
+    }
+
+    #[tokio::test]
+    async fn test_deletes_move_objects_to_trash() {
+        let inner: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new(&Default::default()));
+        let backend = soft_delete(&inner);
+        backend.put("logs/a 100%.log", Bytes::from("a")).await.unwrap();
+        backend.put("logs/b.log", Bytes::from("b")).await.unwrap();
+        let trashed = SOFT_DELETES.with_label_values(&["trashed"]).get();
+
+        backend.delete("logs/a 100%.log").await.unwrap();
+        let results = backend.delete_many(vec!["logs/b.log".to_string(), "logs/missing".to_string()]).await;
+        assert!(results.iter().all(|(_, result)| result.is_ok()), "{results:?}");
+        assert!(SOFT_DELETES.with_label_values(&["trashed"]).get() >= trashed + 2);
+
+        // Gone from their keys and from listings
+        assert!(backend.head("logs/b.log").await.is_err());
+        assert!(keys(&backend, "").await.is_empty());
+        let root = backend.list_with_delimiter("").await.unwrap();
+        assert!(root.objects.is_empty() && root.common_prefixes.is_empty(), "{root:?}");
+
+        // but kept in the trash, listed there with their original keys
+        let trash = keys(&backend, ".trash/").await;
+        assert_eq!(trash.len(), 2, "{trash:?}");
+        assert!(trash[0].starts_with(".trash/") && trash[0].ends_with("/logs/a 100%.log"), "{trash:?}");
+        assert_eq!(backend.get(&trash[1]).await.unwrap(), "b");
+
+        // Deletes in the trash are for real
+        backend.delete(&trash[0]).await.unwrap();
+        assert_eq!(keys(inner.as_ref(), "").await, &trash[1..]);
+    }
+
+    #[tokio::test]
+    async fn test_objects_that_cannot_be_trashed_are_kept() {
+        let inner: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new(&Default::default()));
+        let backend = soft_delete(&inner);
+        // Valid as a key, too long once the trash prefix is added
+        let long = format!("logs/{}", "x".repeat(s3::MAX_KEY_LENGTH - 10));
+        backend.put(&long, Bytes::from("a")).await.unwrap();
+
+        let error = backend.delete(&long).await.unwrap_err();
+        let (status, body) = response(error).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(body.contains("<Code>AccessDenied</Code>") && body.contains("too long to be kept in the trash"), "{body}");
+        assert_eq!(backend.get(&long).await.unwrap(), "a");
+        assert!(keys(inner.as_ref(), ".trash/").await.is_empty());
+
+        // Too large for the backend to copy, as S3 refuses over 5 GiB
+        let mock = Arc::new(MockBackend::new());
+        let inner: Arc<dyn StorageBackend> = mock.clone();
+        let backend = soft_delete(&inner);
+        backend.put("huge.bin", Bytes::from("a")).await.unwrap();
+        mock.push(
+            MockOperation::Copy,
+            MockResponse::Error(object_store::Error::Generic {
+                store: "mock",
+                source: "The specified copy source is larger than the maximum allowable size".into(),
+            }),
+        );
+        let mut meta = inner.head("huge.bin").await.unwrap();
+        meta.size = MAX_COPY_SIZE as usize + 1;
+        mock.push(MockOperation::Head, MockResponse::Meta(meta));
+
+        let (status, body) = response(backend.delete("huge.bin").await.unwrap_err()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(body.contains("too large to copy to the trash"), "{body}");
+        assert_eq!(backend.get("huge.bin").await.unwrap(), "a");
+    }
+
+    #[tokio::test]
+    async fn test_listing_pages_skip_trash() {
+        let inner: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new(&Default::default()));
+        let backend = soft_delete(&inner);
+        for i in 0..10 {
+            backend.put(&format!("{i}"), Bytes::from("x")).await.unwrap();
+        }
+        for i in 0..5 {
+            backend.delete(&format!("{i}")).await.unwrap();
+        }
+
+        // Trash keys sort first, yet each page is full
+        let page = backend.list("", None, Some(3)).await.unwrap();
+        assert_eq!(page.iter().map(decoded).collect::<Vec<_>>(), ["5", "6", "7"]);
+        let page = backend.list("", Some("7"), Some(3)).await.unwrap();
+        assert_eq!(page.iter().map(decoded).collect::<Vec<_>>(), ["8", "9"]);
+    }
+
+    #[tokio::test]
+    async fn test_purge_deletes_expired_objects() {
+        let inner: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new(&Default::default()));
+        let backend = soft_delete(&inner);
+        backend.put("old", Bytes::from("x")).await.unwrap();
+        backend.delete("old").await.unwrap();
+        // Not trash of this proxy, so never purged
+        inner.put(".trash/notes.txt", Bytes::from("x")).await.unwrap();
+
+        assert_eq!(backend.purge(Utc::now()).await.unwrap(), 0);
+        assert_eq!(keys(inner.as_ref(), ".trash/").await.len(), 2);
+
+        let expired = Utc::now() + backend.retention + Duration::from_secs(1);
+        assert_eq!(backend.purge(expired).await.unwrap(), 1);
+        assert_eq!(keys(inner.as_ref(), "").await, [".trash/notes.txt"]);
+    }
+}
@@ -0,0 +1,300 @@
+//! Timing out storage backend decorator
+//!
+//! Wraps another backend and fails operations the backend does not answer
+//! in time with a timeout error, mapped to `503 SlowDown`, so a
+//! hung backend call doesn't hold the request until the server times out.
+//!
+//! Metadata operations (head, list, delete) must complete within
+//! `metadata_ms`. Data transfers are only limited while they make no
+//! progress: a get fails once the backend sends nothing for
+//! `data_idle_ms`, and a streamed put once the backend takes nothing from
+//! the body for that long while the client is not the one holding it up.
+//! Buffered puts and copies report no progress, so `data_idle_ms` caps
+//! them as a whole. Zero disables a limit.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use object_store::{GetOptions, GetResult, GetResultPayload, ListResult, ObjectMeta, ObjectStore, PutResult};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::config::StorageTimeoutsConfig;
+use crate::errors::StorageError;
+use crate::storage::{ObjectAttributes, StorageBackend};
+
+/// Storage backend timing out operations of an inner backend
+pub struct TimeoutBackend {
+    inner: Arc<dyn StorageBackend>,
+    metadata: Option<Duration>,
+    data_idle: Option<Duration>,
+}
+
+impl TimeoutBackend {
+    pub fn new(inner: Arc<dyn StorageBackend>, config: &StorageTimeoutsConfig) -> Self {
+        let limit = |ms: u64| (ms > 0).then(|| Duration::from_millis(ms));
+        Self {
+            inner,
+            metadata: limit(config.metadata_ms),
+            data_idle: limit(config.data_idle_ms),
+        }
+    }
+}
+
+/// Run `call`, failing with a timeout error after `limit`
+async fn within<T, Fut>(limit: Option<Duration>, operation: &str, call: Fut) -> Result<T, StorageError>
+where
+    Fut: Future<Output = Result<T, StorageError>>,
+{
+    let Some(limit) = limit else {
+        return call.await;
+    };
+    match tokio::time::timeout(limit, call).await {
+        Ok(result) => result,
+        Err(_) => Err(timed_out(operation, limit).into()),
+    }
+}
+
+fn timed_out(operation: &str, limit: Duration) -> object_store::Error {
+    object_store::Error::Generic {
+        store: "timeout",
+        source: Box::new(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("Storage {} timed out after {}ms", operation, limit.as_millis()),
+        )),
+    }
+}
+
+/// Fail `stream` once its next chunk takes longer than `idle` to arrive;
+/// time the consumer spends between chunks doesn't count
+fn idle_timeout(
+    stream: BoxStream<'static, Result<Bytes, object_store::Error>>,
+    idle: Duration,
+) -> BoxStream<'static, Result<Bytes, object_store::Error>> {
+    stream::unfold(Some(stream), move |stream| async move {
+        let mut stream = stream?;
+        match tokio::time::timeout(idle, stream.next()).await {
+            Ok(Some(chunk)) => Some((chunk, Some(stream))),
+            Ok(None) => None,
+            Err(_) => Some((Err(timed_out("get", idle)), None)),
+        }
+    })
+    .boxed()
+}
+
+/// Last sign of life of a backend consuming a put body
+struct Progress {
+    at: Instant,
+    /// The body is waiting for the client, not the backend
+    waiting_for_client: bool,
+}
+
+/// Put body recording when the backend takes from it
+struct Watched {
+    inner: BoxStream<'static, Result<Bytes, io::Error>>,
+    progress: Arc<Mutex<Progress>>,
+}
+
+impl Stream for Watched {
+    type Item = Result<Bytes, io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.inner.poll_next_unpin(cx);
+        let mut progress = self.progress.lock().unwrap_or_else(|e| e.into_inner());
+        progress.at = Instant::now();
+        progress.waiting_for_client = poll.is_pending();
+        poll
+    }
+}
+
+#[async_trait]
+impl StorageBackend for TimeoutBackend {
+    async fn get_opts(&self, path: &str, options: GetOptions) -> Result<GetResult, StorageError> {
+        let mut result = within(self.data_idle, "get", self.inner.get_opts(path, options)).await?;
+        if let Some(idle) = self.data_idle {
+            result.payload = match result.payload {
+                GetResultPayload::Stream(stream) => GetResultPayload::Stream(idle_timeout(stream, idle)),
+                file => file,
+            };
+        }
+        Ok(result)
+    }
+
+    async fn put_with_attributes(
+        &self,
+        path: &str,
+        data: Bytes,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        within(self.data_idle, "put", self.inner.put_with_attributes(path, data, attributes)).await
+    }
+
+    async fn put_stream(
+        &self,
+        path: &str,
+        stream: BoxStream<'static, Result<Bytes, io::Error>>,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        let Some(idle) = self.data_idle else {
+            return self.inner.put_stream(path, stream, attributes).await;
+        };
+        let progress = Arc::new(Mutex::new(Progress {
+            at: Instant::now(),
+            waiting_for_client: false,
+        }));
+        let watched = Watched {
+            inner: stream,
+            progress: progress.clone(),
+        };
+        let put = self.inner.put_stream(path, watched.boxed(), attributes);
+        tokio::pin!(put);
+        let mut check = tokio::time::interval(idle / 4);
+        loop {
+            tokio::select! {
+                result = &mut put => return result,
+                _ = check.tick() => {
+                    let progress = progress.lock().unwrap_or_else(|e| e.into_inner());
+                    if !progress.waiting_for_client && progress.at.elapsed() >= idle {
+                        return Err(timed_out("put", idle).into());
+                    }
+                }
+            }
+        }
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        within(self.metadata, "delete", self.inner.delete(path)).await
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        offset: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<ObjectMeta>, StorageError> {
+        within(self.metadata, "list", self.inner.list(prefix, offset, limit)).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: &str) -> Result<ListResult, StorageError> {
+        within(self.metadata, "list", self.inner.list_with_delimiter(prefix)).await
+    }
+
+    async fn head(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        within(self.metadata, "head", self.inner.head(path)).await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        within(self.data_idle, "copy", self.inner.copy(from, to)).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        within(self.data_idle, "copy", self.inner.copy_if_not_exists(from, to)).await
+    }
+
+    async fn delete_many(&self, paths: Vec<String>) -> Vec<(String, Result<(), StorageError>)> {
+        let Some(limit) = self.metadata else {
+            return self.inner.delete_many(paths).await;
+        };
+        match tokio::time::timeout(limit, self.inner.delete_many(paths.clone())).await {
+            Ok(results) => results,
+            Err(_) => paths
+                .into_iter()
+                .map(|path| (path, Err(timed_out("delete", limit).into())))
+                .collect(),
+        }
+    }
+
+    fn object_store(&self) -> &dyn ObjectStore {
+        self.inner.object_store()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::StorageErrorClass;
+    use crate::storage::{MockBackend, MockOperation};
+    use futures::TryStreamExt;
+
+    fn backend(metadata_ms: u64, data_idle_ms: u64) -> (Arc<MockBackend>, TimeoutBackend) {
+        let mock = Arc::new(MockBackend::new());
+        let config = StorageTimeoutsConfig {
+            metadata_ms,
+            data_idle_ms,
+        };
+        (mock.clone(), TimeoutBackend::new(mock, &config))
+    }
+
+    fn is_timeout<T>(result: Result<T, StorageError>) -> bool {
+        matches!(result, Err(e) if e.class() == StorageErrorClass::Timeout)
+    }
+
+    #[tokio::test]
+    async fn test_hung_metadata_operations_time_out() {
+        let (mock, backend) = backend(20, 60_000);
+        mock.put("a", Bytes::from("data")).await.unwrap();
+        mock.set_latency(Duration::from_secs(60));
+
+        let start = Instant::now();
+        assert!(is_timeout(backend.head("a").await));
+        assert!(is_timeout(backend.list("", None, None).await));
+        let results = backend.delete_many(vec!["a".to_string()]).await;
+        assert!(is_timeout(results.into_iter().next().unwrap().1));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_zero_disables_limit() {
+        let (mock, backend) = backend(0, 0);
+        mock.put("a", Bytes::from("data")).await.unwrap();
+        mock.set_latency(Duration::from_millis(30));
+        assert_eq!(backend.head("a").await.unwrap().size, 4);
+    }
+
+    #[tokio::test]
+    async fn test_stalled_download_times_out() {
+        let chunks = stream::iter([Ok(Bytes::from("a"))]).chain(stream::pending()).boxed();
+        let result: Result<Vec<Bytes>, _> = idle_timeout(chunks, Duration::from_millis(20)).try_collect().await;
+        let error = StorageError::from(result.unwrap_err());
+        assert_eq!(error.class(), StorageErrorClass::Timeout);
+    }
+
+    #[tokio::test]
+    async fn test_slow_download_not_killed() {
+        // Each chunk arrives within the limit, the whole body does not
+        let chunks = stream::iter(0..5)
+            .then(|_| async {
+                tokio::time::sleep(Duration::from_millis(15)).await;
+                Ok(Bytes::from("a"))
+            })
+            .boxed();
+        let body: Vec<Bytes> = idle_timeout(chunks, Duration::from_millis(40)).try_collect().await.unwrap();
+        assert_eq!(body.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_stalled_upload_times_out() {
+        let (mock, backend) = backend(60_000, 20);
+        mock.set_operation_latency(MockOperation::PutStream, Duration::from_secs(60));
+        let body = stream::iter([Ok(Bytes::from("data"))]).boxed();
+        assert!(is_timeout(backend.put_stream("a", body, ObjectAttributes::default()).await));
+    }
+
+    #[tokio::test]
+    async fn test_slow_client_upload_not_killed() {
+        let (mock, backend) = backend(60_000, 20);
+        // The client takes longer than the limit between chunks
+        let body = stream::iter(0..3)
+            .then(|_| async {
+                tokio::time::sleep(Duration::from_millis(40)).await;
+                Ok(Bytes::from("a"))
+            })
+            .boxed();
+        backend.put_stream("a", body, ObjectAttributes::default()).await.unwrap();
+        assert_eq!(mock.get("a").await.unwrap(), Bytes::from("aaa"));
+    }
+}
@@ -0,0 +1,219 @@
+//! Write-once storage backend decorator
+//!
+//! Makes objects immutable once written: every put, streamed put and copy
+//! only creates objects, failing with `AlreadyExists` (412
+//! `PreconditionFailed`) when the key is taken. The check is the backend's
+//! own atomic create, never a separate existence check, so of two
+//! concurrent writes of a new key exactly one succeeds. As a multipart
+//! upload cannot be completed conditionally, streamed puts are buffered
+//! and created by one put, see [`put_multipart`](super::put_multipart).
+//! Deletes are rejected unless allowed.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use object_store::{GetOptions, GetResult, ListResult, ObjectMeta, ObjectStore, PutResult};
+use std::io;
+use std::sync::Arc;
+
+use crate::errors::StorageError;
+use crate::storage::{ObjectAttributes, StorageBackend};
+
+/// Store name of the error rejecting deletes
+pub(crate) const WRITE_ONCE_STORE: &str = "WriteOnce";
+
+/// Storage backend that never replaces objects of an inner backend
+pub struct WriteOnceBackend {
+    inner: Arc<dyn StorageBackend>,
+    allow_delete: bool,
+}
+
+impl WriteOnceBackend {
+    /// Protect the objects of `inner`, deleting them only if `allow_delete`
+    pub fn new(inner: Arc<dyn StorageBackend>, allow_delete: bool) -> Self {
+        Self { inner, allow_delete }
+    }
+
+    fn check_delete(&self, path: &str) -> Result<(), StorageError> {
+        if self.allow_delete {
+            return Ok(());
+        }
+        let source = io::Error::new(io::ErrorKind::PermissionDenied, "objects are write-once");
+        let error = object_store::Error::Generic {
+            store: WRITE_ONCE_STORE,
+            source: Box::new(source),
+        };
+        Err(StorageError::from(error).with_context(WRITE_ONCE_STORE, "delete", path))
+    }
+}
+
+#[async_trait]
+impl StorageBackend for WriteOnceBackend {
+    async fn get_opts(&self, path: &str, options: GetOptions) -> Result<GetResult, StorageError> {
+        self.inner.get_opts(path, options).await
+    }
+
+    async fn put_with_attributes(
+        &self,
+        path: &str,
+        data: Bytes,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        let attributes = ObjectAttributes {
+            create_only: true,
+            ..attributes
+        };
+        self.inner.put_with_attributes(path, data, attributes).await
+    }
+
+    async fn put_stream(
+        &self,
+        path: &str,
+        stream: BoxStream<'static, Result<Bytes, io::Error>>,
+        attributes: ObjectAttributes,
+    ) -> Result<PutResult, StorageError> {
+        let attributes = ObjectAttributes {
+            create_only: true,
+            ..attributes
+        };
+        self.inner.put_stream(path, stream, attributes).await
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        self.check_delete(path)?;
+        self.inner.delete(path).await
+    }
+
+    async fn list(
+        &self,
+        prefix: &str,
+        offset: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<ObjectMeta>, StorageError> {
+        self.inner.list(prefix, offset, limit).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: &str) -> Result<ListResult, StorageError> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn head(&self, path: &str) -> Result<ObjectMeta, StorageError> {
+        self.inner.head(path).await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+
+    async fn delete_many(&self, paths: Vec<String>) -> Vec<(String, Result<(), StorageError>)> {
+        if !self.allow_delete {
+            return paths
+                .into_iter()
+                .map(|path| {
+                    let rejected = self.check_delete(&path);
+                    (path, rejected)
+                })
+                .collect();
+        }
+        self.inner.delete_many(paths).await
+    }
+
+    fn object_store(&self) -> &dyn ObjectStore {
+        self.inner.object_store()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{S3ProxyError, StorageErrorClass};
+    use crate::storage::{LocalBackend, MemoryBackend};
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use futures::stream;
+
+    fn status(error: StorageError) -> StatusCode {
+        S3ProxyError::Storage(error).into_response().status()
+    }
+
+    fn body(data: &'static str) -> BoxStream<'static, Result<Bytes, io::Error>> {
+        Box::pin(stream::iter([Ok(Bytes::from(data))]))
+    }
+
+    #[tokio::test]
+    async fn test_objects_cannot_be_replaced() {
+        let root = tempfile::tempdir().unwrap();
+        let inner: Arc<dyn StorageBackend> = Arc::new(LocalBackend::new(root.path()).unwrap());
+        let backend = WriteOnceBackend::new(inner.clone(), false);
+
+        backend.put("a", Bytes::from("first")).await.unwrap();
+        let error = backend.put("a", Bytes::from("second")).await.unwrap_err();
+        assert_eq!(error.class(), StorageErrorClass::Precondition);
+        assert_eq!(status(error), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(backend.get("a").await.unwrap(), "first");
+
+        // Streamed uploads, buffered into one create, and copies alike
+        backend.put_stream("b", body("streamed"), ObjectAttributes::default()).await.unwrap();
+        let error = backend.put_stream("b", body("again"), ObjectAttributes::default()).await.unwrap_err();
+        assert_eq!(status(error), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(backend.get("b").await.unwrap(), "streamed");
+        let error = backend.copy("a", "b").await.unwrap_err();
+        assert_eq!(status(error), StatusCode::PRECONDITION_FAILED);
+        backend.copy("a", "c").await.unwrap();
+        assert_eq!(backend.get("c").await.unwrap(), "first");
+
+        // Nothing else was written along the way
+        let listed = inner.list("", None, None).await.unwrap();
+        let keys: Vec<String> = listed.into_iter().map(|meta| meta.location.into()).collect();
+        assert_eq!(keys, ["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_creates_one_succeeds() {
+        let root = tempfile::tempdir().unwrap();
+        let local: Arc<dyn StorageBackend> = Arc::new(LocalBackend::new(root.path()).unwrap());
+        let memory: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new(&Default::default()));
+
+        for inner in [local, memory] {
+            let backend = Arc::new(WriteOnceBackend::new(inner, false));
+            let writes = (0..2).map(|i| {
+                let backend = backend.clone();
+                tokio::spawn(async move { backend.put("race", Bytes::from(format!("writer {i}"))).await })
+            });
+            let results: Vec<_> = futures::future::join_all(writes).await.into_iter().map(Result::unwrap).collect();
+
+            assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1, "{results:?}");
+            let winner = results.iter().position(Result::is_ok).unwrap();
+            assert_eq!(backend.get("race").await.unwrap(), format!("writer {winner}"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deletes_blocked_unless_allowed() {
+        let inner: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new(&Default::default()));
+        inner.put("a", Bytes::from("data")).await.unwrap();
+
+        let backend = WriteOnceBackend::new(inner.clone(), false);
+        let (status, body) = {
+            let response = S3ProxyError::Storage(backend.delete("a").await.unwrap_err()).into_response();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, String::from_utf8_lossy(&body).into_owned())
+        };
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(body.contains("<Code>AccessDenied</Code>") && body.contains("write-once"), "{body}");
+        let results = backend.delete_many(vec!["a".to_string()]).await;
+        assert!(results[0].1.is_err());
+        assert!(inner.head("a").await.is_ok());
+
+        let backend = WriteOnceBackend::new(inner.clone(), true);
+        backend.delete("a").await.unwrap();
+        assert!(inner.head("a").await.is_err());
+        // A deleted key can be written once more
+        backend.put("a", Bytes::from("new")).await.unwrap();
+    }
+}
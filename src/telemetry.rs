@@ -0,0 +1,219 @@
+//! OpenTelemetry trace export and W3C trace context propagation
+//!
+//! Export is disabled unless `telemetry.otlp_endpoint` is set. When enabled,
+//! request spans and storage operation spans are exported over OTLP in
+//! addition to the JSON logs. The log filter does not apply to exported
+//! spans, so traces stay complete whatever the log level is.
+//!
+//! Propagation is always on: an incoming `traceparent` header decides the
+//! request's trace ID (a new one is generated otherwise), which is logged
+//! with the request span and returned in `x-s3proxy-trace-id`.
+
+use axum::body::Body;
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Request, Response};
+use axum::middleware::Next;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::{TraceContextExt, TraceId};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{self, IdGenerator, RandomIdGenerator, Sampler, Tracer};
+use opentelemetry_sdk::{runtime, Resource};
+use std::time::Duration;
+use tower_http::trace::{DefaultOnRequest, DefaultOnResponse, OnRequest, OnResponse};
+use tracing::field::Empty;
+use tracing::{Level, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::Targets;
+
+use crate::config::TelemetryConfig;
+use crate::{routes, version};
+
+/// Response header carrying the request's trace ID
+pub static TRACE_ID_HEADER: HeaderName = HeaderName::from_static("x-s3proxy-trace-id");
+
+/// Start the OTLP exporter, returning its tracer when export is configured
+pub fn init_tracer(config: &TelemetryConfig) -> Result<Option<Tracer>, Box<dyn std::error::Error>> {
+    let Some(endpoint) = &config.otlp_endpoint else {
+        return Ok(None);
+    };
+    if !(0.0..=1.0).contains(&config.sample_ratio) {
+        return Err(format!("Trace sample ratio must be between 0 and 1, got {}", config.sample_ratio).into());
+    }
+
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio)));
+    let resource = Resource::new([
+        KeyValue::new("service.name", config.service_name.clone()),
+        KeyValue::new("service.version", version::VERSION),
+    ]);
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint))
+        .with_trace_config(trace::config().with_sampler(sampler).with_resource(resource))
+        .install_batch(runtime::Tokio)?;
+    Ok(Some(tracer))
+}
+
+/// Spans exported over OTLP: the proxy's own, at debug level and above
+pub fn export_filter() -> Targets {
+    Targets::new().with_target(env!("CARGO_CRATE_NAME"), Level::DEBUG)
+}
+
+/// Flush pending spans and stop the exporter
+pub async fn shutdown() {
+    // Shutting down the batch processor blocks until it has flushed
+    let _ = tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await;
+}
+
+/// Span for an HTTP request, labeled with the S3 operation it invokes
+///
+/// Logged at info level so the trace ID appears on every request's log
+/// lines.
+pub fn request_span<B>(request: &Request<B>) -> Span {
+    let uri = request.uri();
+    let operation = routes::operation_name(request.method(), uri.path(), uri.query());
+    let (bucket, key) = if routes::is_system_path(uri.path()) {
+        ("", "")
+    } else {
+        uri.path()
+            .trim_start_matches('/')
+            .split_once('/')
+            .unwrap_or((uri.path().trim_start_matches('/'), ""))
+    };
+
+    tracing::info_span!(
+        "request",
+        request_id = Empty,
+        sdk_invocation_id = Empty,
+        sdk_attempt = Empty,
+        trace_id = Empty,
+        span_id = Empty,
+        method = %request.method(),
+        uri = %uri,
+        version = ?request.version(),
+        otel.name = operation,
+        otel.kind = "server",
+        otel.status_code = Empty,
+        s3.operation = operation,
+        s3.bucket = bucket,
+        s3.key = key,
+        http.status_code = Empty,
+        http.request_content_length = content_length(request.headers()),
+        http.response_content_length = Empty,
+    )
+}
+
+/// Log the start of a request, unless disabled
+#[derive(Clone)]
+pub struct LogRequest(Option<DefaultOnRequest>);
+
+impl LogRequest {
+    pub fn new(log: bool) -> Self {
+        Self(log.then(DefaultOnRequest::default))
+    }
+}
+
+impl<B> OnRequest<B> for LogRequest {
+    fn on_request(&mut self, request: &Request<B>, span: &Span) {
+        if let Some(inner) = &mut self.0 {
+            inner.on_request(request, span);
+        }
+    }
+}
+
+/// Record the response status and size on the request span, and log the
+/// response unless disabled
+#[derive(Clone)]
+pub struct RecordResponse(Option<DefaultOnResponse>);
+
+impl RecordResponse {
+    pub fn new(log: bool) -> Self {
+        Self(log.then(DefaultOnResponse::default))
+    }
+}
+
+impl<B> OnResponse<B> for RecordResponse {
+    fn on_response(self, response: &Response<B>, latency: Duration, span: &Span) {
+        let status = response.status();
+        span.record("http.status_code", status.as_u16());
+        if let Some(length) = content_length(response.headers()) {
+            span.record("http.response_content_length", length);
+        }
+        if status.is_server_error() {
+            span.record("otel.status_code", "ERROR");
+        }
+        if let Some(inner) = self.0 {
+            inner.on_response(response, latency, span);
+        }
+    }
+}
+
+/// Continue the caller's trace, or start one, for the current request span
+///
+/// Must run inside the request span created by `request_span`.
+pub async fn propagate(request: Request<Body>, next: Next) -> Response<Body> {
+    let span = Span::current();
+    let remote = TraceContextPropagator::new().extract(&HeaderExtractor(request.headers()));
+    let remote_trace = remote.span().span_context().trace_id();
+    if remote_trace != TraceId::INVALID {
+        span.set_parent(remote);
+    }
+
+    // With OTLP export the exporter's IDs are authoritative; otherwise the
+    // caller's trace is continued or a new one generated
+    let exported = span.context().span().span_context().clone();
+    let (trace_id, span_id) = if exported.is_valid() {
+        (exported.trace_id(), exported.span_id())
+    } else if remote_trace != TraceId::INVALID {
+        (remote_trace, RandomIdGenerator::default().new_span_id())
+    } else {
+        let ids = RandomIdGenerator::default();
+        (ids.new_trace_id(), ids.new_span_id())
+    };
+    span.record("trace_id", tracing::field::display(trace_id));
+    span.record("span_id", tracing::field::display(span_id));
+
+    let mut response = next.run(request).await;
+    response.headers_mut().insert(
+        TRACE_ID_HEADER.clone(),
+        HeaderValue::from_str(&trace_id.to_string()).expect("hex trace ID is a valid header value"),
+    );
+    response
+}
+
+/// Reads propagation headers from a request
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers.get(header::CONTENT_LENGTH)?.to_str().ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_without_endpoint() {
+        assert!(init_tracer(&TelemetryConfig::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_invalid_sample_ratio() {
+        let config = TelemetryConfig {
+            otlp_endpoint: Some("http://localhost:4317".to_string()),
+            sample_ratio: 1.5,
+            ..Default::default()
+        };
+        assert!(init_tracer(&config).unwrap_err().to_string().contains("sample ratio"));
+    }
+}
@@ -0,0 +1,56 @@
+//! Build information
+//!
+//! Captured at compile time by `build.rs` and reported by `/version`, the
+//! `s3proxy_build_info` metric and the startup log line.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Crate version
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Git commit the binary was built from, or `unknown`
+pub const COMMIT: &str = env!("S3PROXY_BUILD_COMMIT");
+
+const BUILD_TIMESTAMP: &str = env!("S3PROXY_BUILD_TIMESTAMP");
+
+const FEATURES: &str = env!("S3PROXY_BUILD_FEATURES");
+
+/// Build information reported by `/version`
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub commit: &'static str,
+    /// Build time in RFC 3339 format
+    pub build_timestamp: String,
+    /// Enabled Cargo features
+    pub features: Vec<&'static str>,
+}
+
+pub fn build_info() -> BuildInfo {
+    let build_timestamp = BUILD_TIMESTAMP
+        .parse()
+        .ok()
+        .and_then(|secs| DateTime::<Utc>::from_timestamp(secs, 0))
+        .map(|time| time.to_rfc3339())
+        .unwrap_or_default();
+    BuildInfo {
+        version: VERSION,
+        commit: COMMIT,
+        build_timestamp,
+        features: FEATURES.split(',').filter(|f| !f.is_empty()).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_info() {
+        let info = build_info();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert!(!info.commit.is_empty());
+        assert!(DateTime::parse_from_rfc3339(&info.build_timestamp).is_ok());
+    }
+}
@@ -0,0 +1,95 @@
+//! GCS backend against a fake-gcs-server container
+//!
+//! Needs Docker, so the test is ignored by default:
+//! `cargo test --test gcs_emulator -- --ignored`
+
+use std::net::{SocketAddr, TcpListener};
+
+use s3proxy_rs::config::{BackendConfig, BackendRetryConfig, GcpConfig};
+use s3proxy_rs::S3Proxy;
+use testcontainers::core::{IntoContainerPort, WaitFor};
+use testcontainers::runners::AsyncRunner;
+use testcontainers::{GenericImage, ImageExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+/// Send an HTTP/1.0 request, answered without chunked encoding, and return
+/// the response status and body
+async fn request(address: SocketAddr, method: &str, path: &str, body: &[u8]) -> (u16, String) {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let head = format!(
+        "{} {} HTTP/1.0\r\nHost: {}\r\nContent-Length: {}\r\n\r\n",
+        method,
+        path,
+        address,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await.unwrap();
+    stream.write_all(body).await.unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+
+    let response = String::from_utf8(response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, body.to_string())
+}
+
+#[tokio::test]
+#[ignore = "needs Docker"]
+async fn test_put_get_list_through_proxy() {
+    // fake-gcs-server only answers XML API requests addressed to its public
+    // host, so the port it is reached on must be known before it starts
+    let port = free_port();
+    let _container = GenericImage::new("fsouza/fake-gcs-server", "latest")
+        .with_wait_for(WaitFor::message_on_stderr("server started"))
+        .with_mapped_port(port, 4443.tcp())
+        .with_cmd([
+            "-scheme",
+            "http",
+            "-backend",
+            "memory",
+            "-public-host",
+            &format!("127.0.0.1:{}", port),
+        ])
+        .start()
+        .await
+        .unwrap();
+    let emulator: SocketAddr = ([127, 0, 0, 1], port).into();
+    let (status, _) = request(emulator, "POST", "/storage/v1/b", br#"{"name": "bucket"}"#).await;
+    assert_eq!(status, 200);
+
+    let proxy = S3Proxy::builder()
+        .bind_address("127.0.0.1:0".parse().unwrap())
+        .backend_config(BackendConfig::Gcp(GcpConfig {
+            bucket_name: "bucket".to_string(),
+            use_managed_identity: false,
+            service_account_path: None,
+            service_account_key: None,
+            endpoint: Some(format!("http://{}", emulator)),
+            allow_http: true,
+            retry: BackendRetryConfig::default(),
+        }))
+        .build()
+        .await
+        .unwrap();
+    let handle = proxy.bind().await.unwrap();
+    let address = handle.local_addr();
+
+    let (status, _) = request(address, "PUT", "/bucket/dir/a.txt", b"hello").await;
+    assert_eq!(status, 200);
+
+    let (status, body) = request(address, "GET", "/bucket/dir/a.txt", b"").await;
+    assert_eq!(status, 200);
+    assert_eq!(body, "hello");
+
+    let (status, body) = request(address, "GET", "/bucket?list-type=2&prefix=dir/", b"").await;
+    assert_eq!(status, 200);
+    assert!(body.contains("<Key>dir/a.txt</Key>"), "{}", body);
+
+    handle.shutdown().await.unwrap();
+}
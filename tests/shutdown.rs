@@ -0,0 +1,119 @@
+//! Graceful shutdown of a running proxy process
+
+#![cfg(unix)]
+
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, ExitStatus};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+/// Start the proxy with an AWS backend that is never contacted
+fn spawn_proxy(port: u16, grace_secs: u64) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_s3proxy-rs"))
+        .env_clear()
+        .env("S3PROXY_SHUTDOWN_GRACE_SECS", grace_secs.to_string())
+        .env("S3PROXY_BIND_ADDRESS", format!("127.0.0.1:{}", port))
+        .env("S3PROXY_BACKEND_TYPE", "aws")
+        .env("S3PROXY_AWS_BUCKET", "bucket")
+        .env("S3PROXY_AWS_REGION", "us-east-1")
+        .env("S3PROXY_AWS_USE_MANAGED_IDENTITY", "false")
+        .env("S3PROXY_AWS_ACCESS_KEY_ID", "AKIDEXAMPLE")
+        .env("S3PROXY_AWS_SECRET_ACCESS_KEY", "secret")
+        .spawn()
+        .expect("Failed to start s3proxy-rs")
+}
+
+fn wait_until_listening(port: u16) {
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(Instant::now() < deadline, "proxy did not start listening");
+        sleep(Duration::from_millis(50));
+    }
+}
+
+fn wait_for_exit(child: &mut Child) -> ExitStatus {
+    let deadline = Instant::now() + Duration::from_secs(30);
+    loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            return status;
+        }
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("proxy did not exit after the shutdown signal");
+        }
+        sleep(Duration::from_millis(50));
+    }
+}
+
+fn signal(child: &Child, name: &str) {
+    let status = Command::new("kill")
+        .arg(format!("-{}", name))
+        .arg(child.id().to_string())
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn test_sigterm_exits_cleanly() {
+    let port = free_port();
+    let mut child = spawn_proxy(port, 30);
+    wait_until_listening(port);
+
+    signal(&child, "TERM");
+    assert!(wait_for_exit(&mut child).success());
+}
+
+#[test]
+fn test_sigint_exits_cleanly() {
+    let port = free_port();
+    let mut child = spawn_proxy(port, 30);
+    wait_until_listening(port);
+
+    signal(&child, "INT");
+    assert!(wait_for_exit(&mut child).success());
+}
+
+/// Start an upload that never finishes sending its body
+fn stalled_upload(port: u16) -> TcpStream {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream
+        .write_all(b"PUT /bucket/key HTTP/1.1\r\nHost: localhost\r\nContent-Length: 1000\r\n\r\npartial")
+        .unwrap();
+    // Give the proxy time to start serving the request
+    sleep(Duration::from_millis(200));
+    stream
+}
+
+#[test]
+fn test_requests_outliving_grace_period_are_abandoned() {
+    let port = free_port();
+    let mut child = spawn_proxy(port, 1);
+    wait_until_listening(port);
+    let _upload = stalled_upload(port);
+
+    let started = Instant::now();
+    signal(&child, "TERM");
+    assert!(wait_for_exit(&mut child).success());
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_secs(1), "exited after {:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(10), "exited after {:?}", elapsed);
+}
+
+#[test]
+fn test_zero_grace_period_exits_immediately() {
+    let port = free_port();
+    let mut child = spawn_proxy(port, 0);
+    wait_until_listening(port);
+    let _upload = stalled_upload(port);
+
+    let started = Instant::now();
+    signal(&child, "TERM");
+    assert!(wait_for_exit(&mut child).success());
+    assert!(started.elapsed() < Duration::from_secs(5));
+}